@@ -0,0 +1,215 @@
+use crate::matrix::Matrix;
+use crate::pattern::Noise;
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// One part of a transform that modifiers can drive. Rotations are in radians, and
+/// `Scale` is added to a uniform scale of 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    TranslateX,
+    TranslateY,
+    TranslateZ,
+    RotateX,
+    RotateY,
+    RotateZ,
+    Scale,
+}
+
+/// Procedural motion: a value for any time, in seconds.
+pub trait Modifier: Debug {
+    fn value_at(&self, time: f64) -> f64;
+}
+
+/// Swings between `-amplitude` and `amplitude`, `frequency` times a second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oscillator {
+    pub amplitude: f64,
+    pub frequency: f64,
+    // Where in the cycle it starts, from 0 to 1.
+    pub phase: f64,
+}
+
+impl Oscillator {
+    pub fn new(amplitude: f64, frequency: f64) -> Self {
+        Self {
+            amplitude,
+            frequency,
+            phase: 0.0,
+        }
+    }
+
+    pub fn with_phase(mut self, phase: f64) -> Self {
+        self.phase = phase;
+        self
+    }
+}
+
+impl Modifier for Oscillator {
+    fn value_at(&self, time: f64) -> f64 {
+        self.amplitude * (2.0 * PI * (self.frequency * time + self.phase)).sin()
+    }
+}
+
+/// Wanders smoothly and unpredictably within `amplitude` of zero, changing direction
+/// about `frequency` times a second, like a hand holding a camera.
+#[derive(Debug, Clone)]
+pub struct NoiseModifier {
+    pub amplitude: f64,
+    pub frequency: f64,
+    noise: Noise,
+}
+
+impl NoiseModifier {
+    // Different seeds wander differently.
+    pub fn new(amplitude: f64, frequency: f64, seed: u64) -> Self {
+        Self {
+            amplitude,
+            frequency,
+            noise: Noise::new(seed),
+        }
+    }
+}
+
+impl Modifier for NoiseModifier {
+    fn value_at(&self, time: f64) -> f64 {
+        // Away from the lattice in y and z, where the noise is always zero.
+        let value = self.noise.at(time * self.frequency, 0.5, 0.5);
+        self.amplitude * value.clamp(-1.0, 1.0)
+    }
+}
+
+/// Modifiers attached to transform channels, giving an offset transform for each moment
+/// of an animation. Channels with several modifiers add them up. Apply the offset after
+/// an object's own transform, `base * offset`, or before a camera's view transform,
+/// `offset * view`, to move either in its own frame.
+#[derive(Debug, Clone, Default)]
+pub struct Modifiers {
+    entries: Vec<(Channel, Arc<dyn Modifier + Send + Sync>)>,
+}
+
+impl Modifiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<M: Modifier + Send + Sync + 'static>(
+        mut self,
+        channel: Channel,
+        modifier: M,
+    ) -> Self {
+        self.entries.push((channel, Arc::new(modifier)));
+        self
+    }
+
+    /// Handheld camera shake: noise that turns the view up to `strength` radians about
+    /// each axis, rolling half as much as it pans and tilts.
+    pub fn camera_shake(strength: f64, frequency: f64, seed: u64) -> Self {
+        Self::new()
+            .with(
+                Channel::RotateX,
+                NoiseModifier::new(strength, frequency, seed),
+            )
+            .with(
+                Channel::RotateY,
+                NoiseModifier::new(strength, frequency, seed + 1),
+            )
+            .with(
+                Channel::RotateZ,
+                NoiseModifier::new(strength / 2.0, frequency, seed + 2),
+            )
+    }
+
+    pub fn value_at(&self, channel: Channel, time: f64) -> f64 {
+        self.entries
+            .iter()
+            .filter(|(c, _)| *c == channel)
+            .map(|(_, modifier)| modifier.value_at(time))
+            .sum()
+    }
+
+    /// Translation, then rotation about x, y and z, then scale.
+    pub fn transform_at(&self, time: f64) -> Matrix {
+        let value = |channel| self.value_at(channel, time);
+        let scale = 1.0 + value(Channel::Scale);
+
+        Matrix::translation(
+            value(Channel::TranslateX),
+            value(Channel::TranslateY),
+            value(Channel::TranslateZ),
+        ) * Matrix::rotation_x(value(Channel::RotateX))
+            * Matrix::rotation_y(value(Channel::RotateY))
+            * Matrix::rotation_z(value(Channel::RotateZ))
+            * Matrix::scaling(scale, scale, scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::transformations::view_transform;
+    use crate::tuple::Tuple;
+    use crate::utils::equal_f64;
+
+    #[test]
+    fn test_an_oscillator_swings_through_its_amplitude() {
+        let o = Oscillator::new(2.0, 0.5);
+
+        assert!(equal_f64(o.value_at(0.0), 0.0));
+        assert!(equal_f64(o.value_at(0.5), 2.0));
+        assert!(equal_f64(o.value_at(1.5), -2.0));
+        assert!(equal_f64(o.clone().with_phase(0.25).value_at(0.0), 2.0));
+    }
+
+    #[test]
+    fn test_noise_wanders_smoothly_within_its_amplitude() {
+        let n = NoiseModifier::new(0.1, 2.0, 7);
+        let values: Vec<f64> = (0..500).map(|i| n.value_at(i as f64 / 100.0)).collect();
+
+        assert!(values.iter().all(|v| v.abs() <= 0.1));
+        assert!(values.iter().any(|v| v.abs() > 0.01));
+        assert!(values.windows(2).all(|w| (w[1] - w[0]).abs() < 0.01));
+        assert_eq!(
+            n.value_at(1.23),
+            NoiseModifier::new(0.1, 2.0, 7).value_at(1.23)
+        );
+    }
+
+    #[test]
+    fn test_modifiers_drive_transform_channels() {
+        let m = Modifiers::new()
+            .with(Channel::TranslateY, Oscillator::new(1.0, 1.0))
+            .with(Channel::TranslateY, Oscillator::new(0.5, 1.0))
+            .with(Channel::RotateZ, Oscillator::new(PI / 2.0, 1.0));
+
+        assert_eq!(m.transform_at(0.0), Matrix::identify());
+        assert_eq!(
+            m.transform_at(0.25) * Tuple::point(1.0, 0.0, 0.0),
+            Tuple::point(0.0, 2.5, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_camera_shake_nudges_the_view() {
+        let view = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        let shake = Modifiers::camera_shake(0.02, 3.0, 1);
+        let mut c = Camera::new(11, 11, PI / 2.0);
+
+        let directions: Vec<Tuple> = (0..10)
+            .map(|frame| {
+                c.transform = shake.transform_at(frame as f64 / 24.0) * view.clone();
+                c.ray_for_pixel(5, 5).direction
+            })
+            .collect();
+
+        let forward = Tuple::vector(0.0, 0.0, 1.0);
+        assert!(directions.iter().all(|d| d.dot(&forward) > 0.999));
+        assert!(directions.iter().any(|d| d.dot(&forward) < 0.999_999));
+    }
+}
@@ -0,0 +1,13 @@
+use ray_tracer::scenes::{chess, chess_camera};
+use std::time::Instant;
+
+fn main() {
+    let world = chess();
+    let camera = chess_camera(1024, 576);
+
+    let start = Instant::now();
+    let canvas = camera.render(&world);
+    println!("Rendered chess scene in {:.2?}", start.elapsed());
+
+    canvas.save("chess.ppm").unwrap();
+}
@@ -1,21 +1,26 @@
+mod examples_common;
+
+use examples_common::Options;
 use ray_tracer::canvas::{Canvas, Color};
 use ray_tracer::light::{lighting, Material, PointLight};
 use ray_tracer::matrix::Matrix;
 use ray_tracer::ray::Ray;
-use ray_tracer::shapes::{Shape, Sphere};
+use ray_tracer::shapes::Sphere;
 use ray_tracer::tuple::Tuple;
 use std::f64::consts::PI;
 use std::sync::Arc;
 
 fn main() {
-    let canvas_pixels = 100;
+    let options = Options::new(100, 100, PI / 3.0, "circle.ppm").with_args();
     let wall_z = 10.0;
     let wall_size = 7.0;
 
-    let pixel_size = wall_size / canvas_pixels as f64;
-    let half = wall_size / 2.0;
+    // The wall fits the shorter side of the canvas.
+    let pixel_size = wall_size / options.width.min(options.height) as f64;
+    let half_width = pixel_size * options.width as f64 / 2.0;
+    let half_height = pixel_size * options.height as f64 / 2.0;
 
-    let mut canvas = Canvas::new(canvas_pixels, canvas_pixels);
+    let mut canvas = Canvas::new(options.width, options.height);
 
     let ray_origin = Tuple::point(0.0, 0.0, -5.0);
     let mut shape_m = Material::new();
@@ -33,11 +38,11 @@ fn main() {
     let light_color = Color::white();
     let light = PointLight::new(&light_position, &light_color);
 
-    for y in 0..canvas_pixels {
-        let world_y = half - pixel_size * y as f64;
+    for y in 0..options.height {
+        let world_y = half_height - pixel_size * y as f64;
 
-        for x in 0..canvas_pixels {
-            let world_x = -half + pixel_size * x as f64;
+        for x in 0..options.width {
+            let world_x = -half_width + pixel_size * x as f64;
             let position = Tuple::point(world_x, world_y, wall_z);
 
             let r = Ray::new(&ray_origin, &(position - ray_origin.clone()).normalize());
@@ -62,5 +67,5 @@ fn main() {
         }
     }
 
-    canvas.save("circle.ppm").unwrap();
+    canvas.save(&options.out).unwrap();
 }
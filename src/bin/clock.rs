@@ -9,7 +9,6 @@ fn main() {
     let origin = Tuple::point(0.0, 0.0, 0.0);
 
     let points: Vec<Tuple> = (0..12)
-        .into_iter()
         .map(|i| {
             let transform =
                 Matrix::rotation_y(i as f64 * PI / 6.0) * Matrix::translation(0.0, 0.0, 1.0);
@@ -0,0 +1,9 @@
+use ray_tracer::scenes::{cornell_box, cornell_box_camera};
+
+fn main() {
+    let world = cornell_box();
+    let camera = cornell_box_camera(512);
+
+    let canvas = camera.render(&world);
+    canvas.save("cornell.ppm").unwrap();
+}
@@ -0,0 +1,140 @@
+// Shared by the example binaries; not every binary uses every option.
+#![allow(dead_code)]
+
+use ray_tracer::camera::Camera;
+use ray_tracer::scenes::{chess, chess_camera, cornell_box, cornell_box_camera, random_spheres};
+use ray_tracer::transformations::view_transform;
+use ray_tracer::tuple::Tuple;
+use ray_tracer::world::World;
+use std::f64::consts::PI;
+use std::process;
+
+pub const SCENES: &str = "chess|cornell|spheres";
+
+const USAGE: &str = "options:
+    --width <pixels>     image width
+    --height <pixels>    image height
+    --fov <radians>      camera field of view
+    --out <file>         where to save the image
+    --samples <count>    rays averaged per pixel";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub width: usize,
+    pub height: usize,
+    pub fov: f64,
+    pub out: String,
+    pub samples: usize,
+}
+
+impl Options {
+    pub fn new(width: usize, height: usize, fov: f64, out: &str) -> Self {
+        Self {
+            width,
+            height,
+            fov,
+            out: out.to_string(),
+            samples: 1,
+        }
+    }
+
+    // Applies the command line to these defaults, exiting with usage on bad input.
+    pub fn with_args(self) -> Self {
+        match self.parse(std::env::args().skip(1)) {
+            Ok(options) => options,
+            Err(message) => {
+                eprintln!("{}\n{}", message, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    pub fn parse<I: Iterator<Item = String>>(mut self, mut args: I) -> Result<Self, String> {
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+            let invalid = || format!("invalid value for {}: {}", flag, value);
+
+            match flag.as_str() {
+                "--width" => self.width = value.parse().map_err(|_| invalid())?,
+                "--height" => self.height = value.parse().map_err(|_| invalid())?,
+                "--fov" => self.fov = value.parse().map_err(|_| invalid())?,
+                "--samples" => self.samples = value.parse().map_err(|_| invalid())?,
+                "--out" => self.out = value,
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+
+        if self.width == 0 || self.height == 0 || self.samples == 0 {
+            return Err("width, height and samples must be positive".to_string());
+        }
+
+        Ok(self)
+    }
+
+    pub fn camera(&self) -> Camera {
+        let mut camera = Camera::new(self.width, self.height, self.fov);
+        camera.samples = self.samples;
+        camera
+    }
+}
+
+// One of the built-in scenes, with a camera suited to it.
+pub fn scene(name: &str) -> Option<(World, Camera)> {
+    match name {
+        "chess" => Some((chess(), chess_camera(1024, 576))),
+        "cornell" => Some((cornell_box(), cornell_box_camera(512))),
+        "spheres" => {
+            let mut camera = Camera::new(1024, 576, PI / 3.0);
+            camera.transform = view_transform(
+                &Tuple::point(0.0, 3.0, -9.0),
+                &Tuple::point(0.0, 0.5, 0.0),
+                &Tuple::vector(0.0, 1.0, 0.0),
+            );
+            Some((random_spheres(0, 200), camera))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn test_defaults_are_kept_without_flags() {
+        let options = Options::new(1024, 500, 1.0, "a.ppm");
+
+        assert_eq!(options.clone().parse(args("")), Ok(options));
+    }
+
+    #[test]
+    fn test_flags_override_the_defaults() {
+        let options = Options::new(1024, 500, 1.0, "a.ppm")
+            .parse(args(
+                "--width 64 --height 48 --fov 0.5 --out b.ppm --samples 4",
+            ))
+            .unwrap();
+
+        assert_eq!(options.width, 64);
+        assert_eq!(options.height, 48);
+        assert_eq!(options.fov, 0.5);
+        assert_eq!(options.out, "b.ppm");
+        assert_eq!(options.camera().samples, 4);
+    }
+
+    #[test]
+    fn test_bad_flags_are_rejected() {
+        let options = Options::new(1024, 500, 1.0, "a.ppm");
+
+        assert!(options.clone().parse(args("--width")).is_err());
+        assert!(options.clone().parse(args("--width wide")).is_err());
+        assert!(options.clone().parse(args("--depth 3")).is_err());
+        assert!(options.parse(args("--samples 0")).is_err());
+    }
+}
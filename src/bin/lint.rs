@@ -0,0 +1,34 @@
+mod examples_common;
+
+use examples_common::{scene, SCENES};
+use ray_tracer::cleanup;
+use std::process;
+
+// Reports problems in built-in scenes, exiting with 1 if any are found.
+fn main() {
+    let names: Vec<String> = std::env::args().skip(1).collect();
+    if names.is_empty() {
+        eprintln!("usage: lint <{}>...", SCENES);
+        process::exit(2);
+    }
+
+    let mut found = false;
+    for name in &names {
+        let (world, camera) = match scene(name) {
+            Some(scene) => scene,
+            None => {
+                eprintln!("unknown scene {}\nusage: lint <{}>...", name, SCENES);
+                process::exit(2);
+            }
+        };
+
+        for issue in cleanup::lint(&world, &[camera]) {
+            println!("{}: {}", name, issue);
+            found = true;
+        }
+    }
+
+    if found {
+        process::exit(1);
+    }
+}
@@ -1,9 +1,11 @@
-use ray_tracer::camera::Camera;
+mod examples_common;
+
+use examples_common::Options;
 use ray_tracer::canvas::Color;
 use ray_tracer::light::{Material, PointLight};
 use ray_tracer::matrix::Matrix;
 use ray_tracer::pattern::{CheckersPattern, GradientPattern, RingPattern, StripePattern};
-use ray_tracer::shapes::{Plane, Shape, Sphere};
+use ray_tracer::shapes::{Plane, Sphere};
 use ray_tracer::transformations::view_transform;
 use ray_tracer::tuple::Tuple;
 use ray_tracer::world::World;
@@ -11,6 +13,8 @@ use std::f64::consts::PI;
 use std::sync::Arc;
 
 fn main() {
+    let options = Options::new(1024, 500, PI / 3.0, "plane.ppm").with_args();
+
     let mut floor_m = Material::new();
     floor_m.color = Color::new(0.97, 0.96, 0.94);
     floor_m.specular = 0.0;
@@ -66,13 +70,10 @@ fn main() {
     ));
 
     let mut world = World::new();
-    world.light = Some(PointLight::new(
-        &Tuple::point(-10.0, 10.0, -10.0),
-        &Color::white(),
-    ));
+    world.lights = vec![PointLight::new(&Tuple::point(-10.0, 10.0, -10.0), &Color::white()).into()];
     world.objects = vec![floor, middle, right, left];
 
-    let mut camera = Camera::new(1024, 500, PI / 3.0);
+    let mut camera = options.camera();
     camera.transform = view_transform(
         &Tuple::point(0.0, 1.5, -5.0),
         &Tuple::point(0.0, 1.0, 0.0),
@@ -80,5 +81,5 @@ fn main() {
     );
 
     let canvas = camera.render(&world);
-    canvas.save("plane.ppm").unwrap();
+    canvas.save(&options.out).unwrap();
 }
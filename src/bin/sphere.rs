@@ -1,8 +1,10 @@
-use ray_tracer::camera::Camera;
+mod examples_common;
+
+use examples_common::Options;
 use ray_tracer::canvas::Color;
 use ray_tracer::light::{Material, PointLight};
 use ray_tracer::matrix::Matrix;
-use ray_tracer::shapes::{Shape, Sphere};
+use ray_tracer::shapes::Sphere;
 use ray_tracer::transformations::view_transform;
 use ray_tracer::tuple::Tuple;
 use ray_tracer::world::World;
@@ -10,6 +12,8 @@ use std::f64::consts::PI;
 use std::sync::Arc;
 
 fn main() {
+    let options = Options::new(1024, 500, PI / 3.0, "sphere.ppm").with_args();
+
     let mut floor_m = Material::new();
     floor_m.color = Color::new(1.0, 0.9, 0.9);
     floor_m.specular = 0.0;
@@ -61,13 +65,14 @@ fn main() {
     ));
 
     let mut world = World::new();
-    world.light = Some(PointLight::new(
-        &Tuple::point(-10.0, 10.0, -10.0),
-        &Color::white(),
-    ));
+    world.lights = vec![PointLight::new(&Tuple::point(-10.0, 10.0, -10.0), &Color::white()).into()];
     world.objects = vec![floor, left_wall, right_wall, middle, right, left];
 
-    let mut camera = Camera::new(1024, 500, PI / 3.0);
+    for issue in world.check_scene() {
+        eprintln!("warning: {}", issue);
+    }
+
+    let mut camera = options.camera();
     camera.transform = view_transform(
         &Tuple::point(0.0, 1.5, -5.0),
         &Tuple::point(0.0, 1.0, 0.0),
@@ -75,5 +80,5 @@ fn main() {
     );
 
     let canvas = camera.render(&world);
-    canvas.save("sphere.ppm").unwrap();
+    canvas.save(&options.out).unwrap();
 }
@@ -0,0 +1,33 @@
+mod examples_common;
+
+use examples_common::{scene, SCENES};
+use std::process;
+
+fn usage() -> String {
+    format!("usage: thumbnail [--no-shadows] <{}>...", SCENES)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let shadows = !args.iter().any(|a| a == "--no-shadows");
+    let names: Vec<&String> = args.iter().filter(|a| *a != "--no-shadows").collect();
+
+    if names.is_empty() {
+        eprintln!("{}", usage());
+        process::exit(2);
+    }
+
+    for name in names {
+        match scene(name) {
+            Some((world, camera)) => {
+                let out = format!("{}-thumb.ppm", name);
+                camera.thumbnail(&world, shadows).save(&out).unwrap();
+                println!("{}", out);
+            }
+            None => {
+                eprintln!("unknown scene {}\n{}", name, usage());
+                process::exit(2);
+            }
+        }
+    }
+}
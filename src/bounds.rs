@@ -0,0 +1,445 @@
+use crate::matrix::Matrix;
+use crate::ray::Ray;
+use crate::tuple::Tuple;
+use crate::utils::EPSILON;
+
+/// An axis-aligned box given by its minimum and maximum corners. The empty box has
+/// its corners at +/- infinity the "wrong" way round so that adding anything to it
+/// yields that thing's bounds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub min: Tuple,
+    pub max: Tuple,
+}
+
+impl BoundingBox {
+    pub fn new(min: Tuple, max: Tuple) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(
+            Tuple::point(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Tuple::point(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn add_point(&mut self, point: &Tuple) {
+        self.min = Tuple::point(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = Tuple::point(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    pub fn merge(&mut self, other: &BoundingBox) {
+        if !other.is_empty() {
+            self.add_point(&other.min);
+            self.add_point(&other.max);
+        }
+    }
+
+    pub fn contains_point(&self, point: &Tuple) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x)
+            && (self.min.y..=self.max.y).contains(&point.y)
+            && (self.min.z..=self.max.z).contains(&point.z)
+    }
+
+    pub fn contains_box(&self, other: &BoundingBox) -> bool {
+        self.contains_point(&other.min) && self.contains_point(&other.max)
+    }
+
+    // Transforms the box one axis at a time (Arvo's method) rather than transforming its
+    // eight corners, so that infinite extents such as a plane's stay well defined.
+    pub fn transform(&self, transform: &Matrix) -> BoundingBox {
+        if self.is_empty() {
+            return Self::empty();
+        }
+
+        let min = [self.min.x, self.min.y, self.min.z];
+        let max = [self.max.x, self.max.y, self.max.z];
+        let mut new_min = [0.0; 3];
+        let mut new_max = [0.0; 3];
+
+        for row in 0..3 {
+            new_min[row] = transform.at(row, 3);
+            new_max[row] = transform.at(row, 3);
+            for col in 0..3 {
+                let m = transform.at(row, col);
+                if m == 0.0 {
+                    continue;
+                }
+                let (a, b) = (m * min[col], m * max[col]);
+                new_min[row] += a.min(b);
+                new_max[row] += a.max(b);
+            }
+        }
+
+        Self::new(
+            Tuple::point(new_min[0], new_min[1], new_min[2]),
+            Tuple::point(new_max[0], new_max[1], new_max[2]),
+        )
+    }
+
+    pub fn overlaps(&self, other: &BoundingBox) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+
+    pub fn intersects(&self, ray: &Ray) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let (xtmin, xtmax) =
+            Self::check_axis(ray.origin.x, ray.direction.x, self.min.x, self.max.x);
+        let (ytmin, ytmax) =
+            Self::check_axis(ray.origin.y, ray.direction.y, self.min.y, self.max.y);
+        let (ztmin, ztmax) =
+            Self::check_axis(ray.origin.z, ray.direction.z, self.min.z, self.max.z);
+
+        let tmin = xtmin.max(ytmin).max(ztmin);
+        let tmax = xtmax.min(ytmax).min(ztmax);
+
+        tmin <= tmax
+    }
+
+    // Splits the box in half along its longest axis.
+    pub fn split(&self) -> (BoundingBox, BoundingBox) {
+        let dx = self.max.x - self.min.x;
+        let dy = self.max.y - self.min.y;
+        let dz = self.max.z - self.min.z;
+
+        let (mut x0, mut y0, mut z0) = (self.min.x, self.min.y, self.min.z);
+        let (mut x1, mut y1, mut z1) = (self.max.x, self.max.y, self.max.z);
+
+        if dx >= dy && dx >= dz {
+            x0 = self.min.x + dx / 2.0;
+            x1 = x0;
+        } else if dy >= dz {
+            y0 = self.min.y + dy / 2.0;
+            y1 = y0;
+        } else {
+            z0 = self.min.z + dz / 2.0;
+            z1 = z0;
+        }
+
+        (
+            Self::new(self.min.clone(), Tuple::point(x1, y1, z1)),
+            Self::new(Tuple::point(x0, y0, z0), self.max.clone()),
+        )
+    }
+
+    fn check_axis(origin: f64, direction: f64, min: f64, max: f64) -> (f64, f64) {
+        let tmin_numerator = min - origin;
+        let tmax_numerator = max - origin;
+
+        let (tmin, tmax) = if direction.abs() >= EPSILON {
+            (tmin_numerator / direction, tmax_numerator / direction)
+        } else {
+            (
+                tmin_numerator * f64::INFINITY,
+                tmax_numerator * f64::INFINITY,
+            )
+        };
+
+        if tmin > tmax {
+            (tmax, tmin)
+        } else {
+            (tmin, tmax)
+        }
+    }
+}
+
+impl Default for BoundingBox {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{Plane, Shape};
+    use std::f64::consts::{FRAC_1_SQRT_2, PI, SQRT_2};
+
+    #[test]
+    fn test_creating_an_empty_bounding_box() {
+        let b = BoundingBox::empty();
+
+        assert!(b.is_empty());
+        assert_eq!(b.min.x, f64::INFINITY);
+        assert_eq!(b.max.x, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_adding_points_to_an_empty_bounding_box() {
+        let mut b = BoundingBox::empty();
+        b.add_point(&Tuple::point(-5.0, 2.0, 0.0));
+        b.add_point(&Tuple::point(7.0, 0.0, -3.0));
+
+        assert_eq!(b.min, Tuple::point(-5.0, 0.0, -3.0));
+        assert_eq!(b.max, Tuple::point(7.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_merging_one_bounding_box_into_another() {
+        let mut a = BoundingBox::new(Tuple::point(-5.0, -2.0, 0.0), Tuple::point(7.0, 4.0, 4.0));
+        let b = BoundingBox::new(Tuple::point(8.0, -7.0, -2.0), Tuple::point(14.0, 2.0, 8.0));
+
+        a.merge(&b);
+
+        assert_eq!(a.min, Tuple::point(-5.0, -7.0, -2.0));
+        assert_eq!(a.max, Tuple::point(14.0, 4.0, 8.0));
+    }
+
+    #[test]
+    fn test_merging_an_empty_box_changes_nothing() {
+        let mut a = BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0));
+
+        a.merge(&BoundingBox::empty());
+
+        assert_eq!(a.min, Tuple::point(-1.0, -1.0, -1.0));
+        assert_eq!(a.max, Tuple::point(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_checking_if_a_box_contains_a_given_point() {
+        let b = BoundingBox::new(Tuple::point(5.0, -2.0, 0.0), Tuple::point(11.0, 4.0, 7.0));
+        let cases = [
+            (Tuple::point(5.0, -2.0, 0.0), true),
+            (Tuple::point(11.0, 4.0, 7.0), true),
+            (Tuple::point(8.0, 1.0, 3.0), true),
+            (Tuple::point(3.0, 0.0, 3.0), false),
+            (Tuple::point(8.0, -4.0, 3.0), false),
+            (Tuple::point(8.0, 1.0, -1.0), false),
+            (Tuple::point(13.0, 1.0, 3.0), false),
+            (Tuple::point(8.0, 5.0, 3.0), false),
+            (Tuple::point(8.0, 1.0, 8.0), false),
+        ];
+
+        for (point, expected) in cases.iter() {
+            assert_eq!(b.contains_point(point), *expected);
+        }
+    }
+
+    #[test]
+    fn test_checking_if_a_box_contains_a_given_box() {
+        let b = BoundingBox::new(Tuple::point(5.0, -2.0, 0.0), Tuple::point(11.0, 4.0, 7.0));
+        let cases = [
+            (
+                Tuple::point(5.0, -2.0, 0.0),
+                Tuple::point(11.0, 4.0, 7.0),
+                true,
+            ),
+            (
+                Tuple::point(6.0, -1.0, 1.0),
+                Tuple::point(10.0, 3.0, 6.0),
+                true,
+            ),
+            (
+                Tuple::point(4.0, -3.0, -1.0),
+                Tuple::point(10.0, 3.0, 6.0),
+                false,
+            ),
+            (
+                Tuple::point(6.0, -1.0, 1.0),
+                Tuple::point(12.0, 5.0, 8.0),
+                false,
+            ),
+        ];
+
+        for (min, max, expected) in cases.iter() {
+            let other = BoundingBox::new(min.clone(), max.clone());
+            assert_eq!(b.contains_box(&other), *expected);
+        }
+    }
+
+    #[test]
+    fn test_checking_if_two_boxes_overlap() {
+        let b = BoundingBox::new(Tuple::point(5.0, -2.0, 0.0), Tuple::point(11.0, 4.0, 7.0));
+        let cases = [
+            (
+                Tuple::point(0.0, 0.0, 0.0),
+                Tuple::point(6.0, 1.0, 1.0),
+                true,
+            ),
+            (
+                Tuple::point(11.0, 4.0, 7.0),
+                Tuple::point(12.0, 5.0, 8.0),
+                true,
+            ),
+            (
+                Tuple::point(0.0, 0.0, 0.0),
+                Tuple::point(4.0, 1.0, 1.0),
+                false,
+            ),
+            (
+                Tuple::point(6.0, 5.0, 1.0),
+                Tuple::point(7.0, 6.0, 2.0),
+                false,
+            ),
+        ];
+
+        for (min, max, result) in cases.iter() {
+            let other = BoundingBox::new(min.clone(), max.clone());
+
+            assert_eq!(b.overlaps(&other), *result);
+            assert_eq!(other.overlaps(&b), *result);
+        }
+        assert!(b.overlaps(&Plane::new().bounds_of()));
+    }
+
+    #[test]
+    fn test_transforming_a_bounding_box() {
+        let b = BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0));
+        let m = Matrix::rotation_x(PI / 4.0) * Matrix::rotation_y(PI / 4.0);
+
+        let b2 = b.transform(&m);
+
+        let corner = 1.0 + FRAC_1_SQRT_2;
+        assert_eq!(b2.min, Tuple::point(-SQRT_2, -corner, -corner));
+        assert_eq!(b2.max, Tuple::point(SQRT_2, corner, corner));
+    }
+
+    #[test]
+    fn test_transforming_an_infinite_bounding_box() {
+        let b = BoundingBox::new(
+            Tuple::point(f64::NEG_INFINITY, 0.0, f64::NEG_INFINITY),
+            Tuple::point(f64::INFINITY, 0.0, f64::INFINITY),
+        );
+
+        let b2 = b.transform(&Matrix::translation(1.0, 2.0, 3.0));
+
+        assert_eq!(b2.min.x, f64::NEG_INFINITY);
+        assert_eq!(b2.min.y, 2.0);
+        assert_eq!(b2.max.z, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_intersecting_a_ray_with_a_bounding_box_at_the_origin() {
+        let b = BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0));
+        let cases = [
+            (
+                Tuple::point(5.0, 0.5, 0.0),
+                Tuple::vector(-1.0, 0.0, 0.0),
+                true,
+            ),
+            (
+                Tuple::point(-5.0, 0.5, 0.0),
+                Tuple::vector(1.0, 0.0, 0.0),
+                true,
+            ),
+            (
+                Tuple::point(0.5, 5.0, 0.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                true,
+            ),
+            (
+                Tuple::point(0.5, 0.0, -5.0),
+                Tuple::vector(0.0, 0.0, 1.0),
+                true,
+            ),
+            (
+                Tuple::point(0.0, 0.5, 0.0),
+                Tuple::vector(0.0, 0.0, 1.0),
+                true,
+            ),
+            (
+                Tuple::point(-2.0, 0.0, 0.0),
+                Tuple::vector(2.0, 4.0, 6.0),
+                false,
+            ),
+            (
+                Tuple::point(0.0, -2.0, 0.0),
+                Tuple::vector(6.0, 2.0, 4.0),
+                false,
+            ),
+            (
+                Tuple::point(2.0, 0.0, 2.0),
+                Tuple::vector(0.0, 0.0, -1.0),
+                false,
+            ),
+            (
+                Tuple::point(0.0, 2.0, 2.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                false,
+            ),
+        ];
+
+        for (origin, direction, expected) in cases.iter() {
+            let r = Ray::new(origin, &direction.normalize());
+            assert_eq!(b.intersects(&r), *expected);
+        }
+    }
+
+    #[test]
+    fn test_intersecting_a_ray_with_a_non_cubic_bounding_box() {
+        let b = BoundingBox::new(Tuple::point(5.0, -2.0, 0.0), Tuple::point(11.0, 4.0, 7.0));
+        let cases = [
+            (
+                Tuple::point(15.0, 1.0, 2.0),
+                Tuple::vector(-1.0, 0.0, 0.0),
+                true,
+            ),
+            (
+                Tuple::point(7.0, 6.0, 5.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                true,
+            ),
+            (
+                Tuple::point(9.0, -1.0, -8.0),
+                Tuple::vector(2.0, 4.0, 6.0),
+                false,
+            ),
+            (
+                Tuple::point(12.0, 5.0, 4.0),
+                Tuple::vector(-1.0, 0.0, 0.0),
+                false,
+            ),
+        ];
+
+        for (origin, direction, expected) in cases.iter() {
+            let r = Ray::new(origin, &direction.normalize());
+            assert_eq!(b.intersects(&r), *expected);
+        }
+    }
+
+    #[test]
+    fn test_splitting_a_perfect_cube() {
+        let b = BoundingBox::new(Tuple::point(-1.0, -4.0, -5.0), Tuple::point(9.0, 6.0, 5.0));
+
+        let (left, right) = b.split();
+
+        assert_eq!(left.min, Tuple::point(-1.0, -4.0, -5.0));
+        assert_eq!(left.max, Tuple::point(4.0, 6.0, 5.0));
+        assert_eq!(right.min, Tuple::point(4.0, -4.0, -5.0));
+        assert_eq!(right.max, Tuple::point(9.0, 6.0, 5.0));
+    }
+
+    #[test]
+    fn test_splitting_a_y_wide_and_a_z_wide_box() {
+        let b = BoundingBox::new(Tuple::point(-1.0, -2.0, -3.0), Tuple::point(9.0, 15.0, 5.0));
+        let (left, right) = b.split();
+        assert_eq!(left.max, Tuple::point(9.0, 6.5, 5.0));
+        assert_eq!(right.min, Tuple::point(-1.0, 6.5, -3.0));
+
+        let b = BoundingBox::new(Tuple::point(-1.0, -2.0, -3.0), Tuple::point(5.0, 3.0, 7.0));
+        let (left, right) = b.split();
+        assert_eq!(left.max, Tuple::point(5.0, 3.0, 2.0));
+        assert_eq!(right.min, Tuple::point(-1.0, -2.0, 2.0));
+    }
+}
@@ -1,9 +1,94 @@
+use crate::bounds::BoundingBox;
 use crate::canvas::{Canvas, Color};
 use crate::matrix::Matrix;
-use crate::ray::Ray;
+use crate::profile::{self, ThreadProfile};
+use crate::ray::{Ray, RayKind};
+use crate::shapes::ShapeId;
+use crate::transformations::view_transform;
 use crate::tuple::Tuple;
+use crate::utils::EPSILON;
 use crate::world::World;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How rays fan out from the camera. `Stereographic` maps the whole sphere of
+/// directions onto the image plane, so fields of view beyond 180 degrees work; pointed
+/// straight down with a field of view near 300 degrees it gives the "little planet" look.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Stereographic,
+}
+
+/// A rectangle of pixels that `render_progressive` renders in one go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    fn center(&self) -> (f64, f64) {
+        (
+            self.x as f64 + self.width as f64 / 2.0,
+            self.y as f64 + self.height as f64 / 2.0,
+        )
+    }
+}
+
+/// The order `render_progressive` works through tiles in. `CenterOut` starts in the
+/// middle of the image, where the subject usually is; `Priority` starts with the tiles
+/// that are brightest in a greyscale map, which is stretched to cover the image.
+#[derive(Debug, Clone)]
+pub enum TileOrder {
+    Scanline,
+    CenterOut,
+    Priority(Arc<Canvas>),
+}
+
+/// Saves samples on pixels that lie inside a single surface. Each pixel first traces
+/// `probes` rays and records what each one hit; only pixels whose probes see different
+/// objects, or normals more than `max_normal_angle` radians apart, are traced again with
+/// the camera's full `samples`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeAware {
+    pub probes: usize,
+    pub max_normal_angle: f64,
+}
+
+impl EdgeAware {
+    pub fn new() -> Self {
+        Self {
+            probes: 4,
+            max_normal_angle: PI / 6.0,
+        }
+    }
+
+    // Whether the surfaces seen by a pixel's probes differ enough to supersample it.
+    fn spans_edge(&self, surfaces: &[Option<(ShapeId, Tuple)>]) -> bool {
+        let min_cos = self.max_normal_angle.cos();
+        surfaces.windows(2).any(|pair| match (&pair[0], &pair[1]) {
+            (Some((a, normal_a)), Some((b, normal_b))) => {
+                a != b || normal_a.dot(normal_b) < min_cos
+            }
+            (None, None) => false,
+            _ => true,
+        })
+    }
+}
+
+impl Default for EdgeAware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Camera {
@@ -11,6 +96,21 @@ pub struct Camera {
     pub vsize: usize,
     pub field_of_view: f64,
     pub transform: Matrix,
+    // Rays averaged per pixel, laid out on a grid; square numbers give an even grid.
+    pub samples: usize,
+    pub projection: Projection,
+    // Caps each channel of every sample before averaging, trading a little energy for
+    // fewer fireflies.
+    pub clamp: Option<f64>,
+    // With four or more samples, drops those far brighter than the pixel's median.
+    pub reject_outliers: bool,
+    pub tile_order: TileOrder,
+    // Diameter of the lens. Zero keeps everything sharp, as through a pinhole; wider
+    // lenses blur what is away from `focal_distance`. Stereographic cameras ignore it.
+    pub aperture: f64,
+    // Distance along the view axis to the plane in sharp focus.
+    pub focal_distance: f64,
+    pub edge_aware: Option<EdgeAware>,
     half_width: f64,
     half_height: f64,
     pixel_size: f64,
@@ -25,6 +125,14 @@ impl Camera {
             vsize,
             field_of_view,
             transform: Matrix::identify(),
+            samples: 1,
+            projection: Projection::Perspective,
+            clamp: None,
+            reject_outliers: false,
+            tile_order: TileOrder::Scanline,
+            aperture: 0.0,
+            focal_distance: 1.0,
+            edge_aware: None,
             half_width,
             half_height,
             pixel_size,
@@ -41,35 +149,688 @@ impl Camera {
         }
     }
 
+    /// Points the camera at the centre of the world's bounds and moves it back along
+    /// its current line of sight until the bounds fit in view, with `padding` as a
+    /// fraction of extra room (0.1 leaves a 10% margin). Unbounded objects such as
+    /// planes are ignored; an empty world leaves the camera where it is.
+    pub fn frame_world(&mut self, world: &World, padding: f64) {
+        let bounds = world.bounds();
+        if bounds.is_empty() {
+            return;
+        }
+
+        let inverse = self.transform.inverse().unwrap();
+        let forward = (&inverse * &Tuple::vector(0.0, 0.0, -1.0)).normalize();
+        let up = &inverse * &Tuple::vector(0.0, 1.0, 0.0);
+
+        let center = Tuple::point(
+            (bounds.min.x + bounds.max.x) / 2.0,
+            (bounds.min.y + bounds.max.y) / 2.0,
+            (bounds.min.z + bounds.max.z) / 2.0,
+        );
+        let radius = (&bounds.max - &center).magnitude().max(EPSILON);
+        let half_angle = match self.projection {
+            Projection::Perspective => self.half_width.min(self.half_height).atan(),
+            Projection::Stereographic => {
+                self.field_of_view / 2.0 * self.hsize.min(self.vsize) as f64
+                    / self.hsize.max(self.vsize) as f64
+            }
+        };
+        let distance = radius * (1.0 + padding) / half_angle.sin();
+
+        let from = &center - &(&forward * distance);
+        self.transform = view_transform(&from, &center, &up);
+    }
+
+    /// Focuses on the centre of the bounds of the object called `name`, wherever it is
+    /// in the world, so that it comes out sharp whatever the aperture.
+    pub fn focus_on(&mut self, world: &World, name: &str) -> Result<(), Box<dyn Error>> {
+        let object = world
+            .find_by_name(name)
+            .ok_or_else(|| format!("no object named {}", name))?;
+        let to_world =
+            object.get_parent_inverse_transform().inverse().unwrap() * object.get_transform();
+        let bounds = object.bounds_of().transform(&to_world);
+        let center = Tuple::point(
+            (bounds.min.x + bounds.max.x) / 2.0,
+            (bounds.min.y + bounds.max.y) / 2.0,
+            (bounds.min.z + bounds.max.z) / 2.0,
+        );
+        if bounds.is_empty() || ![center.x, center.y, center.z].iter().all(|c| c.is_finite()) {
+            return Err(format!("{} has no finite bounds to focus on", name).into());
+        }
+
+        let distance = -(&self.transform * &center).z;
+        if distance <= 0.0 {
+            return Err(format!("{} is behind the camera", name).into());
+        }
+        self.focal_distance = distance;
+
+        Ok(())
+    }
+
     pub fn ray_for_pixel(&self, px: usize, py: usize) -> Ray {
-        let xoffset = (px as f64 + 0.5) * self.pixel_size;
-        let yoffset = (py as f64 + 0.5) * self.pixel_size;
+        self.ray_through(px as f64 + 0.5, py as f64 + 0.5)
+    }
 
-        let world_x = self.half_width - xoffset;
-        let world_y = self.half_height - yoffset;
+    /// Where `point` appears on the canvas, in pixels from its top left corner, or
+    /// `None` when the camera can't see it, e.g. from behind a perspective camera.
+    pub fn project(&self, point: &Tuple) -> Option<(f64, f64)> {
+        let p = &self.transform * point;
+        match self.projection {
+            Projection::Perspective => {
+                if p.z > -EPSILON {
+                    return None;
+                }
+                let world_x = p.x / -p.z;
+                let world_y = p.y / -p.z;
+                Some((
+                    (self.half_width - world_x) / self.pixel_size,
+                    (self.half_height - world_y) / self.pixel_size,
+                ))
+            }
+            Projection::Stereographic => {
+                let d = Tuple::vector(p.x, p.y, p.z).normalize();
+                if 1.0 - d.z < EPSILON {
+                    return None;
+                }
+                let half_span = self.hsize.max(self.vsize) as f64 / 2.0;
+                let scale = (self.field_of_view / 4.0).tan() / half_span;
+                Some((
+                    self.hsize as f64 / 2.0 - d.x / (1.0 - d.z) / scale,
+                    self.vsize as f64 / 2.0 - d.y / (1.0 - d.z) / scale,
+                ))
+            }
+        }
+    }
 
-        let pixel = self.transform.inverse().unwrap() * Tuple::point(world_x, world_y, -1.0);
-        let origin = self.transform.inverse().unwrap() * Tuple::point(0.0, 0.0, 0.0);
-        let direction = (&pixel - &origin).normalize();
+    /// Whether any of `bounds` might be in view. Boxes wholly to one side of the view
+    /// are ruled out, which can miss a few boxes near the corners of the view but
+    /// never rules out one in it. Stereographic cameras may see anything.
+    pub fn can_see(&self, bounds: &BoundingBox) -> bool {
+        if bounds.is_empty() {
+            return false;
+        }
+        if self.projection == Projection::Stereographic {
+            return true;
+        }
+
+        let (min, max) = (&bounds.min, &bounds.max);
+        let corners: Vec<Tuple> = (0..8)
+            .map(|i| {
+                let x = if i & 1 == 0 { min.x } else { max.x };
+                let y = if i & 2 == 0 { min.y } else { max.y };
+                let z = if i & 4 == 0 { min.z } else { max.z };
+                &self.transform * &Tuple::point(x, y, z)
+            })
+            .collect();
+        if corners
+            .iter()
+            .any(|c| !(c.x.is_finite() && c.y.is_finite() && c.z.is_finite()))
+        {
+            return true;
+        }
+
+        // Each is positive on the outer side of a face of the view, which all pass
+        // through the eye: behind, and right, left, top and bottom of the view.
+        let (hw, hh) = (self.half_width, self.half_height);
+        let outside: [&dyn Fn(&Tuple) -> f64; 5] = [
+            &|c| c.z,
+            &|c| c.x + hw * c.z,
+            &|c| -c.x + hw * c.z,
+            &|c| c.y + hh * c.z,
+            &|c| -c.y + hh * c.z,
+        ];
+        !outside
+            .iter()
+            .any(|face| corners.iter().all(|c| face(c) > 0.0))
+    }
+
+    // `x` and `y` are in pixels from the top left corner of the canvas.
+    fn ray_through(&self, x: f64, y: f64) -> Ray {
+        let inverse = self.transform.inverse().unwrap();
+        let origin = &inverse * &Tuple::point(0.0, 0.0, 0.0);
+        let direction = match self.projection {
+            Projection::Perspective => {
+                let world_x = self.half_width - x * self.pixel_size;
+                let world_y = self.half_height - y * self.pixel_size;
+                let pixel = &inverse * &Tuple::point(world_x, world_y, -1.0);
+                (&pixel - &origin).normalize()
+            }
+            Projection::Stereographic => {
+                (&inverse * &self.stereographic_direction(x, y)).normalize()
+            }
+        };
 
         Ray::new(&origin, &direction)
     }
 
+    // A ray through a point on the lens, given by `lens_u` and `lens_v` in [0, 1), that
+    // meets the ray from the centre of the lens at the focal plane.
+    fn lens_ray(&self, x: f64, y: f64, lens_u: f64, lens_v: f64) -> Ray {
+        if self.aperture == 0.0 || self.projection != Projection::Perspective {
+            return self.ray_through(x, y);
+        }
+
+        let inverse = self.transform.inverse().unwrap();
+        let radius = self.aperture / 2.0 * lens_u.sqrt();
+        let angle = 2.0 * PI * lens_v;
+        let lens = &inverse * &Tuple::point(radius * angle.cos(), radius * angle.sin(), 0.0);
+        let focus = &inverse
+            * &Tuple::point(
+                (self.half_width - x * self.pixel_size) * self.focal_distance,
+                (self.half_height - y * self.pixel_size) * self.focal_distance,
+                -self.focal_distance,
+            );
+
+        Ray::new(&lens, &(&focus - &lens).normalize())
+    }
+
+    // Inverse stereographic projection: the image plane is scaled so the edges of its
+    // longer side sit `field_of_view / 2` away from the view axis.
+    fn stereographic_direction(&self, x: f64, y: f64) -> Tuple {
+        let half_span = self.hsize.max(self.vsize) as f64 / 2.0;
+        let scale = (self.field_of_view / 4.0).tan() / half_span;
+        let px = (self.hsize as f64 / 2.0 - x) * scale;
+        let py = (self.vsize as f64 / 2.0 - y) * scale;
+        let r2 = px * px + py * py;
+
+        Tuple::vector(2.0 * px, 2.0 * py, r2 - 1.0) / (1.0 + r2)
+    }
+
     pub fn render(&self, world: &World) -> Canvas {
-        let mut image = Canvas::new(self.hsize, self.vsize);
+        self.render_profiled(world).0
+    }
 
-        let n_pixels = self.hsize * self.vsize;
-        let pixels: Vec<Color> = (0..n_pixels)
+    /// Renders as `render` does, along with where each thread spent its time on
+    /// intersection, shading and shadow rays when `world.options.profile` is set. There
+    /// are no profiles without it.
+    pub fn render_profiled(&self, world: &World) -> (Canvas, Vec<ThreadProfile>) {
+        let results: Vec<(Color, Option<ThreadProfile>)> = (0..self.hsize * self.vsize)
             .into_par_iter()
             .map(|i| {
-                let x = i % self.hsize;
-                let y = i / self.hsize;
+                if !world.options.profile {
+                    return (self.color_for_pixel(world, i), None);
+                }
+                let (color, mut profile) = profile::record(|| self.color_for_pixel(world, i));
+                profile.thread = rayon::current_thread_index().unwrap_or(0);
+                profile.pixels = 1;
+                (color, Some(profile))
+            })
+            .collect();
+
+        let mut threads: Vec<ThreadProfile> = vec![];
+        for profile in results.iter().filter_map(|(_, profile)| profile.as_ref()) {
+            match threads.iter_mut().find(|t| t.thread == profile.thread) {
+                Some(thread) => thread.merge(profile),
+                None => threads.push(profile.clone()),
+            }
+        }
+        threads.sort_by_key(|t| t.thread);
+
+        let pixels: Vec<Color> = results.into_iter().map(|(color, _)| color).collect();
+        (self.to_canvas(&pixels), threads)
+    }
+
+    /// Renders a quick preview 256 pixels wide with the same aspect ratio and view,
+    /// one sample per pixel and, optionally, no shadow rays.
+    pub fn thumbnail(&self, world: &World, shadows: bool) -> Canvas {
+        let width = 256;
+        let height = ((width * self.vsize) as f64 / self.hsize as f64)
+            .round()
+            .max(1.0);
+        let sized = Camera::new(width, height as usize, self.field_of_view);
+        let camera = Camera {
+            hsize: sized.hsize,
+            vsize: sized.vsize,
+            half_width: sized.half_width,
+            half_height: sized.half_height,
+            pixel_size: sized.pixel_size,
+            samples: 1,
+            ..self.clone()
+        };
+        let preview = World {
+            shadows: world.shadows && shadows,
+            ..world.clone()
+        };
+
+        camera.render(&preview)
+    }
+
+    /// A near-instant draft for laying out a scene: each object is drawn as its bounding
+    /// box, rasterized with flat Lambert shading towards the first light rather than
+    /// traced. Infinite shapes such as planes are cut off at `DRAFT_EXTENT`, and the
+    /// perspective projection is always used.
+    pub fn render_draft(&self, world: &World) -> Canvas {
+        let mut canvas = Canvas::new(self.hsize, self.vsize);
+        // The inverse of the distance to the nearest face drawn so far, zero where empty.
+        let mut depth = vec![0.0; self.hsize * self.vsize];
+        let eye = &self.transform.inverse().unwrap() * &Tuple::point(0.0, 0.0, 0.0);
+        let light = world.lights.first().map(|l| l.position());
+
+        for object in &world.objects {
+            let bounds = object.bounds_of();
+            if bounds.is_empty() {
+                continue;
+            }
+            // Cut off before transforming, as a tilted infinite box fills all of space.
+            let bounds = limit_extent(&bounds).transform(&object.get_transform());
+            let color = object.get_material().color;
+
+            for (normal, corners) in box_faces(&bounds) {
+                // Only the faces turned towards the eye, or thin boxes such as planes
+                // flicker between their two sides.
+                if normal.dot(&(&eye - &corners[0])) <= 0.0 {
+                    continue;
+                }
+                let center = corners.iter().fold(Tuple::point(0.0, 0.0, 0.0), |sum, c| {
+                    Tuple::point(sum.x + c.x / 4.0, sum.y + c.y / 4.0, sum.z + c.z / 4.0)
+                });
+                let towards = (light.as_ref().unwrap_or(&eye) - &center).normalize();
+                let shade = color * (0.2 + 0.8 * normal.dot(&towards).max(0.0));
+
+                let in_view: Vec<Tuple> = corners.iter().map(|c| &self.transform * c).collect();
+                let projected: Vec<(f64, f64, f64)> = clip_near(&in_view)
+                    .iter()
+                    .map(|p| {
+                        (
+                            (self.half_width + p.x / p.z) / self.pixel_size,
+                            (self.half_height + p.y / p.z) / self.pixel_size,
+                            -1.0 / p.z,
+                        )
+                    })
+                    .collect();
+                for i in 2..projected.len() {
+                    let triangle = [projected[0], projected[i - 1], projected[i]];
+                    self.fill_triangle(&mut canvas, &mut depth, &triangle, &shade);
+                }
+            }
+        }
+
+        canvas
+    }
+
+    // Vertices are (x, y) in pixels and the inverse of their distance, which unlike the
+    // distance itself varies linearly across the screen.
+    fn fill_triangle(
+        &self,
+        canvas: &mut Canvas,
+        depth: &mut [f64],
+        triangle: &[(f64, f64, f64); 3],
+        color: &Color,
+    ) {
+        let [a, b, c] = triangle;
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area.abs() < EPSILON {
+            return;
+        }
+        let column = |x: f64| (x.max(0.0) as usize).min(self.hsize);
+        let row = |y: f64| (y.max(0.0) as usize).min(self.vsize);
+        let (x0, x1) = (
+            column(a.0.min(b.0).min(c.0)),
+            column(a.0.max(b.0).max(c.0) + 1.0),
+        );
+        let (y0, y1) = (row(a.1.min(b.1).min(c.1)), row(a.1.max(b.1).max(c.1) + 1.0));
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let wa = ((b.0 - px) * (c.1 - py) - (b.1 - py) * (c.0 - px)) / area;
+                let wb = ((c.0 - px) * (a.1 - py) - (c.1 - py) * (a.0 - px)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let inverse_depth = wa * a.2 + wb * b.2 + wc * c.2;
+                let index = y * self.hsize + x;
+                if inverse_depth > depth[index] {
+                    depth[index] = inverse_depth;
+                    canvas.write_pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// Renders one tile at a time in `tile_order`, handing the image so far to
+    /// `on_tile` after each, so a preview can be shown while the rest is still being
+    /// traced. The pixels within a tile are rendered in parallel.
+    pub fn render_progressive<F>(&self, world: &World, tile_size: usize, mut on_tile: F) -> Canvas
+    where
+        F: FnMut(&Tile, &Canvas),
+    {
+        let mut canvas = Canvas::new(self.hsize, self.vsize);
+        for tile in self.tiles(tile_size) {
+            let pixels: Vec<Color> = (0..tile.width * tile.height)
+                .into_par_iter()
+                .map(|i| {
+                    let (x, y) = (tile.x + i % tile.width, tile.y + i / tile.width);
+                    self.color_for_pixel(world, y * self.hsize + x)
+                })
+                .collect();
+            for (i, c) in pixels.iter().enumerate() {
+                canvas.write_pixel(tile.x + i % tile.width, tile.y + i / tile.width, c);
+            }
+            on_tile(&tile, &canvas);
+        }
+
+        canvas
+    }
+
+    /// The image cut into tiles `size` pixels square, smaller along the right and bottom
+    /// edges where needed, in `tile_order`. Ties keep their scanline order.
+    pub fn tiles(&self, size: usize) -> Vec<Tile> {
+        let size = size.max(1);
+        let (hsize, vsize) = (self.hsize, self.vsize);
+        let mut tiles: Vec<Tile> = (0..vsize)
+            .step_by(size)
+            .flat_map(|y| {
+                (0..hsize).step_by(size).map(move |x| Tile {
+                    x,
+                    y,
+                    width: size.min(hsize - x),
+                    height: size.min(vsize - y),
+                })
+            })
+            .collect();
+
+        match &self.tile_order {
+            TileOrder::Scanline => {}
+            TileOrder::CenterOut => {
+                let distance = |tile: &Tile| {
+                    let (x, y) = tile.center();
+                    (x - hsize as f64 / 2.0).hypot(y - vsize as f64 / 2.0)
+                };
+                tiles.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+            }
+            TileOrder::Priority(map) if map.width > 0 && map.height > 0 => {
+                let priority = |tile: &Tile| {
+                    let (x, y) = tile.center();
+                    let mx = ((x / hsize as f64 * map.width as f64) as usize).min(map.width - 1);
+                    let my = ((y / vsize as f64 * map.height as f64) as usize).min(map.height - 1);
+                    map.pixel_at(mx, my).luminance()
+                };
+                tiles.sort_by(|a, b| priority(b).total_cmp(&priority(a)));
+            }
+            TileOrder::Priority(_) => {}
+        }
+
+        tiles
+    }
+
+    /// Renders the whole image with one sample per pixel, then refines it a sample per
+    /// pixel per pass up to `samples`, for when a rough image in time beats a perfect
+    /// one too late, within the world's `options.max_seconds`. Returns the image and
+    /// how many passes finished. Finished renders match `render` apart from edge aware
+    /// sampling, which is not used.
+    pub fn render_refining(&self, world: &World) -> (Canvas, usize) {
+        let start = Instant::now();
+        let out_of_time = || match world.options.max_seconds {
+            Some(max) => start.elapsed().as_secs_f64() >= max,
+            None => false,
+        };
+
+        let mut samples: Vec<Vec<Color>> = vec![vec![]; self.hsize * self.vsize];
+        let mut passes = 0;
+        // A pass cut short is dropped, so every pixel averages the same samples.
+        'passes: for pass in 0..self.samples.max(1) {
+            for y in 0..self.vsize {
+                if pass > 0 && out_of_time() {
+                    break 'passes;
+                }
+                let row: Vec<Color> = (0..self.hsize)
+                    .into_par_iter()
+                    .map(|x| self.color_for_sample(world, y * self.hsize + x, pass))
+                    .collect();
+                for (x, c) in row.into_iter().enumerate() {
+                    samples[y * self.hsize + x].push(c);
+                }
+            }
+            passes += 1;
+        }
+        for pixel in &mut samples {
+            pixel.truncate(passes);
+        }
+
+        let pixels: Vec<Color> = samples.iter().map(|s| self.accumulate(s)).collect();
+        (self.to_canvas(&pixels), passes)
+    }
+
+    /// A fast preview that traces only every `step`th pixel across and down, plus the
+    /// last column and row, and fills in the rest bilinearly. With a `contrast`
+    /// threshold, cells whose corners differ by more than it in any channel, e.g. along
+    /// edges, are then traced in full.
+    pub fn render_sparse(&self, world: &World, step: usize, contrast: Option<f64>) -> Canvas {
+        let columns = lattice(self.hsize, step);
+        let rows = lattice(self.vsize, step);
+        // Single pixel wide or high images have no cells to fill.
+        if columns.len() < 2 || rows.len() < 2 {
+            return self.render(world);
+        }
+
+        let traced: Vec<Color> = rows
+            .iter()
+            .flat_map(|y| columns.iter().map(move |x| (*x, *y)))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(x, y)| self.color_for_pixel(world, y * self.hsize + x))
+            .collect();
+        let corner = |column: usize, row: usize| traced[row * columns.len() + column];
+
+        let mut pixels = vec![Color::black(); self.hsize * self.vsize];
+        let mut detailed = vec![];
+        for row in 0..rows.len() - 1 {
+            for column in 0..columns.len() - 1 {
+                let (x0, x1) = (columns[column], columns[column + 1]);
+                let (y0, y1) = (rows[row], rows[row + 1]);
+                let corners = [
+                    corner(column, row),
+                    corner(column + 1, row),
+                    corner(column, row + 1),
+                    corner(column + 1, row + 1),
+                ];
+                if let Some(threshold) = contrast {
+                    if spread(&corners) > threshold {
+                        detailed.push((x0, x1, y0, y1));
+                    }
+                }
+                for y in y0..=y1 {
+                    let ty = (y - y0) as f64 / (y1 - y0) as f64;
+                    for x in x0..=x1 {
+                        let tx = (x - x0) as f64 / (x1 - x0) as f64;
+                        let top = corners[0] * (1.0 - tx) + corners[1] * tx;
+                        let bottom = corners[2] * (1.0 - tx) + corners[3] * tx;
+                        pixels[y * self.hsize + x] = top * (1.0 - ty) + bottom * ty;
+                    }
+                }
+            }
+        }
+
+        // Neighbouring cells share their edges, which only need tracing once.
+        let mut refine: Vec<usize> = detailed
+            .iter()
+            .flat_map(|(x0, x1, y0, y1)| {
+                (*y0..=*y1).flat_map(move |y| (*x0..=*x1).map(move |x| y * self.hsize + x))
+            })
+            .collect();
+        refine.sort_unstable();
+        refine.dedup();
+        let refined: Vec<(usize, Color)> = refine
+            .into_par_iter()
+            .map(|i| (i, self.color_for_pixel(world, i)))
+            .collect();
+        for (i, color) in refined {
+            pixels[i] = color;
+        }
+
+        self.to_canvas(&pixels)
+    }
+
+    pub fn render_sequential(&self, world: &World) -> Canvas {
+        let pixels: Vec<Color> = (0..self.hsize * self.vsize)
+            .map(|i| self.color_for_pixel(world, i))
+            .collect();
+
+        self.to_canvas(&pixels)
+    }
+
+    // Each pixel is computed from its index alone, so the parallel and sequential
+    // renders perform exactly the same floating point operations per pixel.
+    fn color_for_pixel(&self, world: &World, index: usize) -> Color {
+        let x = index % self.hsize;
+        let y = index / self.hsize;
+
+        if self.samples <= 1 && self.aperture == 0.0 {
+            return self.clamped(world.color_at(&self.ray_for_pixel(x, y)));
+        }
+
+        if let Some(probes) = self.probes_inside_surface(world, index) {
+            let samples: Vec<Color> = probes
+                .iter()
+                .map(|ray| self.clamped(world.color_at(ray)))
+                .collect();
+            return self.accumulate(&samples);
+        }
+
+        let samples: Vec<Color> = self
+            .sample_rays(index, self.samples)
+            .iter()
+            .map(|ray| self.clamped(world.color_at(ray)))
+            .collect();
+
+        self.accumulate(&samples)
+    }
+
+    // One of the samples `color_for_pixel` would average for a pixel.
+    fn color_for_sample(&self, world: &World, index: usize, sample: usize) -> Color {
+        if self.samples <= 1 && self.aperture == 0.0 {
+            return self.color_for_pixel(world, index);
+        }
+
+        let ray = self.sample_ray(index, self.samples, sample);
+        self.clamped(world.color_at(&ray))
+    }
+
+    // The probe rays for a pixel when edge aware sampling finds it inside one surface.
+    fn probes_inside_surface(&self, world: &World, index: usize) -> Option<Vec<Ray>> {
+        let edge_aware = self.edge_aware?;
+        if edge_aware.probes >= self.samples {
+            return None;
+        }
+
+        let probes = self.sample_rays(index, edge_aware.probes);
+        let surfaces: Vec<_> = probes.iter().map(|ray| world.surface_at(ray)).collect();
+        if edge_aware.spans_edge(&surfaces) {
+            None
+        } else {
+            Some(probes)
+        }
+    }
+
+    // `count` rays spread over a pixel on a grid, through random points on the lens.
+    fn sample_rays(&self, index: usize, count: usize) -> Vec<Ray> {
+        (0..count.max(1))
+            .map(|i| self.sample_ray(index, count, i))
+            .collect()
+    }
+
+    // The `i`th of the rays `sample_rays` gives, without making the others.
+    fn sample_ray(&self, index: usize, count: usize, i: usize) -> Ray {
+        let x = index % self.hsize;
+        let y = index / self.hsize;
+        let grid = (count as f64).sqrt().ceil().max(1.0) as usize;
+        // The lens point is the `i`th pair of numbers drawn from the pixel's generator,
+        // each pair taking four words of its stream.
+        let mut rng = ChaCha8Rng::seed_from_u64(index as u64);
+        rng.set_word_pos(4 * i as u128);
+
+        let cell = sample_cell(i, count.max(1));
+        let dx = ((cell % grid) as f64 + 0.5) / grid as f64;
+        let dy = ((cell / grid) as f64 + 0.5) / grid as f64;
+        self.lens_ray(x as f64 + dx, y as f64 + dy, rng.gen(), rng.gen())
+    }
 
+    /// White where edge aware sampling would supersample a pixel and black where its
+    /// probes alone are used, for tuning `EdgeAware`. All white without it.
+    pub fn edge_mask(&self, world: &World) -> Canvas {
+        let pixels: Vec<Color> = (0..self.hsize * self.vsize)
+            .into_par_iter()
+            .map(|i| match self.probes_inside_surface(world, i) {
+                Some(_) => Color::black(),
+                None => Color::white(),
+            })
+            .collect();
+
+        self.to_canvas(&pixels)
+    }
+
+    /// How far, in pixels, the surface seen through the centre of each pixel has moved
+    /// since the previous frame, seen by `previous` in `previous_world`, for temporal
+    /// denoising and motion blur in post. Red holds the motion in x and green in y,
+    /// positive towards the bottom right. Shapes are matched between the frames by
+    /// lineage, so the point follows its shape's transform; shapes new to this frame are taken
+    /// to have stood still. Pixels seeing nothing, or something the previous camera
+    /// couldn't see, are left black.
+    pub fn motion_vectors(
+        &self,
+        world: &World,
+        previous: &Camera,
+        previous_world: &World,
+    ) -> Canvas {
+        let pixels: Vec<Color> = (0..self.hsize * self.vsize)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % self.hsize, i / self.hsize);
                 let ray = self.ray_for_pixel(x, y);
-                world.color_at(&ray)
+                let hit = match world.intersect_visible(&ray, RayKind::Camera).media_hit() {
+                    Some(hit) => hit,
+                    None => return Color::black(),
+                };
+
+                let point = ray.position(hit.t);
+                let before = match previous_world.find_by_lineage(hit.object.lineage()) {
+                    Some(shape) => shape.object_to_world(&hit.object.world_to_object(&point)),
+                    None => point,
+                };
+                match previous.project(&before) {
+                    Some((px, py)) => Color::new(x as f64 + 0.5 - px, y as f64 + 0.5 - py, 0.0),
+                    None => Color::black(),
+                }
             })
             .collect();
 
+        self.to_canvas(&pixels)
+    }
+
+    fn clamped(&self, color: Color) -> Color {
+        match self.clamp {
+            Some(max) => Color::new(
+                color.red.min(max),
+                color.green.min(max),
+                color.blue.min(max),
+            ),
+            None => color,
+        }
+    }
+
+    // Outliers are samples more than four times brighter than the median, or than
+    // white when the median is darker, so edges against black keep their samples.
+    fn accumulate(&self, samples: &[Color]) -> Color {
+        let mut kept = samples.to_vec();
+        if self.reject_outliers && samples.len() >= 4 {
+            let mut luminances: Vec<f64> = samples.iter().map(|c| c.luminance()).collect();
+            luminances.sort_by(f64::total_cmp);
+            let median = luminances[luminances.len() / 2];
+            let limit = 4.0 * median.max(1.0);
+            kept.retain(|c| c.luminance() <= limit);
+        }
+
+        let total = kept.iter().fold(Color::black(), |sum, c| sum + *c);
+        total * (1.0 / kept.len() as f64)
+    }
+
+    fn to_canvas(&self, pixels: &[Color]) -> Canvas {
+        let mut image = Canvas::new(self.hsize, self.vsize);
+
         pixels.iter().enumerate().for_each(|(i, c)| {
             let x = i % self.hsize;
             let y = i / self.hsize;
@@ -81,14 +842,128 @@ impl Camera {
     }
 }
 
+// The cell of a pixel's sample grid that its `i`th of `count` samples falls in.
+// Successive samples stride through the cells by about `count / φ` rather than row by
+// row, so the passes a refining render finishes in time cover the whole pixel.
+fn sample_cell(i: usize, count: usize) -> usize {
+    let mut stride = (count as f64 * 0.618_034).round().max(1.0) as usize;
+    while gcd(stride, count) != 1 {
+        stride += 1;
+    }
+    i * stride % count
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Every `step`th index up to `size`, always including the last.
+fn lattice(size: usize, step: usize) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..size).step_by(step.max(1)).collect();
+    if size > 0 && indices.last() != Some(&(size - 1)) {
+        indices.push(size - 1);
+    }
+    indices
+}
+
+// The largest difference between any two of `colors` in one channel.
+fn spread(colors: &[Color]) -> f64 {
+    let range = |channel: fn(&Color) -> f64| {
+        let values = colors.iter().map(channel);
+        let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+        let min = values.fold(f64::INFINITY, f64::min);
+        max - min
+    };
+    range(|c| c.red)
+        .max(range(|c| c.green))
+        .max(range(|c| c.blue))
+}
+
+/// How far the draft render extends infinite shapes.
+pub const DRAFT_EXTENT: f64 = 1000.0;
+
+fn limit_extent(bounds: &BoundingBox) -> BoundingBox {
+    BoundingBox::new(
+        Tuple::point(
+            bounds.min.x.max(-DRAFT_EXTENT),
+            bounds.min.y.max(-DRAFT_EXTENT),
+            bounds.min.z.max(-DRAFT_EXTENT),
+        ),
+        Tuple::point(
+            bounds.max.x.min(DRAFT_EXTENT),
+            bounds.max.y.min(DRAFT_EXTENT),
+            bounds.max.z.min(DRAFT_EXTENT),
+        ),
+    )
+}
+
+// The faces of a bounding box as outward normals and corners in order around them.
+fn box_faces(bounds: &BoundingBox) -> Vec<(Tuple, [Tuple; 4])> {
+    let (a, b) = (&bounds.min, &bounds.max);
+    let p = |x: &Tuple, y: &Tuple, z: &Tuple| Tuple::point(x.x, y.y, z.z);
+
+    vec![
+        (
+            Tuple::vector(-1.0, 0.0, 0.0),
+            [p(a, a, a), p(a, b, a), p(a, b, b), p(a, a, b)],
+        ),
+        (
+            Tuple::vector(1.0, 0.0, 0.0),
+            [p(b, a, a), p(b, a, b), p(b, b, b), p(b, b, a)],
+        ),
+        (
+            Tuple::vector(0.0, -1.0, 0.0),
+            [p(a, a, a), p(a, a, b), p(b, a, b), p(b, a, a)],
+        ),
+        (
+            Tuple::vector(0.0, 1.0, 0.0),
+            [p(a, b, a), p(b, b, a), p(b, b, b), p(a, b, b)],
+        ),
+        (
+            Tuple::vector(0.0, 0.0, -1.0),
+            [p(a, a, a), p(b, a, a), p(b, b, a), p(a, b, a)],
+        ),
+        (
+            Tuple::vector(0.0, 0.0, 1.0),
+            [p(a, a, b), p(a, b, b), p(b, b, b), p(b, a, b)],
+        ),
+    ]
+}
+
+// Cuts away the part of a convex polygon in camera space that isn't in front of the
+// camera, so that what is left can be projected.
+fn clip_near(polygon: &[Tuple]) -> Vec<Tuple> {
+    let near = -EPSILON;
+    let mut clipped = vec![];
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        if current.z <= near {
+            clipped.push(current.clone());
+        }
+        if (current.z <= near) != (next.z <= near) {
+            let t = (near - current.z) / (next.z - current.z);
+            clipped.push(current + &(&(next - current) * t));
+        }
+    }
+
+    clipped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::canvas::Color;
-    use crate::transformations::view_transform;
+    use crate::light::Material;
+    use crate::shapes::{Group, Plane, Shape, Sphere};
     use crate::utils::equal_f64;
     use crate::world::World;
+    use std::collections::HashSet;
     use std::f64::consts::PI;
+    use std::sync::Arc;
 
     #[test]
     fn test_contructing_a_camera() {
@@ -162,4 +1037,689 @@ mod tests {
 
         assert_eq!(image.pixel_at(5, 5), &Color::new(0.38066, 0.47583, 0.2855));
     }
+
+    #[test]
+    fn test_parallel_and_sequential_renders_are_bit_identical() {
+        let w = World::default_world();
+        let mut c = Camera::new(32, 24, PI / 3.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 1.5, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let parallel = c.render(&w);
+        let sequential = c.render_sequential(&w);
+
+        assert!(parallel
+            .pixels
+            .iter()
+            .zip(sequential.pixels.iter())
+            .all(|(a, b)| a.red.to_bits() == b.red.to_bits()
+                && a.green.to_bits() == b.green.to_bits()
+                && a.blue.to_bits() == b.blue.to_bits()));
+    }
+
+    #[test]
+    fn test_a_single_sample_renders_through_the_pixel_centres() {
+        let w = World::default_world();
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c.samples = 1;
+
+        let image = c.render(&w);
+
+        assert_eq!(image.pixel_at(5, 5), &Color::new(0.38066, 0.47583, 0.2855));
+    }
+
+    #[test]
+    fn test_multiple_samples_average_across_the_pixel() {
+        let w = World::default_world();
+        let mut c = Camera::new(3, 3, 0.4);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c.samples = 4;
+
+        let image = c.render(&w);
+        let expected = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+            .iter()
+            .map(|(dx, dy)| w.color_at(&c.ray_through(*dx, *dy)))
+            .fold(Color::black(), |sum, c| sum + c)
+            * 0.25;
+
+        assert_eq!(image.pixel_at(0, 0), &expected);
+        assert_ne!(image.pixel_at(0, 0), &w.color_at(&c.ray_for_pixel(0, 0)));
+    }
+
+    #[test]
+    fn test_projecting_a_point_finds_its_pixel() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        c.transform = Matrix::rotation_y(PI / 4.0) * Matrix::translation(0.0, -2.0, 5.0);
+
+        let ray = c.ray_for_pixel(100, 50);
+        let (x, y) = c.project(&ray.position(3.0)).unwrap();
+        assert!(equal_f64(x, 100.5) && equal_f64(y, 50.5));
+        assert_eq!(c.project(&ray.position(-3.0)), None);
+
+        c.projection = Projection::Stereographic;
+        c.field_of_view = 1.5 * PI;
+        let ray = c.ray_for_pixel(20, 80);
+        let (x, y) = c.project(&ray.position(2.0)).unwrap();
+        assert!(equal_f64(x, 20.5) && equal_f64(y, 80.5));
+    }
+
+    #[test]
+    fn test_motion_vectors_follow_moving_shapes_and_cameras() {
+        let (mut w, c) = sphere_on_black();
+        let sphere = Sphere::new();
+        w.objects = vec![Arc::new(sphere.clone())];
+
+        let still = c.motion_vectors(&w, &c, &w);
+        assert_eq!(*still.pixel_at(5, 5), Color::black());
+
+        let mut before = World::new();
+        before.objects = vec![Arc::new(
+            sphere.with_transform(&Matrix::translation(0.1, 0.0, 0.0)),
+        )];
+        let moved = c.motion_vectors(&w, &c, &before);
+        let shift = moved.pixel_at(5, 5);
+        assert!(shift.red < 0.0 && equal_f64(shift.green, 0.0));
+        assert_eq!(*moved.pixel_at(0, 0), Color::black());
+
+        let mut raised = c.clone();
+        raised.transform = Matrix::translation(0.0, 0.1, 0.0) * c.transform.clone();
+        assert!(c.motion_vectors(&w, &raised, &w).pixel_at(5, 5).green > 0.0);
+    }
+
+    #[test]
+    fn test_a_camera_sees_only_boxes_in_view() {
+        let (_, c) = sphere_on_black();
+        let cube = |x: f64, y: f64, z: f64| {
+            BoundingBox::new(
+                Tuple::point(x - 0.5, y - 0.5, z - 0.5),
+                Tuple::point(x + 0.5, y + 0.5, z + 0.5),
+            )
+        };
+
+        assert!(c.can_see(&cube(0.0, 0.0, 0.0)));
+        assert!(c.can_see(&cube(1.5, 0.0, 0.0)));
+        assert!(!c.can_see(&cube(5.0, 0.0, 0.0)));
+        assert!(!c.can_see(&cube(0.0, -5.0, 0.0)));
+        assert!(!c.can_see(&cube(0.0, 0.0, -8.0)));
+        assert!(!c.can_see(&BoundingBox::empty()));
+    }
+
+    fn sphere_on_black() -> (World, Camera) {
+        let mut w = World::default_world();
+        w.objects.truncate(1);
+        let mut c = Camera::new(11, 11, 0.5);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c.samples = 16;
+        (w, c)
+    }
+
+    #[test]
+    fn test_edge_aware_sampling_supersamples_only_edges() {
+        let (w, mut c) = sphere_on_black();
+        let full = c.render(&w);
+        c.samples = 4;
+        let probes = c.render(&w);
+        c.samples = 16;
+        c.edge_aware = Some(EdgeAware::new());
+
+        let image = c.render(&w);
+        let mask = c.edge_mask(&w);
+
+        for y in 0..c.vsize {
+            for x in 0..c.hsize {
+                let expected = if *mask.pixel_at(x, y) == Color::white() {
+                    &full
+                } else {
+                    &probes
+                };
+                assert_eq!(image.pixel_at(x, y), expected.pixel_at(x, y));
+            }
+        }
+        assert_eq!(mask.pixel_at(0, 0), &Color::black());
+        assert_eq!(mask.pixel_at(5, 5), &Color::black());
+        assert!(mask.pixels.iter().any(|p| *p == Color::white()));
+    }
+
+    #[test]
+    fn test_edge_aware_sampling_detects_sharp_creases() {
+        let edge_aware = EdgeAware::new();
+        let id = Sphere::new().id();
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+        let tilted = Tuple::vector(0.0, 0.96, 0.28);
+        let side = Tuple::vector(1.0, 0.0, 0.0);
+
+        assert!(!edge_aware.spans_edge(&[Some((id, up.clone())), Some((id, tilted.clone()))]));
+        assert!(edge_aware.spans_edge(&[Some((id, up.clone())), Some((id, side))]));
+        assert!(edge_aware.spans_edge(&[
+            Some((id, up.clone())),
+            Some((Sphere::new().id(), up.clone()))
+        ]));
+        assert!(edge_aware.spans_edge(&[None, Some((id, up))]));
+        assert!(!edge_aware.spans_edge(&[None, None]));
+    }
+
+    #[test]
+    fn test_an_unlimited_refining_render_matches_a_full_render() {
+        let (w, mut c) = sphere_on_black();
+        c.reject_outliers = true;
+
+        let (image, passes) = c.render_refining(&w);
+
+        assert_eq!(passes, 16);
+        assert_eq!(image.pixels, c.render(&w).pixels);
+    }
+
+    #[test]
+    fn test_a_refining_render_out_of_time_keeps_its_first_pass() {
+        let (mut w, c) = sphere_on_black();
+        w.options = w.options.with_max_seconds(0.0);
+
+        let (image, passes) = c.render_refining(&w);
+
+        assert_eq!(passes, 1);
+        for (i, pixel) in image.pixels.iter().enumerate() {
+            let first = &c.sample_rays(i, c.samples)[0];
+            assert_eq!(*pixel, w.color_at(first));
+        }
+    }
+
+    #[test]
+    fn test_the_first_samples_of_a_pixel_are_spread_over_it() {
+        for count in 1..=25 {
+            let mut cells: Vec<usize> = (0..count).map(|i| sample_cell(i, count)).collect();
+            cells.sort_unstable();
+            assert_eq!(cells, (0..count).collect::<Vec<_>>());
+        }
+
+        let rows: HashSet<usize> = (0..8).map(|i| sample_cell(i, 16) / 4).collect();
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn test_a_sparse_render_with_every_pixel_is_a_full_render() {
+        let (w, mut c) = sphere_on_black();
+        c.samples = 1;
+
+        assert_eq!(c.render_sparse(&w, 1, None).pixels, c.render(&w).pixels);
+    }
+
+    #[test]
+    fn test_a_sparse_render_fills_between_traced_pixels() {
+        let (w, mut c) = sphere_on_black();
+        c.samples = 1;
+        let full = c.render(&w);
+
+        let image = c.render_sparse(&w, 4, None);
+
+        assert_eq!(image.pixel_at(4, 4), full.pixel_at(4, 4));
+        assert_eq!(image.pixel_at(10, 10), full.pixel_at(10, 10));
+        assert_eq!(
+            *image.pixel_at(2, 4),
+            (*full.pixel_at(0, 4) + *full.pixel_at(4, 4)) * 0.5
+        );
+        assert_ne!(image.pixel_at(2, 4), full.pixel_at(2, 4));
+    }
+
+    #[test]
+    fn test_a_sparse_render_traces_high_contrast_cells_in_full() {
+        let (w, mut c) = sphere_on_black();
+        c.samples = 1;
+        let full = c.render(&w);
+
+        let image = c.render_sparse(&w, 4, Some(0.1));
+
+        assert_eq!(image.pixel_at(2, 4), full.pixel_at(2, 4));
+        assert_eq!(image.pixel_at(1, 1), &Color::black());
+    }
+
+    #[test]
+    fn test_a_thumbnail_is_256_pixels_wide_with_the_same_aspect_ratio() {
+        let w = World::default_world();
+        let mut c = Camera::new(1024, 512, PI / 2.0);
+        c.samples = 16;
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let image = c.thumbnail(&w, true);
+
+        assert_eq!(image.width, 256);
+        assert_eq!(image.height, 128);
+        let mut small = Camera::new(256, 128, PI / 2.0);
+        small.transform = c.transform.clone();
+        assert_eq!(
+            image.pixel_at(100, 64),
+            &w.color_at(&small.ray_for_pixel(100, 64))
+        );
+    }
+
+    #[test]
+    fn test_a_thumbnail_keeps_the_lens_and_edge_aware_settings() {
+        let w = World::default_world();
+        let mut c = Camera::new(512, 256, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c.aperture = 0.5;
+        c.focal_distance = 3.0;
+        c.edge_aware = Some(EdgeAware::new());
+
+        let image = c.thumbnail(&w, true);
+
+        let mut small = Camera::new(256, 128, PI / 2.0);
+        small.transform = c.transform.clone();
+        small.aperture = 0.5;
+        small.focal_distance = 3.0;
+        small.edge_aware = Some(EdgeAware::new());
+        for &(x, y) in [(100, 64), (128, 40), (160, 90)].iter() {
+            assert_eq!(
+                image.pixel_at(x, y),
+                &small.color_for_pixel(&w, y * 256 + x)
+            );
+        }
+    }
+
+    #[test]
+    fn test_a_stereographic_camera_looks_down_its_axis_at_the_centre() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        c.projection = Projection::Stereographic;
+
+        let r = c.ray_for_pixel(100, 50);
+
+        assert_eq!(r.origin, Tuple::point(0.0, 0.0, 0.0));
+        assert_eq!(r.direction, Tuple::vector(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_a_stereographic_camera_spans_its_field_of_view() {
+        for fov in [PI / 2.0, PI, 1.5 * PI].iter() {
+            let mut c = Camera::new(200, 100, *fov);
+            c.projection = Projection::Stereographic;
+
+            let r = c.ray_through(0.0, 50.0);
+
+            let angle = r.direction.dot(&Tuple::vector(0.0, 0.0, -1.0)).acos();
+            assert!(equal_f64(angle, fov / 2.0));
+            assert!(r.direction.x > 0.0);
+            assert!(equal_f64(r.direction.y, 0.0));
+        }
+    }
+
+    #[test]
+    fn test_a_stereographic_camera_follows_its_transform() {
+        let mut c = Camera::new(101, 101, 1.6 * PI);
+        c.projection = Projection::Stereographic;
+        c.transform = view_transform(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+
+        let r = c.ray_for_pixel(50, 50);
+
+        assert_eq!(r.origin, Tuple::point(0.0, 1.0, 0.0));
+        assert_eq!(r.direction, Tuple::vector(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn test_framing_a_world_keeps_the_view_direction_and_fits_the_bounds() {
+        let mut w = World::default_world();
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(4.0, 2.0, 0.0)),
+        ));
+        let mut c = Camera::new(161, 91, PI / 3.0);
+
+        c.frame_world(&w, 0.1);
+
+        let centre = c.ray_for_pixel(80, 45);
+        assert!(equal_f64(centre.direction.z, -1.0));
+        for (x, y) in [(0, 0), (160, 0), (0, 90), (160, 90)].iter() {
+            let r = c.ray_for_pixel(*x, *y);
+            assert!(w.intersect(&r).hit().is_none());
+        }
+        let b = w.bounds();
+        let half_angle = c.half_width.min(c.half_height).atan();
+        for x in [b.min.x, b.max.x].iter() {
+            for y in [b.min.y, b.max.y].iter() {
+                for z in [b.min.z, b.max.z].iter() {
+                    let corner = Tuple::point(*x, *y, *z);
+                    let direction = (&corner - &centre.origin).normalize();
+                    assert!(direction.dot(&centre.direction).acos() < half_angle);
+                }
+            }
+        }
+        let middle = Tuple::point((b.min.x + b.max.x) / 2.0, (b.min.y + b.max.y) / 2.0, 0.0);
+        let toward_middle = (&middle - &centre.origin).normalize();
+        assert_eq!(toward_middle, centre.direction);
+    }
+
+    #[test]
+    fn test_framing_an_empty_world_leaves_the_camera_alone() {
+        let mut c = Camera::new(160, 90, PI / 3.0);
+        c.transform = Matrix::translation(1.0, 2.0, 3.0);
+
+        c.frame_world(&World::new(), 0.1);
+
+        assert_eq!(c.transform, Matrix::translation(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn test_clamping_caps_every_sample() {
+        let mut m = Material::new();
+        m.ambient = 10.0;
+        let w = World::default_world_with_material(&m);
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        assert!(c.render(&w).pixel_at(5, 5).red > 1.0);
+
+        c.clamp = Some(1.0);
+        c.samples = 4;
+        let pixel = *c.render(&w).pixel_at(5, 5);
+
+        assert_eq!(pixel, Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_rejecting_outliers_drops_fireflies() {
+        let mut c = Camera::new(1, 1, PI / 2.0);
+        let grey = Color::new(0.5, 0.5, 0.5);
+        let samples = [grey, grey, Color::new(50.0, 50.0, 50.0), grey];
+
+        assert!(c.accumulate(&samples).red > 10.0);
+
+        c.reject_outliers = true;
+
+        assert_eq!(c.accumulate(&samples), grey);
+        assert_eq!(
+            c.accumulate(&[Color::black(), Color::black(), Color::white(), grey]),
+            Color::new(0.375, 0.375, 0.375)
+        );
+    }
+
+    #[test]
+    fn test_rejecting_outliers_drops_nan_samples() {
+        let mut c = Camera::new(1, 1, PI / 2.0);
+        c.reject_outliers = true;
+        let grey = Color::new(0.5, 0.5, 0.5);
+
+        assert_eq!(
+            c.accumulate(&[grey, Color::new(f64::NAN, 0.0, 0.0), grey, grey]),
+            grey
+        );
+    }
+
+    #[test]
+    fn test_a_profiled_render_matches_and_counts_its_rays() {
+        let mut w = World::default_world();
+        w.options = w.options.with_profile(true);
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let (image, threads) = c.render_profiled(&w);
+        let mut total = ThreadProfile::default();
+        threads.iter().for_each(|t| total.merge(t));
+        let hits = image
+            .pixels
+            .iter()
+            .filter(|p| **p != Color::black())
+            .count();
+
+        assert_eq!(image.pixels, c.render(&w).pixels);
+        assert_eq!(total.pixels, 121);
+        assert_eq!(total.rays, 121);
+        assert_eq!(total.shadow_rays, hits);
+        assert!(c.render_profiled(&World::default_world()).1.is_empty());
+    }
+
+    #[test]
+    fn test_a_draft_render_draws_object_bounds() {
+        let w = World::default_world();
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let image = c.render_draft(&w);
+
+        let center = image.pixel_at(5, 5);
+        assert!(center.green > center.red && center.red > center.blue);
+        assert_eq!(*image.pixel_at(0, 0), Color::black());
+        assert_eq!(*image.pixel_at(0, 5), Color::black());
+    }
+
+    #[test]
+    fn test_a_draft_render_keeps_the_nearest_box() {
+        let mut w = World::new();
+        let mut red = Material::new();
+        red.color = Color::new(1.0, 0.0, 0.0);
+        let mut blue = Material::new();
+        blue.color = Color::new(0.0, 0.0, 1.0);
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, 0.0, 3.0))
+                .with_material(&red),
+        ));
+        w.objects.push(Arc::new(Sphere::new().with_material(&blue)));
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let image = c.render_draft(&w);
+
+        assert!(image.pixel_at(5, 5).blue > 0.0);
+        assert_eq!(image.pixel_at(5, 5).red, 0.0);
+    }
+
+    #[test]
+    fn test_a_draft_render_clips_infinite_planes_to_the_view() {
+        let mut w = World::new();
+        w.objects.push(Arc::new(Plane::new()));
+        let mut c = Camera::new(20, 10, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(0.0, 1.0, 1.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        let image = c.render_draft(&w);
+
+        assert_eq!(*image.pixel_at(10, 2), Color::black());
+        assert_ne!(*image.pixel_at(10, 8), Color::black());
+        assert_ne!(*image.pixel_at(0, 9), Color::black());
+    }
+
+    #[test]
+    fn test_tiles_cover_the_image_in_scanline_order() {
+        let c = Camera::new(5, 3, PI / 2.0);
+
+        let tiles = c.tiles(2);
+
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[0],
+            Tile {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 2
+            }
+        );
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x: 4,
+                y: 2,
+                width: 1,
+                height: 1
+            }
+        );
+        let area: usize = tiles.iter().map(|t| t.width * t.height).sum();
+        assert_eq!(area, 15);
+    }
+
+    #[test]
+    fn test_center_out_tiles_start_in_the_middle() {
+        let mut c = Camera::new(9, 9, PI / 2.0);
+        c.tile_order = TileOrder::CenterOut;
+
+        let tiles = c.tiles(3);
+
+        assert_eq!((tiles[0].x, tiles[0].y), (3, 3));
+        assert_eq!((tiles[1].x, tiles[1].y), (3, 0));
+        assert_eq!(tiles.len(), 9);
+    }
+
+    #[test]
+    fn test_a_priority_map_puts_its_brightest_tiles_first() {
+        let mut map = Canvas::new(2, 2);
+        map.write_pixel(1, 1, &Color::white());
+        map.write_pixel(0, 1, &Color::new(0.5, 0.5, 0.5));
+        let mut c = Camera::new(8, 8, PI / 2.0);
+        c.tile_order = TileOrder::Priority(Arc::new(map));
+
+        let tiles = c.tiles(4);
+
+        assert_eq!((tiles[0].x, tiles[0].y), (4, 4));
+        assert_eq!((tiles[1].x, tiles[1].y), (0, 4));
+        assert_eq!((tiles[2].x, tiles[2].y), (0, 0));
+    }
+
+    #[test]
+    fn test_a_priority_map_with_nan_pixels_still_orders_every_tile() {
+        let mut map = Canvas::new(2, 2);
+        map.write_pixel(1, 0, &Color::new(f64::NAN, 0.0, 0.0));
+        map.write_pixel(0, 1, &Color::white());
+        let mut c = Camera::new(8, 8, PI / 2.0);
+        c.tile_order = TileOrder::Priority(Arc::new(map));
+
+        let tiles = c.tiles(4);
+
+        assert_eq!(tiles.len(), 4);
+        assert_eq!((tiles[1].x, tiles[1].y), (0, 4));
+    }
+
+    #[test]
+    fn test_a_progressive_render_matches_a_full_render() {
+        let w = World::default_world();
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c.tile_order = TileOrder::CenterOut;
+
+        let mut first = None;
+        let mut updates = 0;
+        let image = c.render_progressive(&w, 4, |tile, _| {
+            first.get_or_insert(*tile);
+            updates += 1;
+        });
+
+        assert_eq!(updates, 9);
+        assert_eq!(first.unwrap().x, 4);
+        assert_eq!(image.pixels, c.render(&w).pixels);
+    }
+
+    #[test]
+    fn test_focusing_on_a_named_object() {
+        let mut w = World::new();
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(1.0, 0.0, 2.0))
+                .with_name("subject"),
+        ));
+        let mut g = Group::new().with_transform(&Matrix::translation(0.0, 0.0, 10.0));
+        g.add_child(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, 3.0, 1.0))
+                .with_name("nested"),
+        );
+        w.objects.push(Arc::new(g));
+        let mut c = Camera::new(11, 11, PI / 2.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        c.focus_on(&w, "subject").unwrap();
+        assert!(equal_f64(c.focal_distance, 7.0));
+
+        c.focus_on(&w, "nested").unwrap();
+        assert!(equal_f64(c.focal_distance, 16.0));
+    }
+
+    #[test]
+    fn test_focusing_needs_a_bounded_object_in_front_of_the_camera() {
+        let mut w = World::new();
+        w.objects.push(Arc::new(Plane::new().with_name("floor")));
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, 0.0, 5.0))
+                .with_name("behind"),
+        ));
+        let mut c = Camera::new(11, 11, PI / 2.0);
+
+        assert!(c.focus_on(&w, "missing").is_err());
+        assert!(c.focus_on(&w, "floor").is_err());
+        assert!(c.focus_on(&w, "behind").is_err());
+        assert_eq!(c.focal_distance, 1.0);
+    }
+
+    #[test]
+    fn test_rays_through_the_lens_meet_at_the_focal_plane() {
+        let mut c = Camera::new(201, 101, PI / 2.0);
+        c.transform = Matrix::rotation_y(PI / 4.0) * Matrix::translation(0.0, -2.0, 5.0);
+        c.aperture = 0.5;
+        c.focal_distance = 4.0;
+        let pinhole = c.ray_for_pixel(30, 80);
+
+        for (u, v) in [(0.0, 0.0), (0.9, 0.2), (0.4, 0.7)].iter() {
+            let r = c.lens_ray(30.5, 80.5, *u, *v);
+            let t = 4.0
+                / pinhole
+                    .direction
+                    .dot(&(&c.transform.inverse().unwrap() * &Tuple::vector(0.0, 0.0, -1.0)));
+            let focus = pinhole.position(t);
+            let along = (&focus - &r.origin).normalize();
+
+            assert_eq!(r.direction, along);
+        }
+        assert_ne!(c.lens_ray(30.5, 80.5, 0.9, 0.2).origin, pinhole.origin);
+    }
 }
@@ -1,4 +1,5 @@
-use crate::utils::equal_f64;
+use crate::utils::{equal_f64, quantize, stable_hash};
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fs::File;
 use std::io::Write;
@@ -12,11 +13,77 @@ pub struct Color {
     pub blue: f64,
 }
 
+/// How channel values are encoded. Lighting works on linear values, while most 8-bit
+/// images and color pickers give sRGB values, which have to be decoded first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Converts a channel value in this space to linear.
+    pub fn decode(&self, value: f64) -> f64 {
+        match self {
+            ColorSpace::Linear => value,
+            ColorSpace::Srgb if value <= 0.04045 => value / 12.92,
+            ColorSpace::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// Converts a linear channel value to this space.
+    pub fn encode(&self, value: f64) -> f64 {
+        match self {
+            ColorSpace::Linear => value,
+            ColorSpace::Srgb if value <= 0.0031308 => value * 12.92,
+            ColorSpace::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+        }
+    }
+}
+
 impl Color {
     pub fn new(red: f64, green: f64, blue: f64) -> Self {
         Color { red, green, blue }
     }
 
+    /// A linear color from channel values given in `space`, e.g. sRGB values from an
+    /// image or a color picker.
+    pub fn from_space(red: f64, green: f64, blue: f64, space: ColorSpace) -> Self {
+        Color::new(space.decode(red), space.decode(green), space.decode(blue))
+    }
+
+    pub fn to_space(&self, space: ColorSpace) -> Self {
+        Color::new(
+            space.encode(self.red),
+            space.encode(self.green),
+            space.encode(self.blue),
+        )
+    }
+
+    /// Exactly three finite values, red, green and blue.
+    pub fn from_slice(values: &[f64]) -> Result<Self, Box<dyn Error>> {
+        if let Some(value) = values.iter().find(|v| !v.is_finite()) {
+            return Err(format!("color values must be finite, not {}", value).into());
+        }
+        match *values {
+            [red, green, blue] => Ok(Color::new(red, green, blue)),
+            _ => Err(format!("a color needs 3 values, not {}", values.len()).into()),
+        }
+    }
+
+    /// Quantized red, green and blue; see `quantize`.
+    pub fn quantized(&self) -> [i64; 3] {
+        [
+            quantize(self.red),
+            quantize(self.green),
+            quantize(self.blue),
+        ]
+    }
+
+    pub fn canonical_hash(&self) -> u64 {
+        stable_hash(&self.quantized())
+    }
+
     pub fn black() -> Self {
         Color::new(0.0, 0.0, 0.0)
     }
@@ -34,11 +101,29 @@ impl Color {
         )
     }
 
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     fn value(f: f64) -> usize {
         (MAX_COLOR as f64 * f).clamp(0.0, 255.0) as usize
     }
 }
 
+impl From<[f64; 3]> for Color {
+    fn from([red, green, blue]: [f64; 3]) -> Self {
+        Color::new(red, green, blue)
+    }
+}
+
+impl TryFrom<&[f64]> for Color {
+    type Error = Box<dyn Error>;
+
+    fn try_from(values: &[f64]) -> Result<Self, Self::Error> {
+        Color::from_slice(values)
+    }
+}
+
 impl PartialEq for Color {
     fn eq(&self, other: &Self) -> bool {
         equal_f64(self.red, other.red)
@@ -92,6 +177,14 @@ impl std::ops::Mul<Color> for Color {
     }
 }
 
+/// Luminance histogram over `0.0..1.0`. Pixels at or above 1.0 are blown out and are
+/// counted in `clipped` rather than in the bins; negative values fall in the first bin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub bins: Vec<usize>,
+    pub clipped: usize,
+}
+
 #[derive(Debug)]
 pub struct Canvas {
     pub width: usize,
@@ -105,7 +198,6 @@ impl Canvas {
             width,
             height,
             pixels: (0..width * height)
-                .into_iter()
                 .map(|_| Color::new(0.0, 0.0, 0.0))
                 .collect(),
         }
@@ -127,6 +219,58 @@ impl Canvas {
         y * self.width + x
     }
 
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let mut histogram = Histogram {
+            bins: vec![0; bins],
+            clipped: 0,
+        };
+
+        for l in self.pixels.iter().map(|c| c.luminance()) {
+            if l >= 1.0 {
+                histogram.clipped += 1;
+            } else if bins > 0 {
+                let bin = ((l.max(0.0) * bins as f64) as usize).min(bins - 1);
+                histogram.bins[bin] += 1;
+            }
+        }
+
+        histogram
+    }
+
+    pub fn mean_luminance(&self) -> f64 {
+        if self.pixels.is_empty() {
+            return 0.0;
+        }
+        self.pixels.iter().map(|c| c.luminance()).sum::<f64>() / self.pixels.len() as f64
+    }
+
+    /// Geometric mean of the luminance, the usual key value for auto-exposure.
+    pub fn log_average_luminance(&self) -> f64 {
+        if self.pixels.is_empty() {
+            return 0.0;
+        }
+        let delta = 0.0001;
+        let sum: f64 = self
+            .pixels
+            .iter()
+            .map(|c| (delta + c.luminance().max(0.0)).ln())
+            .sum();
+
+        (sum / self.pixels.len() as f64).exp()
+    }
+
+    /// Luminance below which `percentile` percent of the pixels fall (nearest rank).
+    pub fn luminance_percentile(&self, percentile: f64) -> f64 {
+        if self.pixels.is_empty() {
+            return 0.0;
+        }
+        let mut values: Vec<f64> = self.pixels.iter().map(|c| c.luminance()).collect();
+        values.sort_by(f64::total_cmp);
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * values.len() as f64).ceil() as usize;
+        values[rank.max(1) - 1]
+    }
+
     pub fn to_ppm(&self) -> String {
         format!(
             "P3\n{} {}\n{}\n{}\n",
@@ -138,16 +282,12 @@ impl Canvas {
     }
 
     fn ppm_pixel_content(&self) -> String {
-        let pixel_rows: Vec<String> = (0..self.height)
-            .into_iter()
-            .map(|j| self.ppm_pixel_row(j))
-            .collect();
+        let pixel_rows: Vec<String> = (0..self.height).map(|j| self.ppm_pixel_row(j)).collect();
         pixel_rows.join("\n")
     }
 
     fn ppm_pixel_row(&self, row: usize) -> String {
         let pixel_colors: Vec<String> = (0..self.width)
-            .into_iter()
             .map(|i| self.pixel_at(i, row).ppm_value())
             .collect();
 
@@ -158,12 +298,12 @@ impl Canvas {
             line.split(' ').for_each(|c| {
                 if s.len() + c.len() > 70 {
                     strings.push(s.clone().trim().to_string());
-                    s = format!("{}", c);
+                    s = c.to_string();
                 } else {
                     s = format!("{} {}", s, c);
                 }
             });
-            if s.len() > 0 {
+            if !s.is_empty() {
                 strings.push(s.trim().to_string());
             }
             strings.join("\n")
@@ -172,6 +312,49 @@ impl Canvas {
         }
     }
 
+    /// Reads a plain (P3) PPM image whose channel values are encoded in `space`.
+    /// Comments are skipped and values are scaled by the file's own maximum.
+    pub fn from_ppm(contents: &str, space: ColorSpace) -> Result<Canvas, Box<dyn Error>> {
+        let mut tokens = contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split_whitespace());
+
+        match tokens.next() {
+            Some("P3") => {}
+            Some(magic) => return Err(format!("unsupported PPM format: {}", magic).into()),
+            None => return Err("empty PPM file".into()),
+        }
+        let mut number = |what: &str| -> Result<f64, Box<dyn Error>> {
+            let token = tokens
+                .next()
+                .ok_or_else(|| format!("PPM file ends before the {}", what))?;
+            Ok(token.parse::<usize>()? as f64)
+        };
+        let width = number("width")? as usize;
+        let height = number("height")? as usize;
+        let max = number("maximum value")?;
+        if max == 0.0 {
+            return Err("PPM maximum value must be positive".into());
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let red = number("pixel data")? / max;
+                let green = number("pixel data")? / max;
+                let blue = number("pixel data")? / max;
+                canvas.write_pixel(x, y, &Color::from_space(red, green, blue, space));
+            }
+        }
+
+        Ok(canvas)
+    }
+
+    pub fn load(path: &str, space: ColorSpace) -> Result<Canvas, Box<dyn Error>> {
+        Self::from_ppm(&std::fs::read_to_string(path)?, space)
+    }
+
     pub fn save(&self, target_file: &str) -> Result<(), Box<dyn Error>> {
         let ppm_contents = self.to_ppm();
         let mut file = File::create(target_file)?;
@@ -181,10 +364,117 @@ impl Canvas {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffReport {
+    pub max_error: Color,
+    pub mean_error: Color,
+    pub differing_pixels: usize,
+}
+
+impl DiffReport {
+    pub fn within(&self, tolerance: f64) -> bool {
+        self.max_error.red <= tolerance
+            && self.max_error.green <= tolerance
+            && self.max_error.blue <= tolerance
+    }
+}
+
+fn abs_difference(a: &Color, b: &Color) -> Color {
+    Color::new(
+        (a.red - b.red).abs(),
+        (a.green - b.green).abs(),
+        (a.blue - b.blue).abs(),
+    )
+}
+
+fn assert_same_size(a: &Canvas, b: &Canvas) {
+    assert!(
+        a.width == b.width && a.height == b.height,
+        "cannot compare a {}x{} canvas with a {}x{} canvas",
+        a.width,
+        a.height,
+        b.width,
+        b.height
+    );
+}
+
+/// Compares two canvases of the same size channel by channel.
+pub fn diff(a: &Canvas, b: &Canvas) -> DiffReport {
+    assert_same_size(a, b);
+
+    let errors: Vec<Color> = a
+        .pixels
+        .iter()
+        .zip(b.pixels.iter())
+        .map(|(x, y)| abs_difference(x, y))
+        .collect();
+
+    let max_error = errors.iter().fold(Color::black(), |m, e| {
+        Color::new(m.red.max(e.red), m.green.max(e.green), m.blue.max(e.blue))
+    });
+    let total = errors.iter().fold(Color::black(), |t, e| t + *e);
+    let mean_error = if errors.is_empty() {
+        Color::black()
+    } else {
+        total * (1.0 / errors.len() as f64)
+    };
+    let differing_pixels = errors.iter().filter(|e| **e != Color::black()).count();
+
+    DiffReport {
+        max_error,
+        mean_error,
+        differing_pixels,
+    }
+}
+
+/// Renders the per-pixel error between two canvases as a heatmap running from black
+/// (identical) through red and yellow to white (an error of `scale` or more).
+pub fn diff_heatmap(a: &Canvas, b: &Canvas, scale: f64) -> Canvas {
+    assert_same_size(a, b);
+
+    let mut image = Canvas::new(a.width, a.height);
+    image.pixels = a
+        .pixels
+        .iter()
+        .zip(b.pixels.iter())
+        .map(|(x, y)| {
+            let e = abs_difference(x, y);
+            let heat = 3.0 * (e.red.max(e.green).max(e.blue) / scale).min(1.0);
+
+            Color::new(
+                heat.min(1.0),
+                (heat - 1.0).clamp(0.0, 1.0),
+                (heat - 2.0).clamp(0.0, 1.0),
+            )
+        })
+        .collect();
+
+    image
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_srgb_values_round_trip_through_linear() {
+        for value in [0.0, 0.02, 0.2, 0.5, 0.8, 1.0].iter() {
+            let linear = ColorSpace::Srgb.decode(*value);
+
+            assert!(equal_f64(ColorSpace::Srgb.encode(linear), *value));
+        }
+        assert!(equal_f64(ColorSpace::Srgb.decode(0.5), 0.21404));
+        assert_eq!(ColorSpace::Linear.decode(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_tagging_a_color_as_srgb_decodes_it() {
+        let c = Color::from_space(1.0, 0.5, 0.0, ColorSpace::Srgb);
+
+        assert_eq!(c, Color::new(1.0, 0.21404, 0.0));
+        assert_eq!(c.to_space(ColorSpace::Srgb), Color::new(1.0, 0.5, 0.0));
+    }
+
     #[test]
     fn test_colors_are_tuples() {
         let c = Color::new(-0.5, 0.4, 1.7);
@@ -324,4 +614,176 @@ mod tests {
 
         assert_eq!(ppm.chars().last(), Some('\n'));
     }
+
+    #[test]
+    fn test_diffing_identical_canvases() {
+        let mut a = Canvas::new(3, 2);
+        a.write_pixel(1, 1, &Color::new(0.2, 0.4, 0.6));
+        let mut b = Canvas::new(3, 2);
+        b.write_pixel(1, 1, &Color::new(0.2, 0.4, 0.6));
+
+        let report = diff(&a, &b);
+
+        assert_eq!(report.max_error, Color::black());
+        assert_eq!(report.mean_error, Color::black());
+        assert_eq!(report.differing_pixels, 0);
+        assert!(report.within(0.0));
+    }
+
+    #[test]
+    fn test_diffing_canvases_reports_per_channel_errors() {
+        let mut a = Canvas::new(2, 2);
+        a.write_pixel(0, 0, &Color::new(1.0, 0.5, 0.0));
+        let mut b = Canvas::new(2, 2);
+        b.write_pixel(0, 0, &Color::new(0.6, 0.5, 0.2));
+
+        let report = diff(&a, &b);
+
+        assert_eq!(report.max_error, Color::new(0.4, 0.0, 0.2));
+        assert_eq!(report.mean_error, Color::new(0.1, 0.0, 0.05));
+        assert_eq!(report.differing_pixels, 1);
+        assert!(!report.within(0.3));
+        assert!(report.within(0.4));
+    }
+
+    #[test]
+    fn test_diff_heatmap_highlights_differing_pixels() {
+        let a = Canvas::new(2, 1);
+        let mut b = Canvas::new(2, 1);
+        b.write_pixel(1, 0, &Color::new(0.0, 0.0, 1.0));
+
+        let heatmap = diff_heatmap(&a, &b, 1.0);
+
+        assert_eq!(heatmap.pixel_at(0, 0), &Color::black());
+        assert_eq!(heatmap.pixel_at(1, 0), &Color::white());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_diffing_canvases_of_different_sizes_panics() {
+        diff(&Canvas::new(2, 2), &Canvas::new(3, 2));
+    }
+
+    #[test]
+    fn test_the_luminance_of_a_color() {
+        assert!(equal_f64(Color::white().luminance(), 1.0));
+        assert!(equal_f64(Color::new(0.0, 1.0, 0.0).luminance(), 0.7152));
+    }
+
+    #[test]
+    fn test_the_histogram_of_a_canvas() {
+        let mut c = Canvas::new(4, 1);
+        c.write_pixel(1, 0, &Color::new(0.3, 0.3, 0.3));
+        c.write_pixel(2, 0, &Color::new(0.8, 0.8, 0.8));
+        c.write_pixel(3, 0, &Color::new(2.0, 2.0, 2.0));
+
+        let h = c.histogram(4);
+
+        assert_eq!(h.bins, vec![1, 1, 0, 1]);
+        assert_eq!(h.clipped, 1);
+    }
+
+    #[test]
+    fn test_luminance_statistics_of_a_canvas() {
+        let mut c = Canvas::new(4, 1);
+        for (x, v) in [0.1, 0.2, 0.3, 0.4].iter().enumerate() {
+            c.write_pixel(x, 0, &Color::new(*v, *v, *v));
+        }
+
+        assert!(equal_f64(c.mean_luminance(), 0.25));
+        assert!(equal_f64(c.luminance_percentile(50.0), 0.2));
+        assert!(equal_f64(c.luminance_percentile(100.0), 0.4));
+        assert!(equal_f64(c.luminance_percentile(0.0), 0.1));
+    }
+
+    #[test]
+    fn test_a_nan_pixel_sorts_above_every_luminance() {
+        let mut c = Canvas::new(3, 1);
+        c.write_pixel(0, 0, &Color::new(f64::NAN, 0.0, 0.0));
+        c.write_pixel(1, 0, &Color::new(0.5, 0.5, 0.5));
+
+        assert!(equal_f64(c.luminance_percentile(0.0), 0.0));
+        assert!(equal_f64(c.luminance_percentile(50.0), 0.5));
+        assert!(c.luminance_percentile(100.0).is_nan());
+    }
+
+    #[test]
+    fn test_the_log_average_luminance_of_a_uniform_canvas() {
+        let mut c = Canvas::new(2, 2);
+        for y in 0..2 {
+            for x in 0..2 {
+                c.write_pixel(x, y, &Color::new(0.5, 0.5, 0.5));
+            }
+        }
+
+        assert!((c.log_average_luminance() - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_reading_a_ppm_file() {
+        let ppm = "P3\n# a comment\n4 3\n255\n255 127 0  0 127 255  0 0 0  255 255 255\n0 0 0  0 0 0  0 0 0  0 0 0\n0 0 0  0 0 0  0 0 0  0 0 0\n";
+
+        let c = Canvas::from_ppm(ppm, ColorSpace::Linear).unwrap();
+
+        assert_eq!((c.width, c.height), (4, 3));
+        assert_eq!(*c.pixel_at(0, 0), Color::new(1.0, 127.0 / 255.0, 0.0));
+        assert_eq!(*c.pixel_at(1, 0), Color::new(0.0, 127.0 / 255.0, 1.0));
+        assert_eq!(*c.pixel_at(3, 0), Color::white());
+    }
+
+    #[test]
+    fn test_reading_a_ppm_file_decodes_srgb_and_scales_by_the_maximum() {
+        let ppm = "P3 1 1 100\n100 50 0";
+
+        let c = Canvas::from_ppm(ppm, ColorSpace::Srgb).unwrap();
+
+        assert_eq!(
+            *c.pixel_at(0, 0),
+            Color::from_space(1.0, 0.5, 0.0, ColorSpace::Srgb)
+        );
+    }
+
+    #[test]
+    fn test_a_written_canvas_reads_back() {
+        let mut c = Canvas::new(3, 2);
+        c.write_pixel(1, 1, &Color::new(1.0, 0.0, 1.0));
+
+        let read = Canvas::from_ppm(&c.to_ppm(), ColorSpace::Linear).unwrap();
+
+        assert_eq!(read.pixels, c.pixels);
+    }
+
+    #[test]
+    fn test_reading_malformed_ppm_files_fails() {
+        assert!(Canvas::from_ppm("P6\n1 1\n255\n", ColorSpace::Linear).is_err());
+        assert!(Canvas::from_ppm("P3\n2 1\n255\n0 0 0", ColorSpace::Linear).is_err());
+        assert!(Canvas::from_ppm("P3\n1 1\n255\nred 0 0", ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn test_colors_from_arrays_and_slices() {
+        let values = [0.2, 0.4, 0.6, 0.8];
+
+        assert_eq!(Color::from([0.2, 0.4, 0.6]), Color::new(0.2, 0.4, 0.6));
+        assert_eq!(
+            Color::try_from(&values[1..]).unwrap(),
+            Color::new(0.4, 0.6, 0.8)
+        );
+        assert!(Color::from_slice(&values).is_err());
+        assert!(Color::from_slice(&[0.0, f64::INFINITY, 0.0]).is_err());
+    }
+
+    #[test]
+    fn test_nearly_equal_colors_hash_the_same() {
+        let a = Color::new(0.5, 0.25, 1.0);
+
+        assert_eq!(
+            a.canonical_hash(),
+            Color::new(0.5, 0.250_000_1, 1.0).canonical_hash()
+        );
+        assert_ne!(
+            a.canonical_hash(),
+            Color::new(0.25, 0.5, 1.0).canonical_hash()
+        );
+    }
 }
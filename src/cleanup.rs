@@ -0,0 +1,376 @@
+use crate::bounds::BoundingBox;
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::ray::Ray;
+use crate::shapes::{Shape, ShapeId};
+use crate::tuple::Tuple;
+use crate::utils::EPSILON;
+use crate::world::World;
+use std::fmt;
+
+/// Shapes squashed thinner than this fraction of their widest axis are reported, as
+/// surfaces so close together are prone to acne and lose precision.
+pub const THIN_RATIO: f64 = 0.01;
+
+/// Something in a scene likely to render badly for reasons that are hard to see.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneIssue {
+    // A shape scaled far thinner along one axis than another, such as a sphere
+    // flattened into a wall.
+    ThinTransform { id: ShapeId, ratio: f64 },
+    // Two infinite planes in the same place, which fight over every pixel they cover.
+    CoincidentPlanes { first: ShapeId, second: ShapeId },
+    // An object outside the view of every camera, which only costs render time unless
+    // it shows in reflections or casts shadows.
+    OutOfView { id: ShapeId },
+    // A light, by its index in the world, that is black or too dim by the time its
+    // light reaches anything.
+    UnlitLight { index: usize },
+    // A named material no shape uses.
+    UnusedMaterial { name: String },
+}
+
+impl fmt::Display for SceneIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneIssue::ThinTransform { id, ratio } => write!(
+                f,
+                "shape {} is squashed to {:.2}% of its width; \
+                 a plane or a thin cube would be more robust",
+                id,
+                ratio * 100.0
+            ),
+            SceneIssue::CoincidentPlanes { first, second } => {
+                write!(
+                    f,
+                    "planes {} and {} lie on top of each other",
+                    first, second
+                )
+            }
+            SceneIssue::OutOfView { id } => {
+                write!(f, "shape {} is outside the view of every camera", id)
+            }
+            SceneIssue::UnlitLight { index } => write!(f, "light {} illuminates nothing", index),
+            SceneIssue::UnusedMaterial { name } => {
+                write!(f, "material {} is not used by any shape", name)
+            }
+        }
+    }
+}
+
+/// Every issue found in the world, including among shapes nested in groups.
+pub fn check(world: &World) -> Vec<SceneIssue> {
+    let mut shapes = vec![];
+    for object in &world.objects {
+        collect(object.as_ref(), &mut shapes);
+    }
+
+    let mut issues = vec![];
+    for shape in &shapes {
+        let ratio = thinness(*shape);
+        if ratio < THIN_RATIO {
+            issues.push(SceneIssue::ThinTransform {
+                id: shape.id(),
+                ratio,
+            });
+        }
+    }
+
+    let planes: Vec<(ShapeId, Tuple, Tuple)> = shapes.iter().filter_map(|s| plane_of(*s)).collect();
+    for (i, first) in planes.iter().enumerate() {
+        for second in &planes[i + 1..] {
+            if coincident(first, second) {
+                issues.push(SceneIssue::CoincidentPlanes {
+                    first: first.0,
+                    second: second.0,
+                });
+            }
+        }
+    }
+
+    issues
+}
+
+/// Everything `check` finds, along with top level objects none of `cameras` can see,
+/// lights that illuminate nothing, and materials in the world's table that no shape
+/// holds, for keeping large scenes tidy. Objects are only checked against cameras when
+/// there are some.
+pub fn lint(world: &World, cameras: &[Camera]) -> Vec<SceneIssue> {
+    let mut issues = check(world);
+
+    if !cameras.is_empty() {
+        for object in &world.objects {
+            let bounds = object.parent_space_bounds_of();
+            if is_finite(&bounds) && !cameras.iter().any(|c| c.can_see(&bounds)) {
+                issues.push(SceneIssue::OutOfView { id: object.id() });
+            }
+        }
+    }
+
+    for (index, light) in world.lights.iter().enumerate() {
+        if !illuminates_anything(world, light) {
+            issues.push(SceneIssue::UnlitLight { index });
+        }
+    }
+
+    let mut shapes = vec![];
+    for object in &world.objects {
+        collect(object.as_ref(), &mut shapes);
+    }
+    for name in world.materials.names() {
+        let material = world.materials.get(name).unwrap();
+        if !shapes
+            .iter()
+            .any(|s| s.material_handle().shares_with(&material))
+        {
+            issues.push(SceneIssue::UnusedMaterial {
+                name: name.to_string(),
+            });
+        }
+    }
+
+    issues
+}
+
+/// Removes planes in the world's top level that coincide with an earlier one, returning
+/// the ids of those removed. Planes within groups are only reported by `check`.
+pub fn remove_coincident_planes(world: &mut World) -> Vec<ShapeId> {
+    let mut kept: Vec<(ShapeId, Tuple, Tuple)> = vec![];
+    let mut removed = vec![];
+
+    world.objects.retain(|object| {
+        let plane = match plane_of(object.as_ref()) {
+            Some(plane) => plane,
+            None => return true,
+        };
+        if kept.iter().any(|k| coincident(k, &plane)) {
+            removed.push(plane.0);
+            false
+        } else {
+            kept.push(plane);
+            true
+        }
+    });
+
+    removed
+}
+
+// Whether some ray from the light reaches a surface while it is still at least a
+// 256th of its full strength. Rays go in the 26 directions out of a cube around the
+// light and towards the centre of every object with finite bounds.
+fn illuminates_anything(world: &World, light: &Light) -> bool {
+    let intensity = light.intensity();
+    let strength = intensity.red.max(intensity.green).max(intensity.blue);
+    if strength <= 0.0 {
+        return false;
+    }
+
+    let origin = light.position();
+    let mut directions = vec![];
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                if (x, y, z) != (0, 0, 0) {
+                    directions.push(Tuple::vector(x as f64, y as f64, z as f64));
+                }
+            }
+        }
+    }
+    for object in &world.objects {
+        let b = object.parent_space_bounds_of();
+        if is_finite(&b) {
+            let center = Tuple::point(
+                (b.min.x + b.max.x) / 2.0,
+                (b.min.y + b.max.y) / 2.0,
+                (b.min.z + b.max.z) / 2.0,
+            );
+            directions.push(&center - &origin);
+        }
+    }
+
+    directions
+        .iter()
+        .filter(|d| d.magnitude() > EPSILON)
+        .filter_map(|d| world.intersect(&Ray::new(&origin, &d.normalize())).hit())
+        .any(|hit| strength * light.falloff().attenuation(hit.t) >= 1.0 / 256.0)
+}
+
+fn is_finite(bounds: &BoundingBox) -> bool {
+    let (min, max) = (&bounds.min, &bounds.max);
+    !bounds.is_empty()
+        && [min.x, min.y, min.z, max.x, max.y, max.z]
+            .iter()
+            .all(|c| c.is_finite())
+}
+
+fn collect<'a>(shape: &'a dyn Shape, shapes: &mut Vec<&'a dyn Shape>) {
+    match shape.as_group() {
+        Some(group) => {
+            for child in group.children() {
+                collect(child.as_ref(), shapes);
+            }
+        }
+        None => shapes.push(shape),
+    }
+}
+
+// The shortest of the shape's axes in world space over the longest.
+fn thinness(shape: &dyn Shape) -> f64 {
+    let lengths: Vec<f64> = [
+        Tuple::vector(1.0, 0.0, 0.0),
+        Tuple::vector(0.0, 1.0, 0.0),
+        Tuple::vector(0.0, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|axis| shape.object_to_world(axis).magnitude())
+    .collect();
+    let longest = lengths.iter().cloned().fold(0.0, f64::max);
+    let shortest = lengths.iter().cloned().fold(f64::INFINITY, f64::min);
+
+    shortest / longest
+}
+
+// A point on the shape and its normal there, when it is an infinite plane.
+fn plane_of(shape: &dyn Shape) -> Option<(ShapeId, Tuple, Tuple)> {
+    let bounds = shape.bounds_of();
+    let flat = bounds.min.y == 0.0 && bounds.max.y == 0.0;
+    let infinite = bounds.min.x.is_infinite()
+        && bounds.max.x.is_infinite()
+        && bounds.min.z.is_infinite()
+        && bounds.max.z.is_infinite();
+    if !(flat && infinite) {
+        return None;
+    }
+
+    let point = shape.object_to_world(&Tuple::point(0.0, 0.0, 0.0));
+    let normal = shape.normal_at(&point);
+    Some((shape.id(), point, normal))
+}
+
+fn coincident(a: &(ShapeId, Tuple, Tuple), b: &(ShapeId, Tuple, Tuple)) -> bool {
+    let (_, point_a, normal_a) = a;
+    let (_, point_b, normal_b) = b;
+    let parallel = (normal_a.dot(normal_b).abs() - 1.0).abs() < EPSILON;
+    let offset = (point_b - point_a).dot(normal_a).abs();
+
+    parallel && offset < EPSILON
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Color;
+    use crate::light::{Falloff, Material, PointLight};
+    use crate::matrix::Matrix;
+    use crate::shapes::{Group, Plane, Sphere};
+    use crate::transformations::view_transform;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_a_squashed_sphere_is_reported_as_thin() {
+        let mut w = World::new();
+        let wall = Sphere::new()
+            .with_transform(&(Matrix::rotation_x(PI / 2.0) * Matrix::scaling(10.0, 0.01, 10.0)));
+        let id = wall.id();
+        w.objects.push(Arc::new(Group::new().with_child(wall)));
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::scaling(1.0, 0.5, 1.0)),
+        ));
+
+        let issues = check(&w);
+
+        assert_eq!(issues.len(), 1);
+        match &issues[0] {
+            SceneIssue::ThinTransform { id: thin, ratio } => {
+                assert_eq!(*thin, id);
+                assert!((ratio - 0.001).abs() < 1e-9);
+            }
+            issue => panic!("unexpected issue {}", issue),
+        }
+    }
+
+    #[test]
+    fn test_coincident_planes_are_reported_and_removed() {
+        let mut w = World::new();
+        let floor = Plane::new();
+        let copy = Plane::new().with_transform(&Matrix::translation(3.0, 0.0, -2.0));
+        let flipped = Plane::new().with_transform(&Matrix::rotation_x(PI));
+        let raised = Plane::new().with_transform(&Matrix::translation(0.0, 1.0, 0.0));
+        let ids = [floor.id(), copy.id(), flipped.id(), raised.id()];
+        w.objects.push(Arc::new(floor));
+        w.objects.push(Arc::new(copy));
+        w.objects.push(Arc::new(flipped));
+        w.objects.push(Arc::new(raised));
+
+        let issues = check(&w);
+
+        assert_eq!(
+            issues,
+            vec![
+                SceneIssue::CoincidentPlanes {
+                    first: ids[0],
+                    second: ids[1]
+                },
+                SceneIssue::CoincidentPlanes {
+                    first: ids[0],
+                    second: ids[2]
+                },
+                SceneIssue::CoincidentPlanes {
+                    first: ids[1],
+                    second: ids[2]
+                },
+            ]
+        );
+        assert_eq!(remove_coincident_planes(&mut w), vec![ids[1], ids[2]]);
+        assert_eq!(w.objects.len(), 2);
+        assert!(check(&w).is_empty());
+    }
+
+    #[test]
+    fn test_lint_finds_hidden_objects_dark_lights_and_unused_materials() {
+        let mut w = World::new();
+        let red = w.materials.register("red", Material::new());
+        w.materials.register("blue", {
+            let mut m = Material::new();
+            m.color = Color::new(0.0, 0.0, 1.0);
+            m
+        });
+        let seen = Sphere::new().with_material(&red);
+        let behind = Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, -20.0));
+        let hidden = behind.id();
+        w.objects.push(Arc::new(seen));
+        w.objects.push(Arc::new(behind));
+        w.objects.push(Arc::new(
+            Plane::new().with_transform(&Matrix::translation(0.0, -1.0, 0.0)),
+        ));
+        w.lights = vec![
+            PointLight::new(&Tuple::point(-10.0, 10.0, -10.0), &Color::white()).into(),
+            PointLight::new(&Tuple::point(0.0, 5.0, 0.0), &Color::black()).into(),
+            PointLight::new(&Tuple::point(0.0, 1000.0, 0.0), &Color::white())
+                .with_falloff(Falloff::quadratic())
+                .into(),
+        ];
+        let mut camera = Camera::new(11, 11, PI / 3.0);
+        camera.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+
+        assert_eq!(
+            lint(&w, &[camera]),
+            vec![
+                SceneIssue::OutOfView { id: hidden },
+                SceneIssue::UnlitLight { index: 1 },
+                SceneIssue::UnlitLight { index: 2 },
+                SceneIssue::UnusedMaterial {
+                    name: "blue".to_string()
+                },
+            ]
+        );
+        assert!(lint(&w, &[])
+            .iter()
+            .all(|issue| !matches!(issue, SceneIssue::OutOfView { .. })));
+    }
+}
@@ -0,0 +1,338 @@
+use crate::canvas::{Canvas, Color};
+use crate::pattern::{UvImage, UvPattern};
+use crate::tuple::Tuple;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Light arriving from every direction, read from a latitude-longitude image: columns
+/// run once around the horizon and rows from straight up to straight down. Directions
+/// can be drawn in proportion to the brightness they carry, so a small bright sun is
+/// found by a handful of samples rather than thousands. An empty image is black.
+pub struct Environment {
+    pub image: Canvas,
+    row_cdf: Vec<f64>,
+    column_cdfs: Vec<Vec<f64>>,
+    total: f64,
+}
+
+impl Environment {
+    pub fn new(image: Canvas) -> Self {
+        let luminance_total: f64 = image.pixels.iter().map(|c| c.luminance().max(0.0)).sum();
+        let weight = |x: usize, y: usize| {
+            let band = Self::band(&image, y);
+            if luminance_total > 0.0 {
+                image.pixel_at(x, y).luminance().max(0.0) * band
+            } else {
+                band
+            }
+        };
+
+        let mut column_cdfs = Vec::with_capacity(image.height);
+        let mut row_totals = Vec::with_capacity(image.height);
+        for y in 0..image.height {
+            let mut sum = 0.0;
+            let cdf: Vec<f64> = (0..image.width)
+                .map(|x| {
+                    sum += weight(x, y);
+                    sum
+                })
+                .collect();
+            row_totals.push(sum);
+            column_cdfs.push(cdf);
+        }
+        let mut sum = 0.0;
+        let row_cdf: Vec<f64> = row_totals
+            .iter()
+            .map(|t| {
+                sum += t;
+                sum
+            })
+            .collect();
+
+        Self {
+            image,
+            row_cdf,
+            column_cdfs,
+            total: sum,
+        }
+    }
+
+    pub fn radiance(&self, direction: &Tuple) -> Color {
+        if self.is_empty() {
+            return Color::black();
+        }
+        let (x, y) = self.pixel_of(direction);
+        *self.image.pixel_at(x, y)
+    }
+
+    /// A direction drawn in proportion to the light arriving along it, from two uniform
+    /// numbers in [0, 1), together with its probability density per steradian, which
+    /// is zero when the image is empty and there is nothing to draw.
+    pub fn sample(&self, u: f64, v: f64) -> (Tuple, f64) {
+        if self.is_empty() {
+            return (Tuple::vector(0.0, 1.0, 0.0), 0.0);
+        }
+        let (y, row_offset) = pick(&self.row_cdf, u);
+        let (x, column_offset) = pick(&self.column_cdfs[y], v);
+
+        // Uniform in cos θ across the row, as in solid angle.
+        let top = Self::theta(&self.image, y as f64).cos();
+        let theta = (top - row_offset * Self::band(&self.image, y))
+            .clamp(-1.0, 1.0)
+            .acos();
+        let phi = 2.0 * PI * (x as f64 + column_offset) / self.image.width as f64;
+        let direction = Tuple::vector(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+
+        let pdf = self.pdf(&direction);
+        (direction, pdf)
+    }
+
+    /// The probability density per steradian of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Tuple) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let (x, y) = self.pixel_of(direction);
+        let previous = if x == 0 {
+            0.0
+        } else {
+            self.column_cdfs[y][x - 1]
+        };
+        let weight = self.column_cdfs[y][x] - previous;
+        let band = Self::band(&self.image, y);
+        if weight == 0.0 || band == 0.0 {
+            return 0.0;
+        }
+
+        // A pixel covers (2π / width) times its row's band of cos θ in steradians.
+        weight * self.image.width as f64 / (self.total * 2.0 * PI * band)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.image.width == 0 || self.image.height == 0
+    }
+
+    fn theta(image: &Canvas, row: f64) -> f64 {
+        PI * row / image.height as f64
+    }
+
+    // How far cos θ falls across row `y`, in proportion to the solid angle it covers.
+    fn band(image: &Canvas, y: usize) -> f64 {
+        Self::theta(image, y as f64).cos() - Self::theta(image, y as f64 + 1.0).cos()
+    }
+
+    fn pixel_of(&self, direction: &Tuple) -> (usize, usize) {
+        let d = direction.normalize();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let phi = d.z.atan2(d.x).rem_euclid(2.0 * PI);
+        let x = (phi / (2.0 * PI) * self.image.width as f64) as usize;
+        let y = (theta / PI * self.image.height as f64) as usize;
+        (x.min(self.image.width - 1), y.min(self.image.height - 1))
+    }
+}
+
+/// Six square images, one per face of a cube around the scene, in the order +x, -x,
+/// +y, -y, +z, -z. Each is drawn as seen from inside the cube: the four side faces
+/// are upright, the +y face is seen looking up with -z at its top, and the -y face is
+/// seen looking down with +z at its top.
+pub struct CubeMap {
+    faces: [UvImage; 6],
+}
+
+impl CubeMap {
+    pub fn new(faces: [Arc<Canvas>; 6]) -> Self {
+        let [px, nx, py, ny, pz, nz] = faces;
+        Self {
+            faces: [
+                UvImage::new(px),
+                UvImage::new(nx),
+                UvImage::new(py),
+                UvImage::new(ny),
+                UvImage::new(pz),
+                UvImage::new(nz),
+            ],
+        }
+    }
+
+    pub fn radiance(&self, direction: &Tuple) -> Color {
+        let (face, u, v) = Self::face_of(direction);
+        self.faces[face].uv_pattern_at(u, v)
+    }
+
+    // The face a direction passes through and where on it, in [0, 1].
+    fn face_of(direction: &Tuple) -> (usize, f64, f64) {
+        let (x, y, z) = (direction.x, direction.y, direction.z);
+        let half = |c: f64, coord: f64| (c / coord + 1.0) / 2.0;
+
+        if x.abs() >= y.abs() && x.abs() >= z.abs() {
+            if x > 0.0 {
+                (0, half(-z, x), half(y, x))
+            } else {
+                (1, half(z, -x), half(y, -x))
+            }
+        } else if y.abs() >= z.abs() {
+            if y > 0.0 {
+                (2, half(x, y), half(-z, y))
+            } else {
+                (3, half(x, -y), half(z, -y))
+            }
+        } else if z > 0.0 {
+            (4, half(x, z), half(y, z))
+        } else {
+            (5, half(-x, -z), half(y, -z))
+        }
+    }
+}
+
+/// What rays that leave the scene without hitting anything see.
+#[derive(Clone)]
+pub enum Skybox {
+    Equirectangular(Arc<Environment>),
+    Cube(Arc<CubeMap>),
+}
+
+impl Skybox {
+    pub fn radiance(&self, direction: &Tuple) -> Color {
+        match self {
+            Skybox::Equirectangular(environment) => environment.radiance(direction),
+            Skybox::Cube(cube) => cube.radiance(direction),
+        }
+    }
+}
+
+// The bin of a cumulative distribution that `u` falls in, and how far through it.
+fn pick(cdf: &[f64], u: f64) -> (usize, f64) {
+    let total = cdf[cdf.len() - 1];
+    let target = u * total;
+    let index = cdf.partition_point(|c| *c <= target).min(cdf.len() - 1);
+    let start = if index == 0 { 0.0 } else { cdf[index - 1] };
+    let width = cdf[index] - start;
+    let offset = if width > 0.0 {
+        ((target - start) / width).clamp(0.0, 1.0 - f64::EPSILON)
+    } else {
+        0.5
+    };
+
+    (index, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn sky_with_sun() -> Environment {
+        let mut image = Canvas::new(64, 32);
+        for pixel in image.pixels.iter_mut() {
+            *pixel = Color::new(0.01, 0.01, 0.01);
+        }
+        image.write_pixel(20, 10, &Color::new(5000.0, 5000.0, 5000.0));
+        Environment::new(image)
+    }
+
+    #[test]
+    fn test_a_uniform_environment_is_sampled_uniformly() {
+        let mut image = Canvas::new(16, 8);
+        for pixel in image.pixels.iter_mut() {
+            *pixel = Color::white();
+        }
+        let env = Environment::new(image);
+
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)].iter() {
+            let (direction, pdf) = env.sample(*u, *v);
+
+            assert!((direction.magnitude() - 1.0).abs() < 1e-9);
+            assert!((pdf * 4.0 * PI - 1.0).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_samples_concentrate_on_a_bright_sun() {
+        let env = sky_with_sun();
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let in_sun = (0..100)
+            .filter(|_| {
+                let (direction, _) = env.sample(rng.gen(), rng.gen());
+                env.pixel_of(&direction) == (20, 10)
+            })
+            .count();
+
+        assert!(in_sun > 90);
+    }
+
+    #[test]
+    fn test_importance_sampling_estimates_the_total_light() {
+        let env = sky_with_sun();
+        let exact: f64 = (0..env.image.height)
+            .flat_map(|y| (0..env.image.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let sin_theta = Environment::theta(&env.image, y as f64 + 0.5).sin();
+                let solid_angle = 2.0 * PI * PI * sin_theta / (64.0 * 32.0);
+                env.image.pixel_at(x, y).luminance() * solid_angle
+            })
+            .sum();
+        let mut rng = StdRng::seed_from_u64(5);
+
+        let estimate = (0..64)
+            .map(|_| {
+                let (direction, pdf) = env.sample(rng.gen(), rng.gen());
+                env.radiance(&direction).luminance() / pdf
+            })
+            .sum::<f64>()
+            / 64.0;
+
+        assert!((estimate - exact).abs() / exact < 0.01);
+    }
+
+    #[test]
+    fn test_an_empty_environment_is_black() {
+        let env = Environment::new(Canvas::new(0, 0));
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+
+        assert_eq!(env.radiance(&up), Color::black());
+        assert_eq!(env.sample(0.5, 0.5).1, 0.0);
+        assert_eq!(env.pdf(&up), 0.0);
+    }
+
+    #[test]
+    fn test_a_cube_map_looks_up_the_face_a_direction_passes_through() {
+        let faces: Vec<Arc<Canvas>> = (0..6)
+            .map(|i| {
+                let mut face = Canvas::new(2, 2);
+                for pixel in face.pixels.iter_mut() {
+                    *pixel = Color::new(i as f64, 0.0, 0.0);
+                }
+                // Mark the top left corner of each face.
+                face.write_pixel(0, 0, &Color::new(i as f64, 1.0, 0.0));
+                Arc::new(face)
+            })
+            .collect();
+        let cube = CubeMap::new([
+            faces[0].clone(),
+            faces[1].clone(),
+            faces[2].clone(),
+            faces[3].clone(),
+            faces[4].clone(),
+            faces[5].clone(),
+        ]);
+
+        let examples = [
+            (Tuple::vector(1.0, 0.5, 0.5), Color::new(0.0, 1.0, 0.0)),
+            (Tuple::vector(-1.0, 0.5, -0.5), Color::new(1.0, 1.0, 0.0)),
+            (Tuple::vector(-0.5, 1.0, -0.5), Color::new(2.0, 1.0, 0.0)),
+            (Tuple::vector(-0.5, -1.0, 0.5), Color::new(3.0, 1.0, 0.0)),
+            (Tuple::vector(-0.5, 0.5, 1.0), Color::new(4.0, 1.0, 0.0)),
+            (Tuple::vector(0.5, 0.5, -1.0), Color::new(5.0, 1.0, 0.0)),
+            (Tuple::vector(0.5, -0.5, 1.0), Color::new(4.0, 0.0, 0.0)),
+        ];
+        for (direction, color) in examples.iter() {
+            assert_eq!(cube.radiance(direction), *color);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::canvas::Color;
+use crate::environment::Skybox;
+use crate::ray::{Ray, RayKind};
+use crate::tuple::Tuple;
+use crate::utils::{rng_at, EPSILON};
+use crate::world::World;
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap};
+use std::f64::consts::PI;
+use std::sync::RwLock;
+
+/// Diffuse light arriving at a point from the rest of the scene, and how far away the
+/// surfaces it came from were, which bounds how far the estimate can be reused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrradianceRecord {
+    pub point: Tuple,
+    pub normal: Tuple,
+    pub irradiance: Color,
+    pub radius: f64,
+}
+
+/// Sparse irradiance estimates that are interpolated between rather than recomputed at
+/// every hit, as indirect diffuse light changes slowly over most surfaces. The first
+/// pass over an image fills the cache; later lookups mostly reuse it. Lower `accuracy`
+/// values give more records and fewer artifacts.
+pub struct IrradianceCache {
+    accuracy: f64,
+    rays: usize,
+    records: RwLock<RecordGrid>,
+}
+
+impl IrradianceCache {
+    pub fn new(accuracy: f64, rays: usize) -> Self {
+        Self {
+            accuracy,
+            rays,
+            records: RwLock::new(RecordGrid::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.read().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The irradiance interpolated from the records close enough to `point`, facing
+    /// the same way, or `None` when there are none.
+    pub fn lookup(&self, point: &Tuple, normal: &Tuple) -> Option<Color> {
+        let records = self.records.read().unwrap();
+        let (total, weights) = records.near(point).into_iter().fold(
+            (Color::black(), 0.0),
+            |(total, weights), record| {
+                let distance = (point - &record.point).magnitude() / record.radius;
+                let turn = (1.0 - normal.dot(&record.normal)).max(0.0).sqrt();
+                let weight = 1.0 / (distance + turn).max(EPSILON);
+                if weight > 1.0 / self.accuracy {
+                    (total + record.irradiance * weight, weights + weight)
+                } else {
+                    (total, weights)
+                }
+            },
+        );
+
+        if weights > 0.0 {
+            Some(total * (1.0 / weights))
+        } else {
+            None
+        }
+    }
+
+    /// The cached irradiance at `point`, sampling the world and storing a new record
+    /// when nothing cached is close enough.
+    pub fn irradiance(&self, world: &World, point: &Tuple, normal: &Tuple) -> Color {
+        if let Some(irradiance) = self.lookup(point, normal) {
+            return irradiance;
+        }
+
+        let record = sample_irradiance(world, point, normal, self.rays);
+        let irradiance = record.irradiance;
+        // A record is only used within `accuracy` of its radius.
+        let reach = self.accuracy * record.radius;
+        self.records.write().unwrap().insert(record, reach);
+        irradiance
+    }
+}
+
+// Records bucketed by position in grids of several cell sizes, each record in the grid
+// whose cells are at least as wide as its reach, so that a lookup only visits the cells
+// around the point in each grid in use rather than every record.
+#[derive(Default)]
+struct RecordGrid {
+    cells: HashMap<(i32, i64, i64, i64), Vec<IrradianceRecord>>,
+    levels: BTreeSet<i32>,
+    // Records from points where every ray escaped, which apply however far away.
+    unbounded: Vec<IrradianceRecord>,
+    len: usize,
+}
+
+impl RecordGrid {
+    fn insert(&mut self, record: IrradianceRecord, reach: f64) {
+        self.len += 1;
+        if !reach.is_finite() {
+            self.unbounded.push(record);
+            return;
+        }
+
+        let level = reach.log2().ceil().clamp(-60.0, 60.0) as i32;
+        let (x, y, z) = cell_of(&record.point, level);
+        self.levels.insert(level);
+        self.cells.entry((level, x, y, z)).or_default().push(record);
+    }
+
+    // The records that might reach `point`.
+    fn near(&self, point: &Tuple) -> Vec<&IrradianceRecord> {
+        let mut records: Vec<&IrradianceRecord> = self.unbounded.iter().collect();
+        for level in &self.levels {
+            let (x, y, z) = cell_of(point, *level);
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        if let Some(cell) = self.cells.get(&(*level, x + dx, y + dy, z + dz)) {
+                            records.extend(cell.iter());
+                        }
+                    }
+                }
+            }
+        }
+        records
+    }
+}
+
+// The cell holding `point` in the grid whose cells are `2^level` wide.
+fn cell_of(point: &Tuple, level: i32) -> (i64, i64, i64) {
+    let size = 2.0_f64.powi(level);
+    (
+        (point.x / size).floor() as i64,
+        (point.y / size).floor() as i64,
+        (point.z / size).floor() as i64,
+    )
+}
+
+/// Estimates the irradiance at `point` by brute force, tracing `rays` rays spread over
+/// the hemisphere around `normal` in proportion to the cosine of their angle to it.
+/// Light from a latitude-longitude skybox is instead gathered by as many rays drawn
+/// towards where it is brightest, as the first rays rarely find a small bright sun.
+pub fn sample_irradiance(
+    world: &World,
+    point: &Tuple,
+    normal: &Tuple,
+    rays: usize,
+) -> IrradianceRecord {
+    let rays = rays.max(1);
+    let mut rng = rng_at(point);
+    let origin = point + &(normal * EPSILON);
+
+    let mut total = Color::black();
+    let mut inverse_distances = 0.0;
+    for _ in 0..rays {
+        let mut direction = normal + &Tuple::random_unit_vector(&mut rng);
+        if direction.magnitude() < EPSILON {
+            direction = normal.clone();
+        }
+        let ray = Ray::new(&origin, &direction.normalize());
+
+        let intersections = world.intersect_visible(&ray, RayKind::Reflection);
+        if let Some(hit) = intersections.media_hit() {
+            let comps = hit.prepare_computation(&ray, &intersections);
+            total = total + world.shade_hit(&comps, world.max_depth.saturating_sub(1));
+            inverse_distances += 1.0 / hit.t.max(EPSILON);
+        } else if let Some(Skybox::Cube(cube)) = &world.skybox {
+            total = total + cube.radiance(&ray.direction);
+        }
+    }
+    let mut irradiance = total * (PI / rays as f64);
+
+    if let Some(Skybox::Equirectangular(environment)) = &world.skybox {
+        let mut sky = Color::black();
+        for _ in 0..rays {
+            let (direction, pdf) = environment.sample(rng.gen(), rng.gen());
+            let cos = direction.dot(normal);
+            if pdf <= 0.0 || cos <= 0.0 {
+                continue;
+            }
+            let ray = Ray::new(&origin, &direction);
+            if world
+                .intersect_visible(&ray, RayKind::Shadow)
+                .media_hit()
+                .is_none()
+            {
+                sky = sky + environment.radiance(&direction) * (cos / pdf);
+            }
+        }
+        irradiance = irradiance + sky * (1.0 / rays as f64);
+    }
+
+    // Rays that escape leave the radius unbounded, as nothing nearby changes the light.
+    let radius = if inverse_distances > 0.0 {
+        rays as f64 / inverse_distances
+    } else {
+        f64::INFINITY
+    };
+
+    IrradianceRecord {
+        point: point.clone(),
+        normal: normal.clone(),
+        irradiance,
+        radius,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Canvas;
+    use crate::environment::Environment;
+    use crate::light::{Material, PointLight};
+    use crate::matrix::Matrix;
+    use crate::shapes::{Plane, Sphere};
+    use std::sync::Arc;
+
+    // Inside a big sphere that glows uniformly white.
+    fn glowing_room() -> World {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, 0.0), &Color::white()).into()];
+        let mut m = Material::new();
+        m.ambient = 1.0;
+        m.diffuse = 0.0;
+        m.specular = 0.0;
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::scaling(10.0, 10.0, 10.0))
+                .with_material(&m),
+        ));
+        w
+    }
+
+    #[test]
+    fn test_irradiance_from_uniform_surroundings() {
+        let w = glowing_room();
+        let normal = Tuple::vector(0.0, 1.0, 0.0);
+
+        let record = sample_irradiance(&w, &Tuple::point(0.0, 0.0, 0.0), &normal, 16);
+
+        assert_eq!(record.irradiance, Color::new(PI, PI, PI));
+        assert!(record.radius > 9.0 && record.radius < 11.0);
+    }
+
+    #[test]
+    fn test_an_empty_cache_has_nothing_to_interpolate() {
+        let cache = IrradianceCache::new(0.5, 16);
+
+        assert!(cache.is_empty());
+        assert!(cache
+            .lookup(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_the_cache_reuses_nearby_records() {
+        let w = glowing_room();
+        let cache = IrradianceCache::new(0.5, 16);
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+
+        let first = cache.irradiance(&w, &Tuple::point(0.0, 0.0, 0.0), &up);
+        let nearby = cache.irradiance(&w, &Tuple::point(0.5, 0.0, 0.0), &up);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(nearby, first);
+
+        cache.irradiance(
+            &w,
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, -1.0, 0.0),
+        );
+        cache.irradiance(&w, &Tuple::point(8.0, 0.0, 0.0), &up);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn test_the_world_adds_cached_indirect_light_to_diffuse_surfaces() {
+        let mut w = glowing_room();
+        let mut floor_m = Material::new();
+        floor_m.ambient = 0.0;
+        floor_m.diffuse = 0.5;
+        floor_m.specular = 0.0;
+        w.objects.push(Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&floor_m),
+        ));
+        let down = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+        let direct = w.color_at(&down);
+
+        let cache = Arc::new(IrradianceCache::new(0.5, 16));
+        w.indirect = Some(cache.clone());
+        let lit = w.color_at(&down);
+
+        assert_eq!(lit - direct, Color::new(0.5, 0.5, 0.5));
+        assert_eq!(cache.len(), 1);
+        w.color_at(&Ray::new(
+            &Tuple::point(0.1, 0.0, 0.0),
+            &Tuple::vector(0.0, -1.0, 0.0),
+        ));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_irradiance_from_an_open_sky_is_drawn_from_the_skybox() {
+        let mut image = Canvas::new(16, 8);
+        for pixel in image.pixels.iter_mut() {
+            *pixel = Color::white();
+        }
+        let mut w = World::new();
+        w.skybox = Some(Skybox::Equirectangular(Arc::new(Environment::new(image))));
+
+        let record = sample_irradiance(
+            &w,
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+            4096,
+        );
+
+        // Within a few standard deviations of the estimate.
+        assert!((record.irradiance.red - PI).abs() / PI < 0.1);
+        assert_eq!(record.radius, f64::INFINITY);
+    }
+}
@@ -1,10 +1,25 @@
+pub mod animation;
+pub mod bounds;
 pub mod camera;
 pub mod canvas;
+pub mod cleanup;
+pub mod environment;
+pub mod irradiance;
 pub mod light;
 pub mod matrix;
 pub mod pattern;
+pub mod plot;
+pub mod ply;
+pub mod post;
+pub mod profile;
+pub mod queue;
 pub mod ray;
+pub mod scatter;
+pub mod scenes;
 pub mod shapes;
+pub mod snapshot;
+pub mod spectrum;
+pub mod stl;
 pub mod transformations;
 pub mod tuple;
 pub mod utils;
@@ -1,24 +1,216 @@
 use crate::canvas::Color;
-use crate::pattern::Pattern;
+use crate::pattern::{Pattern, SolidPattern};
+use crate::ray::{Computation, RayKind};
 use crate::shapes::Shape;
 use crate::tuple::Tuple;
+use crate::utils::EPSILON;
+use crate::world::World;
+use std::collections::HashMap;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+/// How a light dims with distance `d`: its intensity is divided by
+/// `constant + linear * d + quadratic * d * d`. Ambient light is not affected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Falloff {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Falloff {
+    pub fn new(constant: f64, linear: f64, quadratic: f64) -> Self {
+        Self {
+            constant,
+            linear,
+            quadratic,
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(1.0, 0.0, 0.0)
+    }
+
+    pub fn linear() -> Self {
+        Self::new(0.0, 1.0, 0.0)
+    }
+
+    pub fn quadratic() -> Self {
+        Self::new(0.0, 0.0, 1.0)
+    }
+
+    // Never brighter than the light itself, so a surface right next to it isn't blown out.
+    pub fn attenuation(&self, distance: f64) -> f64 {
+        let divisor = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        (1.0 / divisor).min(1.0)
+    }
+}
+
+impl Default for Falloff {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct PointLight {
     pub position: Tuple,
     pub intensity: Color,
+    pub falloff: Falloff,
 }
 
 impl PointLight {
     pub fn new(position: &Tuple, intensity: &Color) -> Self {
         Self {
             position: position.clone(),
-            intensity: intensity.clone(),
+            intensity: *intensity,
+            falloff: Falloff::none(),
         }
     }
+
+    pub fn with_falloff(self, falloff: Falloff) -> Self {
+        Self { falloff, ..self }
+    }
+}
+
+/// A rectangular light spanning `uvec` and `vvec` from `corner`, sampled at the centre
+/// of each cell of a `usteps` by `vsteps` grid. Points that see only some of the cells
+/// are in its penumbra, which softens the edges of shadows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaLight {
+    pub corner: Tuple,
+    pub uvec: Tuple,
+    pub usteps: usize,
+    pub vvec: Tuple,
+    pub vsteps: usize,
+    pub intensity: Color,
+    pub falloff: Falloff,
+}
+
+impl AreaLight {
+    pub fn new(
+        corner: &Tuple,
+        uvec: &Tuple,
+        usteps: usize,
+        vvec: &Tuple,
+        vsteps: usize,
+        intensity: &Color,
+    ) -> Self {
+        Self {
+            corner: corner.clone(),
+            uvec: uvec.clone(),
+            usteps: usteps.max(1),
+            vvec: vvec.clone(),
+            vsteps: vsteps.max(1),
+            intensity: *intensity,
+            falloff: Falloff::none(),
+        }
+    }
+
+    pub fn with_falloff(self, falloff: Falloff) -> Self {
+        Self { falloff, ..self }
+    }
+
+    pub fn point_on_light(&self, u: usize, v: usize) -> Tuple {
+        &self.corner
+            + &(&(&self.uvec * ((u as f64 + 0.5) / self.usteps as f64))
+                + &(&self.vvec * ((v as f64 + 0.5) / self.vsteps as f64)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Light {
+    Point(PointLight),
+    Area(AreaLight),
 }
 
+impl Light {
+    pub fn intensity(&self) -> Color {
+        match self {
+            Light::Point(light) => light.intensity,
+            Light::Area(light) => light.intensity,
+        }
+    }
+
+    pub fn falloff(&self) -> Falloff {
+        match self {
+            Light::Point(light) => light.falloff,
+            Light::Area(light) => light.falloff,
+        }
+    }
+
+    /// The centre of the light.
+    pub fn position(&self) -> Tuple {
+        match self {
+            Light::Point(light) => light.position.clone(),
+            Light::Area(light) => &light.corner + &(&(&light.uvec + &light.vvec) * 0.5),
+        }
+    }
+
+    /// Where shadow rays towards the light are aimed.
+    pub fn samples(&self) -> Vec<Tuple> {
+        match self {
+            Light::Point(light) => vec![light.position.clone()],
+            Light::Area(light) => (0..light.vsteps)
+                .flat_map(|v| (0..light.usteps).map(move |u| (u, v)))
+                .map(|(u, v)| light.point_on_light(u, v))
+                .collect(),
+        }
+    }
+}
+
+impl From<PointLight> for Light {
+    fn from(light: PointLight) -> Self {
+        Light::Point(light)
+    }
+}
+
+impl From<AreaLight> for Light {
+    fn from(light: AreaLight) -> Self {
+        Light::Area(light)
+    }
+}
+
+/// Which kinds of ray can see a surface. Hiding a shape from the camera while it still
+/// casts shadows gives an invisible light blocker; hiding it from everything but
+/// reflections gives a reflection card.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
+    pub refraction: bool,
+}
+
+impl Visibility {
+    pub fn new() -> Self {
+        Self {
+            camera: true,
+            shadow: true,
+            reflection: true,
+            refraction: true,
+        }
+    }
+
+    pub fn allows(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Reflection => self.reflection,
+            RayKind::Refraction => self.refraction,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Custom shading for a hit. Returning `None` falls back to the standard `lighting`.
+pub type Shader = Arc<dyn Fn(&Computation, &World) -> Option<Color> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct Material {
     pub color: Color,
@@ -27,6 +219,39 @@ pub struct Material {
     pub specular: f64,
     pub shininess: f64,
     pub pattern: Option<Arc<dyn Pattern + Sync + Send>>,
+    /// One-sided surfaces are invisible to camera rays from behind, i.e. where the ray
+    /// travels along the normal, but still cast shadows and show in reflections. This
+    /// suits billboards and open meshes.
+    pub two_sided: bool,
+    pub visibility: Visibility,
+    pub shader: Option<Shader>,
+    pub reflective: f64,
+    /// Blurs reflections, from 0.0 for a perfect mirror towards 1.0 for brushed metal.
+    /// With `MetallicRoughness` shading it also spreads the highlights of lights.
+    pub roughness: f64,
+    pub transparency: f64,
+    pub refractive_index: f64,
+    /// How much of each channel a transparent material absorbs per unit of distance
+    /// travelled through it, so that thick glass is darker than thin glass.
+    pub absorption: Color,
+    /// Where transparent shapes overlap, the medium with the higher priority fills the
+    /// shared space, e.g. glass over the water it holds.
+    pub medium_priority: u32,
+    /// How direct light is reflected. `diffuse`, `specular` and `shininess` only apply
+    /// to `Phong`.
+    pub shading: ShadingModel,
+}
+
+/// The reflectance model for direct light. `MetallicRoughness` is the GGX microfacet
+/// model used by most PBR assets: `metallic` blends from a dielectric with a white 4%
+/// highlight to a metal tinted by the surface color, and the material's `roughness`
+/// spreads the highlight from a pinpoint at 0.0 to fully matte at 1.0, as it blurs
+/// reflections. Lights are taken as the irradiance they deliver face on, so a rough
+/// white dielectric is about as bright as Phong with a diffuse of 1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShadingModel {
+    Phong,
+    MetallicRoughness { metallic: f64 },
 }
 
 impl Material {
@@ -38,10 +263,34 @@ impl Material {
             specular: 0.9,
             shininess: 200.0,
             pattern: None,
+            two_sided: true,
+            visibility: Visibility::new(),
+            shader: None,
+            reflective: 0.0,
+            roughness: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+            absorption: Color::black(),
+            medium_priority: 0,
+            shading: ShadingModel::Phong,
+        }
+    }
+
+    /// What colors the surface: the material's pattern, or its color as a solid pattern.
+    pub fn surface_pattern(&self) -> Arc<dyn Pattern + Sync + Send> {
+        match &self.pattern {
+            Some(pattern) => pattern.clone(),
+            None => Arc::new(SolidPattern::new(&self.color)),
         }
     }
 }
 
+impl Default for Material {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Debug for Material {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Material: {{{:?}}}", self.color)
@@ -55,6 +304,105 @@ impl std::cmp::PartialEq for Material {
             && self.diffuse == other.diffuse
             && self.specular == other.specular
             && self.shininess == other.shininess
+            && self.two_sided == other.two_sided
+            && self.visibility == other.visibility
+            && self.reflective == other.reflective
+            && self.roughness == other.roughness
+            && self.transparency == other.transparency
+            && self.refractive_index == other.refractive_index
+            && self.absorption == other.absorption
+            && self.medium_priority == other.medium_priority
+            && self.shading == other.shading
+    }
+}
+
+/// A material that can be shared between shapes cheaply and edited without surprises:
+/// `edit` copies the material first if any other handle still refers to it, so only
+/// this handle sees the change.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaterialHandle {
+    material: Arc<Material>,
+}
+
+impl MaterialHandle {
+    pub fn new(material: Material) -> Self {
+        Self {
+            material: Arc::new(material),
+        }
+    }
+
+    pub fn edit<F: FnOnce(&mut Material)>(&mut self, f: F) {
+        f(Arc::make_mut(&mut self.material));
+    }
+
+    pub fn shares_with(&self, other: &MaterialHandle) -> bool {
+        Arc::ptr_eq(&self.material, &other.material)
+    }
+}
+
+impl From<Material> for MaterialHandle {
+    fn from(material: Material) -> Self {
+        Self::new(material)
+    }
+}
+
+impl From<&Material> for MaterialHandle {
+    fn from(material: &Material) -> Self {
+        Self::new(material.clone())
+    }
+}
+
+impl From<&MaterialHandle> for MaterialHandle {
+    fn from(handle: &MaterialHandle) -> Self {
+        handle.clone()
+    }
+}
+
+impl std::ops::Deref for MaterialHandle {
+    type Target = Material;
+
+    fn deref(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// Materials registered by name, handed out as `MaterialHandle`s. Editing an entry in
+/// the table only affects handles taken from it afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialTable {
+    entries: HashMap<String, MaterialHandle>,
+}
+
+impl MaterialTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &str, material: Material) -> MaterialHandle {
+        let handle = MaterialHandle::new(material);
+        self.entries.insert(name.to_string(), handle.clone());
+        handle
+    }
+
+    pub fn get(&self, name: &str) -> Option<MaterialHandle> {
+        self.entries.get(name).cloned()
+    }
+
+    /// Edits the named material, returning false if there is none.
+    pub fn edit<F: FnOnce(&mut Material)>(&mut self, name: &str, f: F) -> bool {
+        match self.entries.get_mut(name) {
+            Some(handle) => {
+                handle.edit(f);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.entries.keys().map(|n| n.as_str()).collect();
+        names.sort_unstable();
+        names
     }
 }
 
@@ -67,36 +415,122 @@ pub fn lighting(
     normalv: &Tuple,
     in_shadown: bool,
 ) -> Color {
-    let color = if let Some(pattern) = &material.pattern {
-        pattern.at_object(object, point)
-    } else {
-        material.color
-    };
-    let effective_color = color * light.intensity;
-    let lightv = (light.position.clone() - point.clone()).normalize();
-    let ambient = effective_color * material.ambient;
-    let light_dot_normal = lightv.dot(normalv);
-    let (diffuse, specular) = if light_dot_normal < 0.0 {
-        (Color::black(), Color::black())
-    } else {
-        let diffuse = effective_color * material.diffuse * light_dot_normal;
-        let reflectv = (-lightv).reflect(normalv);
-        let reflect_dot_eye = reflectv.dot(eyev);
-        if reflect_dot_eye <= 0.0 {
-            (diffuse, Color::black())
-        } else {
-            let factor = reflect_dot_eye.powf(material.shininess);
-            (diffuse, light.intensity * material.specular * factor)
-        }
-    };
+    let color = surface_color(material, object, point);
+    let ambient = color * light.intensity * material.ambient;
 
     if in_shadown {
         ambient
     } else {
-        ambient + diffuse + specular
+        ambient + direct(material, color, light, point, eyev, normalv)
     }
 }
 
+/// Lighting from a light with several sample points, where `visibility` is the
+/// fraction of them that the point can see.
+pub fn soft_lighting(
+    material: &Material,
+    object: Arc<dyn Shape>,
+    light: &Light,
+    point: &Tuple,
+    eyev: &Tuple,
+    normalv: &Tuple,
+    visibility: f64,
+) -> Color {
+    let color = surface_color(material, object, point);
+    let intensity = light.intensity();
+    let falloff = light.falloff();
+    let ambient = color * intensity * material.ambient;
+    let samples = light.samples();
+
+    let total = samples.iter().fold(Color::black(), |sum, sample| {
+        let sample = PointLight::new(sample, &intensity).with_falloff(falloff);
+        sum + direct(material, color, &sample, point, eyev, normalv)
+    });
+
+    ambient + total * (visibility / samples.len() as f64)
+}
+
+/// The ambient term `lighting` adds for `light`, before any scaling by the world.
+pub fn ambient_lighting(
+    material: &Material,
+    object: Arc<dyn Shape>,
+    light: &Light,
+    point: &Tuple,
+) -> Color {
+    surface_color(material, object, point) * light.intensity() * material.ambient
+}
+
+pub(crate) fn surface_color(material: &Material, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+    material.surface_pattern().at_object(object, point)
+}
+
+// The diffuse and specular light from a point light, or one sample of a larger light.
+fn direct(
+    material: &Material,
+    color: Color,
+    light: &PointLight,
+    point: &Tuple,
+    eyev: &Tuple,
+    normalv: &Tuple,
+) -> Color {
+    let to_light = light.position.clone() - point.clone();
+    let intensity = light.intensity * light.falloff.attenuation(to_light.magnitude());
+    let effective_color = color * intensity;
+    let lightv = to_light.normalize();
+    let light_dot_normal = lightv.dot(normalv);
+    if light_dot_normal < 0.0 {
+        return Color::black();
+    }
+
+    if let ShadingModel::MetallicRoughness { metallic } = material.shading {
+        return intensity
+            * ggx(color, metallic, material.roughness, &lightv, eyev, normalv)
+            * (PI * light_dot_normal);
+    }
+
+    let diffuse = effective_color * material.diffuse * light_dot_normal;
+    let reflectv = (-lightv).reflect(normalv);
+    let reflect_dot_eye = reflectv.dot(eyev);
+    if reflect_dot_eye <= 0.0 {
+        diffuse
+    } else {
+        let factor = reflect_dot_eye.powf(material.shininess);
+        diffuse + intensity * material.specular * factor
+    }
+}
+
+// The Cook-Torrance BRDF with a GGX distribution, Smith-Schlick shadowing and Schlick's
+// Fresnel approximation, plus a Lambert term for the light metals do not reflect.
+fn ggx(
+    color: Color,
+    metallic: f64,
+    roughness: f64,
+    lightv: &Tuple,
+    eyev: &Tuple,
+    normalv: &Tuple,
+) -> Color {
+    let metallic = metallic.clamp(0.0, 1.0);
+    let roughness = roughness.clamp(0.03, 1.0);
+    let dielectric = Color::new(0.04, 0.04, 0.04);
+    let f0 = dielectric * (1.0 - metallic) + color * metallic;
+
+    let n_dot_l = lightv.dot(normalv).max(0.0);
+    let n_dot_v = eyev.dot(normalv).max(EPSILON);
+    let halfway = (lightv + eyev).normalize();
+    let n_dot_h = halfway.dot(normalv).max(0.0);
+    let v_dot_h = halfway.dot(eyev).max(0.0);
+
+    let alpha2 = roughness.powi(4);
+    let d = alpha2 / (PI * (n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0).powi(2));
+    let k = (roughness + 1.0).powi(2) / 8.0;
+    let g = (n_dot_l / (n_dot_l * (1.0 - k) + k)) * (n_dot_v / (n_dot_v * (1.0 - k) + k));
+    let fresnel = f0 + (Color::white() - f0) * (1.0 - v_dot_h).powi(5);
+
+    let specular = fresnel * (d * g / (4.0 * n_dot_l.max(EPSILON) * n_dot_v));
+    let diffuse = (Color::white() - fresnel) * color * ((1.0 - metallic) / PI);
+    diffuse + specular
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,6 +558,14 @@ mod tests {
         assert!(equal_f64(m.diffuse, 0.9));
         assert!(equal_f64(m.specular, 0.9));
         assert!(equal_f64(m.shininess, 200.0));
+        assert!(m.two_sided);
+        assert_eq!(m.visibility, Visibility::new());
+        assert!(equal_f64(m.reflective, 0.0));
+        assert!(equal_f64(m.roughness, 0.0));
+        assert!(equal_f64(m.transparency, 0.0));
+        assert!(equal_f64(m.refractive_index, 1.0));
+        assert_eq!(m.absorption, Color::black());
+        assert_eq!(m.medium_priority, 0);
     }
 
     #[test]
@@ -259,6 +701,29 @@ mod tests {
         assert_eq!(result, Color::new(0.1, 0.1, 0.1));
     }
 
+    #[test]
+    fn test_a_material_without_a_pattern_is_colored_by_a_solid_pattern() {
+        let mut m = Material::new();
+        m.color = Color::new(1.0, 0.5, 0.0);
+        let object: Arc<dyn Shape> = Arc::new(Sphere::new());
+
+        assert_eq!(
+            m.surface_pattern()
+                .at_object(object.clone(), &Tuple::point(0.0, 1.0, 0.0)),
+            Color::new(1.0, 0.5, 0.0)
+        );
+
+        m.pattern = Some(Arc::new(StripePattern::new(
+            &Color::white(),
+            &Color::black(),
+        )));
+        assert_eq!(
+            m.surface_pattern()
+                .at_object(object, &Tuple::point(1.5, 0.0, 0.0)),
+            Color::black()
+        );
+    }
+
     #[test]
     fn test_lighting_with_a_pattern_applied() {
         let mut m = Material::new();
@@ -299,4 +764,245 @@ mod tests {
             Color::black()
         );
     }
+
+    #[test]
+    fn test_finding_a_single_point_on_an_area_light() {
+        let light = AreaLight::new(
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            &Tuple::vector(0.0, 0.0, 1.0),
+            2,
+            &Color::white(),
+        );
+
+        assert_eq!(light.point_on_light(0, 0), Tuple::point(0.25, 0.0, 0.25));
+        assert_eq!(light.point_on_light(1, 0), Tuple::point(0.75, 0.0, 0.25));
+        assert_eq!(light.point_on_light(0, 1), Tuple::point(0.25, 0.0, 0.75));
+        assert_eq!(light.point_on_light(2, 0), Tuple::point(1.25, 0.0, 0.25));
+        assert_eq!(light.point_on_light(3, 1), Tuple::point(1.75, 0.0, 0.75));
+    }
+
+    #[test]
+    fn test_an_area_light_is_sampled_over_its_whole_grid() {
+        let light = Light::from(AreaLight::new(
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            &Tuple::vector(0.0, 0.0, 1.0),
+            2,
+            &Color::white(),
+        ));
+
+        assert_eq!(light.samples().len(), 8);
+        assert_eq!(light.position(), Tuple::point(1.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn test_soft_lighting_scales_by_the_visible_fraction_of_the_light() {
+        let mut m = Material::new();
+        m.ambient = 0.1;
+        m.diffuse = 0.9;
+        m.specular = 0.0;
+        let light = Light::from(PointLight::new(
+            &Tuple::point(0.0, 0.0, -10.0),
+            &Color::white(),
+        ));
+        let point = Tuple::point(0.0, 0.0, -1.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+
+        for (visibility, expected) in [(1.0, 1.0), (0.5, 0.55), (0.0, 0.1)].iter() {
+            let result = soft_lighting(
+                &m,
+                Arc::new(Sphere::new()),
+                &light,
+                &point,
+                &eyev,
+                &normalv,
+                *visibility,
+            );
+
+            assert_eq!(result, Color::new(*expected, *expected, *expected));
+        }
+    }
+
+    #[test]
+    fn test_soft_lighting_samples_every_point_on_an_area_light() {
+        let mut m = Material::new();
+        m.ambient = 0.1;
+        m.diffuse = 0.9;
+        m.specular = 0.0;
+        let light = Light::from(AreaLight::new(
+            &Tuple::point(-0.5, -0.5, -5.0),
+            &Tuple::vector(1.0, 0.0, 0.0),
+            2,
+            &Tuple::vector(0.0, 1.0, 0.0),
+            2,
+            &Color::white(),
+        ));
+        let point = Tuple::point(0.0, 0.0, -1.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+
+        let result = soft_lighting(
+            &m,
+            Arc::new(Sphere::new()),
+            &light,
+            &point,
+            &eyev,
+            &normalv,
+            1.0,
+        );
+
+        let cos = 4.0 / (16.0_f64 + 0.125).sqrt();
+        let expected = 0.1 + 0.9 * cos;
+        assert_eq!(result, Color::new(expected, expected, expected));
+    }
+
+    #[test]
+    fn test_falloff_attenuation() {
+        assert!(equal_f64(Falloff::none().attenuation(10.0), 1.0));
+        assert!(equal_f64(Falloff::linear().attenuation(4.0), 0.25));
+        assert!(equal_f64(Falloff::quadratic().attenuation(4.0), 1.0 / 16.0));
+        assert!(equal_f64(
+            Falloff::new(1.0, 0.5, 0.25).attenuation(2.0),
+            1.0 / 3.0
+        ));
+        assert!(equal_f64(Falloff::quadratic().attenuation(0.5), 1.0));
+    }
+
+    #[test]
+    fn test_lighting_with_falloff_dims_all_but_the_ambient_term() {
+        let m = Material::new();
+        let position = Tuple::point(0.0, 0.0, 0.0);
+        let eyev = Tuple::vector(0.0, 0.0, -1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::white())
+            .with_falloff(Falloff::quadratic());
+
+        let result = lighting(
+            &m,
+            Arc::new(Sphere::new()),
+            &light,
+            &position,
+            &eyev,
+            &normalv,
+            false,
+        );
+
+        assert_eq!(result, Color::new(0.118, 0.118, 0.118));
+    }
+
+    fn pbr(color: Color, metallic: f64, roughness: f64) -> Material {
+        let mut m = Material::new();
+        m.color = color;
+        m.ambient = 0.0;
+        m.roughness = roughness;
+        m.shading = ShadingModel::MetallicRoughness { metallic };
+        m
+    }
+
+    #[test]
+    fn test_a_rough_dielectric_lit_face_on_is_nearly_lambertian() {
+        let m = pbr(Color::white(), 0.0, 1.0);
+        let normalv = Tuple::vector(0.0, 0.0, -1.0);
+        let light = PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::white());
+
+        let result = lighting(
+            &m,
+            Arc::new(Sphere::new()),
+            &light,
+            &Tuple::point(0.0, 0.0, 0.0),
+            &normalv,
+            &normalv,
+            false,
+        );
+
+        assert_eq!(result, Color::new(0.97, 0.97, 0.97));
+    }
+
+    #[test]
+    fn test_a_metal_reflects_only_in_its_own_color() {
+        let m = pbr(Color::new(1.0, 0.5, 0.0), 1.0, 0.3);
+        let normalv = Tuple::vector(0.0, 1.0, 0.0);
+        let eyev = Tuple::vector(0.0, 2.0_f64.sqrt() / 2.0, -(2.0_f64.sqrt()) / 2.0);
+        let mirror = PointLight::new(&Tuple::point(0.0, 10.0, 10.0), &Color::white());
+        let off_axis = PointLight::new(&Tuple::point(10.0, 10.0, 0.0), &Color::white());
+        let shade = |light: &PointLight| {
+            lighting(
+                &m,
+                Arc::new(Sphere::new()),
+                light,
+                &Tuple::point(0.0, 0.0, 0.0),
+                &eyev,
+                &normalv,
+                false,
+            )
+        };
+
+        let highlight = shade(&mirror);
+        let dim = shade(&off_axis);
+
+        assert!(highlight.red > 1.0);
+        assert!((highlight.green / highlight.red - 0.5).abs() < 0.01);
+        assert!(highlight.blue < highlight.red * 0.01);
+        assert!(dim.red < highlight.red * 0.1);
+    }
+
+    #[test]
+    fn test_rougher_surfaces_spread_their_highlights() {
+        let normalv = Tuple::vector(0.0, 1.0, 0.0);
+        let eyev = Tuple::vector(0.0, 2.0_f64.sqrt() / 2.0, -(2.0_f64.sqrt()) / 2.0);
+        let light = PointLight::new(&Tuple::point(0.0, 10.0, 10.0), &Color::white());
+        let shade = |roughness: f64| {
+            lighting(
+                &pbr(Color::white(), 1.0, roughness),
+                Arc::new(Sphere::new()),
+                &light,
+                &Tuple::point(0.0, 0.0, 0.0),
+                &eyev,
+                &normalv,
+                false,
+            )
+            .red
+        };
+
+        assert!(shade(0.1) > shade(0.5));
+        assert!(shade(0.5) > shade(0.9));
+    }
+
+    #[test]
+    fn test_editing_a_shared_material_handle_copies_it() {
+        let mut shiny = MaterialHandle::new(Material::new());
+        let original = shiny.clone();
+        assert!(shiny.shares_with(&original));
+
+        shiny.edit(|m| m.diffuse = 0.5);
+
+        assert!(!shiny.shares_with(&original));
+        assert_eq!(shiny.diffuse, 0.5);
+        assert_eq!(original.diffuse, 0.9);
+    }
+
+    #[test]
+    fn test_a_material_table_hands_out_shared_handles() {
+        let mut table = MaterialTable::new();
+        let mut red = Material::new();
+        red.color = Color::new(1.0, 0.0, 0.0);
+        let given = table.register("red", red);
+        table.register("plain", Material::new());
+
+        assert!(table.get("red").unwrap().shares_with(&given));
+        assert!(table.edit("red", |m| m.specular = 0.0));
+        assert!(!table.edit("blue", |m| m.specular = 0.0));
+
+        let edited = table.get("red").unwrap();
+        assert_eq!(edited.specular, 0.0);
+        assert_eq!(given.specular, 0.9);
+        assert_eq!(table.names(), vec!["plain", "red"]);
+
+        let s = Sphere::new().with_material(&edited);
+        assert_eq!(s.get_material(), *edited);
+    }
 }
@@ -1,6 +1,9 @@
 use crate::tuple::Tuple;
-use crate::utils::equal_f64;
+use crate::utils::{equal_f64, quantize, stable_hash};
 
+/// A square matrix stored in row-major order: element `(row, col)` lives at
+/// `row * size + col`. `new` takes its elements in the same order, one row after another.
+/// Only 1x1 through 4x4 matrices are supported; anything else panics on construction.
 #[derive(Debug, Clone)]
 pub struct Matrix {
     size: usize,
@@ -10,12 +13,47 @@ pub struct Matrix {
 impl Matrix {
     pub fn new(elements: &[f64]) -> Self {
         let size = (elements.len() as f32).sqrt() as usize;
+        assert!(
+            size * size == elements.len() && (1..=4).contains(&size),
+            "a matrix needs 1, 4, 9 or 16 elements, got {}",
+            elements.len()
+        );
+
         Self {
             elements: elements.to_vec(),
             size,
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn from_row_major(elements: &[f64]) -> Self {
+        Self::new(elements)
+    }
+
+    pub fn from_column_major(elements: &[f64]) -> Self {
+        Self::new(elements).transpose()
+    }
+
+    pub fn to_row_major(&self) -> Vec<f64> {
+        self.elements.clone()
+    }
+
+    /// Quantized elements in row major order; see `quantize`.
+    pub fn quantized(&self) -> Vec<i64> {
+        self.elements.iter().map(|e| quantize(*e)).collect()
+    }
+
+    pub fn canonical_hash(&self) -> u64 {
+        stable_hash(&self.quantized())
+    }
+
+    pub fn to_column_major(&self) -> Vec<f64> {
+        self.transpose().elements
+    }
+
     pub fn identify() -> Self {
         Self::new(&[
             1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
@@ -103,25 +141,86 @@ impl Matrix {
         ])
     }
 
-    pub fn at(&self, y: usize, x: usize) -> f64 {
-        let index = self.index(x, y);
+    /// OpenGL-style perspective projection looking down -z. `field_of_view` is the
+    /// vertical angle in radians; after dividing by w, the near and far planes map to
+    /// z = -1 and z = 1.
+    pub fn perspective(field_of_view: f64, aspect: f64, near: f64, far: f64) -> Self {
+        let f = 1.0 / (field_of_view / 2.0).tan();
+        Self::new(&[
+            f / aspect,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            f,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            (far + near) / (near - far),
+            2.0 * far * near / (near - far),
+            0.0,
+            0.0,
+            -1.0,
+            0.0,
+        ])
+    }
+
+    /// OpenGL-style orthographic projection of the given view box onto the -1..1 cube.
+    pub fn orthographic(left: f64, right: f64, bottom: f64, top: f64, near: f64, far: f64) -> Self {
+        Self::new(&[
+            2.0 / (right - left),
+            0.0,
+            0.0,
+            -(right + left) / (right - left),
+            0.0,
+            2.0 / (top - bottom),
+            0.0,
+            -(top + bottom) / (top - bottom),
+            0.0,
+            0.0,
+            -2.0 / (far - near),
+            -(far + near) / (far - near),
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        ])
+    }
+
+    pub fn at(&self, row: usize, col: usize) -> f64 {
+        let index = self.index(row, col);
         self.elements[index]
     }
 
-    fn index(&self, x: usize, y: usize) -> usize {
-        y * self.size + x
+    fn index(&self, row: usize, col: usize) -> usize {
+        row * self.size + col
+    }
+
+    pub fn transform_points(&self, points: &[Tuple]) -> Vec<Tuple> {
+        assert_eq!(self.size, 4, "only 4x4 matrices can transform points");
+        let m = &self.elements;
+        points
+            .iter()
+            .map(|p| {
+                Tuple::new(
+                    m[0] * p.x + m[1] * p.y + m[2] * p.z + m[3] * p.w,
+                    m[4] * p.x + m[5] * p.y + m[6] * p.z + m[7] * p.w,
+                    m[8] * p.x + m[9] * p.y + m[10] * p.z + m[11] * p.w,
+                    m[12] * p.x + m[13] * p.y + m[14] * p.z + m[15] * p.w,
+                )
+            })
+            .collect()
     }
 
     fn mul_item(&self, rhs: &Matrix, row: usize, col: usize) -> f64 {
         (0..self.size)
-            .into_iter()
             .map(|i| self.at(row, i) * rhs.at(i, col))
             .sum()
     }
 
     pub fn transpose(&self) -> Matrix {
         let elements: Vec<f64> = (0..self.size * self.size)
-            .into_iter()
             .map(|index| {
                 let row = index / self.size;
                 let col = index % self.size;
@@ -134,46 +233,22 @@ impl Matrix {
     }
 
     pub fn determinant(&self) -> f64 {
-        if self.size == 2 {
-            self.at(0, 0) * self.at(1, 1) - self.at(0, 1) * self.at(1, 0)
-        } else {
-            (0..self.size)
-                .into_iter()
-                .map(|i| self.at(0, i) * self.cofactor(0, i))
-                .sum()
-        }
+        determinant_of(&self.elements, self.size)
     }
 
     pub fn submatrix(&self, row: usize, col: usize) -> Matrix {
-        let elements: Vec<f64> = (0..self.size * self.size)
-            .into_iter()
-            .map(|index| {
-                let r = index / self.size;
-                let c = index % self.size;
-
-                if r == row || c == col {
-                    None
-                } else {
-                    Some(self.at(r, c))
-                }
-            })
-            .filter_map(|x| x)
-            .collect();
+        let mut elements = [0.0; MAX_ELEMENTS];
+        let size = submatrix_into(&self.elements, self.size, row, col, &mut elements);
 
-        Matrix::new(&elements)
+        Matrix::new(&elements[..size * size])
     }
 
     pub fn minor(&self, row: usize, col: usize) -> f64 {
-        self.submatrix(row, col).determinant()
+        minor_of(&self.elements, self.size, row, col)
     }
 
     pub fn cofactor(&self, row: usize, col: usize) -> f64 {
-        let minor = self.minor(row, col);
-        if (row + col) % 2 == 0 {
-            minor
-        } else {
-            -minor
-        }
+        cofactor_of(&self.elements, self.size, row, col)
     }
 
     pub fn is_invertible(&self) -> bool {
@@ -187,7 +262,6 @@ impl Matrix {
         }
 
         let elements: Vec<f64> = (0..self.size * self.size)
-            .into_iter()
             .map(|index| {
                 let row = index / self.size;
                 let col = index % self.size;
@@ -202,12 +276,62 @@ impl Matrix {
     }
 }
 
+// Large enough for the submatrix of any supported matrix, so minors and cofactors
+// can be computed on the stack instead of allocating a Matrix each time.
+const MAX_ELEMENTS: usize = 16;
+
+fn submatrix_into(
+    elements: &[f64],
+    size: usize,
+    row: usize,
+    col: usize,
+    out: &mut [f64; MAX_ELEMENTS],
+) -> usize {
+    let mut n = 0;
+    for r in (0..size).filter(|r| *r != row) {
+        for c in (0..size).filter(|c| *c != col) {
+            out[n] = elements[r * size + c];
+            n += 1;
+        }
+    }
+
+    size - 1
+}
+
+fn determinant_of(elements: &[f64], size: usize) -> f64 {
+    match size {
+        1 => elements[0],
+        2 => elements[0] * elements[3] - elements[1] * elements[2],
+        _ => (0..size)
+            .map(|col| elements[col] * cofactor_of(elements, size, 0, col))
+            .sum(),
+    }
+}
+
+fn minor_of(elements: &[f64], size: usize, row: usize, col: usize) -> f64 {
+    let mut sub = [0.0; MAX_ELEMENTS];
+    let sub_size = submatrix_into(elements, size, row, col, &mut sub);
+
+    determinant_of(&sub[..sub_size * sub_size], sub_size)
+}
+
+fn cofactor_of(elements: &[f64], size: usize, row: usize, col: usize) -> f64 {
+    let minor = minor_of(elements, size, row, col);
+    if (row + col) & 1 == 0 {
+        minor
+    } else {
+        -minor
+    }
+}
+
 impl PartialEq for Matrix {
     fn eq(&self, other: &Self) -> bool {
-        self.elements
-            .iter()
-            .zip(&other.elements)
-            .all(|(a, b)| equal_f64(*a, *b))
+        self.size == other.size
+            && self
+                .elements
+                .iter()
+                .zip(&other.elements)
+                .all(|(a, b)| equal_f64(*a, *b))
     }
 }
 
@@ -215,14 +339,14 @@ impl std::ops::Mul<Matrix> for Matrix {
     type Output = Self;
 
     fn mul(self, rhs: Matrix) -> Self::Output {
-        let elements: Vec<f64> = (0..self.size * self.size)
-            .into_iter()
-            .map(|index| {
-                let row = index / self.size;
-                let col = index % self.size;
-
-                self.mul_item(&rhs, row, col)
-            })
+        assert_eq!(
+            self.size, rhs.size,
+            "cannot multiply a {0}x{0} matrix by a {1}x{1} matrix",
+            self.size, rhs.size
+        );
+        let elements: Vec<f64> = (0..self.size)
+            .flat_map(|row| (0..self.size).map(move |col| (row, col)))
+            .map(|(row, col)| self.mul_item(&rhs, row, col))
             .collect();
         Self::new(&elements)
     }
@@ -232,24 +356,7 @@ impl std::ops::Mul<Tuple> for Matrix {
     type Output = Tuple;
 
     fn mul(self, rhs: Tuple) -> Self::Output {
-        Tuple::new(
-            self.at(0, 0) * rhs.x
-                + self.at(0, 1) * rhs.y
-                + self.at(0, 2) * rhs.z
-                + self.at(0, 3) * rhs.w,
-            self.at(1, 0) * rhs.x
-                + self.at(1, 1) * rhs.y
-                + self.at(1, 2) * rhs.z
-                + self.at(1, 3) * rhs.w,
-            self.at(2, 0) * rhs.x
-                + self.at(2, 1) * rhs.y
-                + self.at(2, 2) * rhs.z
-                + self.at(2, 3) * rhs.w,
-            self.at(3, 0) * rhs.x
-                + self.at(3, 1) * rhs.y
-                + self.at(3, 2) * rhs.z
-                + self.at(3, 3) * rhs.w,
-        )
+        &self * &rhs
     }
 }
 
@@ -257,24 +364,7 @@ impl std::ops::Mul<&Tuple> for Matrix {
     type Output = Tuple;
 
     fn mul(self, rhs: &Tuple) -> Self::Output {
-        Tuple::new(
-            self.at(0, 0) * rhs.x
-                + self.at(0, 1) * rhs.y
-                + self.at(0, 2) * rhs.z
-                + self.at(0, 3) * rhs.w,
-            self.at(1, 0) * rhs.x
-                + self.at(1, 1) * rhs.y
-                + self.at(1, 2) * rhs.z
-                + self.at(1, 3) * rhs.w,
-            self.at(2, 0) * rhs.x
-                + self.at(2, 1) * rhs.y
-                + self.at(2, 2) * rhs.z
-                + self.at(2, 3) * rhs.w,
-            self.at(3, 0) * rhs.x
-                + self.at(3, 1) * rhs.y
-                + self.at(3, 2) * rhs.z
-                + self.at(3, 3) * rhs.w,
-        )
+        &self * rhs
     }
 }
 
@@ -282,6 +372,8 @@ impl std::ops::Mul<&Tuple> for &Matrix {
     type Output = Tuple;
 
     fn mul(self, rhs: &Tuple) -> Self::Output {
+        assert_eq!(self.size, 4, "only 4x4 matrices can multiply a tuple");
+
         Tuple::new(
             self.at(0, 0) * rhs.x
                 + self.at(0, 1) * rhs.y
@@ -774,4 +866,131 @@ mod tests {
 
         assert_eq!(t * p, Tuple::point(15.0, 0.0, 7.0));
     }
+
+    #[test]
+    fn test_row_major_and_column_major_construction() {
+        let rows = [
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0,
+        ];
+        let columns = [
+            1.0, 5.0, 9.0, 13.0, 2.0, 6.0, 10.0, 14.0, 3.0, 7.0, 11.0, 15.0, 4.0, 8.0, 12.0, 16.0,
+        ];
+
+        let a = Matrix::from_row_major(&rows);
+        let b = Matrix::from_column_major(&columns);
+
+        assert_eq!(a, b);
+        assert!(equal_f64(a.at(0, 1), 2.0));
+        assert!(equal_f64(a.at(1, 0), 5.0));
+        assert_eq!(a.to_row_major(), rows.to_vec());
+        assert_eq!(a.to_column_major(), columns.to_vec());
+    }
+
+    #[test]
+    fn test_transforming_many_points_at_once() {
+        let m = Matrix::translation(1.0, 2.0, 3.0) * Matrix::rotation_y(PI / 2.0);
+        let points = vec![
+            Tuple::point(1.0, 0.0, 0.0),
+            Tuple::point(0.0, 1.0, 0.0),
+            Tuple::vector(0.0, 0.0, 1.0),
+        ];
+
+        let transformed = m.transform_points(&points);
+
+        assert_eq!(transformed.len(), 3);
+        for (p, t) in points.iter().zip(transformed.iter()) {
+            assert_eq!(t, &(m.clone() * p));
+        }
+        assert_eq!(transformed[0], Tuple::point(1.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn test_the_size_of_a_matrix() {
+        assert_eq!(Matrix::new(&[-3.0, 5.0, 1.0, -2.0]).size(), 2);
+        assert_eq!(Matrix::identify().size(), 4);
+        assert_eq!(Matrix::identify().submatrix(0, 0).size(), 3);
+    }
+
+    #[test]
+    fn test_matrices_of_different_sizes_are_not_equal() {
+        let a = Matrix::new(&[1.0, 0.0, 0.0, 1.0]);
+
+        assert_ne!(a, Matrix::identify());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_constructing_a_matrix_from_a_non_square_number_of_elements() {
+        Matrix::new(&[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multiplying_matrices_of_different_sizes() {
+        let _ = Matrix::new(&[1.0, 2.0, 3.0, 4.0]) * Matrix::identify();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multiplying_a_tuple_by_a_non_4x4_matrix() {
+        let _ = Matrix::new(&[1.0, 2.0, 3.0, 4.0]) * Tuple::point(1.0, 2.0, 3.0);
+    }
+
+    fn project(m: &Matrix, p: Tuple) -> Tuple {
+        let clip = m * &p;
+        Tuple::point(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w)
+    }
+
+    #[test]
+    fn test_a_perspective_projection_maps_the_frustum_to_the_unit_cube() {
+        let m = Matrix::perspective(PI / 2.0, 2.0, 1.0, 10.0);
+
+        assert_eq!(
+            project(&m, Tuple::point(0.0, 0.0, -1.0)),
+            Tuple::point(0.0, 0.0, -1.0)
+        );
+        assert_eq!(
+            project(&m, Tuple::point(0.0, 0.0, -10.0)),
+            Tuple::point(0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            project(&m, Tuple::point(2.0, 1.0, -1.0)),
+            Tuple::point(1.0, 1.0, -1.0)
+        );
+        assert_eq!(
+            project(&m, Tuple::point(-20.0, -10.0, -10.0)),
+            Tuple::point(-1.0, -1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_an_orthographic_projection_maps_the_box_to_the_unit_cube() {
+        let m = Matrix::orthographic(-2.0, 4.0, -1.0, 1.0, 1.0, 5.0);
+
+        assert_eq!(
+            m.clone() * Tuple::point(-2.0, -1.0, -1.0),
+            Tuple::point(-1.0, -1.0, -1.0)
+        );
+        assert_eq!(
+            m.clone() * Tuple::point(4.0, 1.0, -5.0),
+            Tuple::point(1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            m * Tuple::point(1.0, 0.0, -3.0),
+            Tuple::point(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_equal_matrices_hash_the_same() {
+        let a = Matrix::rotation_z(PI / 2.0);
+        let b = Matrix::rotation_x(PI / 2.0);
+
+        assert_eq!(
+            a.canonical_hash(),
+            Matrix::rotation_z(PI / 2.0).canonical_hash()
+        );
+        assert_ne!(a.canonical_hash(), b.canonical_hash());
+        assert_eq!(Matrix::identify().quantized()[0], 100000);
+    }
 }
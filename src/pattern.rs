@@ -1,8 +1,13 @@
-use crate::canvas::Color;
+use crate::canvas::{Canvas, Color, ColorSpace};
 use crate::matrix::Matrix;
 use crate::shapes::Shape;
 use crate::tuple::Tuple;
-use crate::utils::equal_f64;
+use crate::utils::{equal_f64, stable_hash};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::error::Error;
+use std::f64::consts::PI;
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -11,7 +16,7 @@ pub trait Pattern {
     fn pattern_at(&self, point: &Tuple) -> Color;
 
     fn at_object(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
-        let object_point = object.get_transform().inverse().unwrap() * point;
+        let object_point = object.world_to_object(point);
         let pattern_point = self.get_transform().inverse().unwrap() * object_point;
 
         self.pattern_at(&pattern_point)
@@ -24,27 +29,124 @@ impl Debug for dyn Pattern {
     }
 }
 
+impl Debug for dyn Pattern + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pattern transform: {{{:?}}}", self.get_transform())
+    }
+}
+
+/// What fills one of a pattern's two slots: a flat color, or another pattern seen
+/// through its own transform from the space of the pattern holding it, e.g. checkers
+/// alternating between stripes running two different ways.
+#[derive(Debug, Clone)]
+pub enum Paint {
+    Solid(Color),
+    Pattern(Arc<dyn Pattern + Send + Sync>),
+}
+
+impl Paint {
+    pub fn at(&self, point: &Tuple) -> Color {
+        match self {
+            Paint::Solid(color) => *color,
+            Paint::Pattern(pattern) => {
+                pattern.pattern_at(&(pattern.get_transform().inverse().unwrap() * point))
+            }
+        }
+    }
+}
+
+// Patterns can't be compared, so nested ones are equal only when they are the same one.
+impl PartialEq for Paint {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Paint::Solid(a), Paint::Solid(b)) => a == b,
+            (Paint::Pattern(a), Paint::Pattern(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq<Color> for Paint {
+    fn eq(&self, other: &Color) -> bool {
+        matches!(self, Paint::Solid(color) if color == other)
+    }
+}
+
+impl From<Color> for Paint {
+    fn from(color: Color) -> Self {
+        Paint::Solid(color)
+    }
+}
+
+/// The same color everywhere, so a plain material color can be used wherever a pattern
+/// is expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolidPattern {
+    color: Color,
+    transform: Matrix,
+}
+
+impl SolidPattern {
+    pub fn new(color: &Color) -> Self {
+        Self {
+            color: *color,
+            transform: Matrix::identify(),
+        }
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self {
+            transform: transform.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Pattern for SolidPattern {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn pattern_at(&self, _point: &Tuple) -> Color {
+        self.color
+    }
+
+    // Skips converting the point, as it makes no difference.
+    fn at_object(&self, _object: Arc<dyn Shape>, _point: &Tuple) -> Color {
+        self.color
+    }
+}
+
+impl From<Color> for SolidPattern {
+    fn from(color: Color) -> Self {
+        Self::new(&color)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct StripePattern {
-    a: Color,
-    b: Color,
+    a: Paint,
+    b: Paint,
     transform: Matrix,
 }
 
 impl StripePattern {
     pub fn new(color_a: &Color, color_b: &Color) -> Self {
+        Self::nested(Paint::Solid(*color_a), Paint::Solid(*color_b))
+    }
+
+    pub fn nested(a: Paint, b: Paint) -> Self {
         Self {
-            a: color_a.clone(),
-            b: color_b.clone(),
+            a,
+            b,
             transform: Matrix::identify(),
         }
     }
 
     pub fn with_transform(&self, transform: &Matrix) -> Self {
         Self {
-            a: self.a.clone(),
-            b: self.b.clone(),
             transform: transform.clone(),
+            ..self.clone()
         }
     }
 }
@@ -56,34 +158,79 @@ impl Pattern for StripePattern {
 
     fn pattern_at(&self, point: &Tuple) -> Color {
         if point.x.floor() % 2.0 == 0.0 {
-            self.a.clone()
+            self.a.at(point)
         } else {
-            self.b.clone()
+            self.b.at(point)
+        }
+    }
+}
+
+/// What a gradient or image does beyond the span it covers, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    /// Starts over, with a hard edge at each boundary.
+    Repeat,
+    /// Holds the values at the ends.
+    Clamp,
+    /// Runs back and forth, so there are no hard edges.
+    Mirror,
+}
+
+impl WrapMode {
+    /// Brings `t` into [0, 1].
+    pub fn apply(&self, t: f64) -> f64 {
+        match self {
+            WrapMode::Repeat => t - t.floor(),
+            WrapMode::Clamp => t.clamp(0.0, 1.0),
+            WrapMode::Mirror => 1.0 - (t.rem_euclid(2.0) - 1.0).abs(),
         }
     }
 }
 
+/// Blends from `a` at the origin to `b` at the tip of `direction`, which is one unit
+/// along x unless changed, so longer directions give wider gradients.
 #[derive(Debug, Clone, PartialEq)]
 pub struct GradientPattern {
-    a: Color,
-    b: Color,
+    a: Paint,
+    b: Paint,
+    direction: Tuple,
+    wrap: WrapMode,
     transform: Matrix,
 }
 
 impl GradientPattern {
     pub fn new(color_a: &Color, color_b: &Color) -> Self {
+        Self::nested(Paint::Solid(*color_a), Paint::Solid(*color_b))
+    }
+
+    pub fn nested(a: Paint, b: Paint) -> Self {
         Self {
-            a: color_a.clone(),
-            b: color_b.clone(),
+            a,
+            b,
+            direction: Tuple::vector(1.0, 0.0, 0.0),
+            wrap: WrapMode::Repeat,
             transform: Matrix::identify(),
         }
     }
 
+    pub fn with_direction(&self, direction: &Tuple) -> Self {
+        Self {
+            direction: Tuple::vector(direction.x, direction.y, direction.z),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_wrap(&self, wrap: WrapMode) -> Self {
+        Self {
+            wrap,
+            ..self.clone()
+        }
+    }
+
     pub fn with_transform(&self, transform: &Matrix) -> Self {
         Self {
-            a: self.a.clone(),
-            b: self.b.clone(),
             transform: transform.clone(),
+            ..self.clone()
         }
     }
 }
@@ -94,34 +241,40 @@ impl Pattern for GradientPattern {
     }
 
     fn pattern_at(&self, point: &Tuple) -> Color {
-        let distance = self.b - self.a;
-        let fraction = point.x - point.x.floor();
+        let d = &self.direction;
+        let along = (point.x * d.x + point.y * d.y + point.z * d.z) / d.dot(d);
+        let fraction = self.wrap.apply(along);
 
-        self.a + distance * fraction
+        let a = self.a.at(point);
+        let distance = self.b.at(point) - a;
+        a + distance * fraction
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RingPattern {
-    a: Color,
-    b: Color,
+    a: Paint,
+    b: Paint,
     transform: Matrix,
 }
 
 impl RingPattern {
     pub fn new(color_a: &Color, color_b: &Color) -> Self {
+        Self::nested(Paint::Solid(*color_a), Paint::Solid(*color_b))
+    }
+
+    pub fn nested(a: Paint, b: Paint) -> Self {
         Self {
-            a: color_a.clone(),
-            b: color_b.clone(),
+            a,
+            b,
             transform: Matrix::identify(),
         }
     }
 
     pub fn with_transform(&self, transform: &Matrix) -> Self {
         Self {
-            a: self.a.clone(),
-            b: self.b.clone(),
             transform: transform.clone(),
+            ..self.clone()
         }
     }
 }
@@ -136,34 +289,37 @@ impl Pattern for RingPattern {
             (point.x * point.x + point.z * point.z).sqrt().floor() % 2.0,
             0.0,
         ) {
-            self.a.clone()
+            self.a.at(point)
         } else {
-            self.b.clone()
+            self.b.at(point)
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct CheckersPattern {
-    a: Color,
-    b: Color,
+    a: Paint,
+    b: Paint,
     transform: Matrix,
 }
 
 impl CheckersPattern {
     pub fn new(color_a: &Color, color_b: &Color) -> Self {
+        Self::nested(Paint::Solid(*color_a), Paint::Solid(*color_b))
+    }
+
+    pub fn nested(a: Paint, b: Paint) -> Self {
         Self {
-            a: color_a.clone(),
-            b: color_b.clone(),
+            a,
+            b,
             transform: Matrix::identify(),
         }
     }
 
     pub fn with_transform(&self, transform: &Matrix) -> Self {
         Self {
-            a: self.a.clone(),
-            b: self.b.clone(),
             transform: transform.clone(),
+            ..self.clone()
         }
     }
 }
@@ -178,19 +334,583 @@ impl Pattern for CheckersPattern {
             (point.x.floor() + point.y.floor() + point.z.floor()) % 2.0,
             0.0,
         ) {
-            self.a.clone()
+            self.a.at(point)
+        } else {
+            self.b.at(point)
+        }
+    }
+}
+
+/// Another pattern looked up at points jittered by Perlin noise, so that stripes and
+/// rings waver like wood grain or marble. `scale` is the furthest a point can move and
+/// `frequency` how many times per unit the noise changes direction.
+#[derive(Debug, Clone)]
+pub struct PerturbedPattern {
+    pattern: Arc<dyn Pattern + Send + Sync>,
+    pub scale: f64,
+    pub frequency: f64,
+    noise: Noise,
+    transform: Matrix,
+}
+
+impl PerturbedPattern {
+    pub fn new(pattern: Arc<dyn Pattern + Send + Sync>, scale: f64) -> Self {
+        Self {
+            pattern,
+            scale,
+            frequency: 1.0,
+            noise: Noise::new(0),
+            transform: Matrix::identify(),
+        }
+    }
+
+    pub fn with_frequency(&self, frequency: f64) -> Self {
+        Self {
+            frequency,
+            ..self.clone()
+        }
+    }
+
+    // Different seeds give differently shaped distortions.
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self {
+            noise: Noise::new(seed),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self {
+            transform: transform.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Pattern for PerturbedPattern {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn pattern_at(&self, point: &Tuple) -> Color {
+        let p = point * self.frequency;
+        // Offsetting the lookups gives three independent-looking noise values.
+        let jitter = Tuple::vector(
+            self.noise.at(p.x, p.y, p.z),
+            self.noise.at(p.x + 31.4, p.y + 27.1, p.z + 82.8),
+            self.noise.at(p.x + 57.7, p.y + 15.6, p.z + 64.9),
+        );
+        let moved = point + &(&jitter * self.scale);
+
+        self.pattern
+            .pattern_at(&(self.pattern.get_transform().inverse().unwrap() * &moved))
+    }
+}
+
+/// Cellular (Voronoi) noise: one feature point scattered at random in each unit cube,
+/// colored by the distance to the nearest of them, from `a` at a feature point to `b`
+/// a unit or more away. Suits cells, stones and cracked earth. Different seeds scatter
+/// the points differently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoronoiPattern {
+    a: Paint,
+    b: Paint,
+    pub seed: u64,
+    transform: Matrix,
+}
+
+impl VoronoiPattern {
+    pub fn new(color_a: &Color, color_b: &Color) -> Self {
+        Self::nested(Paint::Solid(*color_a), Paint::Solid(*color_b))
+    }
+
+    pub fn nested(a: Paint, b: Paint) -> Self {
+        Self {
+            a,
+            b,
+            seed: 0,
+            transform: Matrix::identify(),
+        }
+    }
+
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self {
+            seed,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self {
+            transform: transform.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// The feature point in the unit cube with its least corner at `cell`.
+    pub fn feature_point(&self, cell: (i64, i64, i64)) -> Tuple {
+        let (x, y, z) = cell;
+        let offset = |axis: i64| {
+            let hash = stable_hash(&[x, y, z, self.seed as i64, axis]);
+            (hash >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        Tuple::point(
+            x as f64 + offset(0),
+            y as f64 + offset(1),
+            z as f64 + offset(2),
+        )
+    }
+
+    // The distance to the nearest feature point, searching the cell holding `point`
+    // and its neighbours, which is where it almost always is.
+    fn nearest(&self, point: &Tuple) -> f64 {
+        let (cx, cy, cz) = (
+            point.x.floor() as i64,
+            point.y.floor() as i64,
+            point.z.floor() as i64,
+        );
+        let mut nearest = f64::INFINITY;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let feature = self.feature_point((cx + dx, cy + dy, cz + dz));
+                    nearest = nearest.min((&feature - point).magnitude());
+                }
+            }
+        }
+
+        nearest
+    }
+}
+
+impl Pattern for VoronoiPattern {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn pattern_at(&self, point: &Tuple) -> Color {
+        let fraction = self.nearest(point).min(1.0);
+        let a = self.a.at(point);
+        let distance = self.b.at(point) - a;
+
+        a + distance * fraction
+    }
+}
+
+// Ken Perlin's improved gradient noise, in [-1, 1] and zero at whole numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Noise {
+    permutation: Vec<usize>,
+}
+
+impl Noise {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut permutation: Vec<usize> = (0..256).collect();
+        permutation.shuffle(&mut rng);
+        permutation.extend_from_within(..);
+
+        Self { permutation }
+    }
+
+    pub(crate) fn at(&self, x: f64, y: f64, z: f64) -> f64 {
+        let p = &self.permutation;
+        let cell = |v: f64| (v.floor() as i64).rem_euclid(256) as usize;
+        let (xi, yi, zi) = (cell(x), cell(y), cell(z));
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] + yi;
+        let (aa, ab) = (p[a] + zi, p[a + 1] + zi);
+        let b = p[xi + 1] + yi;
+        let (ba, bb) = (p[b] + zi, p[b + 1] + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(
+                    u,
+                    grad(p[ab], x, y - 1.0, z),
+                    grad(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad(p[aa + 1], x, y, z - 1.0),
+                    grad(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad(p[ab + 1], x, y - 1.0, z - 1.0),
+                    grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+// The dot product of the offset with one of twelve gradients picked by `hash`.
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Where a point lands on a flat texture, as u and v in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UvMapping {
+    // Whatever suits the shape being textured, from `Shape::uv_at`.
+    Shape,
+    Spherical,
+    Planar,
+    Cylindrical,
+    Cubic,
+}
+
+impl UvMapping {
+    // Without a shape to ask, `Shape` falls back to the spherical mapping.
+    pub fn map(&self, point: &Tuple) -> (f64, f64) {
+        match self {
+            UvMapping::Shape | UvMapping::Spherical => spherical_map(point),
+            // Left for the pattern to wrap, as an image may clamp or mirror rather
+            // than repeat beyond the unit square.
+            UvMapping::Planar => (point.x, point.z),
+            UvMapping::Cylindrical => cylindrical_map(point),
+            UvMapping::Cubic => cubic_map(point),
+        }
+    }
+}
+
+/// Longitude and latitude, with u growing anticlockwise seen from above and v from the
+/// south pole up.
+pub fn spherical_map(point: &Tuple) -> (f64, f64) {
+    let radius = Tuple::vector(point.x, point.y, point.z).magnitude();
+    if radius == 0.0 {
+        return (0.5, 0.5);
+    }
+    let phi = (point.y / radius).clamp(-1.0, 1.0).acos();
+
+    (around_y(point), 1.0 - phi / PI)
+}
+
+/// The xz plane, repeating every unit.
+pub fn planar_map(point: &Tuple) -> (f64, f64) {
+    (point.x.rem_euclid(1.0), point.z.rem_euclid(1.0))
+}
+
+/// Once around the y axis, repeating every unit up it.
+pub fn cylindrical_map(point: &Tuple) -> (f64, f64) {
+    (around_y(point), point.y.rem_euclid(1.0))
+}
+
+/// The faces of the cube from -1 to 1 unfolded into a cross four faces wide and three
+/// high: -x, -z, +x and +z run around the middle row, with +y above -z and -y below
+/// it. Points off the cube take the face in their direction from the origin.
+pub fn cubic_map(point: &Tuple) -> (f64, f64) {
+    let scale = point.x.abs().max(point.y.abs()).max(point.z.abs());
+    if scale == 0.0 {
+        return (0.5, 0.5);
+    }
+    let (x, y, z) = (point.x / scale, point.y / scale, point.z / scale);
+    let half = |value: f64| ((value + 1.0) / 2.0).clamp(0.0, 1.0);
+    let ((column, row), (u, v)) = if x.abs() == 1.0 {
+        if x > 0.0 {
+            ((2.0, 1.0), (half(z), half(y)))
+        } else {
+            ((0.0, 1.0), (half(-z), half(y)))
+        }
+    } else if y.abs() == 1.0 {
+        if y > 0.0 {
+            ((1.0, 2.0), (half(x), half(z)))
         } else {
-            self.b.clone()
+            ((1.0, 0.0), (half(x), half(-z)))
+        }
+    } else if z > 0.0 {
+        ((3.0, 1.0), (half(-x), half(y)))
+    } else {
+        ((1.0, 1.0), (half(x), half(y)))
+    };
+
+    ((column + u) / 4.0, (row + v) / 3.0)
+}
+
+fn around_y(point: &Tuple) -> f64 {
+    let theta = point.x.atan2(point.z);
+    (1.0 - (theta / (2.0 * PI) + 0.5)).clamp(0.0, 1.0)
+}
+
+/// A flat pattern laid over texture coordinates rather than space.
+pub trait UvPattern: Debug {
+    fn uv_pattern_at(&self, u: f64, v: f64) -> Color;
+}
+
+/// `width` by `height` squares alternating between two colors, repeating beyond the
+/// unit square.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UvCheckers {
+    pub width: f64,
+    pub height: f64,
+    a: Color,
+    b: Color,
+}
+
+impl UvCheckers {
+    pub fn new(width: f64, height: f64, color_a: &Color, color_b: &Color) -> Self {
+        Self {
+            width,
+            height,
+            a: *color_a,
+            b: *color_b,
         }
     }
 }
 
+impl UvPattern for UvCheckers {
+    fn uv_pattern_at(&self, u: f64, v: f64) -> Color {
+        let repeat = |t: f64| {
+            if (0.0..=1.0).contains(&t) {
+                t
+            } else {
+                WrapMode::Repeat.apply(t)
+            }
+        };
+        let squares = (repeat(u) * self.width).floor() + (repeat(v) * self.height).floor();
+        if squares.rem_euclid(2.0) == 0.0 {
+            self.a
+        } else {
+            self.b
+        }
+    }
+}
+
+/// An image stretched over the texture, with v = 1 along its top row, and tiled or
+/// stretched beyond it by the wrap modes for u and v, which clamp unless changed.
+/// Pixels are picked nearest first, without filtering.
+#[derive(Debug, Clone)]
+pub struct UvImage {
+    pub image: Arc<Canvas>,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl UvImage {
+    pub fn new(image: Arc<Canvas>) -> Self {
+        Self {
+            image,
+            wrap_u: WrapMode::Clamp,
+            wrap_v: WrapMode::Clamp,
+        }
+    }
+
+    /// Reads the image from a PPM file, encoded in `space`.
+    pub fn load(path: &str, space: ColorSpace) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Arc::new(Canvas::load(path, space)?)))
+    }
+
+    pub fn with_wrap(&self, wrap_u: WrapMode, wrap_v: WrapMode) -> Self {
+        Self {
+            wrap_u,
+            wrap_v,
+            ..self.clone()
+        }
+    }
+}
+
+impl UvPattern for UvImage {
+    fn uv_pattern_at(&self, u: f64, v: f64) -> Color {
+        let (width, height) = (self.image.width, self.image.height);
+        if width == 0 || height == 0 {
+            return Color::black();
+        }
+        let u = self.wrap_u.apply(u);
+        let v = self.wrap_v.apply(v);
+        let x = ((u * width as f64) as usize).min(width - 1);
+        let y = (((1.0 - v) * height as f64) as usize).min(height - 1);
+
+        *self.image.pixel_at(x, y)
+    }
+}
+
+/// An image laid on the xz plane over the square from the origin to (1, 0, 1), with its
+/// top row along z = 1, and tiled beyond it unless other wrap modes are given. Scale it
+/// with the transform, e.g. to the size of one brick pattern. The same as a
+/// `TextureMapPattern` of a `UvImage` with the `Planar` mapping.
+#[derive(Debug, Clone)]
+pub struct ImagePattern {
+    image: UvImage,
+    texture: TextureMapPattern,
+}
+
+impl ImagePattern {
+    pub fn new(image: Arc<Canvas>) -> Self {
+        Self::of(
+            UvImage::new(image).with_wrap(WrapMode::Repeat, WrapMode::Repeat),
+            &Matrix::identify(),
+        )
+    }
+
+    /// Reads the image from a PPM file, encoded in `space`.
+    pub fn load(path: &str, space: ColorSpace) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Arc::new(Canvas::load(path, space)?)))
+    }
+
+    fn of(image: UvImage, transform: &Matrix) -> Self {
+        Self {
+            texture: TextureMapPattern::new(Arc::new(image.clone()), UvMapping::Planar)
+                .with_transform(transform),
+            image,
+        }
+    }
+
+    pub fn with_wrap(&self, wrap_u: WrapMode, wrap_v: WrapMode) -> Self {
+        Self::of(
+            self.image.with_wrap(wrap_u, wrap_v),
+            &self.texture.get_transform(),
+        )
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self::of(self.image.clone(), transform)
+    }
+}
+
+impl Pattern for ImagePattern {
+    fn get_transform(&self) -> Matrix {
+        self.texture.get_transform()
+    }
+
+    fn pattern_at(&self, point: &Tuple) -> Color {
+        self.texture.pattern_at(point)
+    }
+
+    fn at_object(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+        self.texture.at_object(object, point)
+    }
+}
+
+/// A flat pattern wrapped onto shapes through a `UvMapping`, as a map of the earth is
+/// wrapped onto a globe.
+#[derive(Debug, Clone)]
+pub struct TextureMapPattern {
+    pub mapping: UvMapping,
+    uv_pattern: Arc<dyn UvPattern + Send + Sync>,
+    transform: Matrix,
+}
+
+impl TextureMapPattern {
+    pub fn new(uv_pattern: Arc<dyn UvPattern + Send + Sync>, mapping: UvMapping) -> Self {
+        Self {
+            mapping,
+            uv_pattern,
+            transform: Matrix::identify(),
+        }
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self {
+            transform: transform.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Pattern for TextureMapPattern {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn pattern_at(&self, point: &Tuple) -> Color {
+        let (u, v) = self.mapping.map(point);
+        self.uv_pattern.uv_pattern_at(u, v)
+    }
+
+    fn at_object(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+        let object_point = object.world_to_object(point);
+        let pattern_point = self.transform.inverse().unwrap() * object_point;
+        let (u, v) = match self.mapping {
+            UvMapping::Shape => object.uv_at(&pattern_point),
+            mapping => mapping.map(&pattern_point),
+        };
+
+        self.uv_pattern.uv_pattern_at(u, v)
+    }
+}
+
+/// An image wrapped onto shapes through their own texture coordinates, or around a
+/// sphere when there is no shape. The same as a `TextureMapPattern` of a `UvImage`
+/// with the `Shape` mapping.
+#[derive(Debug, Clone)]
+pub struct TexturePattern {
+    texture: TextureMapPattern,
+}
+
+impl TexturePattern {
+    pub fn new(image: Arc<Canvas>) -> Self {
+        Self {
+            texture: TextureMapPattern::new(Arc::new(UvImage::new(image)), UvMapping::Shape),
+        }
+    }
+
+    pub fn with_transform(&self, transform: &Matrix) -> Self {
+        Self {
+            texture: self.texture.with_transform(transform),
+        }
+    }
+}
+
+impl Pattern for TexturePattern {
+    fn get_transform(&self) -> Matrix {
+        self.texture.get_transform()
+    }
+
+    fn pattern_at(&self, point: &Tuple) -> Color {
+        self.texture.pattern_at(point)
+    }
+
+    fn at_object(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+        self.texture.at_object(object, point)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{matrix::Matrix, shapes::Sphere};
+    use crate::{
+        matrix::Matrix,
+        shapes::{Cube, Plane, Sphere},
+    };
 
     use super::*;
 
+    #[test]
+    fn test_a_solid_pattern_is_the_same_everywhere() {
+        let pattern = SolidPattern::from(Color::new(0.2, 0.4, 0.6))
+            .with_transform(&Matrix::scaling(2.0, 2.0, 2.0));
+        let object = Arc::new(Sphere::new().with_transform(&Matrix::translation(1.0, 0.0, 0.0)));
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(3.5, -1.0, 0.2)),
+            Color::new(0.2, 0.4, 0.6)
+        );
+        assert_eq!(
+            pattern.at_object(object, &Tuple::point(0.0, 0.5, 7.0)),
+            Color::new(0.2, 0.4, 0.6)
+        );
+    }
+
     #[test]
     fn test_creating_a_stripe_pattern() {
         let pattern = StripePattern::new(&Color::white(), &Color::black());
@@ -319,6 +1039,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_a_gradient_can_run_along_any_direction() {
+        let pattern = GradientPattern::new(&Color::white(), &Color::black())
+            .with_direction(&Tuple::vector(0.0, 2.0, 0.0));
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(5.0, 0.5, -3.0)),
+            Color::new(0.75, 0.75, 0.75)
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.0, 3.0, 0.0)),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn test_gradient_wrap_modes() {
+        let gradient = GradientPattern::new(&Color::white(), &Color::black());
+        let repeat = gradient.clone();
+        let clamp = gradient.with_wrap(WrapMode::Clamp);
+        let mirror = gradient.with_wrap(WrapMode::Mirror);
+        let at = |pattern: &GradientPattern, x| pattern.pattern_at(&Tuple::point(x, 0.0, 0.0));
+        let grey = |v| Color::new(v, v, v);
+
+        assert_eq!(at(&repeat, 1.25), grey(0.75));
+        assert_eq!(at(&repeat, -0.25), grey(0.25));
+        assert_eq!(at(&clamp, 1.25), Color::black());
+        assert_eq!(at(&clamp, -0.25), Color::white());
+        assert_eq!(at(&clamp, 0.25), grey(0.75));
+        assert_eq!(at(&mirror, 1.25), grey(0.25));
+        assert_eq!(at(&mirror, -0.25), grey(0.75));
+        assert_eq!(at(&mirror, 2.25), grey(0.75));
+    }
+
     #[test]
     fn test_a_ring_should_extend_in_both_x_and_z() {
         let pattern = RingPattern::new(&Color::white(), &Color::black());
@@ -394,4 +1148,334 @@ mod tests {
             Color::black()
         );
     }
+
+    #[test]
+    fn test_a_texture_pattern_samples_its_image() {
+        let mut image = Canvas::new(4, 3);
+        image.write_pixel(0, 0, &Color::new(1.0, 0.0, 0.0));
+        image.write_pixel(1, 1, &Color::new(0.0, 1.0, 0.0));
+        image.write_pixel(2, 2, &Color::new(0.0, 0.0, 1.0));
+        let pattern = TexturePattern::new(Arc::new(image));
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.0, 0.0, -1.0)),
+            Color::black()
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(1.0, 0.0, 0.0)),
+            Color::new(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.0, -2.0, 0.0)),
+            Color::new(0.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_an_image_pattern_tiles_by_its_wrap_modes() {
+        let mut image = Canvas::new(2, 1);
+        image.write_pixel(0, 0, &Color::white());
+        let image = Arc::new(image);
+        let pattern = ImagePattern::new(image.clone());
+        let at = |pattern: &ImagePattern, x| pattern.pattern_at(&Tuple::point(x, 0.0, 0.5));
+
+        assert_eq!(at(&pattern, 0.25), Color::white());
+        assert_eq!(at(&pattern, 0.75), Color::black());
+        assert_eq!(at(&pattern, 3.25), Color::white());
+        assert_eq!(at(&pattern, -0.25), Color::black());
+
+        let clamped = pattern.with_wrap(WrapMode::Clamp, WrapMode::Clamp);
+        assert_eq!(at(&clamped, 3.25), Color::black());
+        assert_eq!(at(&clamped, -5.0), Color::white());
+
+        let mirrored = pattern.with_wrap(WrapMode::Mirror, WrapMode::Repeat);
+        assert_eq!(at(&mirrored, 1.25), Color::black());
+        assert_eq!(at(&mirrored, 1.75), Color::white());
+
+        let texture = TextureMapPattern::new(
+            Arc::new(UvImage::new(image).with_wrap(WrapMode::Mirror, WrapMode::Repeat)),
+            UvMapping::Planar,
+        );
+        for x in &[-0.25, 0.25, 1.25, 1.75] {
+            assert_eq!(
+                texture.pattern_at(&Tuple::point(*x, 0.0, 0.5)),
+                at(&mirrored, *x)
+            );
+        }
+    }
+
+    #[test]
+    fn test_an_image_pattern_loads_a_ppm_file() {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "ray-tracer-image-pattern-{}.ppm",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+        std::fs::write(&path, "P3\n1 2\n255\n255 0 0\n0 0 255\n").unwrap();
+
+        let pattern = ImagePattern::load(&path, ColorSpace::Linear).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.5, 0.0, 0.75)),
+            Color::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.5, 0.0, 1.25)),
+            Color::new(0.0, 0.0, 1.0)
+        );
+        assert!(ImagePattern::load("missing.ppm", ColorSpace::Linear).is_err());
+    }
+
+    #[test]
+    fn test_a_texture_pattern_on_a_transformed_sphere() {
+        let mut image = Canvas::new(2, 2);
+        image.write_pixel(0, 0, &Color::white());
+        image.write_pixel(1, 0, &Color::white());
+        let object: Arc<dyn Shape> =
+            Arc::new(Sphere::new().with_transform(&Matrix::scaling(3.0, 3.0, 3.0)));
+        let pattern = TexturePattern::new(Arc::new(image));
+
+        let north = pattern.at_object(object.clone(), &Tuple::point(0.0, 2.9, 0.5));
+        let south = pattern.at_object(object, &Tuple::point(0.0, -2.9, 0.5));
+
+        assert_eq!(north, Color::white());
+        assert_eq!(south, Color::black());
+    }
+
+    #[test]
+    fn test_a_texture_pattern_follows_the_shapes_own_mapping() {
+        let mut image = Canvas::new(2, 2);
+        image.write_pixel(1, 1, &Color::white());
+        let object: Arc<dyn Shape> = Arc::new(Plane::new());
+        let pattern = TexturePattern::new(Arc::new(image));
+
+        assert_eq!(
+            pattern.at_object(object.clone(), &Tuple::point(0.75, 0.0, 0.25)),
+            Color::white()
+        );
+        assert_eq!(
+            pattern.at_object(object, &Tuple::point(0.25, 0.0, 0.25)),
+            Color::black()
+        );
+    }
+
+    #[test]
+    fn test_planar_and_cylindrical_mapping() {
+        let planar = [
+            (Tuple::point(0.25, 0.5, -0.25), (0.25, 0.75)),
+            (Tuple::point(-0.25, 0.0, -1.75), (0.75, 0.25)),
+        ];
+        for (point, (u, v)) in planar.iter() {
+            let (pu, pv) = planar_map(point);
+            assert!(equal_f64(pu, *u) && equal_f64(pv, *v));
+        }
+
+        let cylindrical = [
+            (Tuple::point(0.0, 0.0, -1.0), (0.0, 0.0)),
+            (Tuple::point(0.0, 0.5, -1.0), (0.0, 0.5)),
+            (Tuple::point(1.0, 1.25, 0.0), (0.25, 0.25)),
+            (Tuple::point(-1.0, -0.25, 0.0), (0.75, 0.75)),
+            (Tuple::point(0.0, 3.5, 1.0), (0.5, 0.5)),
+        ];
+        for (point, (u, v)) in cylindrical.iter() {
+            let (cu, cv) = cylindrical_map(point);
+            assert!(equal_f64(cu, *u) && equal_f64(cv, *v));
+        }
+    }
+
+    #[test]
+    fn test_cubic_mapping_matches_a_cube_and_extends_beyond_it() {
+        let cube = Cube::new();
+        for point in [
+            Tuple::point(0.3, -0.7, -1.0),
+            Tuple::point(1.0, 0.2, 0.9),
+            Tuple::point(-0.4, 1.0, 0.1),
+        ]
+        .iter()
+        {
+            assert_eq!(cubic_map(point), cube.uv_at(point));
+        }
+
+        assert_eq!(
+            cubic_map(&Tuple::point(0.6, -1.0, 1.6)),
+            cubic_map(&Tuple::point(0.375, -0.625, 1.0))
+        );
+    }
+
+    #[test]
+    fn test_checkers_in_uv_space() {
+        let checkers = UvCheckers::new(2.0, 2.0, &Color::black(), &Color::white());
+        let cases = [
+            ((0.0, 0.0), Color::black()),
+            ((0.5, 0.0), Color::white()),
+            ((0.0, 0.5), Color::white()),
+            ((0.5, 0.5), Color::black()),
+            ((1.0, 1.0), Color::black()),
+        ];
+
+        for ((u, v), expected) in cases.iter() {
+            assert_eq!(checkers.uv_pattern_at(*u, *v), *expected);
+        }
+    }
+
+    #[test]
+    fn test_a_texture_map_pattern_with_a_spherical_map() {
+        let checkers = UvCheckers::new(16.0, 8.0, &Color::black(), &Color::white());
+        let pattern = TextureMapPattern::new(Arc::new(checkers), UvMapping::Spherical);
+        let cases = [
+            (Tuple::point(0.4315, 0.4670, 0.7719), Color::white()),
+            (Tuple::point(-0.9654, 0.2552, -0.0534), Color::black()),
+            (Tuple::point(0.1039, 0.7090, 0.6975), Color::white()),
+            (Tuple::point(-0.4986, -0.7856, -0.3663), Color::black()),
+            (Tuple::point(-0.0317, -0.9395, 0.3411), Color::black()),
+            (Tuple::point(0.4809, -0.7721, 0.4154), Color::black()),
+            (Tuple::point(0.0285, -0.9612, -0.2745), Color::black()),
+            (Tuple::point(-0.5734, -0.2162, -0.7903), Color::white()),
+            (Tuple::point(0.7688, -0.1470, 0.6223), Color::black()),
+            (Tuple::point(-0.7652, 0.2175, 0.6060), Color::black()),
+        ];
+
+        for (point, expected) in cases.iter() {
+            assert_eq!(pattern.pattern_at(point), *expected);
+        }
+    }
+
+    #[test]
+    fn test_a_texture_map_pattern_can_override_the_shapes_mapping() {
+        let checkers = UvCheckers::new(2.0, 2.0, &Color::black(), &Color::white());
+        let object: Arc<dyn Shape> = Arc::new(Plane::new());
+        let own = TextureMapPattern::new(Arc::new(checkers.clone()), UvMapping::Shape);
+        let cylindrical = TextureMapPattern::new(Arc::new(checkers), UvMapping::Cylindrical);
+        let point = Tuple::point(0.75, 0.0, 0.25);
+
+        assert_eq!(own.at_object(object.clone(), &point), Color::white());
+        assert_eq!(cylindrical.at_object(object, &point), Color::black());
+    }
+
+    #[test]
+    fn test_perlin_noise_is_bounded_and_zero_on_the_lattice() {
+        let noise = Noise::new(0);
+
+        assert_eq!(noise.at(3.0, -2.0, 7.0), 0.0);
+        let values: Vec<f64> = (0..1000)
+            .map(|i| {
+                let t = i as f64 * 0.137;
+                noise.at(t, t * 0.7 + 0.3, -t * 1.3)
+            })
+            .collect();
+        assert!(values.iter().all(|v| v.abs() <= 1.0));
+        assert!(values.iter().any(|v| v.abs() > 0.2));
+        assert_eq!(noise.at(0.5, 0.25, 0.75), Noise::new(0).at(0.5, 0.25, 0.75));
+    }
+
+    #[test]
+    fn test_a_perturbed_pattern_wavers_the_pattern_it_wraps() {
+        let stripes = Arc::new(StripePattern::new(&Color::white(), &Color::black()));
+        let still = PerturbedPattern::new(stripes.clone(), 0.0);
+        let wavy = PerturbedPattern::new(stripes.clone(), 0.5).with_frequency(2.0);
+
+        let points: Vec<Tuple> = (0..200)
+            .map(|i| Tuple::point(0.9 + i as f64 * 0.001, i as f64 * 0.05, 0.3))
+            .collect();
+        let unchanged = |p: &PerturbedPattern| {
+            points
+                .iter()
+                .filter(|point| p.pattern_at(point) == stripes.pattern_at(point))
+                .count()
+        };
+
+        assert_eq!(unchanged(&still), points.len());
+        assert!(unchanged(&wavy) < points.len());
+        assert_eq!(
+            wavy.pattern_at(&Tuple::point(0.0, 0.0, 0.0)),
+            Color::white()
+        );
+    }
+
+    #[test]
+    fn test_checkers_can_alternate_between_two_patterns() {
+        let across = StripePattern::new(&Color::white(), &Color::black())
+            .with_transform(&Matrix::scaling(0.25, 1.0, 1.0));
+        let along = StripePattern::new(&Color::new(1.0, 0.0, 0.0), &Color::new(0.0, 0.0, 1.0))
+            .with_transform(&(Matrix::rotation_y(PI / 2.0) * Matrix::scaling(0.25, 1.0, 1.0)));
+        let pattern = CheckersPattern::nested(
+            Paint::Pattern(Arc::new(across)),
+            Paint::Pattern(Arc::new(along)),
+        );
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.1, 0.0, 0.1)),
+            Color::white()
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.3, 0.0, 0.1)),
+            Color::black()
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(1.1, 0.0, 0.1)),
+            Color::new(0.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(1.1, 0.0, 0.3)),
+            Color::new(1.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_a_gradient_blends_between_nested_patterns() {
+        let stripes = Arc::new(StripePattern::new(&Color::white(), &Color::black()));
+        let pattern = GradientPattern::nested(
+            Paint::Pattern(stripes.clone()),
+            Paint::Solid(Color::new(0.0, 1.0, 0.0)),
+        );
+
+        assert_eq!(
+            pattern.pattern_at(&Tuple::point(0.5, 0.0, 0.0)),
+            Color::new(0.5, 1.0, 0.5)
+        );
+        assert_eq!(
+            pattern,
+            GradientPattern::nested(Paint::Pattern(stripes), Color::new(0.0, 1.0, 0.0).into())
+        );
+        assert_ne!(
+            pattern,
+            GradientPattern::nested(
+                Paint::Pattern(Arc::new(StripePattern::new(
+                    &Color::white(),
+                    &Color::black()
+                ))),
+                Color::new(0.0, 1.0, 0.0).into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_a_voronoi_pattern_shades_by_distance_to_the_nearest_feature() {
+        let pattern = VoronoiPattern::new(&Color::white(), &Color::black()).with_seed(3);
+        let feature = pattern.feature_point((2, -1, 0));
+
+        assert_eq!(pattern.pattern_at(&feature), Color::white());
+        let near = &feature + &Tuple::vector(0.01, 0.0, 0.0);
+        assert!(pattern.pattern_at(&near).red > 0.98);
+
+        let shades: Vec<f64> = (0..200)
+            .map(|i| {
+                pattern
+                    .pattern_at(&Tuple::point(i as f64 * 0.07, 0.3, -1.6))
+                    .red
+            })
+            .collect();
+        assert!(shades.iter().all(|s| (0.0..=1.0).contains(s)));
+        assert!(shades.iter().any(|s| *s < 0.5) && shades.iter().any(|s| *s > 0.8));
+        assert_ne!(pattern.with_seed(4).feature_point((2, -1, 0)), feature);
+        assert_eq!(
+            VoronoiPattern::new(&Color::white(), &Color::black())
+                .with_seed(3)
+                .feature_point((2, -1, 0)),
+            feature
+        );
+    }
 }
@@ -0,0 +1,209 @@
+use crate::bounds::BoundingBox;
+use crate::canvas::Color;
+use crate::light::{Material, PointLight};
+use crate::matrix::Matrix;
+use crate::shapes::{Cube, Group, Shape, Sphere};
+use crate::tuple::Tuple;
+use crate::world::World;
+use std::error::Error;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotStyle {
+    /// A box per row standing on the xz plane, `y` high.
+    Bars,
+    /// A sphere per row centred on `(x, y, z)`.
+    Scatter,
+}
+
+/// Turns rows of `x,y,z` values into a world, colouring each mark by its `y` value
+/// along a ramp from `low_color` to `high_color`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plot {
+    pub style: PlotStyle,
+    pub size: f64,
+    pub low_color: Color,
+    pub high_color: Color,
+}
+
+impl Plot {
+    pub fn new(style: PlotStyle) -> Self {
+        Self {
+            style,
+            size: 0.8,
+            low_color: Color::new(0.1, 0.2, 0.8),
+            high_color: Color::new(0.9, 0.2, 0.1),
+        }
+    }
+
+    pub fn with_size(self, size: f64) -> Self {
+        Self { size, ..self }
+    }
+
+    pub fn with_colors(self, low_color: &Color, high_color: &Color) -> Self {
+        Self {
+            low_color: *low_color,
+            high_color: *high_color,
+            ..self
+        }
+    }
+
+    /// Reads comma separated `x,y,z` rows. Blank lines, lines starting with `#` and a
+    /// non-numeric header row are skipped.
+    pub fn parse_csv(input: &str) -> Result<Vec<Tuple>, Box<dyn Error>> {
+        let mut points = vec![];
+
+        for (number, line) in input.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+            if fields.len() != 3 {
+                return Err(format!(
+                    "line {}: expected 3 columns but found {}",
+                    number + 1,
+                    fields.len()
+                )
+                .into());
+            }
+
+            let values: Result<Vec<f64>, _> = fields.iter().map(|f| f.parse::<f64>()).collect();
+            match values {
+                Ok(v) => points.push(Tuple::point(v[0], v[1], v[2])),
+                Err(_) if points.is_empty() => continue,
+                Err(e) => return Err(format!("line {}: {}", number + 1, e).into()),
+            }
+        }
+
+        Ok(points)
+    }
+
+    pub fn from_csv(&self, input: &str) -> Result<World, Box<dyn Error>> {
+        Ok(self.to_world(&Self::parse_csv(input)?))
+    }
+
+    /// Bars with a zero height have nothing to draw and are left out.
+    pub fn to_world(&self, points: &[Tuple]) -> World {
+        let mut world = World::new();
+        if points.is_empty() {
+            return world;
+        }
+
+        let low = points.iter().map(|p| p.y).fold(f64::INFINITY, f64::min);
+        let high = points.iter().map(|p| p.y).fold(f64::NEG_INFINITY, f64::max);
+
+        let mut group = Group::new();
+        for point in points {
+            let t = if high > low {
+                (point.y - low) / (high - low)
+            } else {
+                0.5
+            };
+            let mut material = Material::new();
+            material.color = self.low_color + (self.high_color - self.low_color) * t;
+
+            let half = self.size / 2.0;
+            match self.style {
+                PlotStyle::Bars if point.y == 0.0 => continue,
+                PlotStyle::Bars => {
+                    group.add_child(Cube::new().with_material(&material).with_transform(
+                        &(Matrix::translation(point.x, point.y / 2.0, point.z)
+                            * Matrix::scaling(half, point.y.abs() / 2.0, half)),
+                    ))
+                }
+                PlotStyle::Scatter => {
+                    group.add_child(Sphere::new().with_material(&material).with_transform(
+                        &(Matrix::translation(point.x, point.y, point.z)
+                            * Matrix::scaling(half, half, half)),
+                    ))
+                }
+            }
+        }
+
+        group.divide(8);
+        world.lights = vec![Self::light_for(&group.bounds_of()).into()];
+        world.objects.push(Arc::new(group));
+
+        world
+    }
+
+    // Up, left and in front of the data, far enough away to light all of it.
+    fn light_for(bounds: &BoundingBox) -> PointLight {
+        let extent = (&bounds.max - &bounds.min).magnitude().max(1.0);
+
+        PointLight::new(
+            &Tuple::point(
+                bounds.min.x - extent,
+                bounds.max.y + extent,
+                bounds.min.z - extent,
+            ),
+            &Color::white(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::utils::equal_f64;
+
+    #[test]
+    fn test_parsing_csv_rows() {
+        let input = "x,y,z\n# comment\n1,2,3\n\n -1.5 , 0.5, 4\n";
+
+        let points = Plot::parse_csv(input).unwrap();
+
+        assert_eq!(
+            points,
+            vec![Tuple::point(1.0, 2.0, 3.0), Tuple::point(-1.5, 0.5, 4.0)]
+        );
+    }
+
+    #[test]
+    fn test_parsing_csv_rejects_bad_rows() {
+        assert!(Plot::parse_csv("1,2,3\n4,5\n").is_err());
+        assert!(Plot::parse_csv("1,2,3\n4,five,6\n").is_err());
+    }
+
+    #[test]
+    fn test_a_bar_plot_stands_on_the_floor() {
+        let world = Plot::new(PlotStyle::Bars)
+            .from_csv("0,1,0\n2,3,0\n4,0,0\n")
+            .unwrap();
+        let down = Tuple::vector(0.0, -1.0, 0.0);
+
+        let xs = world.intersect(&Ray::new(&Tuple::point(2.0, 10.0, 0.0), &down));
+        assert_eq!(xs.count(), 2);
+        assert!(equal_f64(xs.at(0).t, 7.0));
+        assert!(equal_f64(xs.at(1).t, 10.0));
+
+        let xs = world.intersect(&Ray::new(&Tuple::point(4.0, 10.0, 0.0), &down));
+        assert_eq!(xs.count(), 0);
+        assert!(!world.lights.is_empty());
+    }
+
+    #[test]
+    fn test_plot_colors_follow_the_ramp() {
+        let plot = Plot::new(PlotStyle::Scatter)
+            .with_colors(&Color::black(), &Color::white())
+            .with_size(1.0);
+        let world = plot.to_world(&[
+            Tuple::point(0.0, 0.0, 0.0),
+            Tuple::point(5.0, 2.0, 0.0),
+            Tuple::point(10.0, 4.0, 0.0),
+        ]);
+        let toward = Tuple::vector(0.0, 0.0, 1.0);
+
+        let hit = |x: f64, y: f64| {
+            let xs = world.intersect(&Ray::new(&Tuple::point(x, y, -5.0), &toward));
+            xs.hit().unwrap().object.get_material().color
+        };
+
+        assert_eq!(hit(0.0, 0.0), Color::black());
+        assert_eq!(hit(5.0, 2.0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(hit(10.0, 4.0), Color::white());
+    }
+}
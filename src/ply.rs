@@ -0,0 +1,399 @@
+use crate::canvas::{Color, ColorSpace};
+use crate::light::Material;
+use crate::shapes::{Group, Shape, SmoothTriangle, Triangle};
+use crate::tuple::Tuple;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header<'a> {
+    format: Format,
+    elements: Vec<Element>,
+    body: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Vertex {
+    position: Tuple,
+    normal: Option<Tuple>,
+    color: Option<Color>,
+}
+
+// Reads values one at a time from the body of the file in either format.
+struct Reader<'a> {
+    format: Format,
+    data: &'a [u8],
+    words: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, Box<dyn Error>> {
+        if self.format == Format::Ascii {
+            let word = self.words.next().ok_or("unexpected end of file")?;
+            return Ok(word.parse::<f64>()?);
+        }
+
+        let size = match scalar {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        };
+        if self.data.len() < size {
+            return Err("unexpected end of file".into());
+        }
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+
+        Ok(match scalar {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::U16 => u16::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::I32 => i32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::U32 => u32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::F32 => f32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes.try_into()?),
+        })
+    }
+}
+
+/// Reads an ASCII or binary little-endian PLY file into a group of triangles, fanning
+/// out any larger polygons. Faces whose vertices all have normals become smooth
+/// triangles. The group carries `material`, which faces inherit unless their vertices
+/// have colors, in which case they get a copy of it in the average color of their corners.
+pub fn parse(data: &[u8], material: &Material) -> Result<Group, Box<dyn Error>> {
+    let Header {
+        format,
+        elements,
+        body,
+    } = parse_header(data)?;
+    let mut reader = Reader {
+        format,
+        data: body,
+        words: std::str::from_utf8(if format == Format::Ascii { body } else { &[] })?
+            .split_whitespace(),
+    };
+
+    let mut vertices = vec![];
+    let mut group = Group::new().with_material(material);
+
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut scalars = vec![];
+            let mut lists = vec![];
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, scalar) => {
+                        scalars.push((name.as_str(), *scalar, reader.read(*scalar)?))
+                    }
+                    Property::List(name, count, item) => {
+                        let count = reader.read(*count)? as usize;
+                        let values: Result<Vec<f64>, _> =
+                            (0..count).map(|_| reader.read(*item)).collect();
+                        lists.push((name.as_str(), values?));
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => vertices.push(vertex(&scalars)?),
+                "face" => {
+                    let indices = lists
+                        .iter()
+                        .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+                        .ok_or("face without vertex indices")?;
+                    add_face(&mut group, &vertices, &indices.1)?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(group)
+}
+
+pub fn load(path: &str, material: &Material) -> Result<Group, Box<dyn Error>> {
+    let mut group = parse(&fs::read(path)?, material)?;
+    group.divide(8);
+
+    Ok(group)
+}
+
+fn parse_header(data: &[u8]) -> Result<Header<'_>, Box<dyn Error>> {
+    const END: &[u8] = b"end_header";
+    let end = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or("missing end_header")?;
+    let body_start = data[end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|i| end + i + 1)
+        .unwrap_or(data.len());
+    let header = std::str::from_utf8(&data[..end])?;
+
+    let mut lines = header.lines().map(|l| l.trim());
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".into());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, _] => return Err(format!("unsupported format {}", other).into()),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("property before element")?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    scalar(count)?,
+                    scalar(item)?,
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or("property before element")?
+                .properties
+                .push(Property::Scalar(name.to_string(), scalar(ty)?)),
+            _ => {}
+        }
+    }
+
+    Ok(Header {
+        format: format.ok_or("missing format")?,
+        elements,
+        body: &data[body_start..],
+    })
+}
+
+fn scalar(name: &str) -> Result<Scalar, Box<dyn Error>> {
+    Ok(match name {
+        "char" | "int8" => Scalar::I8,
+        "uchar" | "uint8" => Scalar::U8,
+        "short" | "int16" => Scalar::I16,
+        "ushort" | "uint16" => Scalar::U16,
+        "int" | "int32" => Scalar::I32,
+        "uint" | "uint32" => Scalar::U32,
+        "float" | "float32" => Scalar::F32,
+        "double" | "float64" => Scalar::F64,
+        _ => return Err(format!("unknown property type {}", name).into()),
+    })
+}
+
+fn vertex(scalars: &[(&str, Scalar, f64)]) -> Result<Vertex, Box<dyn Error>> {
+    let get = |name: &str| scalars.iter().find(|(n, _, _)| *n == name);
+    let value = |name: &str| get(name).map(|(_, _, v)| *v);
+    let position = match (value("x"), value("y"), value("z")) {
+        (Some(x), Some(y), Some(z)) => Tuple::point(x, y, z),
+        _ => return Err("vertex without a position".into()),
+    };
+    let normal = match (value("nx"), value("ny"), value("nz")) {
+        (Some(x), Some(y), Some(z)) => Some(Tuple::vector(x, y, z)),
+        _ => None,
+    };
+    // Float channels are taken as linear and integer ones as sRGB, like 8-bit images.
+    let channel = |name: &str| {
+        get(name).map(|(_, scalar, v)| match scalar {
+            Scalar::F32 | Scalar::F64 => (*v, ColorSpace::Linear),
+            Scalar::U16 | Scalar::I16 => (v / 65535.0, ColorSpace::Srgb),
+            _ => (v / 255.0, ColorSpace::Srgb),
+        })
+    };
+    let color = match (channel("red"), channel("green"), channel("blue")) {
+        (Some((r, space)), Some((g, _)), Some((b, _))) => Some(Color::from_space(r, g, b, space)),
+        _ => None,
+    };
+
+    Ok(Vertex {
+        position,
+        normal,
+        color,
+    })
+}
+
+fn add_face(group: &mut Group, vertices: &[Vertex], indices: &[f64]) -> Result<(), Box<dyn Error>> {
+    let corners: Option<Vec<&Vertex>> = indices.iter().map(|i| vertices.get(*i as usize)).collect();
+    let corners = corners.ok_or("face refers to a missing vertex")?;
+
+    for i in 1..corners.len().saturating_sub(1) {
+        let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+        let color = match (a.color, b.color, c.color) {
+            (Some(ca), Some(cb), Some(cc)) => Some((ca + cb + cc) * (1.0 / 3.0)),
+            _ => None,
+        };
+
+        let flat = Triangle::new(&a.position, &b.position, &c.position);
+        if flat.normal.x.is_nan() {
+            continue;
+        }
+        match (&a.normal, &b.normal, &c.normal) {
+            (Some(na), Some(nb), Some(nc)) => add_triangle(
+                group,
+                SmoothTriangle::new(&a.position, &b.position, &c.position, na, nb, nc),
+                color,
+            ),
+            _ => add_triangle(group, flat, color),
+        }
+    }
+
+    Ok(())
+}
+
+fn add_triangle<S>(group: &mut Group, mut triangle: S, color: Option<Color>)
+where
+    S: Shape + Send + Sync + 'static,
+{
+    if let Some(color) = color {
+        let mut m = group.get_material();
+        m.color = color;
+        triangle.set_material(&m);
+    }
+    group.add_child(triangle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use std::sync::Arc;
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment a unit square made of one quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 255 0 0
+1 1 0 0 0 255
+0 1 0 0 0 255
+4 0 1 2 3
+";
+
+    fn hit(group: &Arc<Group>, x: f64, y: f64) -> Option<Material> {
+        let r = Ray::new(&Tuple::point(x, y, -1.0), &Tuple::vector(0.0, 0.0, 1.0));
+        r.intersect(group.clone())
+            .hit()
+            .map(|h| h.object.get_material())
+    }
+
+    #[test]
+    fn test_parsing_an_ascii_ply_file_with_vertex_colors() {
+        let group = parse(ASCII.as_bytes(), &Material::new()).unwrap();
+
+        assert_eq!(group.children().len(), 2);
+        let group = Arc::new(group);
+        let lower = hit(&group, 0.9, 0.1);
+        assert_eq!(lower.unwrap().color, Color::new(2.0 / 3.0, 0.0, 1.0 / 3.0));
+        let upper = hit(&group, 0.1, 0.9);
+        assert_eq!(upper.unwrap().color, Color::new(1.0 / 3.0, 0.0, 2.0 / 3.0));
+    }
+
+    #[test]
+    fn test_parsing_a_binary_ply_file_with_vertex_normals() {
+        let mut data = b"ply
+format binary_little_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+        let vertices: [[f32; 6]; 3] = [
+            [0.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+            [-1.0, 0.0, 0.0, -1.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+        ];
+        for v in vertices.iter() {
+            for value in v.iter() {
+                data.extend(&value.to_le_bytes());
+            }
+        }
+        data.push(3);
+        for i in 0u32..3 {
+            data.extend(&i.to_le_bytes());
+        }
+
+        let group = parse(&data, &Material::new()).unwrap();
+        let r = Ray::new(
+            &Tuple::point(-0.2, 0.3, -2.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+        let xs = r.intersect(Arc::new(group));
+        let hit = xs.hit().unwrap();
+
+        assert_eq!(
+            hit.object.normal_at(&r.position(hit.t)),
+            Tuple::vector(-0.5547, 0.83205, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_rejecting_malformed_ply_files() {
+        let m = Material::new();
+
+        assert!(parse(b"not a ply file", &m).is_err());
+        assert!(parse(b"ply\nformat binary_big_endian 1.0\nend_header\n", &m).is_err());
+        assert!(parse(
+            b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n",
+            &m
+        )
+        .is_err());
+        let missing = ASCII.replace("4 0 1 2 3", "3 0 1 7");
+        assert!(parse(missing.as_bytes(), &m).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::canvas::{Canvas, Color, ColorSpace};
+
+/// Maps linear scene values to display values, one channel at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToneCurve {
+    Linear,
+    Reinhard,
+    /// Hable's filmic curve (as used in Uncharted 2), normalized to a white point of 11.2.
+    Filmic,
+    /// Narkowicz's fitted approximation of the ACES reference curve.
+    Aces,
+    /// Piecewise-linear curve through `(input, output)` control points sorted by input.
+    /// Inputs outside the control points take the nearest endpoint's output. Repeating
+    /// an input makes a step, with the later point's output from that input on.
+    Custom(Vec<(f64, f64)>),
+}
+
+impl ToneCurve {
+    pub fn apply(&self, value: f64) -> f64 {
+        let value = value.max(0.0);
+        match self {
+            ToneCurve::Linear => value,
+            ToneCurve::Reinhard => value / (1.0 + value),
+            ToneCurve::Filmic => Self::hable(value) / Self::hable(11.2),
+            ToneCurve::Aces => ((value * (2.51 * value + 0.03))
+                / (value * (2.43 * value + 0.59) + 0.14))
+                .clamp(0.0, 1.0),
+            ToneCurve::Custom(points) => Self::interpolate(points, value),
+        }
+    }
+
+    fn hable(x: f64) -> f64 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    }
+
+    fn interpolate(points: &[(f64, f64)], value: f64) -> f64 {
+        match (points.first(), points.last()) {
+            (Some(first), _) if value <= first.0 => first.1,
+            (_, Some(last)) if value >= last.0 => last.1,
+            (None, _) => value,
+            _ => points
+                .windows(2)
+                .find(|w| value >= w[0].0 && value < w[1].0)
+                .map(|w| {
+                    let (x0, y0) = w[0];
+                    let (x1, y1) = w[1];
+                    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+                })
+                .unwrap_or(value),
+        }
+    }
+}
+
+/// Temperature and tint adjustments, both nominally in `-1.0..=1.0` with `0.0` as neutral.
+/// A positive temperature warms the image (more red, less blue) and a positive tint
+/// pushes it towards magenta (less green).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhiteBalance {
+    pub temperature: f64,
+    pub tint: f64,
+}
+
+impl WhiteBalance {
+    pub fn new(temperature: f64, tint: f64) -> Self {
+        Self { temperature, tint }
+    }
+
+    pub fn neutral() -> Self {
+        Self::new(0.0, 0.0)
+    }
+
+    pub fn apply(&self, color: &Color) -> Color {
+        let gains = Color::new(
+            1.0 + 0.3 * self.temperature,
+            1.0 - 0.3 * self.tint,
+            1.0 - 0.3 * self.temperature,
+        );
+
+        *color * gains
+    }
+}
+
+/// Post-processing applied to a rendered canvas: white balance first, then exposure,
+/// then the tone curve, and finally gamma encoding. An sRGB `output` uses the sRGB
+/// curve in place of `gamma`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcess {
+    pub white_balance: WhiteBalance,
+    pub exposure: f64,
+    pub tone_curve: ToneCurve,
+    pub gamma: f64,
+    pub output: ColorSpace,
+}
+
+impl PostProcess {
+    pub fn new() -> Self {
+        Self {
+            white_balance: WhiteBalance::neutral(),
+            exposure: 1.0,
+            tone_curve: ToneCurve::Linear,
+            gamma: 1.0,
+            output: ColorSpace::Linear,
+        }
+    }
+
+    pub fn with_white_balance(self, white_balance: &WhiteBalance) -> Self {
+        Self {
+            white_balance: *white_balance,
+            ..self
+        }
+    }
+
+    pub fn with_tone_curve(self, tone_curve: &ToneCurve) -> Self {
+        Self {
+            tone_curve: tone_curve.clone(),
+            ..self
+        }
+    }
+
+    pub fn with_output(self, output: ColorSpace) -> Self {
+        Self { output, ..self }
+    }
+
+    pub fn apply_color(&self, color: &Color) -> Color {
+        let balanced = self.white_balance.apply(color) * self.exposure;
+        let channel = |c: f64| match self.output {
+            ColorSpace::Linear => self.tone_curve.apply(c).powf(1.0 / self.gamma),
+            ColorSpace::Srgb => self.output.encode(self.tone_curve.apply(c)),
+        };
+
+        Color::new(
+            channel(balanced.red),
+            channel(balanced.green),
+            channel(balanced.blue),
+        )
+    }
+
+    pub fn apply(&self, canvas: &Canvas) -> Canvas {
+        let mut image = Canvas::new(canvas.width, canvas.height);
+        image.pixels = canvas.pixels.iter().map(|c| self.apply_color(c)).collect();
+
+        image
+    }
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal_f64;
+
+    #[test]
+    fn test_the_default_post_process_leaves_colors_unchanged() {
+        let post = PostProcess::new();
+        let c = Color::new(0.2, 0.5, 1.5);
+
+        assert_eq!(post.apply_color(&c), c);
+    }
+
+    #[test]
+    fn test_tone_curves_map_black_to_black() {
+        for curve in [ToneCurve::Reinhard, ToneCurve::Filmic, ToneCurve::Aces].iter() {
+            assert!(equal_f64(curve.apply(0.0), 0.0));
+        }
+    }
+
+    #[test]
+    fn test_compressing_tone_curves_keep_highlights_below_one() {
+        for curve in [ToneCurve::Reinhard, ToneCurve::Filmic, ToneCurve::Aces].iter() {
+            let low = curve.apply(0.5);
+            let high = curve.apply(8.0);
+
+            assert!(low < high);
+            assert!(high <= 1.0);
+        }
+        assert!(equal_f64(ToneCurve::Reinhard.apply(1.0), 0.5));
+    }
+
+    #[test]
+    fn test_a_custom_tone_curve_interpolates_between_points() {
+        let curve = ToneCurve::Custom(vec![(0.0, 0.0), (1.0, 0.5), (2.0, 1.0)]);
+
+        assert!(equal_f64(curve.apply(0.5), 0.25));
+        assert!(equal_f64(curve.apply(1.5), 0.75));
+        assert!(equal_f64(curve.apply(4.0), 1.0));
+    }
+
+    #[test]
+    fn test_repeating_an_input_in_a_custom_tone_curve_makes_a_step() {
+        let curve = ToneCurve::Custom(vec![(0.0, 0.0), (1.0, 0.2), (1.0, 0.8), (2.0, 1.0)]);
+
+        assert!(equal_f64(curve.apply(0.5), 0.1));
+        assert!(equal_f64(curve.apply(1.0), 0.8));
+        assert!(equal_f64(curve.apply(1.5), 0.9));
+    }
+
+    #[test]
+    fn test_a_warm_white_balance_shifts_white_towards_red() {
+        let wb = WhiteBalance::new(0.5, 0.0);
+
+        let c = wb.apply(&Color::white());
+
+        assert_eq!(c, Color::new(1.15, 1.0, 0.85));
+    }
+
+    #[test]
+    fn test_a_magenta_tint_reduces_green() {
+        let wb = WhiteBalance::new(0.0, 1.0);
+
+        let c = wb.apply(&Color::white());
+
+        assert_eq!(c, Color::new(1.0, 0.7, 1.0));
+    }
+
+    #[test]
+    fn test_applying_post_processing_to_a_canvas() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(0, 0, &Color::new(1.0, 1.0, 1.0));
+        let post = PostProcess::new().with_tone_curve(&ToneCurve::Reinhard);
+
+        let image = post.apply(&canvas);
+
+        assert_eq!(image.pixel_at(0, 0), &Color::new(0.5, 0.5, 0.5));
+        assert_eq!(image.pixel_at(1, 0), &Color::black());
+    }
+
+    #[test]
+    fn test_srgb_output_encodes_linear_values() {
+        let post = PostProcess::new().with_output(ColorSpace::Srgb);
+
+        let c = post.apply_color(&Color::new(0.21404, 1.0, 0.0));
+
+        assert_eq!(c, Color::new(0.5, 1.0, 0.0));
+    }
+}
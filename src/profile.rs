@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Where a profiled render spends its time. Sections nest, e.g. shadow rays are cast
+/// while shading, and each one only counts the time not spent in the sections inside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    Intersection,
+    Shading,
+    Shadows,
+}
+
+/// The work done by one render thread. Which pixels a thread picks up varies between
+/// runs, but the counts summed over all threads are the same for the same scene.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThreadProfile {
+    pub thread: usize,
+    pub pixels: usize,
+    pub rays: usize,
+    pub shadow_rays: usize,
+    pub intersection: Duration,
+    pub shading: Duration,
+    pub shadows: Duration,
+}
+
+impl ThreadProfile {
+    pub fn merge(&mut self, other: &ThreadProfile) {
+        self.pixels += other.pixels;
+        self.rays += other.rays;
+        self.shadow_rays += other.shadow_rays;
+        self.intersection += other.intersection;
+        self.shading += other.shading;
+        self.shadows += other.shadows;
+    }
+
+    fn add(&mut self, section: Section, elapsed: Duration) {
+        match section {
+            Section::Intersection => self.intersection += elapsed,
+            Section::Shading => self.shading += elapsed,
+            Section::Shadows => self.shadows += elapsed,
+        }
+    }
+}
+
+impl fmt::Display for ThreadProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread {}: {} pixels, {} rays, {} shadow rays; intersection {:.2?}, shading {:.2?}, shadows {:.2?}",
+            self.thread,
+            self.pixels,
+            self.rays,
+            self.shadow_rays,
+            self.intersection,
+            self.shading,
+            self.shadows
+        )
+    }
+}
+
+struct Recorder {
+    profile: ThreadProfile,
+    stack: Vec<(Section, Instant)>,
+}
+
+impl Recorder {
+    fn enter(&mut self, section: Section) {
+        let now = Instant::now();
+        if let Some((parent, start)) = self.stack.last() {
+            self.profile.add(*parent, now - *start);
+        }
+        match section {
+            Section::Intersection => self.profile.rays += 1,
+            Section::Shadows => self.profile.shadow_rays += 1,
+            Section::Shading => {}
+        }
+        self.stack.push((section, now));
+    }
+
+    fn exit(&mut self) {
+        let now = Instant::now();
+        if let Some((section, start)) = self.stack.pop() {
+            self.profile.add(section, now - start);
+        }
+        if let Some((_, start)) = self.stack.last_mut() {
+            *start = now;
+        }
+    }
+}
+
+thread_local! {
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+// Times `f` as `section` when this thread is recording, and just runs it otherwise.
+pub(crate) fn section<T, F: FnOnce() -> T>(section: Section, f: F) -> T {
+    let recording = RECORDER.with(|r| match r.borrow_mut().as_mut() {
+        Some(recorder) => {
+            recorder.enter(section);
+            true
+        }
+        None => false,
+    });
+
+    let result = f();
+    if recording {
+        RECORDER.with(|r| {
+            if let Some(recorder) = r.borrow_mut().as_mut() {
+                recorder.exit();
+            }
+        });
+    }
+
+    result
+}
+
+// Runs `f` with recording turned on for the current thread, returning what it recorded.
+pub(crate) fn record<T, F: FnOnce() -> T>(f: F) -> (T, ThreadProfile) {
+    RECORDER.with(|r| {
+        *r.borrow_mut() = Some(Recorder {
+            profile: ThreadProfile::default(),
+            stack: vec![],
+        })
+    });
+
+    let result = f();
+    let recorder = RECORDER.with(|r| r.borrow_mut().take()).unwrap();
+
+    (result, recorder.profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_sections_are_not_timed_unless_recording() {
+        assert_eq!(section(Section::Shading, || 42), 42);
+    }
+
+    #[test]
+    fn test_nested_sections_count_their_own_time() {
+        let (value, profile) = record(|| {
+            section(Section::Shading, || {
+                sleep(Duration::from_millis(5));
+                section(Section::Shadows, || sleep(Duration::from_millis(50)));
+                7
+            })
+        });
+
+        assert_eq!(value, 7);
+        assert_eq!(profile.shadow_rays, 1);
+        assert!(profile.shadows >= Duration::from_millis(50));
+        assert!(profile.shading >= Duration::from_millis(5));
+        assert!(profile.shading < profile.shadows);
+    }
+}
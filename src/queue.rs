@@ -0,0 +1,205 @@
+use crate::camera::Camera;
+use crate::post::PostProcess;
+use crate::world::World;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A render waiting in a `RenderQueue`. The world is shared so that many variations,
+/// e.g. different cameras on one scene, don't need a copy each.
+#[derive(Clone)]
+pub struct RenderJob {
+    pub world: Arc<World>,
+    pub camera: Camera,
+    pub post: Option<PostProcess>,
+    pub out: String,
+}
+
+/// Reported after each job finishes, whether it succeeded or not. Jobs finish in any
+/// order, so `completed` counts the jobs done so far rather than naming one.
+#[derive(Debug)]
+pub struct Progress<'a> {
+    pub completed: usize,
+    pub total: usize,
+    pub out: &'a str,
+    pub elapsed: Duration,
+    pub error: Option<&'a dyn Error>,
+}
+
+/// Renders jobs side by side on a pool of `threads` threads, or as many as there are
+/// cores when no budget is set; the pixels of each image share the same pool. A budget
+/// of one renders the jobs one after another, each sequentially.
+#[derive(Clone, Default)]
+pub struct RenderQueue {
+    pub jobs: Vec<RenderJob>,
+    pub threads: Option<usize>,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Self {
+            jobs: vec![],
+            threads: None,
+        }
+    }
+
+    pub fn with_threads(self, threads: usize) -> Self {
+        Self {
+            threads: Some(threads),
+            ..self
+        }
+    }
+
+    pub fn add(&mut self, world: &Arc<World>, camera: &Camera, out: &str) {
+        self.jobs.push(RenderJob {
+            world: world.clone(),
+            camera: camera.clone(),
+            post: None,
+            out: out.to_string(),
+        });
+    }
+
+    pub fn add_with_post(
+        &mut self,
+        world: &Arc<World>,
+        camera: &Camera,
+        post: &PostProcess,
+        out: &str,
+    ) {
+        self.add(world, camera, out);
+        self.jobs.last_mut().unwrap().post = Some(post.clone());
+    }
+
+    /// Runs every job, carrying on past failures so one bad output path doesn't cost a
+    /// night of renders. Fails at the end if any job did, naming the outputs affected.
+    pub fn run<F>(&self, progress: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Progress) + Send,
+    {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(self.threads.unwrap_or(0))
+            .build()?;
+        let progress = Mutex::new((0, progress));
+
+        let succeeded: Vec<bool> = pool.install(|| {
+            self.jobs
+                .par_iter()
+                .map(|job| {
+                    let start = Instant::now();
+                    let canvas = job.camera.render(&job.world);
+                    let canvas = match &job.post {
+                        Some(post) => post.apply(&canvas),
+                        None => canvas,
+                    };
+                    let result = canvas.save(&job.out);
+
+                    let mut progress = progress.lock().unwrap();
+                    let (completed, report) = &mut *progress;
+                    *completed += 1;
+                    report(&Progress {
+                        completed: *completed,
+                        total: self.jobs.len(),
+                        out: &job.out,
+                        elapsed: start.elapsed(),
+                        error: result.as_ref().err().map(|e| e.as_ref()),
+                    });
+                    result.is_ok()
+                })
+                .collect()
+        });
+        let failed: Vec<&str> = self
+            .jobs
+            .iter()
+            .zip(succeeded)
+            .filter(|(_, ok)| !ok)
+            .map(|(job, _)| job.out.as_str())
+            .collect();
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} of {} renders failed: {}",
+                failed.len(),
+                self.jobs.len(),
+                failed.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformations::view_transform;
+    use crate::tuple::Tuple;
+    use std::fs;
+
+    fn camera() -> Camera {
+        let mut c = Camera::new(8, 6, 1.0);
+        c.transform = view_transform(
+            &Tuple::point(0.0, 0.0, -5.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        c
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("ray-tracer-queue-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_a_queue_renders_every_job() {
+        let world = Arc::new(World::default_world());
+        let (a, b) = (temp_path("a.ppm"), temp_path("b.ppm"));
+        let mut queue = RenderQueue::new().with_threads(2);
+        queue.add(&world, &camera(), &a);
+        queue.add_with_post(&world, &camera(), &PostProcess::new(), &b);
+        let mut reported = vec![];
+
+        queue
+            .run(|p| reported.push((p.completed, p.total, p.out.to_string())))
+            .unwrap();
+
+        reported.sort_by(|x, y| x.2.cmp(&y.2));
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0].2, a);
+        assert_eq!(reported[1].2, b);
+        assert!(reported.iter().all(|r| r.1 == 2));
+        assert_ne!(reported[0].0, reported[1].0);
+        let expected = camera().render(&world).to_ppm();
+        assert_eq!(fs::read_to_string(&a).unwrap(), expected);
+        assert_eq!(fs::read_to_string(&b).unwrap(), expected);
+        fs::remove_file(a).unwrap();
+        fs::remove_file(b).unwrap();
+    }
+
+    #[test]
+    fn test_a_failed_job_does_not_stop_the_queue() {
+        let world = Arc::new(World::default_world());
+        let good = temp_path("good.ppm");
+        let mut queue = RenderQueue::new().with_threads(1);
+        queue.add(&world, &camera(), "/nonexistent/directory/bad.ppm");
+        queue.add(&world, &camera(), &good);
+        let mut errors = vec![];
+
+        let result = queue.run(|p| errors.push((p.out.to_string(), p.error.is_some())));
+
+        assert!(result.is_err());
+        assert_eq!(
+            errors,
+            vec![
+                ("/nonexistent/directory/bad.ppm".to_string(), true),
+                (good.clone(), false)
+            ]
+        );
+        assert!(fs::remove_file(good).is_ok());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::canvas::Color;
+use crate::light::Material;
 use crate::matrix::Matrix;
 use crate::shapes::Shape;
 use crate::tuple::Tuple;
@@ -23,15 +25,15 @@ impl Ray {
     }
 
     pub fn intersect(&self, s: Arc<dyn Shape>) -> Intersections {
-        let transform = s.get_transform();
-        let ray = self.transform(&transform.inverse().unwrap());
+        self.intersect_culling(s, false)
+    }
 
-        Intersections::new(
-            s.intersect(&ray)
-                .iter()
-                .map(|i| Intersection::new(*i, s.clone()))
-                .collect(),
-        )
+    /// As `intersect`, but with `cull` set one-sided materials are only hit on their
+    /// front faces, as camera rays see them.
+    pub fn intersect_culling(&self, s: Arc<dyn Shape>, cull: bool) -> Intersections {
+        let ray = self.transform(&s.get_inverse_transform());
+
+        s.local_intersect(&ray, s.clone(), cull)
     }
 
     pub fn transform(&self, m: &Matrix) -> Self {
@@ -39,6 +41,15 @@ impl Ray {
     }
 }
 
+/// What a ray is traced for, so that shapes can be hidden from some kinds of ray.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RayKind {
+    Camera,
+    Shadow,
+    Reflection,
+    Refraction,
+}
+
 #[derive(Clone)]
 pub struct Computation {
     pub t: f64,
@@ -46,8 +57,39 @@ pub struct Computation {
     pub point: Tuple,
     pub eyev: Tuple,
     pub normalv: Tuple,
+    pub reflectv: Tuple,
+    /// Whether the ray struck the back face, the side the surface normal points away
+    /// from; `normalv` is flipped to face the ray when it did.
     pub inside: bool,
+    /// Whether the ray struck the front face, the side the surface normal faces. Camera
+    /// rays only ever strike one-sided materials here.
+    pub front_face: bool,
     pub over_point: Tuple,
+    pub under_point: Tuple,
+    /// Refractive indices of the media the ray leaves and enters at this hit.
+    pub n1: f64,
+    pub n2: f64,
+    /// Absorption of the medium the ray crossed to reach this hit.
+    pub absorption: Color,
+}
+
+impl Computation {
+    /// Schlick's approximation of the fraction of light a transparent surface reflects,
+    /// which grows towards grazing angles.
+    pub fn schlick(&self) -> f64 {
+        let mut cos = self.eyev.dot(&self.normalv);
+        if self.n1 > self.n2 {
+            let n = self.n1 / self.n2;
+            let sin2_t = n * n * (1.0 - cos * cos);
+            if sin2_t > 1.0 {
+                return 1.0;
+            }
+            cos = (1.0 - sin2_t).sqrt();
+        }
+
+        let r0 = ((self.n1 - self.n2) / (self.n1 + self.n2)).powi(2);
+        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -64,7 +106,9 @@ impl Intersection {
         }
     }
 
-    pub fn prepare_computation(&self, ray: &Ray) -> Computation {
+    /// `xs` are all the intersections along `ray`, sorted, so that the media on either
+    /// side of this hit can be worked out.
+    pub fn prepare_computation(&self, ray: &Ray, xs: &Intersections) -> Computation {
         let point = ray.position(self.t);
         let eyev = -ray.direction.clone();
         let mut normalv = self.object.normal_at(&point);
@@ -74,7 +118,12 @@ impl Intersection {
         } else {
             false
         };
+        let reflectv = ray.direction.reflect(&normalv);
         let over_point = point.clone() + normalv.clone() * EPSILON;
+        let under_point = point.clone() - normalv.clone() * EPSILON;
+        let (before, after) = self.media(xs);
+        let refractive_index =
+            |m: &Option<Material>| m.as_ref().map_or(1.0, |m| m.refractive_index);
 
         Computation {
             t: self.t,
@@ -82,10 +131,54 @@ impl Intersection {
             point: point.clone(),
             eyev,
             inside,
+            front_face: !inside,
             normalv,
+            reflectv,
             over_point,
+            under_point,
+            n1: refractive_index(&before),
+            n2: refractive_index(&after),
+            absorption: before.map_or(Color::black(), |m| m.absorption),
         }
     }
+
+    // The media the ray is in just before and just after this hit; `None` is empty
+    // space.
+    fn media(&self, xs: &Intersections) -> (Option<Material>, Option<Material>) {
+        let mut containers: Vec<&Intersection> = vec![];
+
+        for i in xs.intersections.iter() {
+            if i == self {
+                let before = medium(&containers);
+                toggle(&mut containers, i);
+                return (before, medium(&containers));
+            }
+            toggle(&mut containers, i);
+        }
+
+        (None, None)
+    }
+}
+
+// The medium the ray is in, given the shapes it is inside. Where media overlap the one
+// with the highest priority wins, and among equals the last entered.
+fn medium(containers: &[&Intersection]) -> Option<Material> {
+    containers
+        .iter()
+        .map(|c| c.object.get_material())
+        .max_by_key(|m| m.medium_priority)
+}
+
+fn toggle<'a>(containers: &mut Vec<&'a Intersection>, i: &'a Intersection) {
+    match containers
+        .iter()
+        .position(|c| c.object.id() == i.object.id())
+    {
+        Some(index) => {
+            containers.remove(index);
+        }
+        None => containers.push(i),
+    }
 }
 
 impl PartialEq for &Intersection {
@@ -130,6 +223,49 @@ impl Intersections {
         lowest_index.map(|i| self.at(i))
     }
 
+    /// Every intersection in front of the ray's origin, nearest first.
+    pub fn hits(&self) -> Vec<Intersection> {
+        self.in_range(0.0, f64::INFINITY)
+    }
+
+    /// The intersections with `t0 < t < t1`, nearest first.
+    pub fn in_range(&self, t0: f64, t1: f64) -> Vec<Intersection> {
+        let mut hits: Vec<Intersection> = self
+            .intersections
+            .iter()
+            .filter(|i| i.t > t0 && i.t < t1)
+            .cloned()
+            .collect();
+        hits.sort_by(|a, b| a.t.total_cmp(&b.t));
+        hits
+    }
+
+    /// The hit after `hit()`, e.g. where a ray leaves the object it entered.
+    pub fn second_hit(&self) -> Option<Intersection> {
+        self.hits().into_iter().nth(1)
+    }
+
+    /// The first hit on a real boundary between media. Where transparent shapes overlap,
+    /// e.g. water filling a glass, surfaces inside a medium of higher priority are
+    /// skipped, as that medium fills the space. Expects the intersections to be sorted.
+    pub fn media_hit(&self) -> Option<Intersection> {
+        let mut containers: Vec<&Intersection> = vec![];
+
+        for i in self.intersections.iter() {
+            let priority = i.object.material_handle().medium_priority;
+            let overridden = containers
+                .iter()
+                .filter(|c| c.object.id() != i.object.id())
+                .any(|c| c.object.material_handle().medium_priority > priority);
+            if i.t > 0.0 && !overridden {
+                return Some(i.clone());
+            }
+            toggle(&mut containers, i);
+        }
+
+        None
+    }
+
     pub fn extend(&mut self, intersections: &Self) {
         intersections
             .intersections
@@ -137,18 +273,43 @@ impl Intersections {
             .for_each(|i| self.intersections.push(i.clone()));
     }
 
+    pub fn retain<F: FnMut(&Intersection) -> bool>(&mut self, keep: F) {
+        self.intersections.retain(keep);
+    }
+
     pub fn sort(&mut self) {
-        self.intersections
-            .sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+        self.intersections.sort_by(|a, b| a.t.total_cmp(&b.t));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shapes::Sphere;
+    use crate::shapes::{Plane, Sphere};
     use crate::utils::{equal_f64, EPSILON};
 
+    fn glass_sphere(transform: Matrix, refractive_index: f64, priority: u32) -> Arc<Sphere> {
+        let mut m = Material::new();
+        m.transparency = 1.0;
+        m.refractive_index = refractive_index;
+        m.medium_priority = priority;
+        Arc::new(Sphere::new().with_transform(&transform).with_material(&m))
+    }
+
+    fn nested_spheres(priority: u32) -> Intersections {
+        let a = glass_sphere(Matrix::scaling(2.0, 2.0, 2.0), 1.5, priority);
+        let b = glass_sphere(Matrix::translation(0.0, 0.0, -0.25), 2.0, 0);
+        let c = glass_sphere(Matrix::translation(0.0, 0.0, 0.25), 2.5, 0);
+        Intersections::new(vec![
+            Intersection::new(2.0, a.clone()),
+            Intersection::new(2.75, b.clone()),
+            Intersection::new(3.25, c.clone()),
+            Intersection::new(4.75, b),
+            Intersection::new(5.25, c),
+            Intersection::new(6.0, a),
+        ])
+    }
+
     #[test]
     fn test_creating_and_querying_a_ray() {
         let origin = Tuple::point(1.0, 2.0, 3.0);
@@ -311,6 +472,66 @@ mod tests {
         assert_eq!(&i, &i4);
     }
 
+    #[test]
+    fn test_all_hits_are_nonnegative_and_sorted() {
+        let s = Arc::new(Sphere::new());
+        let xs = Intersections::new(vec![
+            Intersection::new(5.0, s.clone()),
+            Intersection::new(7.0, s.clone()),
+            Intersection::new(-3.0, s.clone()),
+            Intersection::new(2.0, s),
+        ]);
+
+        let ts: Vec<f64> = xs.hits().iter().map(|i| i.t).collect();
+
+        assert_eq!(ts, vec![2.0, 5.0, 7.0]);
+        assert_eq!(xs.second_hit().unwrap().t, 5.0);
+    }
+
+    #[test]
+    fn test_intersections_within_a_range() {
+        let s = Arc::new(Sphere::new());
+        let xs = Intersections::new(vec![
+            Intersection::new(6.0, s.clone()),
+            Intersection::new(1.0, s.clone()),
+            Intersection::new(-1.0, s.clone()),
+            Intersection::new(4.0, s),
+        ]);
+
+        let ts: Vec<f64> = xs.in_range(-2.0, 5.0).iter().map(|i| i.t).collect();
+
+        assert_eq!(ts, vec![-1.0, 1.0, 4.0]);
+        assert!(xs.in_range(7.0, 9.0).is_empty());
+    }
+
+    #[test]
+    fn test_sorting_puts_nan_intersections_last() {
+        let s = Arc::new(Sphere::new());
+        let mut xs = Intersections::new(vec![
+            Intersection::new(f64::NAN, s.clone()),
+            Intersection::new(4.0, s.clone()),
+            Intersection::new(1.0, s),
+        ]);
+
+        xs.sort();
+
+        assert_eq!(xs.at(0).t, 1.0);
+        assert_eq!(xs.at(1).t, 4.0);
+        assert!(xs.at(2).t.is_nan());
+        assert_eq!(xs.in_range(0.0, 5.0).len(), 2);
+    }
+
+    #[test]
+    fn test_there_is_no_second_hit_with_one_intersection_ahead() {
+        let s = Arc::new(Sphere::new());
+        let xs = Intersections::new(vec![
+            Intersection::new(-1.0, s.clone()),
+            Intersection::new(1.0, s),
+        ]);
+
+        assert!(xs.second_hit().is_none());
+    }
+
     #[test]
     fn test_translating_a_ray() {
         let r = Ray::new(&Tuple::point(1.0, 2.0, 3.0), &Tuple::vector(0.0, 1.0, 0.0));
@@ -378,7 +599,7 @@ mod tests {
         let shape = Arc::new(Sphere::new());
         let i = Intersection::new(4.0, shape.clone());
 
-        let comps = i.prepare_computation(&r);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
 
         assert!(equal_f64(comps.t, i.t));
         assert!(std::ptr::eq(comps.object.as_ref(), i.object.as_ref()));
@@ -388,23 +609,25 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_the_hit_when_an_intersection_occurs_on_the_outside() {
         let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
         let shape = Arc::new(Sphere::new());
         let i = Intersection::new(4.0, shape.clone());
 
-        let comps = i.prepare_computation(&r);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
 
         assert_eq!(comps.inside, false);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_the_hit_when_an_intersection_occurs_on_the_inside() {
         let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
         let shape = Arc::new(Sphere::new());
         let i = Intersection::new(1.0, shape.clone());
 
-        let comps = i.prepare_computation(&r);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
 
         assert_eq!(comps.point, Tuple::point(0.0, 0.0, 1.0));
         assert_eq!(comps.eyev, Tuple::vector(0.0, 0.0, -1.0));
@@ -412,15 +635,196 @@ mod tests {
         assert_eq!(comps.normalv, Tuple::vector(0.0, 0.0, -1.0));
     }
 
+    #[test]
+    fn test_the_front_face_is_struck_only_from_outside() {
+        let shape = Arc::new(Sphere::new());
+        let outside = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let inside = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let entering = Intersection::new(4.0, shape.clone());
+        let leaving = Intersection::new(1.0, shape);
+
+        let front =
+            entering.prepare_computation(&outside, &Intersections::new(vec![entering.clone()]));
+        let back = leaving.prepare_computation(&inside, &Intersections::new(vec![leaving.clone()]));
+
+        assert!(front.front_face);
+        assert!(!front.inside);
+        assert!(!back.front_face);
+        assert!(back.inside);
+    }
+
     #[test]
     fn test_the_hit_should_offset_the_point() {
         let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
         let shape = Arc::new(Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, 1.0)));
         let i = Intersection::new(5.0, shape.clone());
 
-        let comps = i.prepare_computation(&r);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
 
         assert!(comps.over_point.z < -EPSILON / 2.0);
         assert!(comps.point.z > comps.over_point.z);
     }
+
+    #[test]
+    fn test_finding_n1_and_n2_at_various_intersections() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -4.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let xs = nested_spheres(0);
+        let expected = [
+            (1.0, 1.5),
+            (1.5, 2.0),
+            (2.0, 2.5),
+            (2.5, 2.5),
+            (2.5, 1.5),
+            (1.5, 1.0),
+        ];
+
+        for (index, (n1, n2)) in expected.iter().enumerate() {
+            let comps = xs.at(index).prepare_computation(&r, &xs);
+
+            assert!(equal_f64(comps.n1, *n1));
+            assert!(equal_f64(comps.n2, *n2));
+        }
+    }
+
+    #[test]
+    fn test_overlapping_copies_of_a_shape_are_separate_media() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -4.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let a = glass_sphere(Matrix::identify(), 1.5, 0);
+        let b = Arc::new(
+            a.as_ref()
+                .clone()
+                .with_transform(&Matrix::translation(0.0, 0.0, 1.0)),
+        );
+        let xs = Intersections::new(vec![
+            Intersection::new(3.0, a.clone()),
+            Intersection::new(4.0, b.clone()),
+            Intersection::new(5.0, a),
+            Intersection::new(6.0, b),
+        ]);
+        let expected = [(1.0, 1.5), (1.5, 1.5), (1.5, 1.5), (1.5, 1.0)];
+
+        for (index, (n1, n2)) in expected.iter().enumerate() {
+            let comps = xs.at(index).prepare_computation(&r, &xs);
+
+            assert!(equal_f64(comps.n1, *n1));
+            assert!(equal_f64(comps.n2, *n2));
+        }
+    }
+
+    #[test]
+    fn test_a_higher_priority_medium_fills_the_space_it_shares() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -4.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let xs = nested_spheres(1);
+
+        for index in 1..5 {
+            let comps = xs.at(index).prepare_computation(&r, &xs);
+
+            assert!(equal_f64(comps.n1, 1.5));
+            assert!(equal_f64(comps.n2, 1.5));
+        }
+        let comps = xs.at(5).prepare_computation(&r, &xs);
+        assert!(equal_f64(comps.n1, 1.5));
+        assert!(equal_f64(comps.n2, 1.0));
+    }
+
+    #[test]
+    fn test_the_media_hit_skips_surfaces_inside_a_higher_priority_medium() {
+        let mut xs = nested_spheres(0);
+        xs.intersections.iter_mut().for_each(|i| i.t -= 2.5);
+        let mut overridden = nested_spheres(1);
+        overridden.intersections.iter_mut().for_each(|i| i.t -= 2.5);
+
+        assert_eq!(xs.media_hit().unwrap().t, 0.25);
+        assert_eq!(overridden.media_hit().unwrap().t, 3.5);
+    }
+
+    #[test]
+    fn test_the_under_point_is_offset_below_the_surface() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let shape = glass_sphere(Matrix::translation(0.0, 0.0, 1.0), 1.5, 0);
+        let i = Intersection::new(5.0, shape);
+        let xs = Intersections::new(vec![i.clone()]);
+
+        let comps = i.prepare_computation(&r, &xs);
+
+        assert!(comps.under_point.z > EPSILON / 2.0);
+        assert!(comps.point.z < comps.under_point.z);
+    }
+
+    #[test]
+    fn test_precomputing_the_reflection_vector() {
+        let shape = Arc::new(Plane::new());
+        let r = Ray::new(
+            &Tuple::point(0.0, 1.0, -1.0),
+            &Tuple::vector(0.0, -(2.0_f64.sqrt()) / 2.0, 2.0_f64.sqrt() / 2.0),
+        );
+        let i = Intersection::new(2.0_f64.sqrt(), shape);
+
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+
+        assert_eq!(
+            comps.reflectv,
+            Tuple::vector(0.0, 2.0_f64.sqrt() / 2.0, 2.0_f64.sqrt() / 2.0)
+        );
+    }
+
+    #[test]
+    fn test_the_schlick_approximation_under_total_internal_reflection() {
+        let shape = glass_sphere(Matrix::identify(), 1.5, 0);
+        let r = Ray::new(
+            &Tuple::point(0.0, 0.0, 2.0_f64.sqrt() / 2.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        let xs = Intersections::new(vec![
+            Intersection::new(-(2.0_f64.sqrt()) / 2.0, shape.clone()),
+            Intersection::new(2.0_f64.sqrt() / 2.0, shape),
+        ]);
+
+        let comps = xs.at(1).prepare_computation(&r, &xs);
+
+        assert!(equal_f64(comps.schlick(), 1.0));
+    }
+
+    #[test]
+    fn test_the_schlick_approximation_with_a_perpendicular_viewing_angle() {
+        let shape = glass_sphere(Matrix::identify(), 1.5, 0);
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+        let xs = Intersections::new(vec![
+            Intersection::new(-1.0, shape.clone()),
+            Intersection::new(1.0, shape),
+        ]);
+
+        let comps = xs.at(1).prepare_computation(&r, &xs);
+
+        assert!(equal_f64(comps.schlick(), 0.04));
+    }
+
+    #[test]
+    fn test_the_schlick_approximation_with_small_angle_and_n2_greater_than_n1() {
+        let shape = glass_sphere(Matrix::identify(), 1.5, 0);
+        let r = Ray::new(
+            &Tuple::point(0.0, 0.99, -2.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+        let xs = Intersections::new(vec![Intersection::new(1.8589, shape)]);
+
+        let comps = xs.at(0).prepare_computation(&r, &xs);
+
+        assert!((comps.schlick() - 0.48873).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_a_hit_records_the_absorption_of_the_medium_crossed_to_reach_it() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let mut m = Material::new();
+        m.absorption = Color::new(0.1, 0.2, 0.3);
+        let shape = Arc::new(Sphere::new().with_material(&m));
+        let xs = r.intersect(shape);
+
+        let entering = xs.at(0).prepare_computation(&r, &xs);
+        let leaving = xs.at(1).prepare_computation(&r, &xs);
+
+        assert_eq!(entering.absorption, Color::black());
+        assert_eq!(leaving.absorption, Color::new(0.1, 0.2, 0.3));
+    }
 }
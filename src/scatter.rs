@@ -0,0 +1,170 @@
+use crate::matrix::Matrix;
+use crate::shapes::{Group, Shape};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Scatters copies of a prop at random over the rectangle of the xz plane from `min`
+/// to `max`, e.g. trees over a hillside or debris over a floor, each turned about y and
+/// scaled by random amounts within the given ranges. The same seed always gives the
+/// same layout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scatter {
+    pub count: usize,
+    // (x, z) corners of the region.
+    pub min: (f64, f64),
+    pub max: (f64, f64),
+    // No two copies are placed closer than this, so a crowded region may get fewer
+    // than `count`.
+    pub min_spacing: f64,
+    // Radians about the y axis.
+    pub rotation: (f64, f64),
+    pub scale: (f64, f64),
+    pub seed: u64,
+}
+
+impl Scatter {
+    pub fn new(count: usize, min: (f64, f64), max: (f64, f64)) -> Self {
+        Self {
+            count,
+            min,
+            max,
+            min_spacing: 0.0,
+            rotation: (0.0, 0.0),
+            scale: (1.0, 1.0),
+            seed: 0,
+        }
+    }
+
+    pub fn with_spacing(mut self, min_spacing: f64) -> Self {
+        self.min_spacing = min_spacing;
+        self
+    }
+
+    pub fn with_rotation(mut self, min: f64, max: f64) -> Self {
+        self.rotation = (min, max);
+        self
+    }
+
+    pub fn with_scale(mut self, min: f64, max: f64) -> Self {
+        self.scale = (min, max);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// A transform placing each copy, to apply before the prop's own transform.
+    pub fn transforms(&self) -> Vec<Matrix> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut between = |(low, high): (f64, f64)| low + rng.gen::<f64>() * (high - low);
+        let mut placed: Vec<(f64, f64)> = vec![];
+        let mut transforms = vec![];
+
+        // Candidates too close to an earlier copy are thrown away, giving up after a
+        // fixed number of tries per copy.
+        for _ in 0..self.count * 30 {
+            if placed.len() == self.count {
+                break;
+            }
+            let x = between((self.min.0, self.max.0));
+            let z = between((self.min.1, self.max.1));
+            if placed
+                .iter()
+                .any(|(px, pz)| (x - px).hypot(z - pz) < self.min_spacing)
+            {
+                continue;
+            }
+
+            let angle = between(self.rotation);
+            let scale = between(self.scale);
+            placed.push((x, z));
+            transforms.push(
+                Matrix::translation(x, 0.0, z)
+                    * Matrix::rotation_y(angle)
+                    * Matrix::scaling(scale, scale, scale),
+            );
+        }
+
+        transforms
+    }
+
+    /// A group of copies of the prop `make` builds, called once per copy so that each
+    /// is a shape of its own.
+    pub fn group<S, F>(&self, mut make: F) -> Group
+    where
+        S: Shape + Send + Sync + 'static,
+        F: FnMut() -> S,
+    {
+        let mut group = Group::new();
+        for transform in self.transforms() {
+            let mut prop = make();
+            prop.set_transform(&(transform * prop.get_transform()));
+            group.add_child(prop);
+        }
+
+        group
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Sphere;
+    use crate::tuple::Tuple;
+    use std::collections::HashSet;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_scattered_copies_stay_in_the_region_and_apart() {
+        let scatter = Scatter::new(40, (-5.0, 0.0), (5.0, 10.0))
+            .with_spacing(1.0)
+            .with_rotation(0.0, PI)
+            .with_scale(0.5, 1.5)
+            .with_seed(7);
+
+        let positions: Vec<Tuple> = scatter
+            .transforms()
+            .iter()
+            .map(|t| t * &Tuple::point(0.0, 0.0, 0.0))
+            .collect();
+
+        assert_eq!(positions.len(), 40);
+        for (i, p) in positions.iter().enumerate() {
+            assert!((-5.0..=5.0).contains(&p.x) && (0.0..=10.0).contains(&p.z));
+            assert_eq!(p.y, 0.0);
+            for q in &positions[i + 1..] {
+                assert!((p - q).magnitude() >= 1.0);
+            }
+        }
+        assert_eq!(scatter.transforms(), scatter.clone().transforms());
+        assert_ne!(
+            scatter.clone().with_seed(8).transforms(),
+            scatter.transforms()
+        );
+    }
+
+    #[test]
+    fn test_a_crowded_region_gets_fewer_copies() {
+        let scatter = Scatter::new(50, (0.0, 0.0), (1.0, 1.0)).with_spacing(0.8);
+
+        let count = scatter.transforms().len();
+
+        assert!((1..=4).contains(&count));
+    }
+
+    #[test]
+    fn test_a_scattered_group_holds_distinct_shapes() {
+        let scatter = Scatter::new(5, (0.0, 0.0), (10.0, 10.0)).with_scale(2.0, 2.0);
+
+        let group =
+            scatter.group(|| Sphere::new().with_transform(&Matrix::translation(0.0, 1.0, 0.0)));
+
+        let ids: HashSet<_> = group.children().iter().map(|c| c.id()).collect();
+        assert_eq!(ids.len(), 5);
+        let first = &group.children()[0];
+        let placed = &scatter.transforms()[0] * &Tuple::point(0.0, 1.0, 0.0);
+        assert_eq!(first.object_to_world(&Tuple::point(0.0, 0.0, 0.0)), placed);
+    }
+}
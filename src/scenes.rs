@@ -0,0 +1,536 @@
+use crate::camera::Camera;
+use crate::canvas::{Canvas, Color};
+use crate::light::{AreaLight, Material, PointLight};
+use crate::matrix::Matrix;
+use crate::pattern::{CheckersPattern, StripePattern};
+use crate::shapes::{Csg, CsgOperation, Cube, Disc, Group, Plane, Shape, Sphere};
+use crate::transformations::view_transform;
+use crate::tuple::Tuple;
+use crate::world::World;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+const BACK_RANK: [Piece; 8] = [
+    Piece::Rook,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Queen,
+    Piece::King,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Rook,
+];
+
+pub const PREVIEW_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Piece {
+    Pawn,
+    Rook,
+    Knight,
+    Bishop,
+    Queen,
+    King,
+}
+
+/// A chessboard in its starting position: an 8x8 board of unit squares centred on the
+/// origin with its top at `y = 0`, white on ranks 1-2 (towards -z) and black on 7-8.
+/// Pieces are built from spheres, cubes and CSG, and held in a single divided group,
+/// so the scene exercises groups, CSG and the BVH together.
+pub fn chess() -> World {
+    let mut board_m = Material::new();
+    board_m.specular = 0.4;
+    board_m.pattern = Some(Arc::new(
+        CheckersPattern::new(&Color::new(0.2, 0.12, 0.08), &Color::new(0.85, 0.75, 0.6))
+            .with_transform(
+                &(Matrix::translation(0.0, 0.5, 0.0) * Matrix::scaling(0.25, 1.0, 0.25)),
+            ),
+    ));
+    let board = Cube::new()
+        .with_material(&board_m)
+        .with_transform(&(Matrix::translation(0.0, -0.2, 0.0) * Matrix::scaling(4.0, 0.2, 4.0)));
+
+    let white = piece_material(Color::new(0.9, 0.88, 0.82));
+    let black = piece_material(Color::new(0.12, 0.12, 0.14));
+
+    let mut pieces = Group::new();
+    for (file, piece) in BACK_RANK.iter().enumerate() {
+        pieces.add_child(place(build(*piece, &white), file, 0));
+        pieces.add_child(place(build(Piece::Pawn, &white), file, 1));
+        pieces.add_child(place(build(Piece::Pawn, &black), file, 6));
+        pieces.add_child(place(build(*piece, &black), file, 7));
+    }
+    pieces.divide(4);
+
+    let mut world = World::new();
+    world.lights = vec![PointLight::new(&Tuple::point(-6.0, 10.0, -8.0), &Color::white()).into()];
+    world.objects = vec![Arc::new(board), Arc::new(pieces)];
+
+    world
+}
+
+/// A camera looking at the chess scene from behind white's pieces.
+pub fn chess_camera(hsize: usize, vsize: usize) -> Camera {
+    let mut camera = Camera::new(hsize, vsize, PI / 3.0);
+    camera.transform = view_transform(
+        &Tuple::point(0.0, 6.5, -9.0),
+        &Tuple::point(0.0, 0.0, 0.0),
+        &Tuple::vector(0.0, 1.0, 0.0),
+    );
+
+    camera
+}
+
+/// The Cornell box: a room spanning `-1..1` in x and z and `0..2` in y, open towards
+/// -z, with a red left wall, a green right wall and two white boxes. It is lit, as the
+/// original is, by a square area light just below a glowing fixture in the ceiling.
+pub fn cornell_box() -> World {
+    let mut world = World::new();
+    let white = world
+        .materials
+        .register("white", matte(Color::new(0.725, 0.71, 0.68)));
+    let red = world
+        .materials
+        .register("red", matte(Color::new(0.63, 0.065, 0.05)));
+    let green = world
+        .materials
+        .register("green", matte(Color::new(0.14, 0.45, 0.091)));
+
+    let mut fixture_m = Material::new();
+    fixture_m.ambient = 1.0;
+    fixture_m.diffuse = 0.0;
+    fixture_m.specular = 0.0;
+
+    let floor = Plane::new().with_material(&white);
+    let ceiling = Plane::new()
+        .with_material(&white)
+        .with_transform(&Matrix::translation(0.0, 2.0, 0.0));
+    let back = Plane::new()
+        .with_material(&white)
+        .with_transform(&(Matrix::translation(0.0, 0.0, 1.0) * Matrix::rotation_x(PI / 2.0)));
+    let left = Plane::new()
+        .with_material(&red)
+        .with_transform(&(Matrix::translation(-1.0, 0.0, 0.0) * Matrix::rotation_z(PI / 2.0)));
+    let right = Plane::new()
+        .with_material(&green)
+        .with_transform(&(Matrix::translation(1.0, 0.0, 0.0) * Matrix::rotation_z(PI / 2.0)));
+    let fixture = Disc::new()
+        .with_material(&fixture_m)
+        .with_transform(&(Matrix::translation(0.0, 1.999, 0.0) * Matrix::scaling(0.25, 1.0, 0.25)));
+    let tall_box = Cube::new().with_material(&white).with_transform(
+        &(Matrix::translation(-0.35, 0.6, 0.3)
+            * Matrix::rotation_y(0.3)
+            * Matrix::scaling(0.3, 0.6, 0.3)),
+    );
+    let short_box = Cube::new().with_material(&white).with_transform(
+        &(Matrix::translation(0.35, 0.3, -0.3)
+            * Matrix::rotation_y(-0.3)
+            * Matrix::scaling(0.3, 0.3, 0.3)),
+    );
+
+    world.lights = vec![AreaLight::new(
+        &Tuple::point(-0.2, 1.98, -0.2),
+        &Tuple::vector(0.4, 0.0, 0.0),
+        4,
+        &Tuple::vector(0.0, 0.0, 0.4),
+        4,
+        &Color::white(),
+    )
+    .into()];
+    world.objects = vec![
+        Arc::new(floor),
+        Arc::new(ceiling),
+        Arc::new(back),
+        Arc::new(left),
+        Arc::new(right),
+        Arc::new(fixture),
+        Arc::new(tall_box),
+        Arc::new(short_box),
+    ];
+
+    world
+}
+
+/// A square camera framing the open side of the Cornell box.
+pub fn cornell_box_camera(size: usize) -> Camera {
+    let mut camera = Camera::new(size, size, 0.69);
+    camera.transform = view_transform(
+        &Tuple::point(0.0, 1.0, -3.8),
+        &Tuple::point(0.0, 1.0, 0.0),
+        &Tuple::vector(0.0, 1.0, 0.0),
+    );
+
+    camera
+}
+
+/// A unit sphere wearing `material`, resting on a grey checkered floor and lit from the
+/// upper left. Nothing but the sphere's material ever changes.
+pub fn material_preview(material: &Material) -> World {
+    let mut floor_m = matte(Color::new(0.5, 0.5, 0.5));
+    floor_m.pattern = Some(Arc::new(
+        CheckersPattern::new(&Color::new(0.35, 0.35, 0.35), &Color::new(0.65, 0.65, 0.65))
+            .with_transform(&Matrix::scaling(0.5, 0.5, 0.5)),
+    ));
+    let floor = Plane::new().with_material(&floor_m);
+    let swatch = Sphere::new()
+        .with_material(material)
+        .with_transform(&Matrix::translation(0.0, 1.0, 0.0));
+
+    let mut world = World::new();
+    world.lights = vec![PointLight::new(&Tuple::point(-5.0, 6.0, -6.0), &Color::white()).into()];
+    world.objects = vec![Arc::new(floor), Arc::new(swatch)];
+
+    world
+}
+
+/// Renders `material_preview` as a square swatch `PREVIEW_SIZE` pixels across with a
+/// fixed camera and no exposure adjustment, so swatches of different materials can be
+/// compared side by side.
+pub fn render_material_preview(material: &Material) -> Canvas {
+    let mut camera = Camera::new(PREVIEW_SIZE, PREVIEW_SIZE, PI / 4.0);
+    camera.samples = 4;
+    camera.transform = view_transform(
+        &Tuple::point(0.0, 2.0, -5.0),
+        &Tuple::point(0.0, 0.9, 0.0),
+        &Tuple::vector(0.0, 1.0, 0.0),
+    );
+
+    camera.render(&material_preview(material))
+}
+
+/// The classic random sphere field: three large spheres in a row at the origin,
+/// surrounded by `count` small spheres on a jittered grid with a random mix of matte,
+/// glossy and striped materials. The same seed always produces the same scene, so it
+/// makes a reproducible benchmark workload.
+pub fn random_spheres(seed: u64, count: usize) -> World {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let big = [
+        (Tuple::point(-2.2, 1.0, 0.0), Color::new(0.4, 0.2, 0.1)),
+        (Tuple::point(0.0, 1.0, 0.0), Color::new(0.9, 0.9, 0.9)),
+        (Tuple::point(2.2, 1.0, 0.0), Color::new(0.1, 0.3, 0.6)),
+    ];
+
+    // Grid cells nearest the middle come first, skipping those the large spheres cover.
+    let side = ((count + 32) as f64).sqrt().ceil() as i64;
+    let mut cells: Vec<(f64, f64)> = (0..side * side)
+        .map(|i| {
+            (
+                (i % side) as f64 - (side - 1) as f64 / 2.0,
+                (i / side) as f64 - (side - 1) as f64 / 2.0,
+            )
+        })
+        .filter(|(x, z)| big.iter().all(|(p, _)| (x - p.x).hypot(z - p.z) > 1.7))
+        .collect();
+    cells.sort_by(|a, b| a.0.hypot(a.1).partial_cmp(&b.0.hypot(b.1)).unwrap());
+
+    let mut small = Group::new();
+    for (x, z) in cells.into_iter().take(count) {
+        let x = x + rng.gen_range(-0.3..0.3);
+        let z = z + rng.gen_range(-0.3..0.3);
+        small.add_child(
+            Sphere::new()
+                .with_material(random_material(&mut rng))
+                .with_transform(&(Matrix::translation(x, 0.2, z) * Matrix::scaling(0.2, 0.2, 0.2))),
+        );
+    }
+    small.divide(8);
+
+    let mut world = World::new();
+    world.lights = vec![PointLight::new(&Tuple::point(-10.0, 10.0, -10.0), &Color::white()).into()];
+    world.objects.push(Arc::new(
+        Plane::new().with_material(matte(Color::new(0.5, 0.5, 0.5))),
+    ));
+    for (position, color) in big.iter() {
+        let mut m = piece_material(*color);
+        m.diffuse = 0.8;
+        world.objects.push(Arc::new(sphere(
+            &m,
+            Matrix::translation(position.x, position.y, position.z),
+        )));
+    }
+    world.objects.push(Arc::new(small));
+
+    world
+}
+
+fn random_material(rng: &mut ChaCha8Rng) -> Material {
+    let choice: f64 = rng.gen();
+    let mut random_color = || Color::new(rng.gen(), rng.gen(), rng.gen());
+
+    if choice < 0.6 {
+        matte(random_color())
+    } else if choice < 0.9 {
+        piece_material(random_color())
+    } else {
+        let mut m = matte(random_color());
+        m.pattern = Some(Arc::new(
+            StripePattern::new(&random_color(), &random_color())
+                .with_transform(&Matrix::scaling(0.25, 0.25, 0.25)),
+        ));
+        m
+    }
+}
+
+fn matte(color: Color) -> Material {
+    let mut m = Material::new();
+    m.color = color;
+    m.specular = 0.0;
+    m
+}
+
+fn piece_material(color: Color) -> Material {
+    let mut m = Material::new();
+    m.color = color;
+    m.diffuse = 0.7;
+    m.specular = 0.9;
+    m.shininess = 300.0;
+    m.reflective = 0.3;
+    m
+}
+
+// Centre of the square on `file` (a-h as 0-7) and `rank` (1-8 as 0-7).
+fn place(piece: Group, file: usize, rank: usize) -> Group {
+    piece.with_transform(&Matrix::translation(
+        file as f64 - 3.5,
+        0.0,
+        rank as f64 - 3.5,
+    ))
+}
+
+fn sphere(m: &Material, transform: Matrix) -> Sphere {
+    Sphere::new().with_material(m).with_transform(&transform)
+}
+
+fn cube(m: &Material, transform: Matrix) -> Cube {
+    Cube::new().with_material(m).with_transform(&transform)
+}
+
+// Box of half-extents (x, y, z) resting on y = `bottom`.
+fn block(m: &Material, x: f64, y: f64, z: f64, bottom: f64) -> Cube {
+    cube(
+        m,
+        Matrix::translation(0.0, bottom + y, 0.0) * Matrix::scaling(x, y, z),
+    )
+}
+
+fn build(piece: Piece, m: &Material) -> Group {
+    let base = block(m, 0.32, 0.05, 0.32, 0.0);
+    let group = Group::new().with_child(base);
+
+    match piece {
+        Piece::Pawn => group
+            .with_child(sphere(
+                m,
+                Matrix::translation(0.0, 0.35, 0.0) * Matrix::scaling(0.2, 0.3, 0.2),
+            ))
+            .with_child(sphere(
+                m,
+                Matrix::translation(0.0, 0.72, 0.0) * Matrix::scaling(0.15, 0.15, 0.15),
+            )),
+        Piece::Rook => {
+            let tower = block(m, 0.24, 0.45, 0.24, 0.0);
+            let slot_x = block(m, 0.3, 0.1, 0.06, 0.82);
+            let slot_z = block(m, 0.06, 0.1, 0.3, 0.82);
+            group.with_child(Csg::new(
+                CsgOperation::Difference,
+                Csg::new(CsgOperation::Difference, tower, slot_x),
+                slot_z,
+            ))
+        }
+        Piece::Knight => group
+            .with_child(block(m, 0.22, 0.35, 0.18, 0.0))
+            .with_child(cube(
+                m,
+                Matrix::translation(0.0, 0.85, -0.12)
+                    * Matrix::rotation_x(-PI / 5.0)
+                    * Matrix::scaling(0.14, 0.28, 0.3),
+            )),
+        Piece::Bishop => {
+            let head = sphere(
+                m,
+                Matrix::translation(0.0, 1.0, 0.0) * Matrix::scaling(0.17, 0.24, 0.17),
+            );
+            let notch = cube(
+                m,
+                Matrix::translation(0.0, 1.1, 0.0)
+                    * Matrix::rotation_z(PI / 4.0)
+                    * Matrix::scaling(0.3, 0.03, 0.3),
+            );
+            group
+                .with_child(sphere(
+                    m,
+                    Matrix::translation(0.0, 0.45, 0.0) * Matrix::scaling(0.2, 0.45, 0.2),
+                ))
+                .with_child(Csg::new(CsgOperation::Difference, head, notch))
+        }
+        Piece::Queen => {
+            let crown = Csg::new(
+                CsgOperation::Intersection,
+                sphere(
+                    m,
+                    Matrix::translation(0.0, 1.15, 0.0) * Matrix::scaling(0.26, 0.26, 0.26),
+                ),
+                block(m, 0.3, 0.2, 0.3, 1.0),
+            );
+            group
+                .with_child(sphere(
+                    m,
+                    Matrix::translation(0.0, 0.55, 0.0) * Matrix::scaling(0.22, 0.55, 0.22),
+                ))
+                .with_child(crown)
+                .with_child(sphere(
+                    m,
+                    Matrix::translation(0.0, 1.27, 0.0) * Matrix::scaling(0.07, 0.07, 0.07),
+                ))
+        }
+        Piece::King => group
+            .with_child(sphere(
+                m,
+                Matrix::translation(0.0, 0.6, 0.0) * Matrix::scaling(0.23, 0.6, 0.23),
+            ))
+            .with_child(block(m, 0.2, 0.06, 0.2, 1.12))
+            .with_child(block(m, 0.05, 0.18, 0.05, 1.24))
+            .with_child(block(m, 0.14, 0.05, 0.05, 1.38)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::diff;
+    use crate::light::Light;
+    use crate::ray::Ray;
+
+    #[test]
+    fn test_the_chess_scene_has_a_light_a_board_and_pieces() {
+        let w = chess();
+
+        assert!(!w.lights.is_empty());
+        assert_eq!(w.objects.len(), 2);
+        assert!(!w.objects[1].bounds_of().is_empty());
+    }
+
+    #[test]
+    fn test_an_empty_square_shows_the_board() {
+        let w = chess();
+        let r = Ray::new(&Tuple::point(0.5, 5.0, 0.5), &Tuple::vector(0.0, -1.0, 0.0));
+
+        let hit = w.intersect(&r).hit().unwrap();
+
+        assert_eq!(height_at(&r, hit.t), 0.0);
+    }
+
+    #[test]
+    fn test_pieces_stand_on_their_starting_squares() {
+        let w = chess();
+        let down = Tuple::vector(0.0, -1.0, 0.0);
+
+        let white_pawn = Ray::new(&Tuple::point(-3.5, 5.0, -2.5), &down);
+        let hit = w.intersect(&white_pawn).hit().unwrap();
+        assert_eq!(height_at(&white_pawn, hit.t), 0.87);
+        assert_eq!(hit.object.get_material().color, Color::new(0.9, 0.88, 0.82));
+        assert!(hit.object.get_material().reflective > 0.0);
+
+        let black_king = Ray::new(&Tuple::point(0.5, 5.0, 3.5), &down);
+        let hit = w.intersect(&black_king).hit().unwrap();
+        assert_eq!(height_at(&black_king, hit.t), 1.6);
+        assert_eq!(
+            hit.object.get_material().color,
+            Color::new(0.12, 0.12, 0.14)
+        );
+    }
+
+    fn height_at(r: &Ray, t: f64) -> f64 {
+        (r.position(t).y * 100.0).round() / 100.0
+    }
+
+    #[test]
+    fn test_the_cornell_box_has_red_and_green_side_walls() {
+        let w = cornell_box();
+        let color_hit = |direction: Tuple| {
+            let r = Ray::new(&Tuple::point(0.0, 1.0, -0.9), &direction);
+            w.intersect(&r).hit().unwrap().object.get_material().color
+        };
+
+        assert_eq!(
+            color_hit(Tuple::vector(-1.0, 0.0, 0.0)),
+            Color::new(0.63, 0.065, 0.05)
+        );
+        assert_eq!(
+            color_hit(Tuple::vector(1.0, 0.0, 0.0)),
+            Color::new(0.14, 0.45, 0.091)
+        );
+        assert_eq!(
+            color_hit(Tuple::vector(0.0, 1.0, 0.0)),
+            Color::new(0.725, 0.71, 0.68)
+        );
+    }
+
+    #[test]
+    fn test_the_cornell_box_light_is_inside_the_room() {
+        let w = cornell_box();
+        let light = w.lights[0].clone();
+
+        assert!(matches!(light, Light::Area(_)));
+        assert!(light.position().y < 2.0);
+        assert!(!w.is_shadowed(&Tuple::point(0.0, 0.01, -0.8), &light));
+        assert!(w.is_shadowed(&Tuple::point(-0.35, 0.01, 0.7), &light));
+    }
+
+    #[test]
+    fn test_random_spheres_are_reproducible_from_a_seed() {
+        let mut camera = Camera::new(32, 24, PI / 3.0);
+        camera.transform = view_transform(
+            &Tuple::point(0.0, 3.0, -8.0),
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        let a = camera.render(&random_spheres(7, 50));
+        let b = camera.render(&random_spheres(7, 50));
+        let c = camera.render(&random_spheres(8, 50));
+
+        assert!(diff(&a, &b).within(0.0));
+        assert!(!diff(&a, &c).within(0.0));
+    }
+
+    #[test]
+    fn test_random_spheres_stay_clear_of_the_large_spheres() {
+        let w = random_spheres(1, 200);
+        let small = w.objects[4].bounds_of();
+
+        assert_eq!(w.objects.len(), 5);
+        assert_eq!(small.min.y, 0.0);
+        assert_eq!(small.max.y, 0.4);
+        for big in &w.objects[1..4] {
+            let r = Ray::new(
+                &Tuple::point(big.get_transform().at(0, 3), 10.0, 0.0),
+                &Tuple::vector(0.0, -1.0, 0.0),
+            );
+            let xs = w.objects[4].local_intersect(&r, w.objects[4].clone(), false);
+            assert!(xs.hit().is_none());
+        }
+    }
+
+    #[test]
+    fn test_material_previews_differ_only_in_the_swatch() {
+        let mut red = Material::new();
+        red.color = Color::new(1.0, 0.0, 0.0);
+        let mut blue = Material::new();
+        blue.color = Color::new(0.0, 0.0, 1.0);
+
+        let a = render_material_preview(&red);
+        let b = render_material_preview(&blue);
+        let center = PREVIEW_SIZE / 2;
+
+        assert_eq!(a.width, PREVIEW_SIZE);
+        assert_eq!(a.height, PREVIEW_SIZE);
+        assert!(a.pixel_at(center, center).red > a.pixel_at(center, center).blue);
+        assert!(b.pixel_at(center, center).blue > b.pixel_at(center, center).red);
+        assert_eq!(
+            a.pixel_at(2, PREVIEW_SIZE - 3),
+            b.pixel_at(2, PREVIEW_SIZE - 3)
+        );
+    }
+}
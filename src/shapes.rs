@@ -1,67 +1,342 @@
-use crate::light::Material;
+use crate::bounds::BoundingBox;
+use crate::light::{Material, MaterialHandle};
 use crate::matrix::Matrix;
-use crate::ray::Ray;
+use crate::pattern::{cubic_map, planar_map, spherical_map};
+use crate::ray::{Intersection, Intersections, Ray};
 use crate::tuple::Tuple;
 use crate::utils::EPSILON;
-use std::fmt::Debug;
+use rand::{Rng, RngCore};
+use std::cmp;
+use std::f64::consts::PI;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Identifies a shape for its whole life: assigned when the shape is created. Each
+/// clone gets an id of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShapeId(u64);
+
+impl ShapeId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        ShapeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// A shape's own id, and the id of the shape it was first cloned from, which is how a
+// shape is matched with its copy in another frame. Not part of a shape's equality.
+#[derive(Debug)]
+struct Identity {
+    id: ShapeId,
+    lineage: ShapeId,
+}
+
+impl Identity {
+    fn new() -> Self {
+        let id = ShapeId::next();
+        Self { id, lineage: id }
+    }
+}
+
+impl Clone for Identity {
+    fn clone(&self) -> Self {
+        Self {
+            id: ShapeId::next(),
+            lineage: self.lineage,
+        }
+    }
+}
+
+impl PartialEq for Identity {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Display for ShapeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Where a shape's material came from. Groups hand their material down to children
+/// that have none of their own, and the world's default material stands in for shapes
+/// that are still on `Default`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaterialSource {
+    Default,
+    Inherited,
+    Own,
+}
 
 pub trait Shape {
     fn get_transform(&self) -> Matrix;
+    fn get_inverse_transform(&self) -> Matrix;
     fn set_transform(&mut self, transform: &Matrix);
 
-    fn get_material(&self) -> Material;
-    fn set_material(&mut self, material: &Material);
+    // Inverse of the combined transform of every group enclosing this shape.
+    fn get_parent_inverse_transform(&self) -> Matrix;
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix);
+
+    // Shapes hold their material through a handle, so shapes given the same handle
+    // share one allocation until one of them edits it.
+    fn material_handle(&self) -> MaterialHandle;
+    fn set_material_handle(&mut self, material: &MaterialHandle);
+
+    fn get_material(&self) -> Material {
+        (*self.material_handle()).clone()
+    }
+
+    fn set_material(&mut self, material: &Material) {
+        self.set_material_handle(&material.into());
+    }
+
+    /// Changes this shape's material alone, copying it first if other shapes share it.
+    fn edit_material(&mut self, edit: &dyn Fn(&mut Material)) {
+        let mut material = self.material_handle();
+        material.edit(edit);
+        self.set_material_handle(&material);
+    }
+
+    fn material_source(&self) -> MaterialSource;
+    // Takes `material` from an enclosing group unless the shape has its own.
+    fn inherit_material(&mut self, material: &MaterialHandle);
+
+    fn id(&self) -> ShapeId;
+    /// The id of the shape this one was first cloned from, or its own id if it is an
+    /// original, so a shape can be followed from one frame to the next.
+    fn lineage(&self) -> ShapeId;
+    fn get_name(&self) -> Option<&str>;
+    fn set_name(&mut self, name: &str);
+
+    fn with_name(mut self, name: &str) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_name(name);
+        self
+    }
+
+    // The first shape beneath this one, depth first, that matches `predicate`. Only
+    // shapes with children have anything to search.
+    fn find(
+        &self,
+        _predicate: &dyn Fn(&dyn Shape) -> bool,
+    ) -> Option<Arc<dyn Shape + Send + Sync>> {
+        None
+    }
 
     fn intersect(&self, ray: &Ray) -> Vec<f64>;
 
+    // The hits where the ray strikes the side the normal faces, for culling back faces.
+    // The sign of the dot product survives the transform to world space.
+    fn intersect_front(&self, ray: &Ray) -> Vec<f64> {
+        let mut ts = self.intersect(ray);
+        ts.retain(|t| self.local_normal_at(&ray.position(*t)).dot(&ray.direction) < 0.0);
+        ts
+    }
+
+    // With `cull` set, one-sided materials are only hit on their front faces.
+    fn local_intersect(
+        &self,
+        local_ray: &Ray,
+        object: Arc<dyn Shape>,
+        cull: bool,
+    ) -> Intersections {
+        let ts = if cull && !self.material_handle().two_sided {
+            self.intersect_front(local_ray)
+        } else {
+            self.intersect(local_ray)
+        };
+
+        Intersections::new(
+            ts.iter()
+                .map(|t| Intersection::new(*t, object.clone()))
+                .collect(),
+        )
+    }
+
+    fn includes(&self, object: &dyn Shape) -> bool {
+        std::ptr::eq(
+            self as *const Self as *const (),
+            object as *const dyn Shape as *const (),
+        )
+    }
+
+    fn world_to_object(&self, world_point: &Tuple) -> Tuple {
+        self.get_inverse_transform() * (self.get_parent_inverse_transform() * world_point)
+    }
+
+    fn object_to_world(&self, local_point: &Tuple) -> Tuple {
+        let to_object = self.get_inverse_transform() * self.get_parent_inverse_transform();
+        to_object.inverse().unwrap() * local_point
+    }
+
+    fn normal_to_world(&self, local_normal: &Tuple) -> Tuple {
+        let normal = self.get_parent_inverse_transform().transpose()
+            * (self.get_inverse_transform().transpose() * local_normal);
+
+        Tuple::vector(normal.x, normal.y, normal.z).normalize()
+    }
+
     fn local_normal_at(&self, local_point: &Tuple) -> Tuple;
     fn normal_at(&self, world_point: &Tuple) -> Tuple {
-        let transform = self.get_transform();
-        let shape_inverse = &transform.inverse().unwrap();
-        let local_point = shape_inverse * world_point;
+        let local_point = self.world_to_object(world_point);
         let local_normal = self.local_normal_at(&local_point);
-        let world_normal = shape_inverse.transpose() * local_normal;
 
-        Tuple::vector(world_normal.x, world_normal.y, world_normal.z).normalize()
+        self.normal_to_world(&local_normal)
+    }
+
+    // Bounds in object space, before this shape's own transform is applied.
+    fn bounds_of(&self) -> BoundingBox;
+    fn parent_space_bounds_of(&self) -> BoundingBox {
+        self.bounds_of().transform(&self.get_transform())
+    }
+
+    // The area of the surface in world space, and a point on it with the normal there,
+    // picked uniformly by area, for sampling glowing shapes as lights. Shapes that don't
+    // support sampling have no area; unbounded ones have an infinite area.
+    fn surface_area(&self) -> f64 {
+        0.0
+    }
+    fn sample_point(&self, _rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        None
+    }
+
+    // Texture coordinates in [0, 1] for a point on the surface in object space. Shapes
+    // without a mapping of their own are wrapped in a sphere around their origin.
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        spherical_map(local_point)
+    }
+
+    // Where a ray that strikes this shape at `world_point` carries on from, for shapes
+    // such as portals that send rays elsewhere instead of being shaded.
+    fn teleport(&self, _ray: &Ray, _world_point: &Tuple) -> Option<Ray> {
+        None
+    }
+
+    // Reorganizes any groups beneath this shape into a bounding volume hierarchy,
+    // splitting those with at least `threshold` children. Shapes that are shared
+    // elsewhere can't be changed and are left as they are.
+    fn divide(&mut self, _threshold: usize) {}
+
+    // How many shapes, other than the groups holding them, this shape is made of.
+    fn primitive_count(&self) -> usize {
+        1
+    }
+
+    // This shape as a group, for walking down a scene graph.
+    fn as_group(&self) -> Option<&Group> {
+        None
     }
 }
 
+// Shapes cache the inverse of their transform, which is `None` when the transform is
+// singular. Such a shape can still be built and placed; as when the inverse was
+// computed on every use, it only panics once something needs the inverse.
+fn inverse_of(inverse: &Option<Matrix>) -> Matrix {
+    inverse
+        .clone()
+        .expect("a shape's transform must be invertible to render it")
+}
+
 impl Debug for dyn Shape {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Shape transform: {{{:?}}}", self.get_transform())
     }
 }
 
+impl Debug for dyn Shape + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Shape transform: {{{:?}}}", self.get_transform())
+    }
+}
+
+#[cfg(test)]
 #[derive(Debug, Clone, PartialEq)]
 struct TestShape {
     transform: Matrix,
-    material: Material,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
 }
 
+#[cfg(test)]
 impl TestShape {
     fn new() -> Self {
         TestShape {
             transform: Matrix::identify(),
-            material: Material::new(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
         }
     }
 }
 
+#[cfg(test)]
 impl Shape for TestShape {
     fn get_transform(&self) -> Matrix {
         self.transform.clone()
     }
 
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
     fn set_transform(&mut self, transform: &Matrix) {
-        self.transform = transform.clone()
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
     }
 
-    fn get_material(&self) -> Material {
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
         self.material.clone()
     }
 
-    fn set_material(&mut self, material: &Material) {
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
         self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
     }
 
     fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
@@ -71,6 +346,10 @@ impl Shape for TestShape {
     fn intersect(&self, _ray: &Ray) -> Vec<f64> {
         todo!()
     }
+
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -78,7 +357,12 @@ pub struct Sphere {
     pub origin: Tuple,
     pub radii: f64,
     transform: Matrix,
-    material: Material,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
 }
 
 impl Sphere {
@@ -87,7 +371,12 @@ impl Sphere {
             origin: Tuple::point(0.0, 0.0, 0.0),
             radii: 1.0,
             transform: Matrix::identify(),
-            material: Material::new(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
         }
     }
 
@@ -96,35 +385,92 @@ impl Sphere {
             origin: self.origin,
             radii: self.radii,
             transform: transform.clone(),
+            inverse: transform.inverse(),
+            parent_inverse: self.parent_inverse,
             material: self.material,
+            identity: self.identity,
+            name: self.name,
+            material_source: self.material_source,
         }
     }
 
-    pub fn with_material(self, material: &Material) -> Self {
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
         Self {
             origin: self.origin,
             radii: self.radii,
             transform: self.transform,
-            material: material.clone(),
+            inverse: self.inverse,
+            parent_inverse: self.parent_inverse,
+            material: material.into(),
+            identity: self.identity,
+            name: self.name,
+            material_source: MaterialSource::Own,
         }
     }
 }
 
+impl Default for Sphere {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Shape for Sphere {
     fn get_transform(&self) -> Matrix {
         self.transform.clone()
     }
 
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
     fn set_transform(&mut self, transform: &Matrix) {
         self.transform = transform.clone();
+        self.inverse = transform.inverse();
     }
 
-    fn get_material(&self) -> Material {
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
         self.material.clone()
     }
 
-    fn set_material(&mut self, material: &Material) {
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
         self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
     }
 
     fn intersect(&self, ray: &Ray) -> Vec<f64> {
@@ -147,52 +493,143 @@ impl Shape for Sphere {
     fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
         local_point - &Tuple::point(0.0, 0.0, 0.0)
     }
+
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0))
+    }
+
+    // Exact for spheres scaled evenly; for stretched ones the scale is averaged over a
+    // spread of directions.
+    fn surface_area(&self) -> f64 {
+        let directions = 256;
+        let scale: f64 = (0..directions)
+            .map(|i| area_scale(self, &fibonacci_direction(i, directions)))
+            .sum();
+
+        4.0 * PI * scale / directions as f64
+    }
+
+    fn sample_point(&self, rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        let y = 1.0 - 2.0 * rng.gen::<f64>();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let local = Tuple::point(r * phi.cos(), y, r * phi.sin());
+
+        Some(world_sample(self, &local))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Plane {
     transform: Matrix,
-    material: Material,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
 }
 
 impl Plane {
     pub fn new() -> Self {
         Self {
             transform: Matrix::identify(),
-            material: Material::new(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
         }
     }
 
     pub fn with_transform(self, transform: &Matrix) -> Self {
         Self {
             transform: transform.clone(),
+            inverse: transform.inverse(),
+            parent_inverse: self.parent_inverse,
             material: self.material,
+            identity: self.identity,
+            name: self.name,
+            material_source: self.material_source,
         }
     }
 
-    pub fn with_material(self, material: &Material) -> Self {
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
         Self {
             transform: self.transform,
-            material: material.clone(),
+            inverse: self.inverse,
+            parent_inverse: self.parent_inverse,
+            material: material.into(),
+            identity: self.identity,
+            name: self.name,
+            material_source: MaterialSource::Own,
         }
     }
 }
 
+impl Default for Plane {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Shape for Plane {
     fn get_transform(&self) -> Matrix {
         self.transform.clone()
     }
 
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
     fn set_transform(&mut self, transform: &Matrix) {
         self.transform = transform.clone();
+        self.inverse = transform.inverse();
     }
 
-    fn get_material(&self) -> Material {
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
         self.material.clone()
     }
 
-    fn set_material(&mut self, material: &Material) {
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
         self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
     }
 
     fn intersect(&self, ray: &Ray) -> Vec<f64> {
@@ -206,230 +643,3535 @@ impl Shape for Plane {
     fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
         Tuple::vector(0.0, 1.0, 0.0)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::f64::consts::PI;
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(
+            Tuple::point(f64::NEG_INFINITY, 0.0, f64::NEG_INFINITY),
+            Tuple::point(f64::INFINITY, 0.0, f64::INFINITY),
+        )
+    }
 
-    // Shapes
-    #[test]
-    fn test_the_default_transformation() {
-        let s = TestShape::new();
+    fn surface_area(&self) -> f64 {
+        f64::INFINITY
+    }
 
-        assert_eq!(s.get_transform(), Matrix::identify());
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        planar_map(local_point)
     }
+}
 
-    #[test]
-    fn test_assigning_a_transform() {
-        let mut s = TestShape::new();
-        s.set_transform(&Matrix::translation(2.0, 3.0, 4.0));
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cube {
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
 
-        assert_eq!(s.get_transform(), Matrix::translation(2.0, 3.0, 4.0));
+impl Cube {
+    pub fn new() -> Self {
+        Self {
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
     }
 
-    #[test]
-    fn test_the_default_material() {
-        let s = TestShape::new();
+    pub fn with_transform(self, transform: &Matrix) -> Self {
+        Self {
+            transform: transform.clone(),
+            inverse: transform.inverse(),
+            parent_inverse: self.parent_inverse,
+            material: self.material,
+            identity: self.identity,
+            name: self.name,
+            material_source: self.material_source,
+        }
+    }
 
-        assert_eq!(s.get_material(), Material::new());
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            transform: self.transform,
+            inverse: self.inverse,
+            parent_inverse: self.parent_inverse,
+            material: material.into(),
+            identity: self.identity,
+            name: self.name,
+            material_source: MaterialSource::Own,
+        }
     }
 
-    #[test]
-    fn test_assign_a_material() {
-        let mut s = TestShape::new();
-        let mut m = Material::new();
-        m.ambient = 1.0;
+    fn check_axis(origin: f64, direction: f64) -> (f64, f64) {
+        let tmin_numerator = -1.0 - origin;
+        let tmax_numerator = 1.0 - origin;
 
-        s.set_material(&m);
+        let (tmin, tmax) = if direction.abs() >= EPSILON {
+            (tmin_numerator / direction, tmax_numerator / direction)
+        } else {
+            (
+                tmin_numerator * f64::INFINITY,
+                tmax_numerator * f64::INFINITY,
+            )
+        };
 
-        assert_eq!(s.get_material(), m);
+        if tmin > tmax {
+            (tmax, tmin)
+        } else {
+            (tmin, tmax)
+        }
     }
 
-    #[test]
-    fn test_computing_the_normal_on_a_translated_shape() {
-        let mut s = TestShape::new();
-        s.set_transform(&Matrix::translation(0.0, 1.0, 0.0));
-        let n = s.normal_at(&Tuple::point(0.0, 1.70711, -0.70711));
+    // World space areas of the +x, -x, +y, -y, +z and -z faces.
+    fn face_areas(&self) -> [f64; 6] {
+        let mut areas = [0.0; 6];
+        for (face, area) in areas.iter_mut().enumerate() {
+            let mut normal = [0.0; 3];
+            normal[face / 2] = if face % 2 == 0 { 1.0 } else { -1.0 };
+            let normal = Tuple::vector(normal[0], normal[1], normal[2]);
+            *area = 4.0 * area_scale(self, &normal);
+        }
 
-        assert_eq!(n, Tuple::vector(0.0, 0.70711, -0.70711));
+        areas
     }
+}
 
-    #[test]
-    fn test_computing_the_normal_on_a_transformed_shape() {
-        let mut s = TestShape::new();
-        let m = Matrix::scaling(1.0, 0.5, 1.0) * Matrix::rotation_z(PI / 5.0);
-        s.set_transform(&m);
-        let n = s.normal_at(&Tuple::point(
-            0.0,
-            2.0_f64.sqrt() / 2.0,
-            -2.0_f64.sqrt() / 2.0,
-        ));
+impl Default for Cube {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        assert_eq!(n, Tuple::vector(0.0, 0.97014, -0.24254));
+impl Shape for Cube {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
     }
 
-    // Spheres
-    #[test]
-    fn test_the_normal_sphere_at_a_point_on_the_x_axis() {
-        let s = Sphere::new();
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
 
-        let n = s.normal_at(&Tuple::point(1.0, 0.0, 0.0));
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
 
-        assert_eq!(n, Tuple::vector(1.0, 0.0, 0.0));
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
     }
 
-    #[test]
-    fn test_the_normal_sphere_at_a_point_on_the_y_axis() {
-        let s = Sphere::new();
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
 
-        let n = s.normal_at(&Tuple::point(0.0, 1.0, 0.0));
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
 
-        assert_eq!(n, Tuple::vector(0.0, 1.0, 0.0));
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
     }
 
-    #[test]
-    fn test_the_normal_sphere_at_a_point_on_the_z_axis() {
-        let s = Sphere::new();
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
 
-        let n = s.normal_at(&Tuple::point(0.0, 0.0, 1.0));
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
 
-        assert_eq!(n, Tuple::vector(0.0, 0.0, 1.0));
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
     }
 
-    #[test]
-    fn test_the_normal_sphere_at_a_point_on_a_nonaxial_point() {
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        let (xtmin, xtmax) = Self::check_axis(ray.origin.x, ray.direction.x);
+        let (ytmin, ytmax) = Self::check_axis(ray.origin.y, ray.direction.y);
+        let (ztmin, ztmax) = Self::check_axis(ray.origin.z, ray.direction.z);
+
+        let tmin = xtmin.max(ytmin).max(ztmin);
+        let tmax = xtmax.min(ytmax).min(ztmax);
+
+        if tmin > tmax {
+            vec![]
+        } else {
+            vec![tmin, tmax]
+        }
+    }
+
+    fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
+        let x = local_point.x.abs();
+        let y = local_point.y.abs();
+        let z = local_point.z.abs();
+        let maxc = x.max(y).max(z);
+
+        if maxc == x {
+            Tuple::vector(local_point.x, 0.0, 0.0)
+        } else if maxc == y {
+            Tuple::vector(0.0, local_point.y, 0.0)
+        } else {
+            Tuple::vector(0.0, 0.0, local_point.z)
+        }
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(Tuple::point(-1.0, -1.0, -1.0), Tuple::point(1.0, 1.0, 1.0))
+    }
+
+    fn surface_area(&self) -> f64 {
+        self.face_areas().iter().sum()
+    }
+
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        cubic_map(local_point)
+    }
+
+    fn sample_point(&self, rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        let areas = self.face_areas();
+        let mut target = rng.gen::<f64>() * areas.iter().sum::<f64>();
+        let face = areas
+            .iter()
+            .position(|a| {
+                target -= a;
+                target < 0.0
+            })
+            .unwrap_or(5);
+
+        let mut coordinates = [
+            2.0 * rng.gen::<f64>() - 1.0,
+            2.0 * rng.gen::<f64>() - 1.0,
+            2.0 * rng.gen::<f64>() - 1.0,
+        ];
+        coordinates[face / 2] = if face % 2 == 0 { 1.0 } else { -1.0 };
+        let local = Tuple::point(coordinates[0], coordinates[1], coordinates[2]);
+
+        Some(world_sample(self, &local))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disc {
+    pub inner_radius: f64,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
+
+impl Disc {
+    pub fn new() -> Self {
+        Self {
+            inner_radius: 0.0,
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            material: material.into(),
+            material_source: MaterialSource::Own,
+            ..self
+        }
+    }
+
+    // Cuts a hole of the given radius out of the middle, turning the disc into a ring.
+    pub fn with_inner_radius(self, inner_radius: f64) -> Self {
+        Self {
+            inner_radius,
+            ..self
+        }
+    }
+}
+
+impl Default for Disc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape for Disc {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        if ray.direction.y.abs() < EPSILON {
+            return vec![];
+        }
+
+        let t = -ray.origin.y / ray.direction.y;
+        let p = ray.position(t);
+        let distance = p.x * p.x + p.z * p.z;
+        if distance <= 1.0 && distance >= self.inner_radius * self.inner_radius {
+            vec![t]
+        } else {
+            vec![]
+        }
+    }
+
+    fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
+        Tuple::vector(0.0, 1.0, 0.0)
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(Tuple::point(-1.0, 0.0, -1.0), Tuple::point(1.0, 0.0, 1.0))
+    }
+
+    fn surface_area(&self) -> f64 {
+        let local = PI * (1.0 - self.inner_radius * self.inner_radius);
+        local * area_scale(self, &Tuple::vector(0.0, 1.0, 0.0))
+    }
+
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        square_uv(local_point)
+    }
+
+    fn sample_point(&self, rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        let inner = self.inner_radius * self.inner_radius;
+        let r = (inner + rng.gen::<f64>() * (1.0 - inner)).sqrt();
+        let theta = 2.0 * PI * rng.gen::<f64>();
+        let local = Tuple::point(r * theta.cos(), 0.0, r * theta.sin());
+
+        Some(world_sample(self, &local))
+    }
+}
+
+/// A square doorway from -1 to 1 in x and z that re-emits the rays striking it from
+/// somewhere else: a ray's position and direction relative to the portal are carried
+/// through `exit`. Portals don't block light, so their far side stays lit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portal {
+    pub exit: Matrix,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
+
+impl Portal {
+    pub fn new() -> Self {
+        let mut material = Material::new();
+        material.visibility.shadow = false;
+        Self {
+            exit: Matrix::identify(),
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: material.into(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
+    }
+
+    /// Two linked portals placed by the given transforms. Stepping into the front of
+    /// either, the side its normal faces, steps out of the front of the other.
+    pub fn pair(a: &Matrix, b: &Matrix) -> (Self, Self) {
+        let turn = Matrix::rotation_z(std::f64::consts::PI);
+        (
+            Self::new()
+                .with_transform(a)
+                .with_exit(&(b.clone() * turn.clone())),
+            Self::new().with_transform(b).with_exit(&(a.clone() * turn)),
+        )
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            material: material.into(),
+            material_source: MaterialSource::Own,
+            ..self
+        }
+    }
+
+    pub fn with_exit(self, exit: &Matrix) -> Self {
+        Self {
+            exit: exit.clone(),
+            ..self
+        }
+    }
+}
+
+impl Default for Portal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape for Portal {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        if ray.direction.y.abs() < EPSILON {
+            return vec![];
+        }
+
+        let t = -ray.origin.y / ray.direction.y;
+        let p = ray.position(t);
+        if p.x.abs() <= 1.0 && p.z.abs() <= 1.0 {
+            vec![t]
+        } else {
+            vec![]
+        }
+    }
+
+    fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
+        Tuple::vector(0.0, 1.0, 0.0)
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        BoundingBox::new(Tuple::point(-1.0, 0.0, -1.0), Tuple::point(1.0, 0.0, 1.0))
+    }
+
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        square_uv(local_point)
+    }
+
+    fn teleport(&self, ray: &Ray, world_point: &Tuple) -> Option<Ray> {
+        let to_object = self.get_inverse_transform() * self.get_parent_inverse_transform();
+        let point = &self.exit * &(self.world_to_object(world_point));
+        let direction = &self.exit * &(&to_object * &ray.direction);
+        // Start just past the exit so the ray doesn't strike it again.
+        let origin = &point + &(&direction.normalize() * EPSILON);
+
+        Some(Ray::new(&origin, &direction))
+    }
+}
+
+/// A polygon in the xz plane, given as `(x, z)` corners, extruded along y from `minimum`
+/// to `maximum`. The polygon may be concave but must not cross itself. Repeated corners,
+/// such as a last one that closes the outline on the first, are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prism {
+    pub points: Vec<(f64, f64)>,
+    pub minimum: f64,
+    pub maximum: f64,
+    // +1 when the corners wind counter-clockwise looking down the y axis, -1 otherwise.
+    winding: f64,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
+
+impl Prism {
+    pub fn new(points: &[(f64, f64)], minimum: f64, maximum: f64) -> Self {
+        // A repeated corner makes a zero-length edge, which has no normal.
+        let mut points = points.to_vec();
+        points.dedup();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        let doubled_area: f64 = Self::edges_of(&points)
+            .map(|((x1, z1), (x2, z2))| x1 * z2 - x2 * z1)
+            .sum();
+
+        Self {
+            points,
+            minimum,
+            maximum,
+            winding: if doubled_area < 0.0 { -1.0 } else { 1.0 },
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            material: material.into(),
+            material_source: MaterialSource::Own,
+            ..self
+        }
+    }
+
+    fn edges_of(points: &[(f64, f64)]) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| (*a, *b))
+    }
+
+    // Even-odd rule: a ray along +x from the point crosses the outline an odd number of
+    // times when the point is inside.
+    fn contains(&self, x: f64, z: f64) -> bool {
+        Self::edges_of(&self.points)
+            .filter(|((x1, z1), (x2, z2))| {
+                (z1 > &z) != (z2 > &z) && x < x1 + (z - z1) * (x2 - x1) / (z2 - z1)
+            })
+            .count()
+            % 2
+            == 1
+    }
+
+    fn intersect_caps(&self, ray: &Ray, xs: &mut Vec<f64>) {
+        if ray.direction.y.abs() < EPSILON {
+            return;
+        }
+
+        for y in [self.minimum, self.maximum].iter() {
+            let t = (y - ray.origin.y) / ray.direction.y;
+            let p = ray.position(t);
+            if self.contains(p.x, p.z) {
+                xs.push(t);
+            }
+        }
+    }
+}
+
+impl Shape for Prism {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    // Each side is a segment in the xz plane; the ray's xz projection is intersected with
+    // it and the hit kept if it falls between the caps. Corners belong to one edge only.
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        let mut xs = vec![];
+        let (dx, dz) = (ray.direction.x, ray.direction.z);
+
+        for ((x1, z1), (x2, z2)) in Self::edges_of(&self.points) {
+            let (ex, ez) = (x2 - x1, z2 - z1);
+            let denominator = dx * ez - dz * ex;
+            if denominator.abs() < EPSILON {
+                continue;
+            }
+
+            let (ox, oz) = (x1 - ray.origin.x, z1 - ray.origin.z);
+            let t = (ox * ez - oz * ex) / denominator;
+            let along = (ox * dz - oz * dx) / denominator;
+            let y = ray.origin.y + t * ray.direction.y;
+            if (0.0..1.0).contains(&along) && self.minimum < y && y < self.maximum {
+                xs.push(t);
+            }
+        }
+        self.intersect_caps(ray, &mut xs);
+
+        xs
+    }
+
+    fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
+        if local_point.y >= self.maximum - EPSILON {
+            return Tuple::vector(0.0, 1.0, 0.0);
+        }
+        if local_point.y <= self.minimum + EPSILON {
+            return Tuple::vector(0.0, -1.0, 0.0);
+        }
+
+        let distance_to = |((x1, z1), (x2, z2)): ((f64, f64), (f64, f64))| {
+            let (ex, ez) = (x2 - x1, z2 - z1);
+            let (px, pz) = (local_point.x - x1, local_point.z - z1);
+            let along = ((px * ex + pz * ez) / (ex * ex + ez * ez)).clamp(0.0, 1.0);
+            (px - along * ex).powi(2) + (pz - along * ez).powi(2)
+        };
+        let ((x1, z1), (x2, z2)) = Self::edges_of(&self.points)
+            .min_by(|a, b| distance_to(*a).total_cmp(&distance_to(*b)))
+            .unwrap();
+
+        Tuple::vector(z2 - z1, 0.0, x1 - x2).normalize() * self.winding
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        let mut bounds = BoundingBox::empty();
+        for (x, z) in &self.points {
+            bounds.add_point(&Tuple::point(*x, self.minimum, *z));
+            bounds.add_point(&Tuple::point(*x, self.maximum, *z));
+        }
+
+        bounds
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Triangle {
+    pub p1: Tuple,
+    pub p2: Tuple,
+    pub p3: Tuple,
+    pub e1: Tuple,
+    pub e2: Tuple,
+    pub normal: Tuple,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
+
+impl Triangle {
+    pub fn new(p1: &Tuple, p2: &Tuple, p3: &Tuple) -> Self {
+        let e1 = p2 - p1;
+        let e2 = p3 - p1;
+        let normal = e2.cross(&e1).normalize();
+
+        Self {
+            p1: p1.clone(),
+            p2: p2.clone(),
+            p3: p3.clone(),
+            e1,
+            e2,
+            normal,
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            material: material.into(),
+            material_source: MaterialSource::Own,
+            ..self
+        }
+    }
+
+    // Barycentric coordinates (u, v) of a point in the triangle's plane, weighting p2
+    // and p3 respectively.
+    fn barycentric(&self, point: &Tuple) -> (f64, f64) {
+        let p = point - &self.p1;
+        let (d11, d12, d22) = (
+            self.e1.dot(&self.e1),
+            self.e1.dot(&self.e2),
+            self.e2.dot(&self.e2),
+        );
+        let (dp1, dp2) = (p.dot(&self.e1), p.dot(&self.e2));
+        let denominator = d11 * d22 - d12 * d12;
+
+        (
+            (d22 * dp1 - d12 * dp2) / denominator,
+            (d11 * dp2 - d12 * dp1) / denominator,
+        )
+    }
+
+    // A point spread uniformly over the triangle in object space.
+    fn sample_local(&self, rng: &mut dyn RngCore) -> Tuple {
+        let s = rng.gen::<f64>().sqrt();
+        let v = rng.gen::<f64>();
+
+        &(&self.p1 + &(&self.e1 * (s * (1.0 - v)))) + &(&self.e2 * (s * v))
+    }
+
+    // Moller-Trumbore. The determinant is negative where the ray meets the front face,
+    // so back faces are dropped before solving for the hit.
+    fn hits(&self, ray: &Ray, front_only: bool) -> Vec<f64> {
+        let dir_cross_e2 = ray.direction.cross(&self.e2);
+        let det = self.e1.dot(&dir_cross_e2);
+        if det.abs() < EPSILON || (front_only && det > 0.0) {
+            return vec![];
+        }
+
+        let f = 1.0 / det;
+        let p1_to_origin = &ray.origin - &self.p1;
+        let u = f * p1_to_origin.dot(&dir_cross_e2);
+        if !(0.0..=1.0).contains(&u) {
+            return vec![];
+        }
+
+        let origin_cross_e1 = p1_to_origin.cross(&self.e1);
+        let v = f * ray.direction.dot(&origin_cross_e1);
+        if v < 0.0 || u + v > 1.0 {
+            return vec![];
+        }
+
+        vec![f * self.e2.dot(&origin_cross_e1)]
+    }
+}
+
+impl Shape for Triangle {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        self.hits(ray, false)
+    }
+
+    fn intersect_front(&self, ray: &Ray) -> Vec<f64> {
+        self.hits(ray, true)
+    }
+
+    fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
+        self.normal.clone()
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        let mut bounds = BoundingBox::empty();
+        bounds.add_point(&self.p1);
+        bounds.add_point(&self.p2);
+        bounds.add_point(&self.p3);
+
+        bounds
+    }
+
+    fn surface_area(&self) -> f64 {
+        self.e1.cross(&self.e2).magnitude() / 2.0 * area_scale(self, &self.normal)
+    }
+
+    fn sample_point(&self, rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        Some(world_sample(self, &self.sample_local(rng)))
+    }
+
+    // The barycentric weights of p2 and p3, so p1 is at (0, 0), p2 at (1, 0) and p3 at
+    // (0, 1).
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        self.barycentric(local_point)
+    }
+}
+
+/// A triangle whose normal is interpolated between the normals at its corners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmoothTriangle {
+    pub n1: Tuple,
+    pub n2: Tuple,
+    pub n3: Tuple,
+    triangle: Triangle,
+}
+
+impl SmoothTriangle {
+    pub fn new(p1: &Tuple, p2: &Tuple, p3: &Tuple, n1: &Tuple, n2: &Tuple, n3: &Tuple) -> Self {
+        Self {
+            n1: n1.clone(),
+            n2: n2.clone(),
+            n3: n3.clone(),
+            triangle: Triangle::new(p1, p2, p3),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(mut self, material: M) -> Self {
+        self.set_material_handle(&material.into());
+        self
+    }
+
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+}
+
+impl Shape for SmoothTriangle {
+    fn get_transform(&self) -> Matrix {
+        self.triangle.get_transform()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        self.triangle.get_inverse_transform()
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.triangle.set_transform(transform);
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.triangle.get_parent_inverse_transform()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.triangle.set_parent_inverse_transform(parent_inverse);
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.triangle.material_handle()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.triangle.set_material_handle(material);
+    }
+
+    fn id(&self) -> ShapeId {
+        self.triangle.id()
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.triangle.lineage()
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.triangle.get_name()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.triangle.set_name(name);
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.triangle.material_source()
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        self.triangle.inherit_material(material);
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        self.triangle.intersect(ray)
+    }
+
+    fn intersect_front(&self, ray: &Ray) -> Vec<f64> {
+        self.triangle.intersect_front(ray)
+    }
+
+    fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
+        let (u, v) = self.triangle.barycentric(local_point);
+
+        &(&(&self.n2 * u) + &(&self.n3 * v)) + &(&self.n1 * (1.0 - u - v))
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        self.triangle.bounds_of()
+    }
+
+    fn surface_area(&self) -> f64 {
+        self.triangle.surface_area()
+    }
+
+    fn sample_point(&self, rng: &mut dyn RngCore) -> Option<(Tuple, Tuple)> {
+        Some(world_sample(self, &self.triangle.sample_local(rng)))
+    }
+
+    fn uv_at(&self, local_point: &Tuple) -> (f64, f64) {
+        self.triangle.uv_at(local_point)
+    }
+}
+
+/// One sphere of influence in a `Blob`. Its field falls smoothly from `weight` at the
+/// centre to zero at `radius`; a negative weight carves the blob away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobComponent {
+    pub center: Tuple,
+    pub radius: f64,
+    pub weight: f64,
+}
+
+impl BlobComponent {
+    fn field(&self, point: &Tuple) -> f64 {
+        let offset = point - &self.center;
+        let falloff = 1.0 - offset.dot(&offset) / (self.radius * self.radius);
+        if falloff > 0.0 {
+            self.weight * falloff * falloff
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A metaball surface: wherever the summed fields of the components reach `threshold`.
+/// Nearby components blend smoothly into one another. The field is ray marched, in
+/// steps of `BLOB_STEPS` per radius of the smallest component, then refined by bisection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blob {
+    pub components: Vec<BlobComponent>,
+    pub threshold: f64,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+}
+
+const BLOB_STEPS: f64 = 32.0;
+
+impl Blob {
+    pub fn new() -> Self {
+        Self {
+            components: vec![],
+            threshold: 0.5,
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(self, material: M) -> Self {
+        Self {
+            material: material.into(),
+            material_source: MaterialSource::Own,
+            ..self
+        }
+    }
+
+    pub fn with_threshold(self, threshold: f64) -> Self {
+        Self { threshold, ..self }
+    }
+
+    pub fn add_component(&mut self, center: &Tuple, radius: f64, weight: f64) {
+        self.components.push(BlobComponent {
+            center: center.clone(),
+            radius,
+            weight,
+        });
+    }
+
+    fn field(&self, point: &Tuple) -> f64 {
+        self.components.iter().map(|c| c.field(point)).sum::<f64>() - self.threshold
+    }
+
+    // The sorted, merged stretches of the ray inside any component's sphere of influence.
+    fn spans(&self, ray: &Ray) -> Vec<(f64, f64)> {
+        let a = ray.direction.dot(&ray.direction);
+        let mut spans: Vec<(f64, f64)> = self
+            .components
+            .iter()
+            .filter_map(|c| {
+                let to_ray = &ray.origin - &c.center;
+                let b = 2.0 * ray.direction.dot(&to_ray);
+                let d = b * b - 4.0 * a * (to_ray.dot(&to_ray) - c.radius * c.radius);
+                if d < 0.0 {
+                    None
+                } else {
+                    Some(((-b - d.sqrt()) / (2.0 * a), (-b + d.sqrt()) / (2.0 * a)))
+                }
+            })
+            .collect();
+        spans.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+        let mut merged: Vec<(f64, f64)> = vec![];
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    fn refine(&self, ray: &Ray, mut low: f64, mut high: f64) -> f64 {
+        let inside_low = self.field(&ray.position(low)) > 0.0;
+        while high - low > EPSILON / 10.0 {
+            let middle = (low + high) / 2.0;
+            if (self.field(&ray.position(middle)) > 0.0) == inside_low {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        (low + high) / 2.0
+    }
+}
+
+impl Default for Blob {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape for Blob {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        let smallest = self
+            .components
+            .iter()
+            .map(|c| c.radius)
+            .fold(f64::INFINITY, f64::min);
+        let step = smallest / BLOB_STEPS / ray.direction.magnitude();
+
+        let mut xs = vec![];
+        for (start, end) in self.spans(ray) {
+            let mut t = start;
+            let mut inside = self.field(&ray.position(t)) > 0.0;
+            while t < end {
+                let next = (t + step).min(end);
+                let next_inside = self.field(&ray.position(next)) > 0.0;
+                if next_inside != inside {
+                    xs.push(self.refine(ray, t, next));
+                    inside = next_inside;
+                }
+                t = next;
+            }
+        }
+
+        xs
+    }
+
+    // The field falls away from the surface outwards, so the normal opposes its gradient.
+    fn local_normal_at(&self, local_point: &Tuple) -> Tuple {
+        let mut normal = Tuple::vector(0.0, 0.0, 0.0);
+        for c in &self.components {
+            let offset = local_point - &c.center;
+            let r2 = c.radius * c.radius;
+            let falloff = 1.0 - offset.dot(&offset) / r2;
+            if falloff > 0.0 {
+                normal = normal + offset * (4.0 * c.weight * falloff / r2);
+            }
+        }
+
+        normal.normalize()
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        let mut bounds = BoundingBox::empty();
+        for c in &self.components {
+            let r = Tuple::vector(c.radius, c.radius, c.radius);
+            bounds.add_point(&(&c.center - &r));
+            bounds.add_point(&(&c.center + &r));
+        }
+
+        bounds
+    }
+}
+
+// How much `shape`'s transforms stretch a small patch of its surface facing
+// `local_normal`, from object space out to world space.
+fn area_scale(shape: &dyn Shape, local_normal: &Tuple) -> f64 {
+    let to_object = shape.get_inverse_transform() * shape.get_parent_inverse_transform();
+    let n = to_object.transpose() * local_normal;
+
+    Tuple::vector(n.x, n.y, n.z).magnitude() / to_object.determinant().abs()
+}
+
+// A sample on `shape` at `local_point`, taken out to world space with its normal.
+fn world_sample(shape: &dyn Shape, local_point: &Tuple) -> (Tuple, Tuple) {
+    let normal = shape.normal_to_world(&shape.local_normal_at(local_point));
+    (shape.object_to_world(local_point), normal)
+}
+
+// The square from -1 to 1 in x and z stretched over the texture.
+fn square_uv(point: &Tuple) -> (f64, f64) {
+    (
+        ((point.x + 1.0) / 2.0).clamp(0.0, 1.0),
+        ((point.z + 1.0) / 2.0).clamp(0.0, 1.0),
+    )
+}
+
+// The `i`th of `n` directions spread evenly over the unit sphere.
+fn fibonacci_direction(i: usize, n: usize) -> Tuple {
+    let y = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+    let r = (1.0 - y * y).sqrt();
+    let phi = i as f64 * PI * (3.0 - 5.0_f64.sqrt());
+
+    Tuple::vector(r * phi.cos(), y, r * phi.sin())
+}
+
+type Children = Vec<Arc<dyn Shape + Send + Sync>>;
+
+pub(crate) fn find_in(
+    shapes: &[Arc<dyn Shape + Send + Sync>],
+    predicate: &dyn Fn(&dyn Shape) -> bool,
+) -> Option<Arc<dyn Shape + Send + Sync>> {
+    shapes.iter().find_map(|shape| {
+        if predicate(shape.as_ref()) {
+            Some(shape.clone())
+        } else {
+            shape.find(predicate)
+        }
+    })
+}
+
+/// A total order on shapes: named shapes first, by name, then the rest; ties go to the
+/// older shape. Sorting with it gives the same order however the shapes were gathered.
+pub fn compare_shapes(a: &dyn Shape, b: &dyn Shape) -> cmp::Ordering {
+    match (a.get_name(), b.get_name()) {
+        (Some(a_name), Some(b_name)) => a_name.cmp(b_name),
+        (Some(_), None) => cmp::Ordering::Less,
+        (None, Some(_)) => cmp::Ordering::Greater,
+        (None, None) => cmp::Ordering::Equal,
+    }
+    .then_with(|| a.id().cmp(&b.id()))
+}
+
+pub fn sort_shapes(shapes: &mut [Arc<dyn Shape + Send + Sync>]) {
+    shapes.sort_by(|a, b| compare_shapes(a.as_ref(), b.as_ref()));
+}
+
+/// How often rays reached a group and how many of them got past its bounding box. Only
+/// debug builds count them; release builds always report zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RayStats {
+    pub tested: u64,
+    pub entered: u64,
+}
+
+/// One group in a scene graph, as listed by `Group::report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupReport {
+    // Nesting below the group the report was made from, which is at depth 0.
+    pub depth: usize,
+    pub children: usize,
+    pub primitives: usize,
+    pub bounds: BoundingBox,
+    pub rays: RayStats,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct RayCounters {
+    tested: AtomicU64,
+    entered: AtomicU64,
+}
+
+pub struct Group {
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+    children: Children,
+    bounds: BoundingBox,
+    #[cfg(debug_assertions)]
+    counters: RayCounters,
+}
+
+impl Group {
+    pub fn new() -> Self {
+        Self {
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+            children: vec![],
+            bounds: BoundingBox::empty(),
+            #[cfg(debug_assertions)]
+            counters: RayCounters::default(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(mut self, material: M) -> Self {
+        self.set_material_handle(&material.into());
+        self
+    }
+
+    pub fn with_child<S: Shape + Send + Sync + 'static>(mut self, child: S) -> Self {
+        self.add_child(child);
+        self
+    }
+
+    // Children are owned by the group so that changing the group's transform can be
+    // pushed down to them; don't hold on to clones of them while doing so.
+    pub fn add_child<S: Shape + Send + Sync + 'static>(&mut self, mut child: S) {
+        child.set_parent_inverse_transform(&self.children_parent_inverse());
+        if self.material_source != MaterialSource::Default {
+            child.inherit_material(&self.material);
+        }
+        self.bounds.merge(&child.parent_space_bounds_of());
+        self.children.push(Arc::new(child));
+    }
+
+    pub fn children(&self) -> &[Arc<dyn Shape + Send + Sync>] {
+        &self.children
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// The box around everything in the group, in the space the group sits in.
+    pub fn bounds(&self) -> BoundingBox {
+        self.parent_space_bounds_of()
+    }
+
+    /// The number of shapes in the group and all the groups nested within it, not
+    /// counting the groups themselves.
+    pub fn total_primitives(&self) -> usize {
+        self.primitive_count()
+    }
+
+    pub fn ray_stats(&self) -> RayStats {
+        #[cfg(debug_assertions)]
+        {
+            RayStats {
+                tested: self.counters.tested.load(Ordering::Relaxed),
+                entered: self.counters.entered.load(Ordering::Relaxed),
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        RayStats::default()
+    }
+
+    /// Zeroes the ray counts of this group and every group within it.
+    pub fn reset_ray_stats(&self) {
+        #[cfg(debug_assertions)]
+        {
+            self.counters.tested.store(0, Ordering::Relaxed);
+            self.counters.entered.store(0, Ordering::Relaxed);
+        }
+        for child in self.children.iter().filter_map(|c| c.as_group()) {
+            child.reset_ray_stats();
+        }
+    }
+
+    /// This group and every group within it, depth first, for seeing where rays spend
+    /// their time in a bounding volume hierarchy.
+    pub fn report(&self) -> Vec<GroupReport> {
+        let mut reports = vec![];
+        self.report_into(0, &mut reports);
+        reports
+    }
+
+    fn report_into(&self, depth: usize, reports: &mut Vec<GroupReport>) {
+        reports.push(GroupReport {
+            depth,
+            children: self.children.len(),
+            primitives: self.total_primitives(),
+            bounds: self.bounds(),
+            rays: self.ray_stats(),
+        });
+        for child in self.children.iter().filter_map(|c| c.as_group()) {
+            child.report_into(depth + 1, reports);
+        }
+    }
+
+    fn update_children_material(&mut self) {
+        for child in self.children.iter_mut() {
+            Arc::get_mut(child)
+                .expect("cannot change a group's material while its children are shared")
+                .inherit_material(&self.material);
+        }
+    }
+
+    fn children_parent_inverse(&self) -> Matrix {
+        inverse_of(&self.inverse) * self.parent_inverse.clone()
+    }
+
+    // Removes the children that fit entirely within either half of the group's bounds,
+    // leaving those that straddle the split in place.
+    fn partition_children(&mut self) -> (Children, Children) {
+        let (left_bounds, right_bounds) = self.bounds.split();
+        let mut left = vec![];
+        let mut right = vec![];
+        let mut remaining = vec![];
+
+        for child in self.children.drain(..) {
+            let bounds = child.parent_space_bounds_of();
+            if left_bounds.contains_box(&bounds) {
+                left.push(child);
+            } else if right_bounds.contains_box(&bounds) {
+                right.push(child);
+            } else {
+                remaining.push(child);
+            }
+        }
+
+        self.children = remaining;
+        (left, right)
+    }
+
+    // Groups shapes without touching them, so they may be shared. Their parent inverse
+    // must already be the one this group would give them, e.g. identity for shapes that
+    // are not yet in any group.
+    pub(crate) fn from_shared(children: Children) -> Self {
+        let mut group = Group::new();
+        for child in children {
+            group.bounds.merge(&child.parent_space_bounds_of());
+            group.children.push(child);
+        }
+
+        group
+    }
+
+    // The children keep their parent inverse, since the subgroup adds no transform.
+    fn make_subgroup(&mut self, children: Children) {
+        let mut subgroup = Group::from_shared(children);
+        subgroup.parent_inverse = self.children_parent_inverse();
+
+        self.children.push(Arc::new(subgroup));
+    }
+
+    fn intersect_children(&self, ray: &Ray, cull: bool) -> Intersections {
+        let mut intersections = Intersections::new(vec![]);
+        #[cfg(debug_assertions)]
+        self.counters.tested.fetch_add(1, Ordering::Relaxed);
+        if !self.bounds.intersects(ray) {
+            return intersections;
+        }
+        #[cfg(debug_assertions)]
+        self.counters.entered.fetch_add(1, Ordering::Relaxed);
+
+        for child in &self.children {
+            intersections.extend(&ray.intersect_culling(child.clone(), cull));
+        }
+
+        intersections.sort();
+        intersections
+    }
+
+    fn update_children(&mut self) {
+        let parent_inverse = self.children_parent_inverse();
+        for child in self.children.iter_mut() {
+            Arc::get_mut(child)
+                .expect("cannot move a group while its children are shared")
+                .set_parent_inverse_transform(&parent_inverse);
+        }
+    }
+}
+
+impl Default for Group {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shape for Group {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+        self.update_children();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+        self.update_children();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+        self.update_children_material();
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+            self.update_children_material();
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        let xs = self.intersect_children(ray, false);
+        (0..xs.count()).map(|i| xs.at(i).t).collect()
+    }
+
+    fn local_intersect(
+        &self,
+        local_ray: &Ray,
+        _object: Arc<dyn Shape>,
+        cull: bool,
+    ) -> Intersections {
+        self.intersect_children(local_ray, cull)
+    }
+
+    fn includes(&self, object: &dyn Shape) -> bool {
+        self.children.iter().any(|c| c.includes(object))
+    }
+
+    fn find(&self, predicate: &dyn Fn(&dyn Shape) -> bool) -> Option<Arc<dyn Shape + Send + Sync>> {
+        find_in(&self.children, predicate)
+    }
+
+    fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
+        unreachable!("groups have no surface; normals come from their children")
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+
+    fn divide(&mut self, threshold: usize) {
+        if threshold <= self.children.len() {
+            let count = self.children.len();
+            let (left, right) = self.partition_children();
+
+            // A split that moves every child to one side would only nest the group again.
+            if left.len() == count || right.len() == count {
+                self.children = if left.is_empty() { right } else { left };
+            } else {
+                if !left.is_empty() {
+                    self.make_subgroup(left);
+                }
+                if !right.is_empty() {
+                    self.make_subgroup(right);
+                }
+            }
+        }
+
+        for child in self.children.iter_mut().filter_map(Arc::get_mut) {
+            child.divide(threshold);
+        }
+    }
+
+    fn primitive_count(&self) -> usize {
+        self.children.iter().map(|c| c.primitive_count()).sum()
+    }
+
+    fn as_group(&self) -> Option<&Group> {
+        Some(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    pub fn intersection_allowed(&self, left_hit: bool, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => (left_hit && !in_right) || (!left_hit && !in_left),
+            CsgOperation::Intersection => (left_hit && in_right) || (!left_hit && in_left),
+            CsgOperation::Difference => (left_hit && !in_right) || (!left_hit && in_left),
+        }
+    }
+}
+
+pub struct Csg {
+    operation: CsgOperation,
+    transform: Matrix,
+    inverse: Option<Matrix>,
+    parent_inverse: Matrix,
+    material: MaterialHandle,
+    identity: Identity,
+    name: Option<String>,
+    material_source: MaterialSource,
+    left: Arc<dyn Shape + Send + Sync>,
+    right: Arc<dyn Shape + Send + Sync>,
+}
+
+impl Csg {
+    pub fn new<L, R>(operation: CsgOperation, mut left: L, mut right: R) -> Self
+    where
+        L: Shape + Send + Sync + 'static,
+        R: Shape + Send + Sync + 'static,
+    {
+        left.set_parent_inverse_transform(&Matrix::identify());
+        right.set_parent_inverse_transform(&Matrix::identify());
+
+        Self {
+            operation,
+            transform: Matrix::identify(),
+            inverse: Some(Matrix::identify()),
+            parent_inverse: Matrix::identify(),
+            material: MaterialHandle::default(),
+            identity: Identity::new(),
+            name: None,
+            material_source: MaterialSource::Default,
+            left: Arc::new(left),
+            right: Arc::new(right),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Matrix) -> Self {
+        self.set_transform(transform);
+        self
+    }
+
+    pub fn with_material<M: Into<MaterialHandle>>(mut self, material: M) -> Self {
+        self.set_material_handle(&material.into());
+        self
+    }
+
+    pub fn operation(&self) -> CsgOperation {
+        self.operation
+    }
+
+    pub fn left(&self) -> &Arc<dyn Shape + Send + Sync> {
+        &self.left
+    }
+
+    pub fn right(&self) -> &Arc<dyn Shape + Send + Sync> {
+        &self.right
+    }
+
+    pub fn filter_intersections(&self, xs: &Intersections) -> Intersections {
+        let mut in_left = false;
+        let mut in_right = false;
+        let mut result = vec![];
+
+        for i in (0..xs.count()).map(|i| xs.at(i)) {
+            let left_hit = self.left.includes(i.object.as_ref());
+
+            if self
+                .operation
+                .intersection_allowed(left_hit, in_left, in_right)
+            {
+                result.push(i);
+            }
+
+            if left_hit {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+        }
+
+        Intersections::new(result)
+    }
+
+    fn intersect_children(&self, ray: &Ray) -> Intersections {
+        let mut xs = ray.intersect(self.left.clone());
+        xs.extend(&ray.intersect(self.right.clone()));
+        xs.sort();
+
+        self.filter_intersections(&xs)
+    }
+
+    fn update_children_material(&mut self) {
+        for child in [&mut self.left, &mut self.right].iter_mut() {
+            Arc::get_mut(child)
+                .expect("cannot change a CSG shape's material while its children are shared")
+                .inherit_material(&self.material);
+        }
+    }
+
+    fn update_children(&mut self) {
+        let parent_inverse = inverse_of(&self.inverse) * self.parent_inverse.clone();
+        for child in [&mut self.left, &mut self.right].iter_mut() {
+            Arc::get_mut(child)
+                .expect("cannot move a CSG shape while its children are shared")
+                .set_parent_inverse_transform(&parent_inverse);
+        }
+    }
+}
+
+impl Shape for Csg {
+    fn get_transform(&self) -> Matrix {
+        self.transform.clone()
+    }
+
+    fn get_inverse_transform(&self) -> Matrix {
+        inverse_of(&self.inverse)
+    }
+
+    fn set_transform(&mut self, transform: &Matrix) {
+        self.transform = transform.clone();
+        self.inverse = transform.inverse();
+        self.update_children();
+    }
+
+    fn get_parent_inverse_transform(&self) -> Matrix {
+        self.parent_inverse.clone()
+    }
+
+    fn set_parent_inverse_transform(&mut self, parent_inverse: &Matrix) {
+        self.parent_inverse = parent_inverse.clone();
+        self.update_children();
+    }
+
+    fn material_handle(&self) -> MaterialHandle {
+        self.material.clone()
+    }
+
+    fn set_material_handle(&mut self, material: &MaterialHandle) {
+        self.material = material.clone();
+        self.material_source = MaterialSource::Own;
+        self.update_children_material();
+    }
+
+    fn id(&self) -> ShapeId {
+        self.identity.id
+    }
+
+    fn lineage(&self) -> ShapeId {
+        self.identity.lineage
+    }
+
+    fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    fn material_source(&self) -> MaterialSource {
+        self.material_source
+    }
+
+    fn inherit_material(&mut self, material: &MaterialHandle) {
+        if self.material_source != MaterialSource::Own {
+            self.material = material.clone();
+            self.material_source = MaterialSource::Inherited;
+            self.update_children_material();
+        }
+    }
+
+    fn intersect(&self, ray: &Ray) -> Vec<f64> {
+        let xs = self.intersect_children(ray);
+        (0..xs.count()).map(|i| xs.at(i).t).collect()
+    }
+
+    // Both faces of each child are needed to tell whether the ray is inside it, so
+    // one-sided children aren't culled.
+    fn local_intersect(
+        &self,
+        local_ray: &Ray,
+        _object: Arc<dyn Shape>,
+        _cull: bool,
+    ) -> Intersections {
+        self.intersect_children(local_ray)
+    }
+
+    fn includes(&self, object: &dyn Shape) -> bool {
+        self.left.includes(object) || self.right.includes(object)
+    }
+
+    fn find(&self, predicate: &dyn Fn(&dyn Shape) -> bool) -> Option<Arc<dyn Shape + Send + Sync>> {
+        find_in(&[self.left.clone(), self.right.clone()], predicate)
+    }
+
+    fn local_normal_at(&self, _local_point: &Tuple) -> Tuple {
+        unreachable!("CSG shapes have no surface; normals come from their children")
+    }
+
+    fn bounds_of(&self) -> BoundingBox {
+        let mut bounds = self.left.parent_space_bounds_of();
+        bounds.merge(&self.right.parent_space_bounds_of());
+
+        bounds
+    }
+
+    fn divide(&mut self, threshold: usize) {
+        for child in [&mut self.left, &mut self.right]
+            .iter_mut()
+            .filter_map(|c| Arc::get_mut(c))
+        {
+            child.divide(threshold);
+        }
+    }
+
+    fn primitive_count(&self) -> usize {
+        self.left.primitive_count() + self.right.primitive_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Color;
+    use crate::utils::equal_f64;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    // Shapes
+    #[test]
+    fn test_the_default_transformation() {
+        let s = TestShape::new();
+
+        assert_eq!(s.get_transform(), Matrix::identify());
+    }
+
+    #[test]
+    fn test_assigning_a_transform() {
+        let mut s = TestShape::new();
+        s.set_transform(&Matrix::translation(2.0, 3.0, 4.0));
+
+        assert_eq!(s.get_transform(), Matrix::translation(2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn test_assigning_a_transform_updates_the_inverse() {
+        let mut s = TestShape::new();
+        s.set_transform(&Matrix::translation(2.0, 3.0, 4.0));
+
+        assert_eq!(
+            s.get_inverse_transform(),
+            Matrix::translation(-2.0, -3.0, -4.0)
+        );
+    }
+
+    #[test]
+    fn test_a_singular_transform_can_be_assigned() {
+        let flat = Matrix::scaling(1.0, 0.0, 1.0);
+        let mut s = TestShape::new();
+        s.set_transform(&flat);
+
+        assert_eq!(s.get_transform(), flat);
+        assert_eq!(Sphere::new().with_transform(&flat).get_transform(), flat);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be invertible")]
+    fn test_a_singular_transform_panics_once_used() {
+        let s = Sphere::new().with_transform(&Matrix::scaling(1.0, 0.0, 1.0));
+
+        s.normal_at(&Tuple::point(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_the_default_material() {
+        let s = TestShape::new();
+
+        assert_eq!(s.get_material(), Material::new());
+    }
+
+    #[test]
+    fn test_assign_a_material() {
+        let mut s = TestShape::new();
+        let mut m = Material::new();
+        m.ambient = 1.0;
+
+        s.set_material(&m);
+
+        assert_eq!(s.get_material(), m);
+    }
+
+    #[test]
+    fn test_shapes_given_one_handle_share_it_until_one_is_edited() {
+        let handle = MaterialHandle::new(Material::new());
+        let mut a = Sphere::new().with_material(&handle);
+        let b = Cube::new().with_material(&handle);
+        let mut g = Group::new().with_material(&handle);
+        g.add_child(Sphere::new());
+
+        assert!(a.material_handle().shares_with(&handle));
+        assert!(b.material_handle().shares_with(&handle));
+        assert!(g.children()[0].material_handle().shares_with(&handle));
+
+        a.edit_material(&|m| m.ambient = 1.0);
+
+        assert_eq!(a.get_material().ambient, 1.0);
+        assert!(!a.material_handle().shares_with(&handle));
+        assert_eq!(b.get_material().ambient, Material::new().ambient);
+        assert!(b.material_handle().shares_with(&handle));
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_computing_the_normal_on_a_translated_shape() {
+        let mut s = TestShape::new();
+        s.set_transform(&Matrix::translation(0.0, 1.0, 0.0));
+        let n = s.normal_at(&Tuple::point(0.0, 1.70711, -0.70711));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.70711, -0.70711));
+    }
+
+    #[test]
+    fn test_computing_the_normal_on_a_transformed_shape() {
+        let mut s = TestShape::new();
+        let m = Matrix::scaling(1.0, 0.5, 1.0) * Matrix::rotation_z(PI / 5.0);
+        s.set_transform(&m);
+        let n = s.normal_at(&Tuple::point(
+            0.0,
+            2.0_f64.sqrt() / 2.0,
+            -2.0_f64.sqrt() / 2.0,
+        ));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.97014, -0.24254));
+    }
+
+    // Spheres
+    #[test]
+    fn test_the_normal_sphere_at_a_point_on_the_x_axis() {
+        let s = Sphere::new();
+
+        let n = s.normal_at(&Tuple::point(1.0, 0.0, 0.0));
+
+        assert_eq!(n, Tuple::vector(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_the_normal_sphere_at_a_point_on_the_y_axis() {
+        let s = Sphere::new();
+
+        let n = s.normal_at(&Tuple::point(0.0, 1.0, 0.0));
+
+        assert_eq!(n, Tuple::vector(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_the_normal_sphere_at_a_point_on_the_z_axis() {
+        let s = Sphere::new();
+
+        let n = s.normal_at(&Tuple::point(0.0, 0.0, 1.0));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_the_normal_sphere_at_a_point_on_a_nonaxial_point() {
+        let s = Sphere::new();
+
+        let n = s.normal_at(&Tuple::point(
+            3.0_f64.sqrt() / 3.0,
+            3.0_f64.sqrt() / 3.0,
+            3.0_f64.sqrt() / 3.0,
+        ));
+
+        assert_eq!(
+            n,
+            Tuple::vector(
+                3.0_f64.sqrt() / 3.0,
+                3.0_f64.sqrt() / 3.0,
+                3.0_f64.sqrt() / 3.0
+            )
+        );
+    }
+
+    #[test]
+    fn test_the_normal_is_a_normalized_vector() {
+        let s = Sphere::new();
+        let n = s.normal_at(&Tuple::point(
+            3.0_f64.sqrt() / 3.0,
+            3.0_f64.sqrt() / 3.0,
+            3.0_f64.sqrt() / 3.0,
+        ));
+
+        assert_eq!(n.clone(), n.normalize());
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_computing_the_normal_on_a_translated_sphere() {
+        let mut s = Sphere::new();
+        s.set_transform(&Matrix::translation(0.0, 1.0, 0.0));
+
+        let n = s.normal_at(&Tuple::point(0.0, 1.70711, -0.70711));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.70711, -0.70711));
+    }
+
+    #[test]
+    fn test_copmuting_the_normal_on_a_transformed_sphere() {
+        let mut s = Sphere::new();
+        let m = Matrix::scaling(1.0, 0.5, 1.0) * Matrix::rotation_z(PI / 0.5);
+        s.set_transform(&m);
+
+        let n = s.normal_at(&Tuple::point(
+            0.0,
+            2.0_f64.sqrt() / 2.0,
+            -2.0_f64.sqrt() / 2.0,
+        ));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.97014, -0.24254));
+    }
+
+    #[test]
+    fn test_a_sphere_has_a_default_material() {
         let s = Sphere::new();
 
-        let n = s.normal_at(&Tuple::point(
-            3.0_f64.sqrt() / 3.0,
-            3.0_f64.sqrt() / 3.0,
-            3.0_f64.sqrt() / 3.0,
+        assert_eq!(*s.material, Material::new());
+    }
+
+    #[test]
+    fn test_a_sphere_may_be_assigned_a_material() {
+        let mut s = Sphere::new();
+        let mut m = Material::new();
+        m.ambient = 1.0;
+
+        s.material = m.clone().into();
+
+        assert_eq!(*s.material, m);
+    }
+
+    // Plane
+    #[test]
+    fn test_the_normal_of_a_plane_is_constant_everywhere() {
+        let p = Plane::new();
+        assert_eq!(
+            p.local_normal_at(&Tuple::point(0.0, 0.0, 0.0)),
+            Tuple::vector(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            p.local_normal_at(&Tuple::point(10.0, 0.0, -10.0)),
+            Tuple::vector(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            p.local_normal_at(&Tuple::point(-5.0, 0.0, 150.0)),
+            Tuple::vector(0.0, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_intersect_with_a_ray_parallel_to_the_plane() {
+        let p = Plane::new();
+        let r = Ray::new(&Tuple::point(0.0, 10.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = p.intersect(&r);
+
+        assert_eq!(xs.len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_with_a_coplanar_ray() {
+        let p = Plane::new();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = p.intersect(&r);
+
+        assert_eq!(xs.len(), 0);
+    }
+
+    #[test]
+    fn test_intersect_with_a_plane_from_above() {
+        let p = Plane::new();
+        let r = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+
+        let xs = p.intersect(&r);
+
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0], 1.0);
+    }
+
+    #[test]
+    fn test_intersect_with_a_plane_from_below() {
+        let p = Plane::new();
+        let r = Ray::new(&Tuple::point(0.0, -1.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+
+        let xs = p.intersect(&r);
+
+        assert_eq!(xs.len(), 1);
+        assert_eq!(xs[0], 1.0);
+    }
+
+    // Cube
+    #[test]
+    fn test_a_ray_intersects_a_cube() {
+        let c = Cube::new();
+        let examples = [
+            ((5.0, 0.5, 0.0), (-1.0, 0.0, 0.0), 4.0, 6.0),
+            ((-5.0, 0.5, 0.0), (1.0, 0.0, 0.0), 4.0, 6.0),
+            ((0.5, 5.0, 0.0), (0.0, -1.0, 0.0), 4.0, 6.0),
+            ((0.5, -5.0, 0.0), (0.0, 1.0, 0.0), 4.0, 6.0),
+            ((0.5, 0.0, 5.0), (0.0, 0.0, -1.0), 4.0, 6.0),
+            ((0.5, 0.0, -5.0), (0.0, 0.0, 1.0), 4.0, 6.0),
+            ((0.0, 0.5, 0.0), (0.0, 0.0, 1.0), -1.0, 1.0),
+        ];
+
+        for (origin, direction, t1, t2) in examples.iter() {
+            let r = Ray::new(
+                &Tuple::point(origin.0, origin.1, origin.2),
+                &Tuple::vector(direction.0, direction.1, direction.2),
+            );
+
+            let xs = c.intersect(&r);
+
+            assert_eq!(xs.len(), 2);
+            assert_eq!(xs[0], *t1);
+            assert_eq!(xs[1], *t2);
+        }
+    }
+
+    #[test]
+    fn test_a_ray_misses_a_cube() {
+        let c = Cube::new();
+        let examples = [
+            ((-2.0, 0.0, 0.0), (0.2673, 0.5345, 0.8018)),
+            ((0.0, -2.0, 0.0), (0.8018, 0.2673, 0.5345)),
+            ((0.0, 0.0, -2.0), (0.5345, 0.8018, 0.2673)),
+            ((2.0, 0.0, 2.0), (0.0, 0.0, -1.0)),
+            ((0.0, 2.0, 2.0), (0.0, -1.0, 0.0)),
+            ((2.0, 2.0, 0.0), (-1.0, 0.0, 0.0)),
+        ];
+
+        for (origin, direction) in examples.iter() {
+            let r = Ray::new(
+                &Tuple::point(origin.0, origin.1, origin.2),
+                &Tuple::vector(direction.0, direction.1, direction.2),
+            );
+
+            assert_eq!(c.intersect(&r).len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_the_normal_on_the_surface_of_a_cube() {
+        let c = Cube::new();
+        let examples = [
+            ((1.0, 0.5, -0.8), (1.0, 0.0, 0.0)),
+            ((-1.0, -0.2, 0.9), (-1.0, 0.0, 0.0)),
+            ((-0.4, 1.0, -0.1), (0.0, 1.0, 0.0)),
+            ((0.3, -1.0, -0.7), (0.0, -1.0, 0.0)),
+            ((-0.6, 0.3, 1.0), (0.0, 0.0, 1.0)),
+            ((0.4, 0.4, -1.0), (0.0, 0.0, -1.0)),
+            ((1.0, 1.0, 1.0), (1.0, 0.0, 0.0)),
+            ((-1.0, -1.0, -1.0), (-1.0, 0.0, 0.0)),
+        ];
+
+        for (point, normal) in examples.iter() {
+            let n = c.local_normal_at(&Tuple::point(point.0, point.1, point.2));
+
+            assert_eq!(n, Tuple::vector(normal.0, normal.1, normal.2));
+        }
+    }
+
+    // Disc
+    #[test]
+    fn test_a_ray_intersects_a_disc_within_its_radius() {
+        let d = Disc::new();
+        let cases = [
+            (
+                Tuple::point(0.0, 1.0, 0.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                1,
+            ),
+            (
+                Tuple::point(0.5, -2.0, 0.5),
+                Tuple::vector(0.0, 1.0, 0.0),
+                1,
+            ),
+            (
+                Tuple::point(1.0, 1.0, 0.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                1,
+            ),
+            (
+                Tuple::point(1.0, 1.0, 1.0),
+                Tuple::vector(0.0, -1.0, 0.0),
+                0,
+            ),
+            (Tuple::point(0.0, 1.0, 0.0), Tuple::vector(1.0, 0.0, 0.0), 0),
+        ];
+
+        for (origin, direction, count) in cases.iter() {
+            let r = Ray::new(origin, direction);
+            assert_eq!(d.intersect(&r).len(), *count);
+        }
+    }
+
+    #[test]
+    fn test_a_ray_passes_through_the_hole_in_a_ring() {
+        let d = Disc::new().with_inner_radius(0.5);
+
+        let through = Ray::new(&Tuple::point(0.2, 1.0, 0.2), &Tuple::vector(0.0, -1.0, 0.0));
+        let on_ring = Ray::new(
+            &Tuple::point(0.0, 1.0, 0.75),
+            &Tuple::vector(0.0, -1.0, 0.0),
+        );
+
+        assert!(d.intersect(&through).is_empty());
+        assert_eq!(d.intersect(&on_ring), vec![1.0]);
+    }
+
+    #[test]
+    fn test_the_normal_of_a_disc_is_constant() {
+        let d = Disc::new().with_transform(&Matrix::rotation_x(PI / 2.0));
+
+        let n = d.normal_at(&Tuple::point(0.5, 0.0, 0.0));
+
+        assert_eq!(n, Tuple::vector(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_a_disc_has_a_flat_bounding_box() {
+        let b = Disc::new().bounds_of();
+
+        assert_eq!(b.min, Tuple::point(-1.0, 0.0, -1.0));
+        assert_eq!(b.max, Tuple::point(1.0, 0.0, 1.0));
+    }
+
+    // Prism
+    fn l_shape() -> Prism {
+        Prism::new(
+            &[
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 1.0),
+                (1.0, 1.0),
+                (1.0, 2.0),
+                (0.0, 2.0),
+            ],
+            0.0,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn test_a_ray_intersects_the_sides_of_a_prism() {
+        let p = l_shape();
+        let examples = vec![
+            (
+                Tuple::point(-1.0, 0.5, 0.5),
+                Tuple::vector(1.0, 0.0, 0.0),
+                1.0,
+                3.0,
+            ),
+            (
+                Tuple::point(0.5, 0.5, 5.0),
+                Tuple::vector(0.0, 0.0, -1.0),
+                3.0,
+                5.0,
+            ),
+            (
+                Tuple::point(-1.0, 0.5, 1.5),
+                Tuple::vector(1.0, 0.0, 0.0),
+                1.0,
+                2.0,
+            ),
+        ];
+
+        for (origin, direction, t1, t2) in examples {
+            let xs = p.intersect(&Ray::new(&origin, &direction));
+
+            assert_eq!(xs.len(), 2);
+            assert!(equal_f64(xs[0].min(xs[1]), t1));
+            assert!(equal_f64(xs[0].max(xs[1]), t2));
+        }
+    }
+
+    #[test]
+    fn test_a_ray_misses_the_notch_of_a_concave_prism() {
+        let p = l_shape();
+        let examples = vec![
+            (Tuple::point(1.5, 5.0, 1.5), Tuple::vector(0.0, -1.0, 0.0)),
+            (Tuple::point(-1.0, 1.5, 0.5), Tuple::vector(1.0, 0.0, 0.0)),
+            (Tuple::point(3.0, 0.5, 3.0), Tuple::vector(0.0, 0.0, 1.0)),
+        ];
+
+        for (origin, direction) in examples {
+            assert!(p.intersect(&Ray::new(&origin, &direction)).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_a_ray_intersects_the_caps_of_a_prism() {
+        let p = l_shape();
+        let r = Ray::new(&Tuple::point(0.5, 5.0, 1.5), &Tuple::vector(0.0, -1.0, 0.0));
+
+        let mut xs = p.intersect(&r);
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        assert_eq!(xs, vec![4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_the_normal_on_a_prism() {
+        let clockwise = Prism::new(&[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)], -1.0, 1.0);
+        let examples = vec![
+            (Tuple::point(0.5, 1.0, 0.5), Tuple::vector(0.0, 1.0, 0.0)),
+            (Tuple::point(0.5, -1.0, 0.5), Tuple::vector(0.0, -1.0, 0.0)),
+            (Tuple::point(0.0, 0.0, 0.5), Tuple::vector(-1.0, 0.0, 0.0)),
+            (Tuple::point(1.0, 0.5, 0.3), Tuple::vector(1.0, 0.0, 0.0)),
+            (Tuple::point(0.4, 0.0, 1.0), Tuple::vector(0.0, 0.0, 1.0)),
+        ];
+
+        for (point, normal) in examples {
+            assert_eq!(clockwise.local_normal_at(&point), normal);
+        }
+        assert_eq!(
+            l_shape().local_normal_at(&Tuple::point(1.5, 0.5, 1.0)),
+            Tuple::vector(0.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_a_closed_outline_makes_a_prism_without_a_zero_length_side() {
+        let closed = Prism::new(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)], 0.0, 1.0);
+        let repeated = Prism::new(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (0.0, 1.0)], 0.0, 1.0);
+        assert_eq!(closed.points, vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        assert_eq!(repeated.points, closed.points);
+        let p: Arc<dyn Shape> = Arc::new(closed);
+        let r = Ray::new(
+            &Tuple::point(0.25, 0.5, -2.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+
+        let xs = r.intersect(p.clone());
+
+        assert_eq!(xs.count(), 2);
+        assert_eq!(
+            p.normal_at(&r.position(xs.at(0).t)),
+            Tuple::vector(0.0, 0.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn test_a_prism_has_bounds() {
+        let b = l_shape().bounds_of();
+
+        assert_eq!(b.min, Tuple::point(0.0, 0.0, 0.0));
+        assert_eq!(b.max, Tuple::point(2.0, 1.0, 2.0));
+    }
+
+    // Triangle
+    #[test]
+    fn test_constructing_a_triangle() {
+        let p1 = Tuple::point(0.0, 1.0, 0.0);
+        let p2 = Tuple::point(-1.0, 0.0, 0.0);
+        let p3 = Tuple::point(1.0, 0.0, 0.0);
+
+        let t = Triangle::new(&p1, &p2, &p3);
+
+        assert_eq!(t.p1, p1);
+        assert_eq!(t.p2, p2);
+        assert_eq!(t.p3, p3);
+        assert_eq!(t.e1, Tuple::vector(-1.0, -1.0, 0.0));
+        assert_eq!(t.e2, Tuple::vector(1.0, -1.0, 0.0));
+        assert_eq!(t.normal, Tuple::vector(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_finding_the_normal_on_a_triangle() {
+        let t = default_triangle();
+
+        assert_eq!(t.local_normal_at(&Tuple::point(0.0, 0.5, 0.0)), t.normal);
+        assert_eq!(t.local_normal_at(&Tuple::point(-0.5, 0.75, 0.0)), t.normal);
+        assert_eq!(t.local_normal_at(&Tuple::point(0.5, 0.25, 0.0)), t.normal);
+    }
+
+    #[test]
+    fn test_a_ray_misses_a_triangle() {
+        let t = default_triangle();
+        let cases = [
+            (Tuple::point(0.0, -1.0, -2.0), Tuple::vector(0.0, 1.0, 0.0)),
+            (Tuple::point(1.0, 1.0, -2.0), Tuple::vector(0.0, 0.0, 1.0)),
+            (Tuple::point(-1.0, 1.0, -2.0), Tuple::vector(0.0, 0.0, 1.0)),
+            (Tuple::point(0.0, -1.0, -2.0), Tuple::vector(0.0, 0.0, 1.0)),
+        ];
+
+        for (origin, direction) in cases.iter() {
+            assert!(t.intersect(&Ray::new(origin, direction)).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_a_ray_strikes_a_triangle() {
+        let t = default_triangle();
+        let r = Ray::new(&Tuple::point(0.0, 0.5, -2.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        assert_eq!(t.intersect(&r), vec![2.0]);
+    }
+
+    #[test]
+    fn test_a_triangle_has_a_bounding_box() {
+        let t = Triangle::new(
+            &Tuple::point(-3.0, 7.0, 2.0),
+            &Tuple::point(6.0, 2.0, -4.0),
+            &Tuple::point(2.0, -1.0, -1.0),
+        );
+
+        let b = t.bounds_of();
+
+        assert_eq!(b.min, Tuple::point(-3.0, -1.0, -4.0));
+        assert_eq!(b.max, Tuple::point(6.0, 7.0, 2.0));
+    }
+
+    #[test]
+    fn test_a_triangle_drops_its_back_face_when_culling() {
+        let t = default_triangle();
+        let front = Ray::new(&Tuple::point(0.0, 0.5, -2.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let back = Ray::new(&Tuple::point(0.0, 0.5, 2.0), &Tuple::vector(0.0, 0.0, -1.0));
+
+        assert_eq!(t.intersect_front(&front), vec![2.0]);
+        assert!(t.intersect_front(&back).is_empty());
+        assert_eq!(t.intersect(&back), vec![2.0]);
+    }
+
+    #[test]
+    fn test_a_mesh_of_one_sided_triangles_is_culled_only_when_asked() {
+        let mut m = Material::new();
+        m.two_sided = false;
+        let mut g = Group::new();
+        g.add_child(default_triangle().with_material(&m));
+        g.add_child(
+            default_triangle()
+                .with_material(&m)
+                .with_transform(&Matrix::translation(0.0, 0.0, 1.0)),
+        );
+        let g: Arc<dyn Shape> = Arc::new(g);
+        let front = Ray::new(&Tuple::point(0.0, 0.5, -2.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let back = Ray::new(&Tuple::point(0.0, 0.5, 3.0), &Tuple::vector(0.0, 0.0, -1.0));
+
+        assert_eq!(front.intersect_culling(g.clone(), true).count(), 2);
+        assert_eq!(back.intersect_culling(g.clone(), true).count(), 0);
+        assert_eq!(back.intersect(g).count(), 2);
+    }
+
+    fn default_triangle() -> Triangle {
+        Triangle::new(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(-1.0, 0.0, 0.0),
+            &Tuple::point(1.0, 0.0, 0.0),
+        )
+    }
+
+    // Smooth triangle
+    #[test]
+    fn test_constructing_a_smooth_triangle() {
+        let tri = default_smooth_triangle();
+
+        assert_eq!(tri.triangle().p1, Tuple::point(0.0, 1.0, 0.0));
+        assert_eq!(tri.n1, Tuple::vector(0.0, 1.0, 0.0));
+        assert_eq!(tri.n2, Tuple::vector(-1.0, 0.0, 0.0));
+        assert_eq!(tri.n3, Tuple::vector(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_a_smooth_triangle_intersects_like_a_triangle() {
+        let tri = default_smooth_triangle();
+        let r = Ray::new(
+            &Tuple::point(-0.2, 0.3, -2.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+
+        assert_eq!(tri.intersect(&r), vec![2.0]);
+    }
+
+    #[test]
+    fn test_a_smooth_triangle_interpolates_the_normal() {
+        let tri = default_smooth_triangle();
+
+        let n = tri.normal_at(&Tuple::point(-0.2, 0.3, 0.0));
+
+        assert_eq!(n, Tuple::vector(-0.5547, 0.83205, 0.0));
+    }
+
+    #[test]
+    fn test_a_smooth_triangle_uses_the_corner_normals_at_its_corners() {
+        let tri = default_smooth_triangle();
+
+        assert_eq!(tri.normal_at(&Tuple::point(0.0, 1.0, 0.0)), tri.n1);
+        assert_eq!(tri.normal_at(&Tuple::point(-1.0, 0.0, 0.0)), tri.n2);
+        assert_eq!(tri.normal_at(&Tuple::point(1.0, 0.0, 0.0)), tri.n3);
+    }
+
+    fn default_smooth_triangle() -> SmoothTriangle {
+        SmoothTriangle::new(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(-1.0, 0.0, 0.0),
+            &Tuple::point(1.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+            &Tuple::vector(-1.0, 0.0, 0.0),
+            &Tuple::vector(1.0, 0.0, 0.0),
+        )
+    }
+
+    // Blob
+    fn blob(centers: &[f64]) -> Blob {
+        let mut b = Blob::new().with_threshold(0.25);
+        for x in centers {
+            b.add_component(&Tuple::point(*x, 0.0, 0.0), 1.0, 1.0);
+        }
+        b
+    }
+
+    #[test]
+    fn test_a_ray_intersects_a_single_blob_component() {
+        let b = blob(&[0.0]);
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = b.intersect(&r);
+
+        assert_eq!(xs.len(), 2);
+        assert!(equal_f64(xs[0], 5.0 - FRAC_1_SQRT_2));
+        assert!(equal_f64(xs[1], 5.0 + FRAC_1_SQRT_2));
+        assert_eq!(
+            b.local_normal_at(&r.position(xs[0])),
+            Tuple::vector(0.0, 0.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn test_nearby_blob_components_blend_together() {
+        let r = Ray::new(&Tuple::point(0.0, 5.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+
+        assert!(blob(&[0.8]).intersect(&r).is_empty());
+        assert_eq!(blob(&[-0.8, 0.8]).intersect(&r).len(), 2);
+        assert_eq!(blob(&[-1.2, 1.2]).intersect(&r).len(), 0);
+    }
+
+    #[test]
+    fn test_a_degenerate_blob_component_does_not_stop_the_others() {
+        let mut b = blob(&[0.0]);
+        b.add_component(&Tuple::point(f64::NAN, 0.0, 0.0), 1.0, 1.0);
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = b.intersect(&r);
+
+        assert!(xs.iter().any(|t| equal_f64(*t, 5.0 - FRAC_1_SQRT_2)));
+    }
+
+    #[test]
+    fn test_a_blob_is_bounded_by_its_components() {
+        let b = blob(&[-0.8, 0.8]);
+
+        let bounds = b.bounds_of();
+
+        assert_eq!(bounds.min, Tuple::point(-1.8, -1.0, -1.0));
+        assert_eq!(bounds.max, Tuple::point(1.8, 1.0, 1.0));
+    }
+
+    // Names and ids
+    #[test]
+    fn test_every_shape_gets_its_own_id() {
+        let a = Sphere::new();
+        let b = Sphere::new();
+
+        assert_ne!(a.id(), b.id());
+        assert_ne!(a.clone().id(), a.id());
+        assert_eq!(a.clone().lineage(), a.id());
+        assert_eq!(a.clone().clone().lineage(), a.lineage());
+        assert_eq!(a.clone(), a);
+        assert_eq!(
+            a.with_transform(&Matrix::scaling(2.0, 2.0, 2.0)).get_name(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_naming_a_shape() {
+        let s = Cube::new().with_name("crate");
+        let t = SmoothTriangle::new(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(-1.0, 0.0, 0.0),
+            &Tuple::point(1.0, 0.0, 0.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+            &Tuple::vector(-1.0, 0.0, 0.0),
+            &Tuple::vector(1.0, 0.0, 0.0),
+        )
+        .with_name("fan");
+
+        assert_eq!(s.get_name(), Some("crate"));
+        assert_eq!(s.with_material(Material::new()).get_name(), Some("crate"));
+        assert_eq!(t.get_name(), Some("fan"));
+        assert_eq!(t.id(), t.triangle().id());
+    }
+
+    #[test]
+    fn test_finding_a_shape_inside_groups_and_csg() {
+        let csg = Csg::new(
+            CsgOperation::Union,
+            Sphere::new().with_name("left"),
+            Cube::new().with_name("right"),
+        );
+        let mut inner = Group::new();
+        inner.add_child(csg);
+        let mut outer = Group::new();
+        outer.add_child(Sphere::new());
+        outer.add_child(inner);
+
+        let found = outer.find(&|s| s.get_name() == Some("right")).unwrap();
+
+        assert_eq!(found.get_name(), Some("right"));
+        assert!(outer.includes(found.as_ref()));
+        assert!(outer.find(&|s| s.get_name() == Some("missing")).is_none());
+        assert!(Sphere::new().find(&|_| true).is_none());
+    }
+
+    // Material inheritance
+    fn colored(red: f64, green: f64, blue: f64) -> Material {
+        let mut m = Material::new();
+        m.color = Color::new(red, green, blue);
+        m
+    }
+
+    #[test]
+    fn test_children_inherit_their_group_material() {
+        let mut g = Group::new().with_material(colored(1.0, 0.0, 0.0));
+        g.add_child(Sphere::new());
+        g.add_child(Cube::new().with_material(colored(0.0, 1.0, 0.0)));
+
+        assert_eq!(g.children()[0].get_material(), colored(1.0, 0.0, 0.0));
+        assert_eq!(g.children()[0].material_source(), MaterialSource::Inherited);
+        assert_eq!(g.children()[1].get_material(), colored(0.0, 1.0, 0.0));
+        assert_eq!(g.children()[1].material_source(), MaterialSource::Own);
+    }
+
+    #[test]
+    fn test_changing_a_group_material_reaches_nested_children() {
+        let mut inner = Group::new();
+        inner.add_child(Sphere::new().with_name("nested"));
+        let mut outer = Group::new();
+        outer.add_child(inner);
+        outer.add_child(Csg::new(
+            CsgOperation::Union,
+            Sphere::new().with_name("left"),
+            Cube::new()
+                .with_material(colored(0.0, 1.0, 0.0))
+                .with_name("right"),
         ));
 
+        outer.set_material(&colored(0.0, 0.0, 1.0));
+
+        let material_of = |name: &str| {
+            outer
+                .find(&|s| s.get_name() == Some(name))
+                .unwrap()
+                .get_material()
+        };
+        assert_eq!(material_of("nested"), colored(0.0, 0.0, 1.0));
+        assert_eq!(material_of("left"), colored(0.0, 0.0, 1.0));
+        assert_eq!(material_of("right"), colored(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_shapes_without_a_group_material_keep_the_default() {
+        let mut g = Group::new();
+        g.add_child(Sphere::new());
+
+        assert_eq!(g.children()[0].material_source(), MaterialSource::Default);
+    }
+
+    #[test]
+    fn test_a_ray_through_a_portal_leaves_the_front_of_its_twin() {
+        let (a, _) = Portal::pair(&Matrix::identify(), &Matrix::translation(10.0, 0.0, 0.0));
+        let r = Ray::new(&Tuple::point(0.5, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+
+        let next = a.teleport(&r, &Tuple::point(0.5, 0.0, 0.0)).unwrap();
+
+        assert_eq!(next.direction, Tuple::vector(0.0, 1.0, 0.0));
+        assert!(equal_f64(next.origin.x, 9.5));
+        assert!(next.origin.y > 0.0 && next.origin.y < 2.0 * EPSILON);
+        assert!(equal_f64(next.origin.z, 0.0));
+    }
+
+    #[test]
+    fn test_a_portal_is_a_square() {
+        let p = Portal::new();
+        let down = Tuple::vector(0.0, -1.0, 0.0);
+
         assert_eq!(
-            n,
-            Tuple::vector(
-                3.0_f64.sqrt() / 3.0,
-                3.0_f64.sqrt() / 3.0,
-                3.0_f64.sqrt() / 3.0
-            )
+            p.intersect(&Ray::new(&Tuple::point(0.9, 1.0, -0.9), &down)),
+            vec![1.0]
         );
+        assert!(p
+            .intersect(&Ray::new(&Tuple::point(1.1, 1.0, 0.0), &down))
+            .is_empty());
+        assert!(!p.get_material().visibility.shadow);
     }
 
+    // Surface sampling
     #[test]
-    fn test_the_normal_is_a_normalized_vector() {
-        let s = Sphere::new();
-        let n = s.normal_at(&Tuple::point(
+    fn test_the_surface_area_of_shapes() {
+        let sphere = Sphere::new().with_transform(&Matrix::scaling(2.0, 2.0, 2.0));
+        let cube =
+            Cube::new().with_transform(&(Matrix::rotation_y(0.5) * Matrix::scaling(1.0, 2.0, 3.0)));
+        let ring = Disc::new()
+            .with_inner_radius(0.5)
+            .with_transform(&Matrix::scaling(2.0, 1.0, 2.0));
+        let triangle = Triangle::new(
+            &Tuple::point(0.0, 0.0, 0.0),
+            &Tuple::point(3.0, 0.0, 0.0),
+            &Tuple::point(0.0, 4.0, 0.0),
+        )
+        .with_transform(&(Matrix::translation(5.0, 1.0, 0.0) * Matrix::rotation_x(1.0)));
+
+        assert!(equal_f64(sphere.surface_area(), 16.0 * PI));
+        assert!(equal_f64(cube.surface_area(), 88.0));
+        assert!(equal_f64(ring.surface_area(), 3.0 * PI));
+        assert!(equal_f64(triangle.surface_area(), 6.0));
+        assert_eq!(Plane::new().surface_area(), f64::INFINITY);
+        assert_eq!(Group::new().surface_area(), 0.0);
+    }
+
+    #[test]
+    fn test_the_area_of_a_stretched_sphere_is_estimated() {
+        let s = Sphere::new().with_transform(&Matrix::scaling(2.0, 1.0, 1.0));
+
+        // Knud Thomsen's approximation for the surface of an ellipsoid.
+        let p = 1.6075;
+        let thomsen = 4.0 * PI * ((2.0_f64.powf(p) * 2.0 + 1.0) / 3.0).powf(1.0 / p);
+
+        assert!((s.surface_area() - thomsen).abs() / thomsen < 0.01);
+    }
+
+    #[test]
+    fn test_sampled_points_lie_on_the_surface() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let sphere = Sphere::new()
+            .with_transform(&(Matrix::translation(1.0, 2.0, 3.0) * Matrix::scaling(2.0, 2.0, 2.0)));
+        let cube = Cube::new().with_transform(&Matrix::scaling(1.0, 2.0, 3.0));
+        let triangle = Triangle::new(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(-1.0, 0.0, 0.0),
+            &Tuple::point(1.0, 0.0, 0.0),
+        );
+
+        for _ in 0..20 {
+            let (point, normal) = sphere.sample_point(&mut rng).unwrap();
+            let offset = &point - &Tuple::point(1.0, 2.0, 3.0);
+            assert!(equal_f64(offset.magnitude(), 2.0));
+            assert_eq!(normal, offset.normalize());
+
+            let (point, normal) = cube.sample_point(&mut rng).unwrap();
+            let local = cube.world_to_object(&point);
+            assert!(equal_f64(
+                local.x.abs().max(local.y.abs()).max(local.z.abs()),
+                1.0
+            ));
+            assert!(equal_f64(normal.magnitude(), 1.0));
+
+            let (point, normal) = triangle.sample_point(&mut rng).unwrap();
+            assert!(!triangle
+                .intersect(&Ray::new(
+                    &Tuple::point(point.x, point.y, -1.0),
+                    &Tuple::vector(0.0, 0.0, 1.0)
+                ))
+                .is_empty());
+            assert_eq!(normal, Tuple::vector(0.0, 0.0, -1.0));
+        }
+        assert!(Plane::new().sample_point(&mut rng).is_none());
+    }
+
+    #[test]
+    fn test_samples_are_spread_by_area() {
+        let mut rng = StdRng::seed_from_u64(11);
+        // The top and bottom are four times the size of each other face.
+        let cube = Cube::new().with_transform(&Matrix::scaling(2.0, 0.5, 2.0));
+
+        let on_top_or_bottom = (0..1000)
+            .filter(|_| cube.sample_point(&mut rng).unwrap().1.y.abs() > 0.5)
+            .count();
+
+        assert!((on_top_or_bottom as f64 / 1000.0 - 2.0 / 3.0).abs() < 0.05);
+    }
+
+    // UV mapping
+    #[test]
+    fn test_uv_mapping_of_a_sphere() {
+        let cases = [
+            (Tuple::point(0.0, 0.0, -1.0), (0.0, 0.5)),
+            (Tuple::point(1.0, 0.0, 0.0), (0.25, 0.5)),
+            (Tuple::point(0.0, 0.0, 1.0), (0.5, 0.5)),
+            (Tuple::point(-1.0, 0.0, 0.0), (0.75, 0.5)),
+            (Tuple::point(0.0, 1.0, 0.0), (0.5, 1.0)),
+            (Tuple::point(0.0, -1.0, 0.0), (0.5, 0.0)),
+            (
+                Tuple::point(2.0_f64.sqrt() / 2.0, 2.0_f64.sqrt() / 2.0, 0.0),
+                (0.25, 0.75),
+            ),
+        ];
+
+        for (point, (u, v)) in cases.iter() {
+            let (mu, mv) = Sphere::new().uv_at(point);
+            assert!(equal_f64(mu, *u));
+            assert!(equal_f64(mv, *v));
+        }
+    }
+
+    #[test]
+    fn test_uv_mapping_of_a_plane() {
+        let p = Plane::new();
+        let cases = [
+            (Tuple::point(0.25, 0.0, 0.5), (0.25, 0.5)),
+            (Tuple::point(0.25, 0.0, -0.25), (0.25, 0.75)),
+            (Tuple::point(0.25, 0.5, -0.25), (0.25, 0.75)),
+            (Tuple::point(1.25, 0.0, 0.5), (0.25, 0.5)),
+            (Tuple::point(-0.25, 0.0, -1.75), (0.75, 0.25)),
+        ];
+
+        for (point, (u, v)) in cases.iter() {
+            let (pu, pv) = p.uv_at(point);
+            assert!(equal_f64(pu, *u));
+            assert!(equal_f64(pv, *v));
+        }
+    }
+
+    #[test]
+    fn test_uv_mapping_of_a_cube_unfolds_its_faces_into_a_cross() {
+        let c = Cube::new();
+        let cases = [
+            // The centre of each face.
+            (Tuple::point(0.0, 0.0, -1.0), (1.5 / 4.0, 1.5 / 3.0)),
+            (Tuple::point(1.0, 0.0, 0.0), (2.5 / 4.0, 1.5 / 3.0)),
+            (Tuple::point(0.0, 0.0, 1.0), (3.5 / 4.0, 1.5 / 3.0)),
+            (Tuple::point(-1.0, 0.0, 0.0), (0.5 / 4.0, 1.5 / 3.0)),
+            (Tuple::point(0.0, 1.0, 0.0), (1.5 / 4.0, 2.5 / 3.0)),
+            (Tuple::point(0.0, -1.0, 0.0), (1.5 / 4.0, 0.5 / 3.0)),
+            // Neighbouring faces meet along their shared edges.
+            (Tuple::point(0.9999999, 0.5, -1.0), (2.0 / 4.0, 1.75 / 3.0)),
+            (Tuple::point(1.0, 0.5, -0.9999999), (2.0 / 4.0, 1.75 / 3.0)),
+            (Tuple::point(-0.5, 1.0, -0.9999999), (1.25 / 4.0, 2.0 / 3.0)),
+        ];
+
+        for (point, (u, v)) in cases.iter() {
+            let (cu, cv) = c.uv_at(point);
+            assert!(equal_f64(cu, *u), "u at {:?}", point);
+            assert!(equal_f64(cv, *v), "v at {:?}", point);
+        }
+    }
+
+    #[test]
+    fn test_uv_mapping_of_triangles_and_discs() {
+        let t = Triangle::new(
+            &Tuple::point(0.0, 1.0, 0.0),
+            &Tuple::point(-1.0, 0.0, 0.0),
+            &Tuple::point(1.0, 0.0, 0.0),
+        );
+        let (u, v) = t.uv_at(&Tuple::point(0.0, 0.5, 0.0));
+        assert!(equal_f64(u, 0.25) && equal_f64(v, 0.25));
+        let (u, v) = t.uv_at(&Tuple::point(1.0, 0.0, 0.0));
+        assert!(equal_f64(u, 0.0) && equal_f64(v, 1.0));
+
+        let (u, v) = Disc::new().uv_at(&Tuple::point(0.5, 0.0, -1.0));
+        assert!(equal_f64(u, 0.75) && equal_f64(v, 0.0));
+    }
+
+    #[test]
+    fn test_sorting_shapes_by_name_then_age() {
+        let unnamed = Sphere::new();
+        let older = Sphere::new().with_name("ball");
+        let newer = Cube::new().with_name("ball");
+        let box_ = Cube::new().with_name("box");
+        let ids = [older.id(), newer.id(), box_.id(), unnamed.id()];
+        let mut shapes: Vec<Arc<dyn Shape + Send + Sync>> = vec![
+            Arc::new(box_),
+            Arc::new(unnamed),
+            Arc::new(newer),
+            Arc::new(older),
+        ];
+
+        sort_shapes(&mut shapes);
+
+        let sorted: Vec<ShapeId> = shapes.iter().map(|s| s.id()).collect();
+        assert_eq!(sorted, ids);
+    }
+
+    // Group
+    #[test]
+    fn test_creating_a_new_group() {
+        let g = Group::new();
+
+        assert_eq!(g.get_transform(), Matrix::identify());
+        assert!(g.is_empty());
+    }
+
+    #[test]
+    fn test_adding_a_child_to_a_group() {
+        let g = Group::new().with_child(TestShape::new());
+
+        assert_eq!(g.children().len(), 1);
+        assert_eq!(
+            g.children()[0].get_parent_inverse_transform(),
+            Matrix::identify()
+        );
+    }
+
+    #[test]
+    fn test_intersecting_a_ray_with_an_empty_group() {
+        let g = Group::new();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = r.intersect(Arc::new(g));
+
+        assert_eq!(xs.count(), 0);
+    }
+
+    #[test]
+    fn test_intersecting_a_ray_with_a_nonempty_group() {
+        let g = Group::new()
+            .with_child(Sphere::new())
+            .with_child(Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, -3.0)))
+            .with_child(Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let s1 = g.children()[0].clone();
+        let s2 = g.children()[1].clone();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = r.intersect(Arc::new(g));
+
+        assert_eq!(xs.count(), 4);
+        assert!(Arc::ptr_eq(
+            &xs.at(0).object,
+            &(s2.clone() as Arc<dyn Shape>)
+        ));
+        assert!(Arc::ptr_eq(&xs.at(1).object, &(s2 as Arc<dyn Shape>)));
+        assert!(Arc::ptr_eq(
+            &xs.at(2).object,
+            &(s1.clone() as Arc<dyn Shape>)
+        ));
+        assert!(Arc::ptr_eq(&xs.at(3).object, &(s1 as Arc<dyn Shape>)));
+    }
+
+    #[test]
+    fn test_intersecting_a_transformed_group() {
+        let g = Group::new()
+            .with_transform(&Matrix::scaling(2.0, 2.0, 2.0))
+            .with_child(Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let r = Ray::new(
+            &Tuple::point(10.0, 0.0, -10.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+
+        let xs = r.intersect(Arc::new(g));
+
+        assert_eq!(xs.count(), 2);
+    }
+
+    #[test]
+    fn test_converting_a_point_from_world_to_object_space() {
+        let g2 = Group::new()
+            .with_transform(&Matrix::scaling(2.0, 2.0, 2.0))
+            .with_child(Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let g1 = Group::new()
+            .with_transform(&Matrix::rotation_y(PI / 2.0))
+            .with_child(g2);
+
+        let s = object_hit_along_negative_z(g1);
+        let p = s.world_to_object(&Tuple::point(-2.0, 0.0, -10.0));
+
+        assert_eq!(p, Tuple::point(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_converting_a_normal_from_object_to_world_space() {
+        let g2 = Group::new()
+            .with_transform(&Matrix::scaling(1.0, 2.0, 3.0))
+            .with_child(Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let g1 = Group::new()
+            .with_transform(&Matrix::rotation_y(PI / 2.0))
+            .with_child(g2);
+
+        let s = object_hit_along_negative_z(g1);
+        let n = s.normal_to_world(&Tuple::vector(
             3.0_f64.sqrt() / 3.0,
             3.0_f64.sqrt() / 3.0,
             3.0_f64.sqrt() / 3.0,
         ));
 
-        assert_eq!(n.clone(), n.normalize());
+        assert_eq!(n, Tuple::vector(0.28571, 0.42857, -0.85714));
     }
 
     #[test]
-    fn test_computing_the_normal_on_a_translated_sphere() {
-        let mut s = Sphere::new();
-        s.set_transform(&Matrix::translation(0.0, 1.0, 0.0));
+    fn test_finding_the_normal_on_a_child_object() {
+        let g2 = Group::new()
+            .with_transform(&Matrix::scaling(1.0, 2.0, 3.0))
+            .with_child(Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let g1 = Group::new()
+            .with_transform(&Matrix::rotation_y(PI / 2.0))
+            .with_child(g2);
 
-        let n = s.normal_at(&Tuple::point(0.0, 1.70711, -0.70711));
+        let s = object_hit_along_negative_z(g1);
+        let n = s.normal_at(&Tuple::point(1.7321, 1.1547, -5.5774));
 
-        assert_eq!(n, Tuple::vector(0.0, 0.70711, -0.70711));
+        assert_eq!(n, Tuple::vector(0.28570, 0.42854, -0.85716));
     }
 
     #[test]
-    fn test_copmuting_the_normal_on_a_transformed_sphere() {
-        let mut s = Sphere::new();
-        let m = Matrix::scaling(1.0, 0.5, 1.0) * Matrix::rotation_z(PI / 0.5);
-        s.set_transform(&m);
+    fn test_transforming_a_group_after_adding_children() {
+        let mut g = Group::new().with_child(Sphere::new());
+        g.set_transform(&Matrix::translation(0.0, 0.0, 3.0));
 
-        let n = s.normal_at(&Tuple::point(
-            0.0,
-            2.0_f64.sqrt() / 2.0,
-            -2.0_f64.sqrt() / 2.0,
-        ));
+        let s = g.children()[0].clone();
 
-        assert_eq!(n, Tuple::vector(0.0, 0.97014, -0.24254));
+        assert_eq!(
+            s.world_to_object(&Tuple::point(0.0, 0.0, 3.0)),
+            Tuple::point(0.0, 0.0, 0.0)
+        );
     }
 
+    fn object_hit_along_negative_z(group: Group) -> Arc<dyn Shape> {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, -1.0));
+        r.intersect(Arc::new(group)).at(0).object
+    }
+
+    // CSG
     #[test]
-    fn test_a_sphere_has_a_default_material() {
-        let s = Sphere::new();
+    fn test_csg_is_created_with_an_operation_and_two_shapes() {
+        let c = Csg::new(CsgOperation::Union, Sphere::new(), Cube::new());
 
-        assert_eq!(s.material, Material::new());
+        assert_eq!(c.operation(), CsgOperation::Union);
+        assert_eq!(c.left().get_transform(), Matrix::identify());
+        assert_eq!(c.right().get_transform(), Matrix::identify());
     }
 
     #[test]
-    fn test_a_sphere_may_be_assigned_a_material() {
-        let mut s = Sphere::new();
-        let mut m = Material::new();
-        m.ambient = 1.0;
+    fn test_evaluating_the_rule_for_a_csg_operation() {
+        let examples = [
+            (CsgOperation::Union, true, true, true, false),
+            (CsgOperation::Union, true, true, false, true),
+            (CsgOperation::Union, true, false, true, false),
+            (CsgOperation::Union, true, false, false, true),
+            (CsgOperation::Union, false, true, true, false),
+            (CsgOperation::Union, false, true, false, false),
+            (CsgOperation::Union, false, false, true, true),
+            (CsgOperation::Union, false, false, false, true),
+            (CsgOperation::Intersection, true, true, true, true),
+            (CsgOperation::Intersection, true, true, false, false),
+            (CsgOperation::Intersection, true, false, true, true),
+            (CsgOperation::Intersection, true, false, false, false),
+            (CsgOperation::Intersection, false, true, true, true),
+            (CsgOperation::Intersection, false, true, false, true),
+            (CsgOperation::Intersection, false, false, true, false),
+            (CsgOperation::Intersection, false, false, false, false),
+            (CsgOperation::Difference, true, true, true, false),
+            (CsgOperation::Difference, true, true, false, true),
+            (CsgOperation::Difference, true, false, true, false),
+            (CsgOperation::Difference, true, false, false, true),
+            (CsgOperation::Difference, false, true, true, true),
+            (CsgOperation::Difference, false, true, false, true),
+            (CsgOperation::Difference, false, false, true, false),
+            (CsgOperation::Difference, false, false, false, false),
+        ];
 
-        s.material = m.clone();
+        for (op, lhit, inl, inr, result) in examples.iter() {
+            assert_eq!(op.intersection_allowed(*lhit, *inl, *inr), *result);
+        }
+    }
+
+    #[test]
+    fn test_filtering_a_list_of_intersections() {
+        let examples = [
+            (CsgOperation::Union, 0, 3),
+            (CsgOperation::Intersection, 1, 2),
+            (CsgOperation::Difference, 0, 1),
+        ];
 
-        assert_eq!(s.material, m);
+        for (op, x0, x1) in examples.iter() {
+            let c = Csg::new(*op, Sphere::new(), Cube::new());
+            let s1: Arc<dyn Shape> = c.left().clone();
+            let s2: Arc<dyn Shape> = c.right().clone();
+            let xs = Intersections::new(vec![
+                Intersection::new(1.0, s1.clone()),
+                Intersection::new(2.0, s2.clone()),
+                Intersection::new(3.0, s1),
+                Intersection::new(4.0, s2),
+            ]);
+
+            let result = c.filter_intersections(&xs);
+
+            assert_eq!(result.count(), 2);
+            assert!(&result.at(0) == &xs.at(*x0));
+            assert!(&result.at(1) == &xs.at(*x1));
+        }
     }
 
-    // Plane
     #[test]
-    fn test_the_normal_of_a_plane_is_constant_everywhere() {
-        let p = Plane::new();
+    fn test_a_ray_misses_a_csg_object() {
+        let c = Csg::new(CsgOperation::Union, Sphere::new(), Cube::new());
+        let r = Ray::new(&Tuple::point(0.0, 2.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = r.intersect(Arc::new(c));
+
+        assert_eq!(xs.count(), 0);
+    }
+
+    #[test]
+    fn test_a_ray_hits_a_csg_object() {
+        let c = Csg::new(
+            CsgOperation::Union,
+            Sphere::new(),
+            Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, 0.5)),
+        );
+        let s1: Arc<dyn Shape> = c.left().clone();
+        let s2: Arc<dyn Shape> = c.right().clone();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = r.intersect(Arc::new(c));
+
+        assert_eq!(xs.count(), 2);
+        assert!(equal_f64(xs.at(0).t, 4.0));
+        assert!(Arc::ptr_eq(&xs.at(0).object, &s1));
+        assert!(equal_f64(xs.at(1).t, 6.5));
+        assert!(Arc::ptr_eq(&xs.at(1).object, &s2));
+    }
+
+    #[test]
+    fn test_a_group_includes_its_descendants() {
+        let g = Group::new().with_child(Group::new().with_child(Sphere::new()));
+        let other = Sphere::new();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let g = Arc::new(g);
+
+        let xs = r.intersect(g.clone());
+
+        assert!(g.includes(xs.at(0).object.as_ref()));
+        assert!(!g.includes(&other));
+    }
+
+    // Bounds
+    #[test]
+    fn test_a_sphere_has_a_bounding_box() {
+        let b = Sphere::new().bounds_of();
+
+        assert_eq!(b.min, Tuple::point(-1.0, -1.0, -1.0));
+        assert_eq!(b.max, Tuple::point(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_a_plane_has_a_bounding_box() {
+        let b = Plane::new().bounds_of();
+
+        assert_eq!(b.min.x, f64::NEG_INFINITY);
+        assert_eq!(b.min.y, 0.0);
+        assert_eq!(b.min.z, f64::NEG_INFINITY);
+        assert_eq!(b.max.x, f64::INFINITY);
+        assert_eq!(b.max.y, 0.0);
+        assert_eq!(b.max.z, f64::INFINITY);
+    }
+
+    #[test]
+    fn test_querying_a_shapes_bounding_box_in_its_parents_space() {
+        let s = Sphere::new().with_transform(
+            &(Matrix::translation(1.0, -3.0, 5.0) * Matrix::scaling(0.5, 2.0, 4.0)),
+        );
+
+        let b = s.parent_space_bounds_of();
+
+        assert_eq!(b.min, Tuple::point(0.5, -5.0, 1.0));
+        assert_eq!(b.max, Tuple::point(1.5, -1.0, 9.0));
+    }
+
+    #[test]
+    fn test_a_group_has_a_bounding_box_that_contains_its_children() {
+        let s = Sphere::new().with_transform(
+            &(Matrix::translation(2.0, 5.0, -3.0) * Matrix::scaling(2.0, 2.0, 2.0)),
+        );
+        let c = Cube::new().with_transform(
+            &(Matrix::translation(-4.0, -1.0, 4.0) * Matrix::scaling(0.5, 1.0, 0.5)),
+        );
+        let g = Group::new().with_child(s).with_child(c);
+
+        let b = g.bounds_of();
+
+        assert_eq!(b.min, Tuple::point(-4.5, -2.0, -5.0));
+        assert_eq!(b.max, Tuple::point(4.0, 7.0, 4.5));
+    }
+
+    #[test]
+    fn test_an_empty_group_has_an_empty_bounding_box() {
+        assert!(Group::new().bounds_of().is_empty());
+    }
+
+    #[test]
+    fn test_a_csg_shape_has_a_bounding_box_that_contains_its_children() {
+        let left = Sphere::new();
+        let right = Sphere::new().with_transform(&Matrix::translation(2.0, 3.0, 4.0));
+        let shape = Csg::new(CsgOperation::Difference, left, right);
+
+        let b = shape.bounds_of();
+
+        assert_eq!(b.min, Tuple::point(-1.0, -1.0, -1.0));
+        assert_eq!(b.max, Tuple::point(3.0, 4.0, 5.0));
+    }
+
+    // Bounding volume hierarchies
+    #[test]
+    fn test_intersecting_a_group_does_not_test_children_if_its_box_is_missed() {
+        let g = Group::new().with_child(TestShape::new());
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 1.0, 0.0));
+
+        let xs = r.intersect(Arc::new(g));
+
+        assert_eq!(xs.count(), 0);
+    }
+
+    #[test]
+    fn test_querying_a_groups_bounds_and_primitives() {
+        let inner = Group::new().with_child(Sphere::new()).with_child(Csg::new(
+            CsgOperation::Union,
+            Sphere::new(),
+            Cube::new(),
+        ));
+        let g = Group::new()
+            .with_transform(&Matrix::translation(1.0, 0.0, 0.0))
+            .with_child(inner)
+            .with_child(Sphere::new().with_transform(&Matrix::translation(0.0, 3.0, 0.0)));
+
+        let b = g.bounds();
+
+        assert_eq!(b.min, Tuple::point(0.0, -1.0, -1.0));
+        assert_eq!(b.max, Tuple::point(2.0, 4.0, 1.0));
+        assert_eq!(g.total_primitives(), 4);
+        assert_eq!(Group::new().total_primitives(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_groups_count_the_rays_that_reach_them() {
+        let mut g = Group::new();
+        for x in [-2.0, 2.0].iter() {
+            g.add_child(Sphere::new().with_transform(&Matrix::translation(*x, 0.0, 0.0)));
+        }
+        g.divide(1);
+        let g = Arc::new(g);
+        let r = Ray::new(
+            &Tuple::point(-2.0, 0.0, -5.0),
+            &Tuple::vector(0.0, 0.0, 1.0),
+        );
+
+        r.intersect(g.clone());
+        let report = g.report();
+
+        assert_eq!(report.len(), 3);
+        assert_eq!(report[0].depth, 0);
+        assert_eq!(report[0].primitives, 2);
         assert_eq!(
-            p.local_normal_at(&Tuple::point(0.0, 0.0, 0.0)),
-            Tuple::vector(0.0, 1.0, 0.0)
+            report[0].rays,
+            RayStats {
+                tested: 1,
+                entered: 1
+            }
         );
         assert_eq!(
-            p.local_normal_at(&Tuple::point(10.0, 0.0, -10.0)),
-            Tuple::vector(0.0, 1.0, 0.0)
+            report[1].rays,
+            RayStats {
+                tested: 1,
+                entered: 1
+            }
         );
         assert_eq!(
-            p.local_normal_at(&Tuple::point(-5.0, 0.0, 150.0)),
-            Tuple::vector(0.0, 1.0, 0.0)
+            report[2].rays,
+            RayStats {
+                tested: 1,
+                entered: 0
+            }
         );
+
+        g.reset_ray_stats();
+        assert!(g
+            .report()
+            .iter()
+            .all(|node| node.rays == RayStats::default()));
     }
 
     #[test]
-    fn test_intersect_with_a_ray_parallel_to_the_plane() {
-        let p = Plane::new();
-        let r = Ray::new(&Tuple::point(0.0, 10.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+    fn test_partitioning_a_groups_children() {
+        let s1 = Sphere::new().with_transform(&Matrix::translation(-2.0, 0.0, 0.0));
+        let s2 = Sphere::new().with_transform(&Matrix::translation(2.0, 0.0, 0.0));
+        let s3 = Sphere::new();
+        let mut g = Group::new().with_child(s1).with_child(s2).with_child(s3);
 
-        let xs = p.intersect(&r);
+        let (left, right) = g.partition_children();
 
-        assert_eq!(xs.len(), 0);
+        assert_eq!(g.children().len(), 1);
+        assert_eq!(g.children()[0].get_transform(), Matrix::identify());
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].get_transform(), Matrix::translation(-2.0, 0.0, 0.0));
+        assert_eq!(right.len(), 1);
+        assert_eq!(right[0].get_transform(), Matrix::translation(2.0, 0.0, 0.0));
     }
 
     #[test]
-    fn test_intersect_with_a_coplanar_ray() {
-        let p = Plane::new();
-        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+    fn test_subdividing_a_group_partitions_its_children() {
+        let s1 = Sphere::new().with_transform(&Matrix::translation(-2.0, -2.0, 0.0));
+        let s2 = Sphere::new().with_transform(&Matrix::translation(-2.0, 2.0, 0.0));
+        let s3 = Sphere::new().with_transform(&Matrix::scaling(4.0, 4.0, 4.0));
+        let mut g = Group::new().with_child(s1).with_child(s2).with_child(s3);
 
-        let xs = p.intersect(&r);
+        g.divide(1);
 
-        assert_eq!(xs.len(), 0);
+        assert_eq!(g.children().len(), 2);
+        assert_eq!(
+            g.children()[0].get_transform(),
+            Matrix::scaling(4.0, 4.0, 4.0)
+        );
+        let subgroup = g.children()[1].bounds_of();
+        assert_eq!(subgroup.min, Tuple::point(-3.0, -3.0, -1.0));
+        assert_eq!(subgroup.max, Tuple::point(-1.0, 3.0, 1.0));
     }
 
     #[test]
-    fn test_intersect_with_a_plane_from_above() {
-        let p = Plane::new();
-        let r = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+    fn test_subdividing_a_group_with_too_few_children() {
+        let s1 = Sphere::new().with_transform(&Matrix::translation(-2.0, 0.0, 0.0));
+        let s2 = Sphere::new().with_transform(&Matrix::translation(2.0, 0.0, 0.0));
+        let mut g = Group::new().with_child(s1).with_child(s2);
 
-        let xs = p.intersect(&r);
+        g.divide(3);
 
-        assert_eq!(xs.len(), 1);
-        assert_eq!(xs[0], 1.0);
+        assert_eq!(g.children().len(), 2);
+        assert_eq!(
+            g.children()[0].get_transform(),
+            Matrix::translation(-2.0, 0.0, 0.0)
+        );
     }
 
     #[test]
-    fn test_intersect_with_a_plane_from_below() {
-        let p = Plane::new();
-        let r = Ray::new(&Tuple::point(0.0, -1.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+    fn test_subdividing_a_group_keeps_its_intersections() {
+        let build = || {
+            let mut g = Group::new().with_transform(&Matrix::translation(0.0, 0.0, 10.0));
+            for i in 0..8 {
+                for j in 0..8 {
+                    let x = i as f64 * 2.5 - 8.75;
+                    let y = j as f64 * 2.5 - 8.75;
+                    g.add_child(Sphere::new().with_transform(&Matrix::translation(x, y, 0.0)));
+                }
+            }
+            g
+        };
+        let mut divided = build();
+        divided.divide(4);
+        let flat: Arc<dyn Shape> = Arc::new(build());
+        let divided: Arc<dyn Shape> = Arc::new(divided);
 
-        let xs = p.intersect(&r);
+        for (x, y) in [(-8.75, -8.75), (1.25, 0.5), (3.0, 3.0), (8.75, 1.0)].iter() {
+            let r = Ray::new(&Tuple::point(*x, *y, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+            let a = r.intersect(flat.clone());
+            let b = r.intersect(divided.clone());
 
-        assert_eq!(xs.len(), 1);
-        assert_eq!(xs[0], 1.0);
+            assert_eq!(a.count(), b.count());
+            for i in 0..a.count() {
+                assert!(equal_f64(a.at(i).t, b.at(i).t));
+                assert_eq!(
+                    a.at(i).object.normal_at(&r.position(a.at(i).t)),
+                    b.at(i).object.normal_at(&r.position(b.at(i).t))
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,381 @@
+use crate::canvas::Color;
+use crate::environment::Skybox;
+use crate::light::{Light, Material, ShadingModel};
+use crate::shapes::{Shape, ShapeId};
+use crate::tuple::Tuple;
+use crate::world::{RenderOptions, World};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Hashes of the state of a world, object by object, for checking between frames of an
+/// animation that only the intended properties changed. Patterns, shaders, skyboxes and
+/// irradiance caches are compared by identity, as they can't be inspected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldSnapshot {
+    pub objects: Vec<ObjectState>,
+    pub lights: u64,
+    // Everything else that changes how the world renders, e.g. `max_depth`.
+    pub settings: u64,
+}
+
+/// The state of one shape, including each group and the shapes within it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectState {
+    pub id: ShapeId,
+    pub lineage: ShapeId,
+    pub name: Option<String>,
+    pub transform: u64,
+    pub material: u64,
+    // The shape's bounds, which change when e.g. a mesh is edited.
+    pub geometry: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(ShapeId),
+    Removed(ShapeId),
+    Transform(ShapeId),
+    Material(ShapeId),
+    Geometry(ShapeId),
+    Lights,
+    Settings,
+}
+
+impl WorldSnapshot {
+    pub fn new(world: &World) -> Self {
+        // Destructured so that a new field of `World` has to be accounted for here.
+        let World {
+            lights: world_lights,
+            objects: world_objects,
+            shadows,
+            default_material,
+            max_depth,
+            options,
+            ambient,
+            skybox,
+            indirect,
+            // Shapes' materials are recorded with the shapes themselves.
+            materials: _,
+        } = world;
+        let RenderOptions {
+            glossy_samples,
+            adaptive_shadows,
+            // How long a render may take, not what it shows.
+            max_seconds: _,
+            profile: _,
+        } = options;
+
+        let mut objects = vec![];
+        for object in world_objects {
+            record(object.as_ref(), &mut objects);
+        }
+
+        let mut lights = DefaultHasher::new();
+        for light in world_lights {
+            hash_light(light, &mut lights);
+        }
+
+        let mut settings = DefaultHasher::new();
+        shadows.hash(&mut settings);
+        max_depth.hash(&mut settings);
+        glossy_samples.hash(&mut settings);
+        hash_color(ambient, &mut settings);
+        if let Some(material) = default_material {
+            hash_material(material, &mut settings);
+        }
+        if let Some(adaptive) = adaptive_shadows {
+            adaptive.probes.hash(&mut settings);
+            hash_f64s(&[adaptive.tolerance], &mut settings);
+        }
+        match skybox {
+            None => 0.hash(&mut settings),
+            Some(Skybox::Equirectangular(environment)) => {
+                1.hash(&mut settings);
+                (Arc::as_ptr(environment) as usize).hash(&mut settings);
+            }
+            Some(Skybox::Cube(cube)) => {
+                2.hash(&mut settings);
+                (Arc::as_ptr(cube) as usize).hash(&mut settings);
+            }
+        }
+        indirect
+            .as_ref()
+            .map(|cache| Arc::as_ptr(cache) as usize)
+            .hash(&mut settings);
+
+        Self {
+            objects,
+            lights: lights.finish(),
+            settings: settings.finish(),
+        }
+    }
+
+    /// A single hash of everything in the snapshot.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for object in &self.objects {
+            object.lineage.hash(&mut hasher);
+            object.name.hash(&mut hasher);
+            object.transform.hash(&mut hasher);
+            object.material.hash(&mut hasher);
+            object.geometry.hash(&mut hasher);
+        }
+        self.lights.hash(&mut hasher);
+        self.settings.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// What changed between this snapshot and a `later` one, with objects matched by
+    /// lineage, and copies of the same shape in the order they appear. Changes name the
+    /// object's id in the later snapshot, or in this one if it was removed.
+    pub fn diff(&self, later: &WorldSnapshot) -> Vec<Change> {
+        let before = by_lineage(&self.objects);
+        let after = by_lineage(&later.objects);
+        let mut changes = vec![];
+
+        for (key, object) in keyed(&self.objects) {
+            if !after.contains_key(&key) {
+                changes.push(Change::Removed(object.id));
+            }
+        }
+        for (key, object) in keyed(&later.objects) {
+            match before.get(&key) {
+                None => changes.push(Change::Added(object.id)),
+                Some(earlier) => {
+                    if earlier.transform != object.transform {
+                        changes.push(Change::Transform(object.id));
+                    }
+                    if earlier.material != object.material {
+                        changes.push(Change::Material(object.id));
+                    }
+                    if earlier.geometry != object.geometry {
+                        changes.push(Change::Geometry(object.id));
+                    }
+                }
+            }
+        }
+        if self.lights != later.lights {
+            changes.push(Change::Lights);
+        }
+        if self.settings != later.settings {
+            changes.push(Change::Settings);
+        }
+
+        changes
+    }
+}
+
+// Each object with its lineage and how many objects of that lineage come before it.
+fn keyed(objects: &[ObjectState]) -> Vec<((ShapeId, usize), &ObjectState)> {
+    let mut seen: HashMap<ShapeId, usize> = HashMap::new();
+    objects
+        .iter()
+        .map(|object| {
+            let count = seen.entry(object.lineage).or_insert(0);
+            *count += 1;
+            ((object.lineage, *count - 1), object)
+        })
+        .collect()
+}
+
+fn by_lineage(objects: &[ObjectState]) -> HashMap<(ShapeId, usize), &ObjectState> {
+    keyed(objects).into_iter().collect()
+}
+
+// Adds the state of `shape` and of the shapes in it, when it is a group.
+fn record(shape: &dyn Shape, objects: &mut Vec<ObjectState>) {
+    let mut transform = DefaultHasher::new();
+    hash_f64s(&shape.get_transform().to_row_major(), &mut transform);
+
+    let mut material = DefaultHasher::new();
+    hash_material(&shape.get_material(), &mut material);
+
+    let mut geometry = DefaultHasher::new();
+    let bounds = shape.bounds_of();
+    hash_tuple(&bounds.min, &mut geometry);
+    hash_tuple(&bounds.max, &mut geometry);
+
+    objects.push(ObjectState {
+        id: shape.id(),
+        lineage: shape.lineage(),
+        name: shape.get_name().map(|name| name.to_string()),
+        transform: transform.finish(),
+        material: material.finish(),
+        geometry: geometry.finish(),
+    });
+
+    if let Some(group) = shape.as_group() {
+        for child in group.children() {
+            record(child.as_ref(), objects);
+        }
+    }
+}
+
+fn hash_f64s(values: &[f64], hasher: &mut DefaultHasher) {
+    for value in values {
+        value.to_bits().hash(hasher);
+    }
+}
+
+fn hash_color(color: &Color, hasher: &mut DefaultHasher) {
+    hash_f64s(&[color.red, color.green, color.blue], hasher);
+}
+
+fn hash_tuple(tuple: &Tuple, hasher: &mut DefaultHasher) {
+    hash_f64s(&[tuple.x, tuple.y, tuple.z, tuple.w], hasher);
+}
+
+fn hash_material(m: &Material, hasher: &mut DefaultHasher) {
+    hash_color(&m.color, hasher);
+    hash_color(&m.absorption, hasher);
+    hash_f64s(
+        &[
+            m.ambient,
+            m.diffuse,
+            m.specular,
+            m.shininess,
+            m.reflective,
+            m.roughness,
+            m.transparency,
+            m.refractive_index,
+        ],
+        hasher,
+    );
+    m.two_sided.hash(hasher);
+    let v = m.visibility;
+    [v.camera, v.shadow, v.reflection, v.refraction].hash(hasher);
+    m.medium_priority.hash(hasher);
+    match m.shading {
+        ShadingModel::Phong => 0.hash(hasher),
+        ShadingModel::MetallicRoughness { metallic } => {
+            1.hash(hasher);
+            hash_f64s(&[metallic], hasher);
+        }
+    }
+    m.pattern
+        .as_ref()
+        .map(|p| Arc::as_ptr(p) as *const () as usize)
+        .hash(hasher);
+    m.shader
+        .as_ref()
+        .map(|s| Arc::as_ptr(s) as *const () as usize)
+        .hash(hasher);
+}
+
+fn hash_light(light: &Light, hasher: &mut DefaultHasher) {
+    hash_color(&light.intensity(), hasher);
+    let falloff = light.falloff();
+    hash_f64s(
+        &[falloff.constant, falloff.linear, falloff.quadratic],
+        hasher,
+    );
+    for point in light.samples() {
+        hash_tuple(&point, hasher);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Canvas;
+    use crate::environment::Environment;
+    use crate::light::PointLight;
+    use crate::matrix::Matrix;
+    use crate::shapes::{Group, Sphere};
+
+    #[test]
+    fn test_an_unchanged_world_has_the_same_fingerprint() {
+        let w = World::default_world();
+
+        let a = WorldSnapshot::new(&w);
+        let b = WorldSnapshot::new(&w);
+
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert!(a.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn test_a_diff_names_what_changed() {
+        let sphere = Sphere::new();
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 5.0, 0.0), &Color::white()).into()];
+        w.objects
+            .push(Arc::new(Group::new().with_child(sphere.clone())));
+        let before = WorldSnapshot::new(&w);
+
+        let mut material = Material::new();
+        material.diffuse = 0.5;
+        let moved = sphere
+            .clone()
+            .with_transform(&Matrix::translation(1.0, 0.0, 0.0))
+            .with_material(&material);
+        w.objects[0] = Arc::new(Group::new().with_child(moved));
+        w.max_depth = 2;
+        let after = WorldSnapshot::new(&w);
+
+        let group_before = before.objects[0].id;
+        let group_after = after.objects[0].id;
+        let id = after.objects[1].id;
+        assert_eq!(after.objects[1].lineage, sphere.id());
+        assert_ne!(before.fingerprint(), after.fingerprint());
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                Change::Removed(group_before),
+                Change::Added(group_after),
+                Change::Transform(id),
+                Change::Material(id),
+                Change::Settings,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copies_of_a_shape_are_told_apart() {
+        let sphere = Sphere::new();
+        let mut w = World::new();
+        w.objects.push(Arc::new(sphere.clone()));
+        w.objects.push(Arc::new(sphere.clone()));
+        let before = WorldSnapshot::new(&w);
+
+        w.objects[1] = Arc::new(
+            sphere
+                .clone()
+                .with_transform(&Matrix::translation(0.0, 2.0, 0.0)),
+        );
+        let after = WorldSnapshot::new(&w);
+
+        assert_eq!(before.objects.len(), 2);
+        assert_ne!(before.objects[0].id, before.objects[1].id);
+        assert_eq!(
+            before.diff(&after),
+            vec![Change::Transform(after.objects[1].id)]
+        );
+    }
+
+    #[test]
+    fn test_moving_a_light_changes_the_lights() {
+        let mut w = World::default_world();
+        let before = WorldSnapshot::new(&w);
+
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, 0.0), &Color::white()).into()];
+
+        assert_eq!(before.diff(&WorldSnapshot::new(&w)), vec![Change::Lights]);
+    }
+
+    #[test]
+    fn test_changing_the_skybox_changes_the_settings() {
+        let mut w = World::default_world();
+        let before = WorldSnapshot::new(&w);
+
+        let sky = Arc::new(Environment::new(Canvas::new(4, 2)));
+        w.skybox = Some(Skybox::Equirectangular(sky.clone()));
+        let with_sky = WorldSnapshot::new(&w);
+        w.skybox = Some(Skybox::Equirectangular(sky));
+
+        assert_eq!(before.diff(&with_sky), vec![Change::Settings]);
+        assert!(with_sky.diff(&WorldSnapshot::new(&w)).is_empty());
+    }
+}
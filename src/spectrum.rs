@@ -0,0 +1,265 @@
+use crate::canvas::Color;
+use crate::matrix::Matrix;
+use crate::tuple::Tuple;
+use crate::utils::equal_f64;
+use std::ops::{Add, Mul, Sub};
+
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 730.0;
+
+/// What the renderer needs from a color representation, so that experiments can swap
+/// `Color`, the fast default, for a `Spectrum`.
+pub trait ColorModel:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> + Mul<Output = Self>
+{
+    fn black() -> Self;
+    fn from_rgb(color: &Color) -> Self;
+    fn to_rgb(&self) -> Color;
+}
+
+impl ColorModel for Color {
+    fn black() -> Self {
+        Color::black()
+    }
+
+    fn from_rgb(color: &Color) -> Self {
+        *color
+    }
+
+    fn to_rgb(&self) -> Color {
+        *self
+    }
+}
+
+/// Power in `N` equal wavelength bins spanning `MIN_WAVELENGTH` to `MAX_WAVELENGTH`
+/// nanometres, for experimenting with dispersion and metamerism. RGB colors are lifted
+/// to spectra with box-shaped red, green and blue primaries; spectra go back to RGB
+/// through the CIE 1931 observer, corrected so that the primaries round trip exactly.
+#[derive(Debug, Clone, Copy)]
+pub struct Spectrum<const N: usize> {
+    pub bins: [f64; N],
+}
+
+impl<const N: usize> Spectrum<N> {
+    pub fn new(bins: [f64; N]) -> Self {
+        assert!(N >= 3, "a spectrum needs at least one bin per primary");
+        Self { bins }
+    }
+
+    pub fn constant(power: f64) -> Self {
+        Self::new([power; N])
+    }
+
+    /// All of `power` in the bin containing `wavelength`.
+    pub fn monochromatic(wavelength: f64, power: f64) -> Self {
+        let mut s = Self::constant(0.0);
+        s.bins[Self::bin_of(wavelength)] = power;
+        s
+    }
+
+    /// The wavelength at the centre of bin `i`.
+    pub fn wavelength(i: usize) -> f64 {
+        MIN_WAVELENGTH + (i as f64 + 0.5) * (MAX_WAVELENGTH - MIN_WAVELENGTH) / N as f64
+    }
+
+    fn bin_of(wavelength: f64) -> usize {
+        let fraction = (wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH);
+        ((fraction * N as f64) as usize).min(N - 1)
+    }
+
+    // Blue below 500nm, green below 600nm and red above, by bin centre.
+    fn primary(channel: usize) -> Self {
+        let mut s = Self::constant(0.0);
+        for (i, bin) in s.bins.iter_mut().enumerate() {
+            let lambda = Self::wavelength(i);
+            let primary = if lambda >= 600.0 {
+                0
+            } else if lambda >= 500.0 {
+                1
+            } else {
+                2
+            };
+            if primary == channel {
+                *bin = 1.0;
+            }
+        }
+        s
+    }
+
+    // Linear sRGB seen by the CIE observer, before correcting for the primaries.
+    fn observed(&self) -> Tuple {
+        self.bins
+            .iter()
+            .enumerate()
+            .fold(Tuple::vector(0.0, 0.0, 0.0), |sum, (i, power)| {
+                &sum + &(&observer_rgb(Self::wavelength(i)) * *power)
+            })
+    }
+}
+
+impl<const N: usize> ColorModel for Spectrum<N> {
+    fn black() -> Self {
+        Self::constant(0.0)
+    }
+
+    fn from_rgb(color: &Color) -> Self {
+        Self::primary(0) * color.red
+            + Self::primary(1) * color.green
+            + Self::primary(2) * color.blue
+    }
+
+    fn to_rgb(&self) -> Color {
+        let columns: Vec<Tuple> = (0..3).map(|c| Self::primary(c).observed()).collect();
+        let primaries = Matrix::new(&[
+            columns[0].x,
+            columns[1].x,
+            columns[2].x,
+            columns[0].y,
+            columns[1].y,
+            columns[2].y,
+            columns[0].z,
+            columns[1].z,
+            columns[2].z,
+        ]);
+        let rgb = primaries.inverse().unwrap().to_row_major();
+        let observed = self.observed();
+
+        Color::new(
+            rgb[0] * observed.x + rgb[1] * observed.y + rgb[2] * observed.z,
+            rgb[3] * observed.x + rgb[4] * observed.y + rgb[5] * observed.z,
+            rgb[6] * observed.x + rgb[7] * observed.y + rgb[8] * observed.z,
+        )
+    }
+}
+
+impl<const N: usize> PartialEq for Spectrum<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bins
+            .iter()
+            .zip(other.bins.iter())
+            .all(|(a, b)| equal_f64(*a, *b))
+    }
+}
+
+impl<const N: usize> Add for Spectrum<N> {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self {
+        self.bins
+            .iter_mut()
+            .zip(rhs.bins.iter())
+            .for_each(|(a, b)| *a += b);
+        self
+    }
+}
+
+impl<const N: usize> Sub for Spectrum<N> {
+    type Output = Self;
+
+    fn sub(mut self, rhs: Self) -> Self {
+        self.bins
+            .iter_mut()
+            .zip(rhs.bins.iter())
+            .for_each(|(a, b)| *a -= b);
+        self
+    }
+}
+
+impl<const N: usize> Mul<f64> for Spectrum<N> {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f64) -> Self {
+        self.bins.iter_mut().for_each(|a| *a *= rhs);
+        self
+    }
+}
+
+impl<const N: usize> Mul for Spectrum<N> {
+    type Output = Self;
+
+    fn mul(mut self, rhs: Self) -> Self {
+        self.bins
+            .iter_mut()
+            .zip(rhs.bins.iter())
+            .for_each(|(a, b)| *a *= b);
+        self
+    }
+}
+
+// The CIE 1931 colour matching functions, using the piecewise Gaussian fit from Wyman,
+// Sloan and Shirley (2013), converted from XYZ to linear sRGB.
+fn observer_rgb(wavelength: f64) -> Tuple {
+    let g = |mean: f64, below: f64, above: f64| {
+        let spread = if wavelength < mean { below } else { above };
+        (-0.5 * ((wavelength - mean) / spread).powi(2)).exp()
+    };
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+
+    Tuple::vector(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Spectrum16 = Spectrum<16>;
+
+    #[test]
+    fn test_rgb_colors_round_trip_through_a_spectrum() {
+        let colors = [
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(0.8, 0.2, 0.1),
+            Color::new(0.0, 0.5, 0.25),
+        ];
+
+        for c in colors.iter() {
+            assert_eq!(Spectrum16::from_rgb(c).to_rgb(), *c);
+        }
+    }
+
+    #[test]
+    fn test_a_flat_spectrum_is_white() {
+        assert_eq!(
+            Spectrum16::constant(0.5).to_rgb(),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn test_monochromatic_light_has_the_expected_hue() {
+        let blue = Spectrum16::monochromatic(450.0, 1.0).to_rgb();
+        let red = Spectrum16::monochromatic(650.0, 1.0).to_rgb();
+
+        assert!(blue.blue > blue.red && blue.blue > blue.green);
+        assert!(red.red > red.green && red.red > red.blue);
+    }
+
+    #[test]
+    fn test_different_spectra_can_match_the_same_color() {
+        let lifted = Spectrum16::from_rgb(&Color::new(0.2, 0.6, 0.2));
+        let mut shifted = lifted;
+        shifted.bins[Spectrum16::bin_of(560.0)] += 0.3;
+        let shifted = shifted - Spectrum16::from_rgb(&(shifted.to_rgb() - lifted.to_rgb()));
+
+        assert_ne!(lifted, shifted);
+        assert_eq!(lifted.to_rgb(), shifted.to_rgb());
+    }
+
+    #[test]
+    fn test_filtering_one_spectrum_by_another() {
+        let light = Spectrum16::constant(2.0);
+        let filter = Spectrum16::monochromatic(500.0, 0.5);
+
+        let filtered = light * filter;
+
+        assert_eq!(filtered.bins.iter().sum::<f64>(), 1.0);
+        assert_eq!(Spectrum16::wavelength(0), 380.0 + 350.0 / 32.0);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::light::Material;
+use crate::shapes::{Group, Shape, SmoothTriangle, Triangle};
+use crate::tuple::Tuple;
+use crate::utils::EPSILON;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs;
+
+/// Reads the triangles from an ASCII or binary STL file. Facet normals in the file
+/// are ignored in favour of the winding order, and degenerate facets are dropped.
+pub fn parse(data: &[u8]) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    // Binary files may also start with "solid", so check the size first.
+    if data.len() >= 84 {
+        let count = u32::from_le_bytes(data[80..84].try_into()?) as usize;
+        if data.len() == 84 + count * 50 {
+            return Ok(parse_binary(&data[84..], count));
+        }
+    }
+
+    if data.starts_with(b"solid") {
+        parse_ascii(std::str::from_utf8(data)?)
+    } else {
+        Err("not an STL file".into())
+    }
+}
+
+/// Loads an STL file into a group of triangles with the given material, divided into
+/// a bounding volume hierarchy.
+pub fn load(path: &str, material: &Material) -> Result<Group, Box<dyn Error>> {
+    let mut group = Group::new().with_material(material);
+    for triangle in parse(&fs::read(path)?)? {
+        group.add_child(triangle);
+    }
+    group.divide(8);
+
+    Ok(group)
+}
+
+/// Loads an STL file like `load`, but with normals smoothed across facets that meet at
+/// no more than `crease_angle` radians; sharper edges stay hard.
+pub fn load_smooth(
+    path: &str,
+    material: &Material,
+    crease_angle: f64,
+) -> Result<Group, Box<dyn Error>> {
+    let mut group = Group::new().with_material(material);
+    for triangle in smooth(&parse(&fs::read(path)?)?, crease_angle) {
+        group.add_child(triangle);
+    }
+    group.divide(8);
+
+    Ok(group)
+}
+
+/// Gives each corner the average normal of the facets sharing its vertex, weighted by
+/// their angle at the vertex, leaving out facets turned more than `crease_angle` away.
+pub fn smooth(triangles: &[Triangle], crease_angle: f64) -> Vec<SmoothTriangle> {
+    // Adding zero turns -0.0 into 0.0, so both land on the same vertex.
+    let key = |p: &Tuple| {
+        (
+            (p.x + 0.0).to_bits(),
+            (p.y + 0.0).to_bits(),
+            (p.z + 0.0).to_bits(),
+        )
+    };
+
+    let mut corners: HashMap<_, Vec<(usize, f64)>> = HashMap::new();
+    for (i, t) in triangles.iter().enumerate() {
+        for (point, angle) in [&t.p1, &t.p2, &t.p3].iter().zip(corner_angles(t).iter()) {
+            corners.entry(key(point)).or_default().push((i, *angle));
+        }
+    }
+
+    let min_cos = crease_angle.cos() - EPSILON;
+    triangles
+        .iter()
+        .map(|t| {
+            let normal_at = |point: &Tuple| {
+                corners[&key(point)]
+                    .iter()
+                    .map(|(j, angle)| (&triangles[*j].normal, angle))
+                    .filter(|(normal, _)| normal.dot(&t.normal) >= min_cos)
+                    .fold(Tuple::vector(0.0, 0.0, 0.0), |sum, (normal, angle)| {
+                        sum + normal * *angle
+                    })
+                    .normalize()
+            };
+
+            SmoothTriangle::new(
+                &t.p1,
+                &t.p2,
+                &t.p3,
+                &normal_at(&t.p1),
+                &normal_at(&t.p2),
+                &normal_at(&t.p3),
+            )
+        })
+        .collect()
+}
+
+fn corner_angles(t: &Triangle) -> [f64; 3] {
+    let angle = |a: Tuple, b: Tuple| (a.normalize().dot(&b.normalize())).clamp(-1.0, 1.0).acos();
+    let a1 = angle(t.e1.clone(), t.e2.clone());
+    let a2 = angle(&t.p1 - &t.p2, &t.p3 - &t.p2);
+
+    [a1, a2, PI - a1 - a2]
+}
+
+fn parse_binary(facets: &[u8], count: usize) -> Vec<Triangle> {
+    let float = |bytes: &[u8], i: usize| {
+        f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()) as f64
+    };
+    let point = |bytes: &[u8], i: usize| {
+        Tuple::point(float(bytes, i), float(bytes, i + 1), float(bytes, i + 2))
+    };
+
+    facets
+        .chunks_exact(50)
+        .take(count)
+        .filter_map(|facet| triangle(&[point(facet, 3), point(facet, 6), point(facet, 9)]))
+        .collect()
+}
+
+fn parse_ascii(text: &str) -> Result<Vec<Triangle>, Box<dyn Error>> {
+    let mut triangles = vec![];
+    let mut vertices = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let values: Result<Vec<f64>, _> = words.map(|w| w.parse::<f64>()).collect();
+                match values {
+                    Ok(v) if v.len() == 3 => vertices.push(Tuple::point(v[0], v[1], v[2])),
+                    _ => return Err(format!("line {}: invalid vertex", number + 1).into()),
+                }
+            }
+            Some("endfacet") => {
+                if vertices.len() != 3 {
+                    return Err(format!(
+                        "line {}: expected 3 vertices but found {}",
+                        number + 1,
+                        vertices.len()
+                    )
+                    .into());
+                }
+                triangles.extend(triangle(&vertices));
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+fn triangle(vertices: &[Tuple]) -> Option<Triangle> {
+    let t = Triangle::new(&vertices[0], &vertices[1], &vertices[2]);
+    if t.normal.x.is_nan() {
+        None
+    } else {
+        Some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::BoundingBox;
+
+    const ASCII: &str = "solid test
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 1
+      vertex 2 2 2
+    endloop
+  endfacet
+  facet normal 0 -1 0
+    outer loop
+      vertex 0 0 0
+      vertex 0 0 1
+      vertex 1 0 0
+    endloop
+  endfacet
+endsolid test
+";
+
+    fn binary(triangles: &[[f32; 9]]) -> Vec<u8> {
+        let mut data = b"solid but actually binary".to_vec();
+        data.resize(80, 0);
+        data.extend(&(triangles.len() as u32).to_le_bytes());
+        for t in triangles {
+            data.extend(&[0u8; 12]);
+            for value in t {
+                data.extend(&value.to_le_bytes());
+            }
+            data.extend(&[0u8; 2]);
+        }
+        data
+    }
+
+    fn bounds(triangles: &[Triangle]) -> BoundingBox {
+        let mut b = BoundingBox::empty();
+        for t in triangles {
+            b.merge(&t.bounds_of());
+        }
+        b
+    }
+
+    #[test]
+    fn test_parsing_an_ascii_stl_file() {
+        let triangles = parse(ASCII.as_bytes()).unwrap();
+
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles[0].p2, Tuple::point(1.0, 0.0, 0.0));
+        assert_eq!(triangles[1].p2, Tuple::point(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_parsing_a_binary_stl_file() {
+        let data = binary(&[
+            [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 0.0, -2.5, 1.0, 0.0, 0.0],
+        ]);
+
+        let triangles = parse(&data).unwrap();
+
+        assert_eq!(triangles.len(), 2);
+        let b = bounds(&triangles);
+        assert_eq!(b.min, Tuple::point(0.0, 0.0, -2.5));
+        assert_eq!(b.max, Tuple::point(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn test_rejecting_malformed_stl_files() {
+        assert!(parse(b"not a mesh").is_err());
+        assert!(parse(b"solid x\nfacet\nouter loop\nvertex 0 0\nendloop\nendfacet\n").is_err());
+        assert!(parse(b"solid x\nfacet\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n").is_err());
+    }
+
+    fn fold(angle: f64) -> Vec<Triangle> {
+        let wing = Tuple::point(-angle.cos(), angle.sin(), 0.0);
+        vec![
+            Triangle::new(
+                &Tuple::point(0.0, 0.0, 1.0),
+                &Tuple::point(0.0, 0.0, 0.0),
+                &Tuple::point(1.0, 0.0, 0.0),
+            ),
+            Triangle::new(
+                &Tuple::point(0.0, 0.0, 0.0),
+                &Tuple::point(0.0, 0.0, 1.0),
+                &wing,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_smoothing_a_flat_mesh_keeps_its_normal() {
+        let smoothed = smooth(&fold(0.0), PI / 4.0);
+
+        for t in &smoothed {
+            assert_eq!(t.n1, t.triangle().normal);
+            assert_eq!(t.n2, t.triangle().normal);
+            assert_eq!(t.n3, t.triangle().normal);
+        }
+        assert_eq!(smoothed[0].n1, smoothed[1].n2);
+    }
+
+    #[test]
+    fn test_smoothing_respects_the_crease_angle() {
+        let triangles = fold(PI / 2.0);
+
+        let hard = smooth(&triangles, PI / 3.0);
+        assert_eq!(hard[0].n1, triangles[0].normal);
+        assert_eq!(hard[1].n1, triangles[1].normal);
+
+        let soft = smooth(&triangles, 2.0 * PI / 3.0);
+        let expected = (&triangles[0].normal + &triangles[1].normal).normalize();
+        assert_eq!(soft[0].n1, expected);
+        assert_eq!(soft[0].n2, expected);
+        assert_eq!(soft[1].n1, expected);
+        assert_eq!(soft[0].n3, triangles[0].normal);
+        assert_eq!(soft[1].n3, triangles[1].normal);
+    }
+}
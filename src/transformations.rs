@@ -15,9 +15,18 @@ pub fn view_transform(from: &Tuple, to: &Tuple, up: &Tuple) -> Matrix {
     orientation * Matrix::translation(-from.x, -from.y, -from.z)
 }
 
+/// A view transform with the camera rolled by `roll` radians about its line of sight.
+/// Positive angles turn the camera counter-clockwise, so the scene appears to turn
+/// clockwise in the image.
+pub fn view_transform_with_roll(from: &Tuple, to: &Tuple, up: &Tuple, roll: f64) -> Matrix {
+    Matrix::rotation_z(roll) * view_transform(from, to, up)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::camera::Camera;
+    use std::f64::consts::PI;
 
     #[test]
     fn test_the_transformation_matrix_for_the_default_orientation() {
@@ -68,4 +77,44 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_a_view_transform_without_roll_is_unchanged() {
+        let from = Tuple::point(1.0, 3.0, 2.0);
+        let to = Tuple::point(4.0, -2.0, 8.0);
+        let up = Tuple::vector(1.0, 1.0, 0.0);
+
+        let t = view_transform_with_roll(&from, &to, &up, 0.0);
+
+        assert_eq!(t, view_transform(&from, &to, &up));
+    }
+
+    #[test]
+    fn test_rolling_the_camera_turns_the_scene_clockwise() {
+        let from = Tuple::point(0.0, 0.0, 5.0);
+        let to = Tuple::point(0.0, 0.0, 0.0);
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+        let mut c = Camera::new(101, 101, PI / 2.0);
+        c.transform = view_transform_with_roll(&from, &to, &up, PI / 2.0);
+
+        let right_of_image = c.ray_for_pixel(100, 50);
+        let top_of_image = c.ray_for_pixel(50, 0);
+
+        assert!(right_of_image.direction.y > 0.5);
+        assert!(top_of_image.direction.y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rolling_keeps_the_line_of_sight() {
+        let from = Tuple::point(1.0, 2.0, 3.0);
+        let to = Tuple::point(-2.0, 0.0, 1.0);
+        let up = Tuple::vector(0.0, 1.0, 0.0);
+        let mut c = Camera::new(101, 101, PI / 2.0);
+        c.transform = view_transform_with_roll(&from, &to, &up, 0.7);
+
+        let r = c.ray_for_pixel(50, 50);
+
+        assert_eq!(r.origin, from);
+        assert_eq!(r.direction, (&to - &from).normalize());
+    }
 }
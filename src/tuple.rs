@@ -1,7 +1,12 @@
+use rand::Rng;
 use std::cmp::{Eq, PartialEq};
+use std::convert::TryFrom;
+use std::error::Error;
 
-use crate::utils::equal_f64;
+use crate::utils::{equal_f64, quantize, stable_hash};
 
+// Only the tests ask a tuple for its kind.
+#[allow(dead_code)]
 #[derive(Debug, PartialEq)]
 enum TupleKind {
     Vector,
@@ -29,6 +34,36 @@ impl Tuple {
         Self::new(x, y, z, 0.0)
     }
 
+    /// A point from three values or any tuple from four, where w must be 0 or 1. Values
+    /// must be finite.
+    pub fn from_slice(values: &[f64]) -> Result<Self, Box<dyn Error>> {
+        if let Some(value) = values.iter().find(|v| !v.is_finite()) {
+            return Err(format!("tuple values must be finite, not {}", value).into());
+        }
+        match *values {
+            [x, y, z] => Ok(Tuple::point(x, y, z)),
+            [x, y, z, w] if w == 0.0 || w == 1.0 => Ok(Tuple::new(x, y, z, w)),
+            [_, _, _, w] => Err(format!("w must be 0 or 1, not {}", w).into()),
+            _ => Err(format!("a tuple needs 3 or 4 values, not {}", values.len()).into()),
+        }
+    }
+
+    /// Quantized x, y, z and w, for using tuples as keys in hash maps and sorted
+    /// collections; see `quantize`.
+    pub fn quantized(&self) -> [i64; 4] {
+        [
+            quantize(self.x),
+            quantize(self.y),
+            quantize(self.z),
+            quantize(self.w),
+        ]
+    }
+
+    pub fn canonical_hash(&self) -> u64 {
+        stable_hash(&self.quantized())
+    }
+
+    #[allow(dead_code)]
     fn kind(&self) -> TupleKind {
         if self.w == 0.0 {
             TupleKind::Vector
@@ -66,6 +101,60 @@ impl Tuple {
     pub fn reflect(&self, normal: &Tuple) -> Tuple {
         self - &(normal * 2.0 * self.dot(normal))
     }
+
+    // Rejection-samples a vector strictly inside the unit sphere.
+    pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Tuple {
+        loop {
+            let v = Tuple::vector(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            if v.magnitude() < 1.0 {
+                return v;
+            }
+        }
+    }
+
+    // Uniformly distributed over the surface of the unit sphere.
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Tuple {
+        let z: f64 = rng.gen_range(-1.0..1.0);
+        let theta: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
+        let r = (1.0 - z * z).sqrt();
+
+        Tuple::vector(r * theta.cos(), r * theta.sin(), z)
+    }
+
+    // Rejection-samples a vector inside the unit disc in the xy plane.
+    pub fn random_in_unit_disc<R: Rng + ?Sized>(rng: &mut R) -> Tuple {
+        loop {
+            let v = Tuple::vector(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+            if v.magnitude() < 1.0 {
+                return v;
+            }
+        }
+    }
+}
+
+/// Three values make a point, four give w as well.
+impl From<[f64; 3]> for Tuple {
+    fn from([x, y, z]: [f64; 3]) -> Self {
+        Tuple::point(x, y, z)
+    }
+}
+
+impl From<[f64; 4]> for Tuple {
+    fn from([x, y, z, w]: [f64; 4]) -> Self {
+        Tuple::new(x, y, z, w)
+    }
+}
+
+impl TryFrom<&[f64]> for Tuple {
+    type Error = Box<dyn Error>;
+
+    fn try_from(values: &[f64]) -> Result<Self, Self::Error> {
+        Tuple::from_slice(values)
+    }
 }
 
 impl PartialEq for Tuple {
@@ -165,6 +254,8 @@ impl std::ops::Div<f64> for Tuple {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_a_tuple_with_w_1_is_a_point() {
@@ -343,4 +434,90 @@ mod tests {
 
         assert_eq!(r, Tuple::vector(1.0, 0.0, 0.0));
     }
+
+    #[test]
+    fn test_random_vectors_in_the_unit_sphere() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..1000 {
+            let v = Tuple::random_in_unit_sphere(&mut rng);
+            assert_eq!(v.w, 0.0);
+            assert!(v.magnitude() < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_random_unit_vectors_are_normalized_and_unbiased() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut sum = Tuple::vector(0.0, 0.0, 0.0);
+
+        for _ in 0..10000 {
+            let v = Tuple::random_unit_vector(&mut rng);
+            assert_eq!(v.w, 0.0);
+            assert!(equal_f64(v.magnitude(), 1.0));
+            sum = sum + v;
+        }
+
+        assert!((sum / 10000.0).magnitude() < 0.05);
+    }
+
+    #[test]
+    fn test_random_vectors_in_the_unit_disc() {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        for _ in 0..1000 {
+            let v = Tuple::random_in_unit_disc(&mut rng);
+            assert_eq!(v.z, 0.0);
+            assert_eq!(v.w, 0.0);
+            assert!(v.magnitude() < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_random_sampling_is_reproducible_from_a_seed() {
+        let a = Tuple::random_unit_vector(&mut StdRng::seed_from_u64(42));
+        let b = Tuple::random_unit_vector(&mut StdRng::seed_from_u64(42));
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_tuples_from_arrays() {
+        assert_eq!(Tuple::from([1.0, 2.0, 3.0]), Tuple::point(1.0, 2.0, 3.0));
+        assert_eq!(
+            Tuple::from([1.0, 2.0, 3.0, 0.0]),
+            Tuple::vector(1.0, 2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn test_tuples_from_slices_are_validated() {
+        let values = [4.0, -4.0, 3.0, 0.0];
+
+        assert_eq!(
+            Tuple::from_slice(&values[..3]).unwrap(),
+            Tuple::point(4.0, -4.0, 3.0)
+        );
+        assert_eq!(
+            Tuple::try_from(&values[..]).unwrap(),
+            Tuple::vector(4.0, -4.0, 3.0)
+        );
+        assert!(Tuple::from_slice(&[1.0, 2.0]).is_err());
+        assert!(Tuple::from_slice(&[1.0, 2.0, 3.0, 0.5]).is_err());
+        assert!(Tuple::from_slice(&[1.0, f64::NAN, 3.0]).is_err());
+    }
+
+    #[test]
+    fn test_nearly_equal_tuples_hash_the_same() {
+        let a = Tuple::point(1.0, 2.0, 3.0);
+        let b = Tuple::point(1.000_000_1, 2.0, 3.0);
+
+        assert_eq!(a.quantized(), b.quantized());
+        assert_eq!(a.canonical_hash(), b.canonical_hash());
+        assert_ne!(
+            a.canonical_hash(),
+            Tuple::vector(1.0, 2.0, 3.0).canonical_hash()
+        );
+        assert!(Tuple::point(0.0, 5.0, 0.0).quantized() < Tuple::point(1.0, 0.0, 0.0).quantized());
+    }
 }
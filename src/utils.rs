@@ -1,24 +1,75 @@
+use crate::tuple::Tuple;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
 pub const EPSILON: f64 = 0.00001;
 
 pub fn equal_f64(x: f64, y: f64) -> bool {
-    if (x - y).abs() < EPSILON {
-        true
+    (x - y).abs() < EPSILON
+}
+
+/// `value` rounded to a whole number of `EPSILON`s, for hashing and ordering floats
+/// that are compared with `equal_f64`. Values that compare equal usually quantize the
+/// same, but two within `EPSILON` of each other can fall either side of a boundary.
+/// NaN quantizes as negative infinity does, to `i64::MIN`.
+pub fn quantize(value: f64) -> i64 {
+    if value.is_nan() {
+        i64::MIN
     } else {
-        false
+        (value / EPSILON).round() as i64
     }
 }
 
-#[cfg(tests)]
+/// A 64-bit FNV-1a hash of quantized values that, unlike the standard library's hasher,
+/// is the same on every platform and Rust version, e.g. for naming cached files.
+pub fn stable_hash(values: &[i64]) -> u64 {
+    values
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+// A random number generator seeded from a point, for sampling at a hit in a way that
+// repeats from render to render whatever order the threads run in.
+pub(crate) fn rng_at(point: &Tuple) -> ChaCha8Rng {
+    let seed =
+        point.x.to_bits() ^ point.y.to_bits().rotate_left(21) ^ point.z.to_bits().rotate_left(42);
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_equal_f64_should_return_true_for_eq() {
         assert!(equal_f64(3.0, 3.0));
         assert!(equal_f64(3.14, 3.14));
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_equal_f64_should_return_false_for_non_eq() {
         assert!(!equal_f64(3.0, 2.9));
         assert!(!equal_f64(3.14, 3.13));
     }
+
+    #[test]
+    fn test_quantizing_values() {
+        assert_eq!(quantize(1.0), 100000);
+        assert_eq!(quantize(1.0 + EPSILON / 10.0), quantize(1.0));
+        assert_eq!(quantize(-0.0), quantize(0.0));
+        assert_eq!(quantize(f64::NAN), quantize(f64::NEG_INFINITY));
+        assert!(quantize(f64::NAN) < quantize(-1e6));
+    }
+
+    #[test]
+    fn test_stable_hashes_are_fixed() {
+        assert_eq!(stable_hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(&[1, 2]), stable_hash(&[1, 2]));
+        assert_ne!(stable_hash(&[1, 2]), stable_hash(&[2, 1]));
+    }
 }
@@ -1,21 +1,138 @@
+use crate::bounds::BoundingBox;
 use crate::canvas::Color;
-use crate::light::{lighting, Material, PointLight};
+use crate::cleanup::{self, SceneIssue};
+use crate::environment::Skybox;
+use crate::irradiance::IrradianceCache;
+use crate::light::{
+    ambient_lighting, lighting, soft_lighting, surface_color, Light, Material, MaterialTable,
+    PointLight,
+};
 use crate::matrix::Matrix;
-use crate::ray::{Computation, Intersections, Ray};
-use crate::shapes::{Shape, Sphere};
+use crate::profile::{self, Section};
+use crate::ray::{Computation, Intersections, Ray, RayKind};
+use crate::shapes::{find_in, Group, MaterialSource, Shape, ShapeId, Sphere};
+use crate::snapshot::WorldSnapshot;
 use crate::tuple::Tuple;
+use crate::utils::rng_at;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct World {
-    pub light: Option<PointLight>,
+    pub lights: Vec<Light>,
     pub objects: Vec<Arc<dyn Shape + Send + Sync>>,
+    pub shadows: bool,
+    // Shading material for shapes that have none of their own, nor from a group.
+    pub default_material: Option<Material>,
+    // How many times a ray may bounce or bend before it is given up on as black.
+    pub max_depth: usize,
+    pub options: RenderOptions,
+    // Scales every material's ambient term: black turns ambient light off everywhere,
+    // e.g. when indirect light is computed instead.
+    pub ambient: Color,
+    // Seen by rays, camera or bounced, that miss every object; black when there is none.
+    pub skybox: Option<Skybox>,
+    // Diffuse light bounced off other surfaces, added to what the camera sees directly.
+    pub indirect: Option<Arc<IrradianceCache>>,
+    // Named materials for shapes to share; `cleanup::lint` reports any no shape holds.
+    pub materials: MaterialTable,
+}
+
+/// Saves shadow rays towards lights with many sample points. A few probes spread over
+/// the light are cast first, and the rest only when the probes disagree, i.e. when
+/// the point is likely in a penumbra.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveShadows {
+    pub probes: usize,
+    // How far from fully lit or fully shadowed the probes may be and still be trusted.
+    pub tolerance: f64,
+}
+
+impl AdaptiveShadows {
+    pub fn new() -> Self {
+        Self {
+            probes: 4,
+            tolerance: 0.0,
+        }
+    }
+}
+
+impl Default for AdaptiveShadows {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a world is rendered, trading quality for time, as opposed to what is in it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderOptions {
+    // Reflection rays averaged for rough surfaces seen directly; deeper bounces take one.
+    pub glossy_samples: usize,
+    pub adaptive_shadows: Option<AdaptiveShadows>,
+    // `Camera::render_refining` stops once this many seconds have passed, keeping the
+    // samples so far. The first pass always completes, so there is a whole image.
+    pub max_seconds: Option<f64>,
+    // Times intersection, shading and shadow rays for `Camera::render_profiled`. Off,
+    // rendering doesn't touch the profiler at all.
+    pub profile: bool,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self {
+            glossy_samples: 8,
+            adaptive_shadows: None,
+            max_seconds: None,
+            profile: false,
+        }
+    }
+
+    pub fn with_glossy_samples(mut self, glossy_samples: usize) -> Self {
+        self.glossy_samples = glossy_samples;
+        self
+    }
+
+    pub fn with_adaptive_shadows(mut self, adaptive_shadows: AdaptiveShadows) -> Self {
+        self.adaptive_shadows = Some(adaptive_shadows);
+        self
+    }
+
+    pub fn with_max_seconds(mut self, max_seconds: f64) -> Self {
+        self.max_seconds = Some(max_seconds);
+        self
+    }
+
+    pub fn with_profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
         Self {
-            light: None,
+            lights: vec![],
             objects: vec![],
+            shadows: true,
+            default_material: None,
+            max_depth: 5,
+            options: RenderOptions::new(),
+            ambient: Color::white(),
+            skybox: None,
+            indirect: None,
+            materials: MaterialTable::new(),
         }
     }
 
@@ -33,8 +150,9 @@ impl World {
         let s2 = Arc::new(Sphere::new().with_transform(&Matrix::scaling(0.5, 0.5, 0.5)));
 
         Self {
-            light: Some(light),
+            lights: vec![light.into()],
             objects: vec![s1, s2],
+            ..Self::new()
         }
     }
 
@@ -47,77 +165,446 @@ impl World {
         false
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Intersections {
-        if self.objects.len() <= 0 {
-            return Intersections::new(vec![]);
+    /// The first shape with the given name, searching inside groups and CSG shapes too.
+    pub fn find_by_name(&self, name: &str) -> Option<Arc<dyn Shape + Send + Sync>> {
+        find_in(&self.objects, &|shape| shape.get_name() == Some(name))
+    }
+
+    pub fn find_by_id(&self, id: ShapeId) -> Option<Arc<dyn Shape + Send + Sync>> {
+        find_in(&self.objects, &|shape| shape.id() == id)
+    }
+
+    /// The first shape cloned from the shape with the given id, or that shape itself,
+    /// e.g. to find where a shape was in the previous frame of an animation.
+    pub fn find_by_lineage(&self, lineage: ShapeId) -> Option<Arc<dyn Shape + Send + Sync>> {
+        find_in(&self.objects, &|shape| shape.lineage() == lineage)
+    }
+
+    /// Updates the object at `index` in place, e.g. to move it or swap its material
+    /// between frames. Only that object's cached state is refreshed. Returns `false`
+    /// if the index is out of range or the object is shared elsewhere.
+    pub fn update_object<F>(&mut self, index: usize, update: F) -> bool
+    where
+        F: FnOnce(&mut (dyn Shape + Send + Sync)),
+    {
+        match self.objects.get_mut(index).and_then(Arc::get_mut) {
+            Some(object) => {
+                update(object);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The bounds of every object that has finite bounds; infinite shapes such as
+    /// planes are left out.
+    pub fn bounds(&self) -> BoundingBox {
+        let mut bounds = BoundingBox::empty();
+        for object in self.objects.iter().filter(|o| is_bounded(o.as_ref())) {
+            bounds.merge(&object.parent_space_bounds_of());
+        }
+
+        bounds
+    }
+
+    /// Opts the world into a bounding volume hierarchy: when there are at least
+    /// `threshold` objects with finite bounds they are gathered into one group, and every
+    /// group is then divided recursively. Unbounded objects such as planes stay as they
+    /// are, as do objects shared outside the world.
+    pub fn divide(&mut self, threshold: usize) {
+        let (bounded, mut unbounded): (Vec<_>, Vec<_>) =
+            self.objects.drain(..).partition(|o| is_bounded(o.as_ref()));
+
+        if bounded.len() >= threshold.max(2) {
+            unbounded.push(Arc::new(Group::from_shared(bounded)));
+            self.objects = unbounded;
+        } else {
+            self.objects = bounded;
+            self.objects.extend(unbounded);
         }
 
-        let mut intersections = ray.intersect(self.objects[0].clone());
-        for o in &self.objects[1..] {
-            intersections.extend(&ray.intersect(o.clone()));
+        for object in self.objects.iter_mut().filter_map(Arc::get_mut) {
+            object.divide(threshold);
         }
+    }
 
-        intersections.sort();
+    pub fn intersect(&self, ray: &Ray) -> Intersections {
+        intersect_objects(&self.objects, ray, false)
+    }
+
+    /// The intersections of shapes visible to rays of the given kind. Camera rays also
+    /// skip the back faces of one-sided materials, which still cast shadows and show
+    /// in reflections and refractions.
+    pub fn intersect_visible(&self, ray: &Ray, kind: RayKind) -> Intersections {
+        let mut intersections = intersect_objects(&self.objects, ray, kind == RayKind::Camera);
+        intersections.retain(|i| i.object.material_handle().visibility.allows(kind));
         intersections
     }
 
-    pub fn shade_hit(&self, comps: &Computation) -> Color {
-        if let Some(light) = &self.light {
-            let is_shadowed = self.is_shadowed(&comps.over_point);
-            lighting(
-                &comps.object.get_material(),
-                comps.object.clone(),
-                light,
-                &comps.point,
-                &comps.eyev,
-                &comps.normalv,
-                is_shadowed,
-            )
+    /// A hash of the objects, materials, lights and settings of the world, which
+    /// changes whenever any of them do.
+    pub fn fingerprint(&self) -> u64 {
+        self.snapshot().fingerprint()
+    }
+
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot::new(self)
+    }
+
+    /// Problems likely to cause acne or numeric trouble, such as coincident planes; see
+    /// the `cleanup` module.
+    pub fn check_scene(&self) -> Vec<SceneIssue> {
+        cleanup::check(self)
+    }
+
+    /// The object a camera ray sees first and its normal there, without shading it, or
+    /// `None` when the ray escapes.
+    pub fn surface_at(&self, ray: &Ray) -> Option<(ShapeId, Tuple)> {
+        self.intersect_visible(ray, RayKind::Camera)
+            .media_hit()
+            .map(|hit| (hit.object.id(), hit.object.normal_at(&ray.position(hit.t))))
+    }
+
+    // The material `object` is shaded with: the world's default for objects that were
+    // never given one of their own.
+    fn material_of(&self, object: &dyn Shape) -> Material {
+        match (&self.default_material, object.material_source()) {
+            (Some(default), MaterialSource::Default) => default.clone(),
+            _ => object.get_material(),
+        }
+    }
+
+    pub fn shade_hit(&self, comps: &Computation, remaining: usize) -> Color {
+        let material = self.material_of(comps.object.as_ref());
+        if let Some(color) = material
+            .shader
+            .as_ref()
+            .and_then(|shader| shader(comps, self))
+        {
+            return color;
+        }
+
+        // The ambient term is added separately so that the world can scale it.
+        let lit = Material {
+            ambient: 0.0,
+            ..material.clone()
+        };
+        let surface = self.lights.iter().fold(Color::black(), |sum, light| {
+            let ambient = ambient_lighting(&material, comps.object.clone(), light, &comps.point);
+            sum + ambient * self.ambient
+                + match light {
+                    Light::Point(point_light) => {
+                        let attenuation = if self.shadows {
+                            self.shadow_attenuation(&comps.over_point, light)
+                        } else {
+                            Color::white()
+                        };
+                        lighting(
+                            &lit,
+                            comps.object.clone(),
+                            point_light,
+                            &comps.point,
+                            &comps.eyev,
+                            &comps.normalv,
+                            attenuation == Color::black(),
+                        ) * attenuation
+                    }
+                    Light::Area(_) => {
+                        let visibility = if self.shadows {
+                            self.light_visibility(&comps.over_point, &light.samples())
+                        } else {
+                            Color::white()
+                        };
+                        soft_lighting(
+                            &lit,
+                            comps.object.clone(),
+                            light,
+                            &comps.point,
+                            &comps.eyev,
+                            &comps.normalv,
+                            1.0,
+                        ) * visibility
+                    }
+                }
+        });
+
+        let surface = match &self.indirect {
+            // Only at the surfaces seen directly, as the cache shades the surfaces its
+            // rays hit one level deeper, and these need not be cached themselves.
+            Some(cache) if remaining == self.max_depth => {
+                let irradiance = cache.irradiance(self, &comps.over_point, &comps.normalv);
+                surface
+                    + surface_color(&material, comps.object.clone(), &comps.point)
+                        * irradiance
+                        * (material.diffuse / PI)
+            }
+            _ => surface,
+        };
+
+        let reflected = self.reflected_with(&material, comps, remaining);
+        let refracted = self.refracted_with(&material, comps, remaining);
+        if material.reflective > 0.0 && material.transparency > 0.0 {
+            let reflectance = comps.schlick();
+            surface + reflected * reflectance + refracted * (1.0 - reflectance)
         } else {
-            Color::black()
+            surface + reflected + refracted
+        }
+    }
+
+    /// The light bouncing off a reflective surface, as seen in a mirror. Rough surfaces
+    /// average rays jittered within a cone around the mirror direction.
+    pub fn reflected_color(&self, comps: &Computation, remaining: usize) -> Color {
+        self.reflected_with(&self.material_of(comps.object.as_ref()), comps, remaining)
+    }
+
+    fn reflected_with(&self, material: &Material, comps: &Computation, remaining: usize) -> Color {
+        if material.reflective == 0.0 || remaining == 0 {
+            return Color::black();
+        }
+        if material.roughness == 0.0 {
+            let reflected = Ray::new(&comps.over_point, &comps.reflectv);
+            return self.color_for(&reflected, RayKind::Reflection, remaining - 1)
+                * material.reflective;
+        }
+
+        let mut rng = rng_at(&comps.over_point);
+        let samples = if remaining == self.max_depth {
+            self.options.glossy_samples.max(1)
+        } else {
+            1
+        };
+
+        let total = (0..samples).fold(Color::black(), |sum, _| {
+            let jitter = Tuple::random_in_unit_sphere(&mut rng) * material.roughness;
+            let mut direction = (comps.reflectv.clone() + jitter).normalize();
+            if direction.dot(&comps.normalv) <= 0.0 {
+                direction = comps.reflectv.clone();
+            }
+            let reflected = Ray::new(&comps.over_point, &direction);
+            sum + self.color_for(&reflected, RayKind::Reflection, remaining - 1)
+        });
+
+        total * (material.reflective / samples as f64)
+    }
+
+    /// The light arriving through a transparent surface, bent by Snell's law.
+    pub fn refracted_color(&self, comps: &Computation, remaining: usize) -> Color {
+        self.refracted_with(&self.material_of(comps.object.as_ref()), comps, remaining)
+    }
+
+    fn refracted_with(&self, material: &Material, comps: &Computation, remaining: usize) -> Color {
+        let transparency = material.transparency;
+        if transparency == 0.0 || remaining == 0 {
+            return Color::black();
         }
+
+        let n_ratio = comps.n1 / comps.n2;
+        let cos_i = comps.eyev.dot(&comps.normalv);
+        let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            // Total internal reflection.
+            return Color::black();
+        }
+
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let direction =
+            comps.normalv.clone() * (n_ratio * cos_i - cos_t) - comps.eyev.clone() * n_ratio;
+        let refracted = Ray::new(&comps.under_point, &direction);
+
+        self.color_for(&refracted, RayKind::Refraction, remaining - 1) * transparency
     }
 
     pub fn color_at(&self, ray: &Ray) -> Color {
-        let intersections = self.intersect(ray);
-        if let Some(hit) = intersections.hit() {
-            let comps = hit.prepare_computation(ray);
-            self.shade_hit(&comps)
+        self.color_for(ray, RayKind::Camera, self.max_depth)
+    }
+
+    /// The color seen along a ray of the given kind, which may spawn up to `remaining`
+    /// further rays in turn.
+    pub fn color_for(&self, ray: &Ray, kind: RayKind, remaining: usize) -> Color {
+        let intersections =
+            self.profiled(Section::Intersection, || self.intersect_visible(ray, kind));
+        if let Some(hit) = intersections.media_hit() {
+            if let Some(next) = hit.object.teleport(ray, &ray.position(hit.t)) {
+                return if remaining == 0 {
+                    Color::black()
+                } else {
+                    self.color_for(&next, kind, remaining - 1)
+                };
+            }
+            self.profiled(Section::Shading, || {
+                let comps = hit.prepare_computation(ray, &intersections);
+                let distance = comps.t * ray.direction.magnitude();
+                self.shade_hit(&comps, remaining) * transmittance(&comps.absorption, distance)
+            })
+        } else if let Some(skybox) = &self.skybox {
+            skybox.radiance(&ray.direction)
         } else {
             Color::black()
         }
     }
 
-    pub fn is_shadowed(&self, point: &Tuple) -> bool {
-        if let Some(light) = &self.light {
-            let v = &light.position - point;
-            let distance = v.magnitude();
-            let direction = v.normalize();
+    /// Whether any of the light from `light` is held back before it reaches `point`,
+    /// e.g. in the penumbra of an area light.
+    pub fn is_shadowed(&self, point: &Tuple, light: &Light) -> bool {
+        self.shadow_attenuation(point, light) != Color::white()
+    }
 
-            let r = Ray::new(&point, &direction);
-            let intersections = self.intersect(&r);
+    /// The filter the light from `light` passes through to reach `point`, averaged over
+    /// the light's samples: white when nothing is in the way, black behind an opaque
+    /// object, and tinted by the colour of any transparent objects on the way.
+    pub fn shadow_attenuation(&self, point: &Tuple, light: &Light) -> Color {
+        self.light_visibility(point, &light.samples())
+    }
 
-            if let Some(h) = intersections.hit() {
-                h.t < distance
-            } else {
-                false
+    /// The light from `light_samples`, points spread over a light, that reaches `point`
+    /// as a filter averaged over the samples, as `shadow_attenuation` gives for one.
+    pub fn light_visibility(&self, point: &Tuple, light_samples: &[Tuple]) -> Color {
+        if light_samples.is_empty() {
+            return Color::black();
+        }
+        let visible = |samples: &mut dyn Iterator<Item = &Tuple>| {
+            let (lit, total) = samples.fold((Color::black(), 0), |(lit, total), sample| {
+                let through = self.profiled(Section::Shadows, || {
+                    transmitted(&self.objects, point, sample)
+                });
+                (lit + through, total + 1)
+            });
+            lit * (1.0 / total as f64)
+        };
+
+        if let Some(adaptive) = self.options.adaptive_shadows {
+            let probes = adaptive.probes.max(1);
+            if probes < light_samples.len() {
+                let mut spread =
+                    (0..probes).map(|i| &light_samples[i * light_samples.len() / probes]);
+                let probed = visible(&mut spread);
+                let channels = [probed.red, probed.green, probed.blue];
+                if channels.iter().all(|c| *c <= adaptive.tolerance)
+                    || channels.iter().all(|c| *c >= 1.0 - adaptive.tolerance)
+                {
+                    return probed;
+                }
             }
+        }
+
+        visible(&mut light_samples.iter())
+    }
+
+    /// How much of `light` each point is cut off from: 1.0 in full shadow, 0.0 lit, and
+    /// in between in an area light's penumbra, averaged over the light's samples. Only
+    /// objects overlapping the box around the points and the light can block any of the
+    /// rays, so the rest are culled once for the whole batch rather than for every ray.
+    pub fn shadow_fractions(&self, points: &[Tuple], light: &Light) -> Vec<f64> {
+        let samples = light.samples();
+        let mut region = BoundingBox::empty();
+        samples.iter().for_each(|s| region.add_point(s));
+        points.iter().for_each(|p| region.add_point(p));
+
+        let blockers: Vec<_> = self
+            .objects
+            .iter()
+            .filter(|o| o.parent_space_bounds_of().overlaps(&region))
+            .cloned()
+            .collect();
+
+        points
+            .iter()
+            .map(|point| {
+                let through = samples.iter().fold(Color::black(), |sum, sample| {
+                    sum + self.profiled(Section::Shadows, || transmitted(&blockers, point, sample))
+                }) * (1.0 / samples.len() as f64);
+                1.0 - (through.red + through.green + through.blue) / 3.0
+            })
+            .collect()
+    }
+
+    // Times `f` as `section` when profiling, and otherwise just runs it.
+    fn profiled<T, F: FnOnce() -> T>(&self, section: Section, f: F) -> T {
+        if self.options.profile {
+            profile::section(section, f)
         } else {
-            false
+            f()
+        }
+    }
+}
+
+fn intersect_objects(
+    objects: &[Arc<dyn Shape + Send + Sync>],
+    ray: &Ray,
+    cull: bool,
+) -> Intersections {
+    let mut intersections = Intersections::new(vec![]);
+    for o in objects {
+        intersections.extend(&ray.intersect_culling(o.clone(), cull));
+    }
+
+    intersections.sort();
+    intersections
+}
+
+// The filter that the light at `light_position` passes through to reach `point`. Each
+// surface of a transparent object on the way lets through its `transparency` of the
+// light, tinted by its colour; opaque ones stop it.
+fn transmitted(
+    objects: &[Arc<dyn Shape + Send + Sync>],
+    point: &Tuple,
+    light_position: &Tuple,
+) -> Color {
+    let v = light_position - point;
+    let distance = v.magnitude();
+    let r = Ray::new(point, &v.normalize());
+
+    let intersections = intersect_objects(objects, &r, false);
+
+    let mut filter = Color::white();
+    for hit in intersections.in_range(0.0, distance) {
+        let material = hit.object.get_material();
+        if !material.visibility.shadow {
+            continue;
+        }
+        if material.transparency == 0.0 {
+            return Color::black();
         }
+        let color = surface_color(&material, hit.object.clone(), &r.position(hit.t));
+        filter = filter * color * material.transparency;
     }
+
+    filter
+}
+
+// The fraction of light left after crossing `distance` of an absorbing medium, by the
+// Beer-Lambert law.
+fn transmittance(absorption: &Color, distance: f64) -> Color {
+    Color::new(
+        (-absorption.red * distance).exp(),
+        (-absorption.green * distance).exp(),
+        (-absorption.blue * distance).exp(),
+    )
+}
+
+fn is_bounded(object: &(dyn Shape + Send + Sync)) -> bool {
+    let b = object.parent_space_bounds_of();
+    [b.min.x, b.min.y, b.min.z, b.max.x, b.max.y, b.max.z]
+        .iter()
+        .all(|c| c.is_finite())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::canvas::Canvas;
+    use crate::environment::{CubeMap, Environment};
+    use crate::light::AreaLight;
     use crate::ray::Intersection;
+    use crate::shapes::{Cube, Plane, Portal};
+    use crate::utils::equal_f64;
+    use std::f64::consts::PI;
 
     #[test]
     fn test_creating_a_world() {
         let w = World::new();
 
-        assert!(w.light.is_none());
+        assert!(w.lights.is_empty());
         assert_eq!(w.objects.len(), 0);
     }
 
@@ -141,8 +628,8 @@ mod tests {
         let shape = w.objects[0].clone();
         let i = Intersection::new(4.0, shape);
 
-        let comps = i.prepare_computation(&r);
-        let c = w.shade_hit(&comps);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+        let c = w.shade_hit(&comps, 5);
 
         assert_eq!(c, Color::new(0.38066, 0.47583, 0.2855));
     }
@@ -150,16 +637,13 @@ mod tests {
     #[test]
     fn test_shading_an_intersection_from_the_inside() {
         let mut w = World::default_world();
-        w.light = Some(PointLight::new(
-            &Tuple::point(0.0, 0.25, 0.0),
-            &Color::white(),
-        ));
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.25, 0.0), &Color::white()).into()];
         let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
         let shape = w.objects[1].clone();
         let i = Intersection::new(0.5, shape);
 
-        let comps = i.prepare_computation(&r);
-        let c = w.shade_hit(&comps);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+        let c = w.shade_hit(&comps, 5);
 
         assert_eq!(c, Color::new(0.90498, 0.90498, 0.90498));
     }
@@ -174,6 +658,41 @@ mod tests {
         assert_eq!(c, Color::black());
     }
 
+    #[test]
+    fn test_a_ray_that_misses_sees_the_skybox() {
+        let mut w = World::default_world();
+        let mut image = Canvas::new(4, 2);
+        image.write_pixel(1, 0, &Color::new(0.2, 0.4, 0.9));
+        w.skybox = Some(Skybox::Equirectangular(Arc::new(Environment::new(image))));
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 1.0, 0.1));
+
+        let c = w.color_at(&r);
+
+        assert_eq!(c, Color::new(0.2, 0.4, 0.9));
+    }
+
+    #[test]
+    fn test_a_mirror_reflects_the_skybox() {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 10.0, 0.0), &Color::black()).into()];
+        w.objects.push(reflective_floor(1.0));
+        let sky = Arc::new(Canvas::new(1, 1));
+        let mut up = Canvas::new(1, 1);
+        up.write_pixel(0, 0, &Color::new(0.5, 0.5, 1.0));
+        w.skybox = Some(Skybox::Cube(Arc::new(CubeMap::new([
+            sky.clone(),
+            sky.clone(),
+            Arc::new(up),
+            sky.clone(),
+            sky.clone(),
+            sky,
+        ]))));
+
+        let c = w.color_at(&ray_at_floor());
+
+        assert_eq!(c, Color::new(0.5, 0.5, 1.0));
+    }
+
     #[test]
     fn test_the_color_when_a_ray_hits() {
         let w = World::default_world();
@@ -185,53 +704,924 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_there_is_no_shadown_when_nothing_is_collinear_with_point_and_light() {
         let w = World::default_world();
         let p = Tuple::point(0.0, 10.0, 0.0);
 
-        assert_eq!(w.is_shadowed(&p), false);
+        assert_eq!(w.is_shadowed(&p, &w.lights[0]), false);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_the_shadow_when_an_object_is_between_the_point_and_the_light() {
         let w = World::default_world();
         let p = Tuple::point(10.0, -10.0, 10.0);
 
-        assert_eq!(w.is_shadowed(&p), true);
+        assert_eq!(w.is_shadowed(&p, &w.lights[0]), true);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_there_is_no_shadow_when_an_object_is_behind_the_light() {
         let w = World::default_world();
         let p = Tuple::point(-20.0, 20.0, -20.0);
 
-        assert_eq!(w.is_shadowed(&p), false);
+        assert_eq!(w.is_shadowed(&p, &w.lights[0]), false);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_there_is_no_shadow_when_an_object_is_behind_the_point() {
         let w = World::default_world();
         let p = Tuple::point(-2.0, 2.0, -2.0);
 
-        assert_eq!(w.is_shadowed(&p), false);
+        assert_eq!(w.is_shadowed(&p, &w.lights[0]), false);
+    }
+
+    #[test]
+    fn test_updating_an_objects_transform() {
+        let mut w = World::default_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let updated = w.update_object(1, |o| o.set_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+        let xs = w.intersect(&r);
+
+        assert!(updated);
+        assert_eq!(xs.count(), 2);
+        assert_eq!(xs.at(0).t, 4.0);
+        assert_eq!(xs.at(1).t, 6.0);
+    }
+
+    #[test]
+    fn test_updating_an_objects_material() {
+        let mut w = World::default_world();
+        let mut m = Material::new();
+        m.ambient = 1.0;
+
+        let updated = w.update_object(0, |o| o.set_material(&m));
+
+        assert!(updated);
+        assert_eq!(w.objects[0].get_material(), m);
+    }
+
+    #[test]
+    fn test_updating_a_shared_object_is_refused() {
+        let mut w = World::default_world();
+        let shared = w.objects[0].clone();
+
+        let updated = w.update_object(0, |o| o.set_transform(&Matrix::translation(5.0, 0.0, 0.0)));
+
+        assert!(!updated);
+        assert_eq!(shared.get_transform(), Matrix::identify());
+        assert!(!w.update_object(5, |_| {}));
     }
 
     #[test]
     fn test_shade_hit_is_given_an_intersection_in_shadow() {
         let mut w = World::default_world();
-        w.light = Some(PointLight::new(
-            &Tuple::point(0.0, 0.0, -10.0),
-            &Color::white(),
-        ));
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::white()).into()];
         let s1 = Arc::new(Sphere::new());
         let s2 = Arc::new(Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, 10.0)));
         w.objects = vec![s1.clone(), s2.clone()];
         let r = Ray::new(&Tuple::point(0.0, 0.0, 5.0), &Tuple::vector(0.0, 0.0, 1.0));
         let i = Intersection::new(4.0, s2);
 
-        let comps = i.prepare_computation(&r);
-        let c = w.shade_hit(&comps);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+        let c = w.shade_hit(&comps, 5);
 
         assert_eq!(c, Color::new(0.1, 0.1, 0.1));
     }
+
+    #[test]
+    fn test_shadows_can_be_turned_off() {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::white()).into()];
+        let s1 = Arc::new(Sphere::new());
+        let s2 = Arc::new(Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, 10.0)));
+        w.objects = vec![s1, s2.clone()];
+        w.shadows = false;
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let i = Intersection::new(4.0, s2);
+
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+        let c = w.shade_hit(&comps, 5);
+
+        assert_eq!(c, Color::new(1.9, 1.9, 1.9));
+    }
+
+    #[test]
+    fn test_the_bounds_of_a_world_skip_infinite_objects() {
+        let mut w = World::default_world();
+        w.objects.push(Arc::new(Plane::new()));
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(5.0, 0.0, 0.0)),
+        ));
+
+        let b = w.bounds();
+
+        assert_eq!(b.min, Tuple::point(-1.0, -1.0, -1.0));
+        assert_eq!(b.max, Tuple::point(6.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_dividing_a_world_groups_its_bounded_objects() {
+        let mut w = World::default_world();
+        w.objects.push(Arc::new(Plane::new()));
+        for i in 0..50 {
+            let x = (i % 10) as f64 * 3.0 - 13.5;
+            let y = (i / 10) as f64 * 3.0 - 6.0;
+            w.objects.push(Arc::new(
+                Sphere::new().with_transform(&Matrix::translation(x, y, 4.0)),
+            ));
+        }
+        let shared = w.objects[0].clone();
+        let rays: Vec<Ray> = (0..40)
+            .map(|i| {
+                Ray::new(
+                    &Tuple::point(i as f64 * 0.7 - 14.0, i as f64 * 0.3 - 6.0, -10.0),
+                    &Tuple::vector(0.0, -0.1, 1.0).normalize(),
+                )
+            })
+            .collect();
+        let before: Vec<Vec<f64>> = rays
+            .iter()
+            .map(|r| {
+                let xs = w.intersect(r);
+                (0..xs.count()).map(|i| xs.at(i).t).collect()
+            })
+            .collect();
+
+        w.divide(4);
+
+        assert_eq!(w.objects.len(), 2);
+        assert!(w.objects.iter().any(|o| o.includes(shared.as_ref())));
+        for (r, expected) in rays.iter().zip(before.iter()) {
+            let xs = w.intersect(r);
+            let ts: Vec<f64> = (0..xs.count()).map(|i| xs.at(i).t).collect();
+            assert_eq!(ts.len(), expected.len());
+            assert!(ts
+                .iter()
+                .zip(expected.iter())
+                .all(|(a, b)| equal_f64(*a, *b)));
+        }
+    }
+
+    #[test]
+    fn test_dividing_a_small_world_leaves_it_alone() {
+        let mut w = World::default_world();
+
+        w.divide(4);
+
+        assert_eq!(w.objects.len(), 2);
+    }
+
+    #[test]
+    fn test_a_shape_hidden_from_the_camera_still_casts_shadows() {
+        let mut m = Material::new();
+        m.visibility.camera = false;
+        let w = World::default_world_with_material(&m);
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = w.intersect_visible(&r, RayKind::Camera);
+
+        assert_eq!(xs.count(), 2);
+        assert_eq!(xs.hit().unwrap().t, 4.5);
+        assert!(w.is_shadowed(&Tuple::point(10.0, -10.0, 10.0), &w.lights[0]));
+    }
+
+    fn one_sided() -> Material {
+        let mut m = Material::new();
+        m.two_sided = false;
+        m
+    }
+
+    #[test]
+    fn test_camera_rays_miss_a_one_sided_plane_from_behind() {
+        let mut w = World::new();
+        w.objects
+            .push(Arc::new(Plane::new().with_material(one_sided())));
+        let above = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+        let below = Ray::new(&Tuple::point(0.0, -1.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+
+        assert_eq!(w.intersect_visible(&above, RayKind::Camera).count(), 1);
+        assert_eq!(w.intersect_visible(&below, RayKind::Camera).count(), 0);
+        assert_eq!(w.intersect_visible(&below, RayKind::Shadow).count(), 1);
+        assert_eq!(w.intersect_visible(&below, RayKind::Reflection).count(), 1);
+        assert_eq!(w.intersect_visible(&below, RayKind::Refraction).count(), 1);
+    }
+
+    #[test]
+    fn test_camera_rays_see_only_the_front_of_a_one_sided_sphere() {
+        let mut w = World::new();
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_material(one_sided())
+                .with_transform(&Matrix::scaling(2.0, 2.0, 2.0)),
+        ));
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let xs = w.intersect_visible(&r, RayKind::Camera);
+
+        assert_eq!(xs.count(), 1);
+        assert!(equal_f64(xs.at(0).t, 3.0));
+    }
+
+    #[test]
+    fn test_one_sided_children_of_a_transformed_group_are_culled() {
+        let mut g = Group::new().with_transform(&Matrix::rotation_x(PI));
+        g.add_child(Plane::new().with_material(one_sided()));
+        let mut w = World::new();
+        w.objects.push(Arc::new(g));
+        let above = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+        let below = Ray::new(&Tuple::point(0.0, -1.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+
+        assert_eq!(w.intersect_visible(&above, RayKind::Camera).count(), 0);
+        assert_eq!(w.intersect_visible(&below, RayKind::Camera).count(), 1);
+    }
+
+    #[test]
+    fn test_a_one_sided_plane_shadows_points_behind_it() {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 10.0, 0.0), &Color::white()).into()];
+        w.objects
+            .push(Arc::new(Plane::new().with_material(one_sided())));
+
+        assert!(w.is_shadowed(&Tuple::point(0.0, -1.0, 0.0), &w.lights[0]));
+    }
+
+    #[test]
+    fn test_a_shape_hidden_from_shadow_rays_casts_no_shadow() {
+        let mut m = Material::new();
+        m.visibility.shadow = false;
+        let mut w = World::default_world_with_material(&m);
+        w.objects.truncate(1);
+
+        assert!(!w.is_shadowed(&Tuple::point(10.0, -10.0, 10.0), &w.lights[0]));
+        assert_eq!(
+            w.intersect_visible(
+                &Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0)),
+                RayKind::Camera
+            )
+            .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_a_material_shader_replaces_the_lighting() {
+        let mut m = Material::new();
+        m.shader = Some(Arc::new(|comps: &Computation, _: &World| {
+            let n = &comps.normalv;
+            Some(Color::new(n.x.abs(), n.y.abs(), n.z.abs()))
+        }));
+        let w = World::default_world_with_material(&m);
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        assert_eq!(w.color_at(&r), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_a_shader_returning_none_falls_back_to_lighting() {
+        let mut m = World::default_world().objects[0].get_material();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let expected = World::default_world().color_at(&r);
+        m.shader = Some(Arc::new(|comps: &Computation, world: &World| {
+            if world.is_shadowed(&comps.over_point, &world.lights[0]) {
+                Some(Color::black())
+            } else {
+                None
+            }
+        }));
+        let w = World::default_world_with_material(&m);
+
+        assert_eq!(w.color_at(&r), expected);
+    }
+
+    #[test]
+    fn test_shadow_fractions_match_individual_shadow_rays() {
+        let mut w = World::default_world();
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(20.0, 0.0, 0.0)),
+        ));
+        let light = w.lights[0].clone();
+        let points = vec![
+            Tuple::point(0.0, 10.0, 0.0),
+            Tuple::point(10.0, -10.0, 10.0),
+            Tuple::point(-20.0, 20.0, -20.0),
+            Tuple::point(-2.0, 2.0, -2.0),
+            Tuple::point(1.5, -1.5, 1.5),
+        ];
+
+        let fractions = w.shadow_fractions(&points, &light);
+
+        assert_eq!(fractions, vec![0.0, 1.0, 0.0, 0.0, 1.0]);
+        for (point, fraction) in points.iter().zip(fractions.iter()) {
+            assert_eq!(w.is_shadowed(point, &w.lights[0]), *fraction == 1.0);
+        }
+    }
+
+    #[test]
+    fn test_shadow_fractions_are_partial_in_a_penumbra() {
+        let mut w = World::new();
+        let light: Light = AreaLight::new(
+            &Tuple::point(-1.0, 5.0, -1.0),
+            &Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            &Tuple::vector(0.0, 0.0, 2.0),
+            4,
+            &Color::white(),
+        )
+        .into();
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(0.0, 2.0, 0.0)),
+        ));
+        let points = vec![
+            Tuple::point(0.0, 0.0, 0.0),
+            Tuple::point(1.2, 0.0, 0.0),
+            Tuple::point(5.0, 0.0, 0.0),
+        ];
+
+        let fractions = w.shadow_fractions(&points, &light);
+
+        assert!(equal_f64(fractions[0], 1.0));
+        assert!(fractions[1] > 0.0 && fractions[1] < 1.0);
+        assert!(equal_f64(fractions[2], 0.0));
+        let visible = w.light_visibility(&points[1], &light.samples());
+        assert!(equal_f64(fractions[1], 1.0 - visible.red));
+    }
+
+    #[test]
+    fn test_finding_objects_by_name_and_id() {
+        let mut w = World::default_world();
+        let mut g = Group::new();
+        g.add_child(Sphere::new().with_name("marble"));
+        w.objects.push(Arc::new(g));
+
+        let marble = w.find_by_name("marble").unwrap();
+
+        assert_eq!(marble.get_name(), Some("marble"));
+        assert!(w.find_by_name("missing").is_none());
+        assert_eq!(
+            w.find_by_id(marble.id()).unwrap().get_name(),
+            Some("marble")
+        );
+    }
+
+    #[test]
+    fn test_an_intersection_records_the_id_of_the_hit_shape() {
+        let w = World::default_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let hit = w.intersect(&r).hit().unwrap();
+
+        assert_eq!(hit.object.id(), w.objects[0].id());
+    }
+
+    #[test]
+    fn test_the_world_default_material_shades_shapes_without_one() {
+        let mut w = World::default_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        w.objects.remove(0);
+        let before = w.color_at(&r);
+
+        let mut m = Material::new();
+        m.color = Color::new(1.0, 0.0, 0.0);
+        w.default_material = Some(m);
+        let after = w.color_at(&r);
+
+        assert!(equal_f64(after.red, before.red));
+        assert!(after.green < before.green);
+        assert!(equal_f64(after.green, after.blue));
+    }
+
+    fn glass_world() -> World {
+        let mut m = World::default_world().objects[0].get_material();
+        m.transparency = 1.0;
+        m.refractive_index = 1.5;
+        World::default_world_with_material(&m)
+    }
+
+    #[test]
+    fn test_the_refracted_color_with_an_opaque_surface() {
+        let w = World::default_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let xs = Intersections::new(vec![
+            Intersection::new(4.0, w.objects[0].clone()),
+            Intersection::new(6.0, w.objects[0].clone()),
+        ]);
+
+        let comps = xs.at(0).prepare_computation(&r, &xs);
+
+        assert_eq!(w.refracted_color(&comps, 5), Color::black());
+    }
+
+    #[test]
+    fn test_the_refracted_color_at_the_maximum_recursive_depth() {
+        let w = glass_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let xs = Intersections::new(vec![
+            Intersection::new(4.0, w.objects[0].clone()),
+            Intersection::new(6.0, w.objects[0].clone()),
+        ]);
+
+        let comps = xs.at(0).prepare_computation(&r, &xs);
+
+        assert_eq!(w.refracted_color(&comps, 0), Color::black());
+    }
+
+    #[test]
+    fn test_the_refracted_color_under_total_internal_reflection() {
+        let w = glass_world();
+        let r = Ray::new(
+            &Tuple::point(0.0, 0.0, 2.0_f64.sqrt() / 2.0),
+            &Tuple::vector(0.0, 1.0, 0.0),
+        );
+        let xs = Intersections::new(vec![
+            Intersection::new(-(2.0_f64.sqrt()) / 2.0, w.objects[0].clone()),
+            Intersection::new(2.0_f64.sqrt() / 2.0, w.objects[0].clone()),
+        ]);
+
+        let comps = xs.at(1).prepare_computation(&r, &xs);
+
+        assert_eq!(w.refracted_color(&comps, 5), Color::black());
+    }
+
+    #[test]
+    fn test_shade_hit_with_a_transparent_material() {
+        let mut w = World::default_world();
+        let mut floor_material = Material::new();
+        floor_material.transparency = 0.5;
+        floor_material.refractive_index = 1.5;
+        let floor = Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&floor_material),
+        );
+        let mut ball_material = Material::new();
+        ball_material.color = Color::new(1.0, 0.0, 0.0);
+        ball_material.ambient = 0.5;
+        let ball = Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, -3.5, -0.5))
+                .with_material(&ball_material),
+        );
+        w.objects.push(floor.clone());
+        w.objects.push(ball);
+        let r = Ray::new(
+            &Tuple::point(0.0, 0.0, -3.0),
+            &Tuple::vector(0.0, -(2.0_f64.sqrt()) / 2.0, 2.0_f64.sqrt() / 2.0),
+        );
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&r, &xs);
+
+        assert_eq!(
+            w.shade_hit(&comps, 5),
+            Color::new(1.12547, 0.68642, 0.68642)
+        );
+    }
+
+    #[test]
+    fn test_a_transparent_shape_lets_the_background_through() {
+        let opaque = World::default_world();
+        let glass = glass_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let mut shallow = glass_world();
+        shallow.max_depth = 0;
+
+        assert_ne!(glass.color_at(&r), opaque.color_at(&r));
+        assert_eq!(shallow.color_at(&r), opaque.color_at(&r));
+    }
+
+    fn reflective_floor(reflective: f64) -> Arc<Plane> {
+        let mut m = Material::new();
+        m.reflective = reflective;
+        Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&m),
+        )
+    }
+
+    fn ray_at_floor() -> Ray {
+        Ray::new(
+            &Tuple::point(0.0, 0.0, -3.0),
+            &Tuple::vector(0.0, -(2.0_f64.sqrt()) / 2.0, 2.0_f64.sqrt() / 2.0),
+        )
+    }
+
+    #[test]
+    fn test_the_reflected_color_for_a_nonreflective_material() {
+        let mut m = World::default_world().objects[1].get_material();
+        m.ambient = 1.0;
+        let mut w = World::default_world();
+        w.update_object(1, |o| o.set_material(&m));
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let xs = Intersections::new(vec![Intersection::new(1.0, w.objects[1].clone())]);
+
+        let comps = xs.at(0).prepare_computation(&r, &xs);
+
+        assert_eq!(w.reflected_color(&comps, 5), Color::black());
+    }
+
+    #[test]
+    fn test_the_reflected_color_for_a_reflective_material() {
+        let mut w = World::default_world();
+        let floor = reflective_floor(0.5);
+        w.objects.push(floor.clone());
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&ray_at_floor(), &xs);
+
+        assert_eq!(
+            w.reflected_color(&comps, 5),
+            Color::new(0.19033, 0.23791, 0.14274)
+        );
+        assert_eq!(w.reflected_color(&comps, 0), Color::black());
+    }
+
+    #[test]
+    fn test_the_world_default_material_makes_shapes_without_one_reflective() {
+        let mut w = World::default_world();
+        let mut m = Material::new();
+        m.reflective = 0.5;
+        w.default_material = Some(m);
+        let floor: Arc<Plane> =
+            Arc::new(Plane::new().with_transform(&Matrix::translation(0.0, -1.0, 0.0)));
+        w.objects.push(floor.clone());
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&ray_at_floor(), &xs);
+
+        assert_eq!(
+            w.reflected_color(&comps, 5),
+            Color::new(0.19033, 0.23791, 0.14274)
+        );
+    }
+
+    #[test]
+    fn test_shade_hit_with_a_reflective_material() {
+        let mut w = World::default_world();
+        let floor = reflective_floor(0.5);
+        w.objects.push(floor.clone());
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&ray_at_floor(), &xs);
+
+        assert_eq!(
+            w.shade_hit(&comps, 5),
+            Color::new(0.87676, 0.92434, 0.82917)
+        );
+    }
+
+    #[test]
+    fn test_color_at_with_mutually_reflective_surfaces() {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, 0.0), &Color::white()).into()];
+        let mut m = Material::new();
+        m.reflective = 1.0;
+        w.objects.push(Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&m),
+        ));
+        w.objects.push(Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, 1.0, 0.0))
+                .with_material(&m),
+        ));
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
+
+        let c = w.color_at(&r);
+
+        assert!(c.red.is_finite());
+    }
+
+    #[test]
+    fn test_shade_hit_with_a_reflective_transparent_material() {
+        let mut w = World::default_world();
+        let mut floor_material = Material::new();
+        floor_material.reflective = 0.5;
+        floor_material.transparency = 0.5;
+        floor_material.refractive_index = 1.5;
+        let floor = Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&floor_material),
+        );
+        let mut ball_material = Material::new();
+        ball_material.color = Color::new(1.0, 0.0, 0.0);
+        ball_material.ambient = 0.5;
+        w.objects.push(floor.clone());
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, -3.5, -0.5))
+                .with_material(&ball_material),
+        ));
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&ray_at_floor(), &xs);
+
+        assert_eq!(
+            w.shade_hit(&comps, 5),
+            Color::new(1.11500, 0.69643, 0.69243)
+        );
+    }
+
+    fn tinted_glass_world(thickness: f64) -> World {
+        let mut w = World::new();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 10.0, -10.0), &Color::white()).into()];
+        let mut glass = Material::new();
+        glass.ambient = 0.0;
+        glass.diffuse = 0.0;
+        glass.specular = 0.0;
+        glass.transparency = 1.0;
+        glass.absorption = Color::new(0.5, 0.0, 0.0);
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::scaling(1.0, 1.0, thickness / 2.0))
+                .with_material(&glass),
+        ));
+        let mut backdrop = Material::new();
+        backdrop.ambient = 1.0;
+        backdrop.diffuse = 0.0;
+        backdrop.specular = 0.0;
+        w.objects.push(Arc::new(
+            Plane::new()
+                .with_transform(
+                    &(Matrix::translation(0.0, 0.0, 5.0) * Matrix::rotation_x(PI / 2.0)),
+                )
+                .with_material(&backdrop),
+        ));
+        w
+    }
+
+    #[test]
+    fn test_light_is_absorbed_by_the_distance_travelled_through_a_medium() {
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+
+        let thin = tinted_glass_world(0.5).color_at(&r);
+        let thick = tinted_glass_world(2.0).color_at(&r);
+
+        assert!((thick.red - (-1.0_f64).exp()).abs() < 1e-4);
+        assert!((thin.red - (-0.25_f64).exp()).abs() < 1e-4);
+        assert!(equal_f64(thick.green, 1.0));
+        assert!(equal_f64(thick.blue, 1.0));
+    }
+
+    #[test]
+    fn test_a_rough_surface_blurs_its_reflection_repeatably() {
+        let mut w = World::default_world();
+        let mut m = Material::new();
+        m.reflective = 0.5;
+        m.roughness = 0.3;
+        let floor = Arc::new(
+            Plane::new()
+                .with_transform(&Matrix::translation(0.0, -1.0, 0.0))
+                .with_material(&m),
+        );
+        w.objects.push(floor.clone());
+        let xs = Intersections::new(vec![Intersection::new(2.0_f64.sqrt(), floor)]);
+
+        let comps = xs.at(0).prepare_computation(&ray_at_floor(), &xs);
+        let glossy = w.reflected_color(&comps, 5);
+
+        assert_ne!(glossy, Color::new(0.19033, 0.23791, 0.14274));
+        assert_eq!(w.reflected_color(&comps, 5), glossy);
+        assert!(glossy.green > 0.0);
+    }
+
+    #[test]
+    fn test_shading_sums_the_contribution_of_every_light() {
+        let mut w = World::default_world();
+        let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let single = w.color_at(&r);
+
+        w.lights.push(w.lights[0].clone());
+
+        assert_eq!(w.color_at(&r), single * 2.0);
+    }
+
+    #[test]
+    fn test_shadows_are_checked_for_each_light() {
+        let mut w = World::default_world();
+        w.lights
+            .push(PointLight::new(&Tuple::point(10.0, -10.0, 10.0), &Color::white()).into());
+        let p = Tuple::point(5.0, -5.0, 5.0);
+
+        assert!(w.is_shadowed(&p, &w.lights[0]));
+        assert!(!w.is_shadowed(&p, &w.lights[1]));
+    }
+
+    fn light_samples() -> Vec<Tuple> {
+        (0..4)
+            .flat_map(|u| (0..4).map(move |v| (u, v)))
+            .map(|(u, v)| {
+                Tuple::point(
+                    -10.0 + (u as f64 - 1.5) * 0.5,
+                    10.0 + (v as f64 - 1.5) * 0.5,
+                    -10.0,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_light_visibility_is_the_fraction_of_samples_seen() {
+        let w = World::default_world();
+
+        assert_eq!(
+            w.light_visibility(&Tuple::point(0.0, 10.0, 0.0), &light_samples()),
+            Color::white()
+        );
+        assert_eq!(
+            w.light_visibility(&Tuple::point(10.0, -10.0, 10.0), &light_samples()),
+            Color::black()
+        );
+        assert_eq!(
+            w.light_visibility(&Tuple::point(5.0, -3.0, 5.0), &light_samples()),
+            Color::white() * (13.0 / 16.0)
+        );
+    }
+
+    #[test]
+    fn test_adaptive_shadows_only_probe_when_the_probes_agree() {
+        let mut w = World::default_world();
+        let exact = World::default_world();
+        w.options = w
+            .options
+            .with_adaptive_shadows(AdaptiveShadows::new())
+            .with_profile(true);
+        let lit = Tuple::point(0.0, 10.0, 0.0);
+        let penumbra = Tuple::point(5.0, -3.0, 5.0);
+
+        let (visibility, probed) = profile::record(|| w.light_visibility(&lit, &light_samples()));
+        let (partial, full) = profile::record(|| w.light_visibility(&penumbra, &light_samples()));
+
+        assert_eq!(visibility, Color::white());
+        assert_eq!(probed.shadow_rays, 4);
+        assert_eq!(partial, exact.light_visibility(&penumbra, &light_samples()));
+        assert_eq!(full.shadow_rays, 4 + 16);
+    }
+
+    #[test]
+    fn test_an_area_light_casts_a_soft_shadow() {
+        let mut w = World::new();
+        w.lights = vec![AreaLight::new(
+            &Tuple::point(-1.0, 5.0, -1.0),
+            &Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            &Tuple::vector(0.0, 0.0, 2.0),
+            4,
+            &Color::white(),
+        )
+        .into()];
+        w.objects.push(Arc::new(Plane::new()));
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(0.0, 2.0, 0.0)),
+        ));
+        let floor_at =
+            |x: f64| Ray::new(&Tuple::point(x, 1.0, -5.0), &Tuple::vector(0.0, -1.0, 5.0));
+        let ambient = 0.1;
+
+        let umbra = w.color_at(&floor_at(0.0)).red;
+        let penumbra = w.color_at(&floor_at(1.5)).red;
+        w.shadows = false;
+        let unshadowed = w.color_at(&floor_at(1.5)).red;
+
+        assert!(equal_f64(umbra, ambient));
+        assert!(penumbra > ambient);
+        assert!(penumbra < unshadowed);
+    }
+
+    #[test]
+    fn test_an_area_light_is_partly_shadowed_in_its_penumbra() {
+        let mut w = World::new();
+        w.lights = vec![AreaLight::new(
+            &Tuple::point(-1.0, 5.0, -1.0),
+            &Tuple::vector(2.0, 0.0, 0.0),
+            4,
+            &Tuple::vector(0.0, 0.0, 2.0),
+            4,
+            &Color::white(),
+        )
+        .into()];
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(0.0, 2.0, 0.0)),
+        ));
+        let light = &w.lights[0];
+        // The sphere hides the light's centre from here, but not all of it.
+        let penumbra = Tuple::point(1.2, 0.0, 0.0);
+
+        let attenuation = w.shadow_attenuation(&penumbra, light);
+
+        assert!(w.is_shadowed(&penumbra, light));
+        assert!(attenuation.red > 0.0 && attenuation.red < 1.0);
+        assert!(!w.is_shadowed(&Tuple::point(5.0, 0.0, 0.0), light));
+        assert_eq!(
+            w.shadow_attenuation(&Tuple::point(0.0, 0.0, 0.0), light),
+            Color::black()
+        );
+    }
+
+    #[test]
+    fn test_rays_entering_a_portal_continue_from_its_twin() {
+        let mut w = World::default_world();
+        let (a, b) = Portal::pair(&Matrix::identify(), &Matrix::translation(20.0, 0.0, 0.0));
+        w.objects = vec![Arc::new(a), Arc::new(b)];
+        let mut red = Material::new();
+        red.color = Color::new(1.0, 0.0, 0.0);
+        red.ambient = 1.0;
+        red.diffuse = 0.0;
+        red.specular = 0.0;
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(20.0, 5.0, 0.0))
+                .with_material(&red),
+        ));
+        let r = Ray::new(&Tuple::point(0.0, 5.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+
+        assert_eq!(w.color_at(&r), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_facing_portals_stop_at_the_maximum_depth() {
+        let mut w = World::default_world();
+        let (a, b) = Portal::pair(
+            &Matrix::identify(),
+            &(Matrix::translation(0.0, 2.0, 0.0) * Matrix::rotation_x(PI)),
+        );
+        w.objects = vec![Arc::new(a), Arc::new(b)];
+        let r = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
+
+        assert_eq!(w.color_at(&r), Color::black());
+    }
+
+    #[test]
+    fn test_the_world_ambient_light_scales_every_material() {
+        let mut w = World::default_world();
+        w.lights = vec![PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::white()).into()];
+        let s1 = Arc::new(Sphere::new());
+        let s2 = Arc::new(Sphere::new().with_transform(&Matrix::translation(0.0, 0.0, 10.0)));
+        w.objects = vec![s1, s2.clone()];
+        let r = Ray::new(&Tuple::point(0.0, 0.0, 5.0), &Tuple::vector(0.0, 0.0, 1.0));
+        let i = Intersection::new(4.0, s2);
+        let comps = i.prepare_computation(&r, &Intersections::new(vec![i.clone()]));
+
+        w.ambient = Color::new(0.5, 1.0, 0.0);
+        assert_eq!(w.shade_hit(&comps, 5), Color::new(0.05, 0.1, 0.0));
+
+        w.ambient = Color::black();
+        assert_eq!(w.shade_hit(&comps, 5), Color::black());
+    }
+
+    #[test]
+    fn test_transparent_objects_cast_partial_shadows() {
+        let mut w = World::new();
+        let light: Light = PointLight::new(&Tuple::point(0.0, 10.0, 0.0), &Color::white()).into();
+        w.lights = vec![light.clone()];
+        let mut glass = Material::new();
+        glass.transparency = 0.5;
+        w.objects.push(Arc::new(
+            Sphere::new()
+                .with_transform(&Matrix::translation(0.0, 5.0, 0.0))
+                .with_material(&glass),
+        ));
+        let point = Tuple::point(0.0, 0.0, 0.0);
+
+        assert_eq!(w.shadow_attenuation(&point, &light), Color::white() * 0.25);
+        assert!(w.is_shadowed(&point, &light));
+        assert_eq!(
+            w.shadow_attenuation(&Tuple::point(5.0, 0.0, 0.0), &light),
+            Color::white()
+        );
+
+        w.objects.push(Arc::new(
+            Sphere::new().with_transform(&Matrix::translation(0.0, 8.0, 0.0)),
+        ));
+        assert_eq!(w.shadow_attenuation(&point, &light), Color::black());
+    }
+
+    #[test]
+    fn test_colored_glass_tints_the_light_through_it() {
+        let mut w = World::new();
+        let light: Light = PointLight::new(&Tuple::point(0.0, 10.0, 0.0), &Color::white()).into();
+        w.lights = vec![light.clone()];
+        let mut pane = Material::new();
+        pane.color = Color::new(1.0, 0.2, 0.2);
+        pane.transparency = 0.9;
+        w.objects.push(Arc::new(
+            Cube::new()
+                .with_transform(
+                    &(Matrix::translation(0.0, 5.0, 0.0) * Matrix::scaling(2.0, 0.05, 2.0)),
+                )
+                .with_material(&pane),
+        ));
+
+        let filter = w.shadow_attenuation(&Tuple::point(0.0, 0.0, 0.0), &light);
+
+        assert_eq!(filter, Color::new(0.81, 0.0324, 0.0324));
+    }
 }